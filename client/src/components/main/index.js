@@ -21,7 +21,7 @@ import { toast } from "react-toastify";
 import Dropdown from "../dropDown";
 import Modal from "../Modal/Modal";
 import { Tooltip } from "react-tooltip";
-import { getERC20Balance } from "../../utils/helper";
+import { fetchCoins, getERC20Balance } from "../../utils/helper";
 import { currencies } from "../../utils/Currencies";
 import { BrowserRouter as Router, Routes, Route } from "react-router-dom";
 import InProgress from "../Modal/InProgress";
@@ -113,11 +113,11 @@ const Main = () => {
     dispatch(setReceivedCoinsLoading(true));
 
     const coinsIntervalId = setInterval(() => {
-      axios.get(`${coreEndpoint}/coins`).then((result) => {
+      fetchCoins(coreEndpoint).then((coins) => {
         dispatch(
           setReceivedCoins({
             type: "SET_RECEIVED_COINS",
-            payload: result.data.coins,
+            payload: coins,
           })
         );
         dispatch(setReceivedCoinsLoading(false));
@@ -180,11 +180,12 @@ const Main = () => {
             tokenContract,
             utils.toBigInt(to_wei_token_amount),
             address,
-            OwshenWallet.contract_address
+            OwshenWallet.contract_address,
+            result.data.view_tag
           );
           await tx.wait();
-          axios.get(`${coreEndpoint}/coins`).then((result) => {
-            setReceivedCoins(result.data.coins);
+          fetchCoins(coreEndpoint).then((coins) => {
+            setReceivedCoins(coins);
             setIsOpen(false);
           });
         } catch (error) {
@@ -233,6 +234,13 @@ const Main = () => {
         },
       })
       .then(async (result) => {
+        const gas = result.data.gas || {};
+        const overrides = {
+          gasLimit: gas.gas_limit || options.gasLimit,
+          ...(gas.gas_price && { gasPrice: gas.gas_price }),
+          ...(gas.max_fee && { maxFeePerGas: gas.max_fee }),
+          ...(gas.priority_fee && { maxPriorityFeePerGas: gas.priority_fee }),
+        };
         let abi = OwshenWallet.contract_abi;
         let commitment1 = result.data.sender_commitment;
         let commitment2 = result.data.receiver_commitment;
@@ -275,7 +283,8 @@ const Main = () => {
             result.data.obfuscated_receiver_amount,
             result.data.obfuscated_sender_amount,
             true,
-            options
+            (result.data.receiver_view_tag << 8) | result.data.sender_view_tag,
+            overrides
           );
           console.log("Transaction response", txResponse);
           const txReceipt = await txResponse.wait();
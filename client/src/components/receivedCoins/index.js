@@ -41,9 +41,17 @@ const ReceivedCoinList = () => {
           index: index,
           address: owshen.wallet,
           desire_amount: "1",
+          to: address,
         },
       })
       .then(async (result) => {
+        const gas = result.data.gas || {};
+        const overrides = {
+          gasLimit: gas.gas_limit || options.gasLimit,
+          ...(gas.gas_price && { gasPrice: gas.gas_price }),
+          ...(gas.max_fee && { maxFeePerGas: gas.max_fee }),
+          ...(gas.priority_fee && { maxPriorityFeePerGas: gas.priority_fee }),
+        };
         let abi = owshen.contract_abi;
         let commitment = result.data.commitment;
         let provider = new ethers.BrowserProvider(window.ethereum);
@@ -73,11 +81,13 @@ const ReceivedCoinList = () => {
             ephemeral,
             proof,
             result.data.token,
-            toBigInt(1),
+            toBigInt(result.data.withdrawn_amount),
             result.data.obfuscated_remaining_amount,
-            address,
+            result.data.to,
             commitment,
-            options
+            result.data.view_tag,
+            toBigInt(result.data.relayer_fee),
+            overrides
           );
           console.log("Transaction response", txResponse);
           const txReceipt = await txResponse.wait();
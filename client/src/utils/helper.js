@@ -1,3 +1,4 @@
+import axios from "axios";
 import { ethers, formatUnits } from "ethers";
 
 export const openSend = () => {
@@ -16,3 +17,16 @@ export const getERC20Balance = async (tokenAddress, userAddress, ABI) => {
   const balance = await contract.balanceOf(userAddress);
   return formatUnits(balance, 18); // Assumes the token has 18 decimal places
 };
+
+// `/coins` responds with a page of the coins, the next ones are fetched while
+// it says it has more.
+export const fetchCoins = async (coreEndpoint) => {
+  let coins = [];
+  for (;;) {
+    const result = await axios.get(`${coreEndpoint}/coins`, {
+      params: { offset: coins.length },
+    });
+    coins = coins.concat(result.data.coins);
+    if (!result.data.has_more) return coins;
+  }
+};
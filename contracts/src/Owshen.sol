@@ -28,9 +28,16 @@ contract Owshen {
         uint256 timestamp,
         uint256 _hint_amount,
         uint256 _hint_tokenAddress,
-        uint256 _commitment
+        uint256 _commitment,
+        // First byte of the shared secret hash plus one, 0 when the sender
+        // didn't compute it. Lets wallets skip foreign coins cheaply.
+        uint8 _view_tag
     );
 
+    // Bumped whenever a function or event wallets rely on changes, e.g. the
+    // view tags of version 2 and the relayer fee of withdrawals.
+    uint256 public constant VERSION = 2;
+
     event Spend(uint256 nullifier);
     event Deposit(Point indexed pub_key, Point ephemeral, uint256 nullifier);
 
@@ -56,13 +63,14 @@ contract Owshen {
         address _tokenAddress,
         uint256 _amount,
         address _from,
-        address _to
+        address _to,
+        uint8 _view_tag
     ) public payable {
         uint256 uint_tokenaddress = getUintTokenAddress(_tokenAddress);
         uint256 leaf = mimc.poseidon([_pub_key.x, _pub_key.y, _amount, uint_tokenaddress]);
         tree.set(depositIndex, leaf);
         _processDeposit(_from, _to, _tokenAddress, _amount);
-        emit Sent(ephemeral, depositIndex, block.timestamp, _amount, uint_tokenaddress, leaf);
+        emit Sent(ephemeral, depositIndex, block.timestamp, _amount, uint_tokenaddress, leaf, _view_tag);
         depositIndex += 1;
     }
 
@@ -95,16 +103,30 @@ contract Owshen {
         uint256 _amount,
         uint256 _obfuscated_remaining_amount,
         address _to,
-        uint256 _commitment
+        uint256 _commitment,
+        uint8 _view_tag,
+        // Paid out of `_amount` to the sender of the transaction, e.g. a relayer.
+        uint256 _fee
     ) public {
+        require(_fee <= _amount, "Fee exceeds the amount");
         uint256 uint_tokenaddress = getUintTokenAddress(_tokenAddress);
-        uint256 commitment2 = mimc.poseidon([0, 0, _amount, uint_tokenaddress]);
+        // The withdrawn coin is committed to the receiver and the fee in place
+        // of a public key, the proof binds both.
+        uint256 commitment2 = mimc.poseidon([uint256(uint160(_to)), _fee, _amount, uint_tokenaddress]);
         spend(nullifier, proof, commitment2, _commitment);
         tree.set(depositIndex, _commitment);
-        IERC20 payToken = IERC20(_tokenAddress);
-        payToken.transfer(_to, _amount);
+        IERC20(_tokenAddress).transfer(_to, _amount - _fee);
+        if (_fee > 0) {
+            IERC20(_tokenAddress).transfer(msg.sender, _fee);
+        }
         emit Sent(
-            _ephemeral, depositIndex, block.timestamp, _obfuscated_remaining_amount, uint_tokenaddress, _commitment
+            _ephemeral,
+            depositIndex,
+            block.timestamp,
+            _obfuscated_remaining_amount,
+            uint_tokenaddress,
+            _commitment,
+            _view_tag
         );
         emit Spend(nullifier);
         depositIndex += 1;
@@ -120,18 +142,32 @@ contract Owshen {
         uint256 _token_address_hint,
         uint256 _receiver_amount_hint,
         uint256 _sender_amount_hint,
-        bool isDualOutput
+        bool isDualOutput,
+        // Receiver's view tag in the high byte, sender's in the low one.
+        uint16 _view_tags
     ) public {
         spend(nullifier, proof, _commitment2, _commitment1);
         tree.set(depositIndex, _commitment2);
         emit Sent(
-            receiver_ephemeral, depositIndex, block.timestamp, _receiver_amount_hint, _token_address_hint, _commitment2
+            receiver_ephemeral,
+            depositIndex,
+            block.timestamp,
+            _receiver_amount_hint,
+            _token_address_hint,
+            _commitment2,
+            uint8(_view_tags >> 8)
         );
         depositIndex += 1;
         if (isDualOutput) {
             tree.set(depositIndex, _commitment1);
             emit Sent(
-                sender_ephemeral, depositIndex, block.timestamp, _sender_amount_hint, _token_address_hint, _commitment1
+                sender_ephemeral,
+                depositIndex,
+                block.timestamp,
+                _sender_amount_hint,
+                _token_address_hint,
+                _commitment1,
+                uint8(_view_tags)
             );
             depositIndex += 1;
         }
@@ -4,7 +4,16 @@ pragma solidity ^0.8.13;
 import "openzeppelin-contracts/contracts/token/ERC20/ERC20.sol";
 
 contract SimpleErc20 is ERC20 {
-    constructor(uint256 initialSupply, string memory _name, string memory _symbol) ERC20(_name, _symbol) {
+    uint8 private immutable _decimals;
+
+    constructor(uint256 initialSupply, string memory _name, string memory _symbol, uint8 decimals_)
+        ERC20(_name, _symbol)
+    {
+        _decimals = decimals_;
         _mint(msg.sender, initialSupply);
     }
+
+    function decimals() public view override returns (uint8) {
+        return _decimals;
+    }
 }
@@ -19,7 +19,8 @@ contract OwshenTest is Test {
             0xB4FBF271143F4FBf7B91A5ded31805e42b2208d6,
             1000,
             0x90F8bf6A479f320ead074411a4B0e7944Ea8c9C1,
-            0x90F8bf6A479f320ead074411a4B0e7944Ea8c9C1
+            0x90F8bf6A479f320ead074411a4B0e7944Ea8c9C1,
+            0
         );
         assertEq(owshen.depositIndex(), 1);
         owshen.deposit(
@@ -28,7 +29,8 @@ contract OwshenTest is Test {
             0xB4FBF271143F4FBf7B91A5ded31805e42b2208d6,
             2000, // Add the amount here
             0x90F8bf6A479f320ead074411a4B0e7944Ea8c9C1,
-            0x90F8bf6A479f320ead074411a4B0e7944Ea8c9C1
+            0x90F8bf6A479f320ead074411a4B0e7944Ea8c9C1,
+            0
         );
         assertEq(owshen.depositIndex(), 2);
     }
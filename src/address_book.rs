@@ -0,0 +1,151 @@
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::apis::ApiError;
+use crate::keys::PublicKey;
+use crate::write_atomic;
+
+/// Labelled Owshen addresses of frequent receivers.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct AddressBook {
+    entries: BTreeMap<String, PublicKey>,
+}
+
+impl AddressBook {
+    /// Loads the book at `path`, an absent file is an empty book.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_atomic(path, serde_json::to_string(self)?.as_bytes())
+    }
+
+    pub fn entries(&self) -> &BTreeMap<String, PublicKey> {
+        &self.entries
+    }
+
+    /// Stores `address` under `label`. A label already in the book is only
+    /// given another address with `replace`, payments to it would otherwise
+    /// silently go elsewhere.
+    pub fn add(
+        &mut self,
+        label: &str,
+        address: &str,
+        replace: bool,
+    ) -> Result<PublicKey, ApiError> {
+        let label = label.trim();
+        if label.is_empty() {
+            return Err(ApiError::BadRequest("Label cannot be empty!".to_string()));
+        }
+        // Otherwise the label would shadow the address it spells.
+        if PublicKey::from_str(label).is_ok() {
+            return Err(ApiError::BadRequest(
+                "Label cannot be an address!".to_string(),
+            ));
+        }
+        let address = PublicKey::from_str(address.trim())?;
+        match self.entries.get(label) {
+            Some(listed) if *listed != address && !replace => {
+                return Err(ApiError::BadRequest(format!(
+                    "{:?} already labels {}, ask to replace it!",
+                    label, listed
+                )))
+            }
+            _ => {}
+        }
+        self.entries.insert(label.to_string(), address);
+        Ok(address)
+    }
+
+    pub fn remove(&mut self, label: &str) -> Result<PublicKey, ApiError> {
+        self.entries
+            .remove(label.trim())
+            .ok_or(ApiError::NotFound(format!(
+                "No address labelled {:?}!",
+                label
+            )))
+    }
+
+    /// Parses `label_or_address` as an address, or looks it up as a label.
+    pub fn resolve(&self, label_or_address: &str) -> Result<PublicKey, ApiError> {
+        let label_or_address = label_or_address.trim();
+        if let Ok(address) = PublicKey::from_str(label_or_address) {
+            return Ok(address);
+        }
+        self.entries
+            .get(label_or_address)
+            .copied()
+            .ok_or(ApiError::BadRequest(format!(
+                "{:?} is neither an address nor a known label!",
+                label_or_address
+            )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::PrivateKey;
+
+    #[test]
+    fn test_address_book() {
+        let alice = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        let mut book = AddressBook::default();
+
+        assert_eq!(book.add("alice", &alice.to_string(), false).unwrap(), alice);
+        assert_eq!(book.resolve("alice").unwrap(), alice);
+        assert_eq!(book.resolve(" alice ").unwrap(), alice);
+        assert_eq!(book.resolve(&alice.to_string()).unwrap(), alice);
+        assert!(matches!(book.resolve("bob"), Err(ApiError::BadRequest(_))));
+
+        // An existing label keeps its address unless asked to replace it.
+        let mallory = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        assert!(matches!(
+            book.add("alice", &mallory.to_string(), false),
+            Err(ApiError::BadRequest(_))
+        ));
+        assert_eq!(book.resolve("alice").unwrap(), alice);
+        assert_eq!(book.add("alice", &alice.to_string(), false).unwrap(), alice);
+        assert_eq!(
+            book.add("alice", &mallory.to_string(), true).unwrap(),
+            mallory
+        );
+        assert_eq!(book.resolve("alice").unwrap(), mallory);
+        book.add("alice", &alice.to_string(), true).unwrap();
+
+        let path = tempfile::NamedTempFile::new().unwrap();
+        book.save(path.path()).unwrap();
+        assert_eq!(AddressBook::load(path.path()).unwrap(), book);
+
+        assert_eq!(book.remove("alice").unwrap(), alice);
+        assert!(matches!(book.remove("alice"), Err(ApiError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_address_book_rejects_invalid_addresses() {
+        let alice = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        let mut book = AddressBook::default();
+        for address in ["", "OoOo", "OoOo2xyz", "0x1234"] {
+            assert!(matches!(
+                book.add("bob", address, false),
+                Err(ApiError::BadRequest(_))
+            ));
+        }
+        assert!(matches!(
+            book.add(&alice.to_string(), &alice.to_string(), false),
+            Err(ApiError::BadRequest(_))
+        ));
+        assert!(matches!(
+            book.add(" ", &alice.to_string(), false),
+            Err(ApiError::BadRequest(_))
+        ));
+        assert!(book.entries().is_empty());
+    }
+}
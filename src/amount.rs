@@ -0,0 +1,383 @@
+use ethers::types::U256;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::apis::ApiError;
+use crate::fp::Fp;
+
+/// An amount of a token, in the token's smallest unit.
+///
+/// On the wire amounts are decimal strings, so that clients don't lose
+/// precision parsing them as floats. Hex strings, which is how amounts were
+/// serialized before, are still accepted when deserializing.
+///
+/// Amounts of requests stay strings, they only become an `Amount` once parsed
+/// along with the request's `decimals`, see `parse_request_amount`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(U256);
+
+impl Amount {
+    pub fn zero() -> Self {
+        Amount(U256::zero())
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+}
+
+impl From<U256> for Amount {
+    fn from(value: U256) -> Self {
+        Amount(value)
+    }
+}
+
+impl From<u64> for Amount {
+    fn from(value: u64) -> Self {
+        Amount(U256::from(value))
+    }
+}
+
+impl From<Amount> for U256 {
+    fn from(amount: Amount) -> Self {
+        amount.0
+    }
+}
+
+impl FromStr for Amount {
+    type Err = ApiError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_raw_amount(s)
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AmountVisitor;
+
+        impl<'de> de::Visitor<'de> for AmountVisitor {
+            type Value = Amount;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an amount as a decimal or hex string")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Amount, E> {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Amount, E> {
+                Ok(Amount::from(v))
+            }
+        }
+
+        deserializer.deserialize_any(AmountVisitor)
+    }
+}
+
+// Amounts end up in commitments, which are computed in the field: larger
+// values would silently wrap around.
+fn ensure_in_field(amount: &str, value: U256) -> Result<Amount, ApiError> {
+    Fp::from_u256_checked(value)
+        .map(|_| Amount(value))
+        .map_err(|_| {
+            ApiError::BadRequest(format!(
+                "Invalid amount {:?}: not below the field modulus",
+                amount
+            ))
+        })
+}
+
+/// Parses an amount given in the token's smallest unit, either as a decimal
+/// number or as a `0x` prefixed hex number.
+pub fn parse_raw_amount(amount: &str) -> Result<Amount, ApiError> {
+    let invalid =
+        |reason: &str| ApiError::BadRequest(format!("Invalid amount {:?}: {}", amount, reason));
+    let trimmed = amount.trim();
+    if trimmed.is_empty() {
+        return Err(invalid("empty amount"));
+    }
+    if trimmed.starts_with('-') {
+        return Err(invalid("amounts cannot be negative"));
+    }
+    let value = match trimmed
+        .strip_prefix("0x")
+        .or_else(|| trimmed.strip_prefix("0X"))
+    {
+        Some("") => return Err(invalid("empty hex number")),
+        Some(hex) if hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+            U256::from_str_radix(hex, 16).map_err(|_| invalid("does not fit in 256 bits"))
+        }
+        Some(_) => Err(invalid("not a hex number")),
+        None if trimmed.chars().all(|c| c.is_ascii_digit()) => {
+            U256::from_dec_str(trimmed).map_err(|_| invalid("does not fit in 256 bits"))
+        }
+        None => Err(invalid("not a number")),
+    }?;
+    ensure_in_field(amount, value)
+}
+
+/// Parses an amount given in whole tokens, e.g. `"1.5"`, into the token's
+/// smallest unit. Nothing is ever rounded: more fractional digits than the
+/// token has `decimals` is an error.
+pub fn parse_amount(amount: &str, decimals: u8) -> Result<Amount, ApiError> {
+    let invalid =
+        |reason: &str| ApiError::BadRequest(format!("Invalid amount {:?}: {}", amount, reason));
+    let trimmed = amount.trim();
+    if trimmed.starts_with('-') {
+        return Err(invalid("amounts cannot be negative"));
+    }
+    let (integer, fraction) = trimmed.split_once('.').unwrap_or((trimmed, ""));
+    if integer.is_empty() || (trimmed.contains('.') && fraction.is_empty()) {
+        return Err(invalid(
+            "expected digits on both sides of the decimal point",
+        ));
+    }
+    if !integer
+        .chars()
+        .chain(fraction.chars())
+        .all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid("not a number"));
+    }
+    if fraction.len() > decimals as usize {
+        return Err(invalid(&format!(
+            "more than {} fractional digits",
+            decimals
+        )));
+    }
+    let overflow = || invalid("does not fit in 256 bits");
+    let unit = U256::from(10)
+        .checked_pow(U256::from(decimals))
+        .ok_or_else(overflow)?;
+    let integer = U256::from_dec_str(integer).map_err(|_| overflow())?;
+    let fraction = if fraction.is_empty() {
+        U256::zero()
+    } else {
+        U256::from_dec_str(fraction).map_err(|_| overflow())?
+            * U256::from(10).pow(U256::from(decimals as usize - fraction.len()))
+    };
+    integer
+        .checked_mul(unit)
+        .and_then(|integer| integer.checked_add(fraction))
+        .ok_or_else(overflow)
+        .and_then(|value| ensure_in_field(amount, value))
+}
+
+/// Refuses a request amount in whole tokens, i.e. with `decimals` given, that
+/// has more fractional digits than the token, which has `token_decimals`.
+/// Whatever `decimals` the client gave, the digits past the token's own
+/// precision don't stand for anything the token can hold.
+pub fn check_precision(
+    amount: &str,
+    decimals: Option<u8>,
+    token_decimals: u8,
+) -> Result<(), ApiError> {
+    if decimals.is_none() {
+        // The smallest unit has no fraction.
+        return Ok(());
+    }
+    let fraction = amount.trim().split_once('.').map_or("", |(_, f)| f);
+    if fraction.len() > token_decimals as usize {
+        return Err(ApiError::BadRequest(format!(
+            "Invalid amount {:?}: the token has {} decimals, amounts take at most {} fractional digits",
+            amount, token_decimals, token_decimals
+        )));
+    }
+    Ok(())
+}
+
+/// Parses a request amount, in whole tokens when the client gives the
+/// token's `decimals` and in the smallest unit otherwise.
+pub fn parse_request_amount(amount: &str, decimals: Option<u8>) -> Result<Amount, ApiError> {
+    match decimals {
+        Some(decimals) => parse_amount(amount, decimals),
+        None => parse_raw_amount(amount),
+    }
+}
+
+/// Formats an amount of the token's smallest unit in whole tokens. The result
+/// is exact, trailing zeros of the fraction are dropped.
+pub fn format_amount(amount: Amount, decimals: u8) -> String {
+    let digits = amount.to_string();
+    let decimals = decimals as usize;
+    if decimals == 0 {
+        return digits;
+    }
+    let digits = format!("{:0>width$}", digits, width = decimals + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        integer.to_string()
+    } else {
+        format!("{}.{}", integer, fraction)
+    }
+}
+
+/// Formats an amount the way the client gave it, see `parse_request_amount`.
+pub fn format_request_amount(amount: Amount, decimals: Option<u8>) -> String {
+    match decimals {
+        Some(decimals) => format_amount(amount, decimals),
+        None => amount.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODULUS: &str =
+        "21888242871839275222246405745257275088548364400416034343698204186575808495617";
+
+    #[test]
+    fn test_parse_raw_amount() {
+        assert_eq!(parse_raw_amount("1000").unwrap(), Amount::from(1000));
+        assert_eq!(parse_raw_amount(" 42 ").unwrap(), Amount::from(42));
+        assert_eq!(parse_raw_amount("0x3e8").unwrap(), Amount::from(1000));
+        let max = Amount::from(U256::from_dec_str(MODULUS).unwrap() - 1);
+        assert_eq!(parse_raw_amount(&max.to_string()).unwrap(), max);
+
+        for invalid in [
+            "",
+            "  ",
+            "0x",
+            "-5",
+            "abc",
+            "1.5",
+            "1e18",
+            "0xzz",
+            MODULUS,
+            "21888242871839275222246405745257275088548364400416034343698204186575808495618",
+            U256::MAX.to_string().as_str(),
+            // U256::MAX + 1
+            "115792089237316195423570985008687907853269984665640564039457584007913129639936",
+            "0x10000000000000000000000000000000000000000000000000000000000000000",
+        ] {
+            assert!(
+                matches!(parse_raw_amount(invalid), Err(ApiError::BadRequest(_))),
+                "{:?} should be rejected",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_token_amounts() {
+        // USDC-like, 6 decimals
+        assert_eq!(parse_amount("1", 6).unwrap(), Amount::from(1_000_000));
+        assert_eq!(parse_amount("1.5", 6).unwrap(), Amount::from(1_500_000));
+        assert_eq!(parse_amount("0.000001", 6).unwrap(), Amount::from(1));
+        assert_eq!(parse_amount("0", 6).unwrap(), Amount::zero());
+        assert!(parse_amount("0.0000001", 6).is_err());
+        assert_eq!(format_amount(Amount::from(1_500_000), 6), "1.5");
+        assert_eq!(format_amount(Amount::from(1), 6), "0.000001");
+        assert_eq!(format_amount(Amount::from(1_000_000), 6), "1");
+        assert_eq!(format_amount(Amount::zero(), 6), "0");
+
+        // ETH-like, 18 decimals
+        let wei = U256::exp10(18);
+        assert_eq!(parse_amount("2", 18).unwrap(), Amount::from(wei * 2));
+        assert_eq!(
+            parse_amount("0.000000000000000001", 18).unwrap(),
+            Amount::from(1)
+        );
+        assert!(parse_amount("0.0000000000000000001", 18).is_err());
+        assert_eq!(format_amount(Amount::from(1), 18), "0.000000000000000001");
+        assert_eq!(format_amount(Amount::from(wei * 1234 / 1000), 18), "1.234");
+
+        // Edge values
+        let modulus = U256::from_dec_str(MODULUS).unwrap();
+        let max = Amount::from(modulus - 1);
+        assert_eq!(parse_amount(&format_amount(max, 18), 18).unwrap(), max);
+        assert!(parse_amount(&format_amount(Amount::from(modulus), 18), 18).is_err());
+        let too_many_tokens = U256::MAX / wei + 1;
+        assert!(parse_amount(&too_many_tokens.to_string(), 18).is_err());
+        assert_eq!(
+            format_amount(Amount::from(U256::MAX), 0),
+            U256::MAX.to_string()
+        );
+        for invalid in ["", ".", "1.", ".5", "-1", "1.2.3", "1,5", "0x10", "1e6"] {
+            assert!(parse_amount(invalid, 18).is_err(), "{:?}", invalid);
+        }
+
+        for amount in ["0", "1", "0.5", "123.000001", "999999.999999"] {
+            assert_eq!(format_amount(parse_amount(amount, 6).unwrap(), 6), amount);
+        }
+    }
+
+    #[test]
+    fn test_check_precision() {
+        // A 6 decimals token, e.g. USDC.
+        assert!(check_precision("1.000001", Some(6), 6).is_ok());
+        assert!(check_precision("1.0000001", Some(6), 6).is_err());
+        // Even when the client claims more decimals than the token has.
+        let err = check_precision("1.0000001", Some(18), 6).unwrap_err();
+        assert!(
+            err.to_string().contains("the token has 6 decimals"),
+            "{}",
+            err
+        );
+        assert!(check_precision("1.5", Some(18), 6).is_ok());
+        assert!(check_precision("1000001", None, 6).is_ok());
+    }
+
+    #[test]
+    fn test_amount_serde() {
+        let amount = Amount::from(U256::exp10(18) * 3);
+        let json = serde_json::to_string(&amount).unwrap();
+        assert_eq!(json, "\"3000000000000000000\"");
+        assert_eq!(serde_json::from_str::<Amount>(&json).unwrap(), amount);
+
+        // Hex strings, as written by older versions, and plain numbers.
+        assert_eq!(
+            serde_json::from_str::<Amount>("\"0x64\"").unwrap(),
+            Amount::from(100)
+        );
+        assert_eq!(
+            serde_json::from_str::<Amount>("100").unwrap(),
+            Amount::from(100)
+        );
+        assert!(serde_json::from_str::<Amount>("\"-1\"").is_err());
+        assert!(serde_json::from_str::<Amount>("\"1.5\"").is_err());
+        assert!(serde_json::from_str::<Amount>("-1").is_err());
+    }
+
+    #[test]
+    fn test_amount_checked_arithmetic() {
+        let max = Amount::from(U256::MAX);
+        assert_eq!(
+            Amount::from(40).checked_add(Amount::from(2)),
+            Some(Amount::from(42))
+        );
+        assert_eq!(max.checked_add(Amount::from(1)), None);
+        assert_eq!(max.checked_add(Amount::zero()), Some(max));
+        assert_eq!(
+            Amount::from(42).checked_sub(Amount::from(42)),
+            Some(Amount::zero())
+        );
+        assert_eq!(Amount::from(1).checked_sub(Amount::from(2)), None);
+        assert_eq!("0x2a".parse::<Amount>().unwrap(), Amount::from(42));
+        assert_eq!(Amount::from(42).to_string(), "42");
+    }
+}
@@ -0,0 +1,39 @@
+use axum::extract::Json;
+use std::path::PathBuf;
+
+use crate::apis::ApiError;
+use crate::SharedContext;
+use crate::{GetAddressBookResponse, PostAddressBookAddRequest, PostAddressBookRemoveRequest};
+
+pub async fn address_book_list(context: SharedContext) -> Result<GetAddressBookResponse, ApiError> {
+    Ok(GetAddressBookResponse {
+        entries: context.read().await.address_book.entries().clone(),
+    })
+}
+
+pub async fn address_book_add(
+    Json(req): Json<PostAddressBookAddRequest>,
+    context: SharedContext,
+    address_book_path: PathBuf,
+) -> Result<GetAddressBookResponse, ApiError> {
+    let mut ctx = context.write().await;
+    ctx.address_book
+        .add(&req.label, &req.address, req.replace)?;
+    ctx.address_book.save(&address_book_path)?;
+    Ok(GetAddressBookResponse {
+        entries: ctx.address_book.entries().clone(),
+    })
+}
+
+pub async fn address_book_remove(
+    Json(req): Json<PostAddressBookRemoveRequest>,
+    context: SharedContext,
+    address_book_path: PathBuf,
+) -> Result<GetAddressBookResponse, ApiError> {
+    let mut ctx = context.write().await;
+    ctx.address_book.remove(&req.label)?;
+    ctx.address_book.save(&address_book_path)?;
+    Ok(GetAddressBookResponse {
+        entries: ctx.address_book.entries().clone(),
+    })
+}
@@ -0,0 +1,12 @@
+use axum::http::header;
+use axum::response::IntoResponse;
+
+use crate::apis::ApiError;
+use crate::keys::PublicKey;
+use crate::qr;
+
+/// Returns the wallet's address as an SVG QR code.
+pub async fn address_qr(address: PublicKey) -> Result<impl IntoResponse, ApiError> {
+    let code = qr::address_qr(&address)?;
+    Ok(([(header::CONTENT_TYPE, "image/svg+xml")], qr::to_svg(&code)))
+}
@@ -0,0 +1,174 @@
+use axum::extract::Query;
+use std::sync::Arc;
+
+use crate::amount::Amount;
+use crate::apis::ApiError;
+use crate::{
+    Context, GetBalancesRequest, GetBalancesResponse, SharedContext, TokenBalance, TokenInfo,
+};
+
+fn add(total: &mut Amount, amount: Amount) -> Result<(), ApiError> {
+    *total = total
+        .checked_add(amount)
+        .ok_or(ApiError::Internal("Balance overflow!".to_string()))?;
+    Ok(())
+}
+
+/// Sums the unspent coins of `ctx` per token, coins not buried under
+/// `min_confirmations` blocks yet count as pending. Coins spent in such
+/// blocks don't count at all, they are on their way out. The tokens of the wallet
+/// come first, even without coins, then the unknown ones in order of
+/// appearance.
+pub fn sum_balances(ctx: &Context, tokens: &[TokenInfo]) -> Result<Vec<TokenBalance>, ApiError> {
+    let mut balances = tokens
+        .iter()
+        .map(|token| TokenBalance {
+            address: token.token_address,
+            symbol: Some(token.symbol.clone()),
+            decimals: Some(token.decimals),
+            confirmed: Amount::zero(),
+            pending: Amount::zero(),
+        })
+        .collect::<Vec<_>>();
+    for coin in ctx.coins.iter().filter(|coin| !ctx.is_pending_spend(coin)) {
+        let position = match balances
+            .iter()
+            .position(|balance| balance.address == coin.uint_token)
+        {
+            Some(position) => position,
+            None => {
+                balances.push(TokenBalance {
+                    address: coin.uint_token,
+                    symbol: None,
+                    decimals: None,
+                    confirmed: Amount::zero(),
+                    pending: Amount::zero(),
+                });
+                balances.len() - 1
+            }
+        };
+        let balance = &mut balances[position];
+        if ctx.is_spendable(coin) {
+            add(&mut balance.confirmed, coin.amount)?;
+        } else {
+            add(&mut balance.pending, coin.amount)?;
+        }
+    }
+    Ok(balances)
+}
+
+/// The balance of every token, optionally of a single one.
+pub async fn balances(
+    Query(req): Query<GetBalancesRequest>,
+    context: SharedContext,
+    tokens: Arc<Vec<TokenInfo>>,
+) -> Result<GetBalancesResponse, ApiError> {
+    let balances = sum_balances(&*context.read().await, &tokens)?;
+    Ok(GetBalancesResponse {
+        balances: balances
+            .into_iter()
+            .filter(|balance| req.token.is_none_or(|token| token == balance.address))
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::{PrivateKey, PublicKey};
+    use crate::Coin;
+    use ethers::types::{H160, U256, U64};
+    use tokio::sync::RwLock;
+
+    fn coin(index: u64, amount: u64, token: H160, block: u64) -> Coin {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        Coin {
+            index: U256::from(index),
+            uint_token: token,
+            amount: Amount::from(amount),
+            priv_key: Some(priv_key),
+            pub_key: PublicKey::from(priv_key),
+            nullifier: Some(U256::from(1000 + index)),
+            commitment: U256::from(2000 + index),
+            block_number: Some(U64::from(block)),
+            tx_hash: None,
+            ephemeral: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_balances() {
+        let weth = H160::from_low_u64_be(1);
+        let usdc = H160::from_low_u64_be(2);
+        let unknown = H160::from_low_u64_be(3);
+        let tokens = Arc::new(vec![
+            TokenInfo {
+                token_address: weth,
+                symbol: "WETH".to_string(),
+                decimals: 18,
+            },
+            TokenInfo {
+                token_address: usdc,
+                symbol: "USDC".to_string(),
+                decimals: 6,
+            },
+        ]);
+        let coins = vec![
+            coin(0, 10, weth, 90),
+            coin(1, 5, unknown, 90),
+            coin(2, 7, weth, 99),
+            coin(3, 20, weth, 80),
+        ];
+        let mut ctx = Context::new(H160::default(), false);
+        ctx.coins = Arc::new(coins.clone());
+        ctx.last_synced_block = Some(U64::from(100));
+        ctx.min_confirmations = 6;
+        let context = Arc::new(RwLock::new(ctx));
+
+        let all = GetBalancesRequest { token: None };
+        let response = balances(Query(all), context.clone(), tokens.clone())
+            .await
+            .unwrap();
+        let addresses = response
+            .balances
+            .iter()
+            .map(|balance| balance.address)
+            .collect::<Vec<_>>();
+        assert_eq!(addresses, vec![weth, usdc, unknown]);
+        // Each coin is counted exactly once.
+        for balance in response.balances.iter() {
+            let raw = coins
+                .iter()
+                .filter(|coin| coin.uint_token == balance.address)
+                .fold(Amount::zero(), |total, coin| {
+                    total.checked_add(coin.amount).unwrap()
+                });
+            assert_eq!(balance.confirmed.checked_add(balance.pending), Some(raw));
+        }
+        let weth_balance = &response.balances[0];
+        assert_eq!(weth_balance.confirmed, Amount::from(30u64));
+        assert_eq!(weth_balance.pending, Amount::from(7u64));
+        assert_eq!(weth_balance.symbol.as_deref(), Some("WETH"));
+        assert!(response.balances[1].confirmed.is_zero());
+        assert_eq!(response.balances[2].symbol, None);
+        assert_eq!(response.balances[2].confirmed, Amount::from(5u64));
+
+        // Spent in a shallow block, the coin no longer counts.
+        context
+            .write()
+            .await
+            .pending_nullifiers
+            .insert(coins[0].nullifier.unwrap(), U64::from(99));
+        let all = GetBalancesRequest { token: None };
+        let response = balances(Query(all), context.clone(), tokens.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.balances[0].confirmed, Amount::from(20u64));
+        assert_eq!(response.balances[0].pending, Amount::from(7u64));
+
+        let only_usdc = GetBalancesRequest { token: Some(usdc) };
+        let only_usdc = balances(Query(only_usdc), context, tokens).await.unwrap();
+        assert_eq!(only_usdc.balances.len(), 1);
+        assert_eq!(only_usdc.balances[0].decimals, Some(6));
+    }
+}
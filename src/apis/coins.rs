@@ -1,136 +1,1904 @@
-use axum::Json;
+use axum::body::{Bytes, StreamBody};
+use axum::extract::Query;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
 use bindings::owshen::{SentFilter, SpendFilter};
 use ethers::prelude::*;
 use eyre::Result;
+use futures::{FutureExt, StreamExt};
 
-use std::str::FromStr;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::ops::Range;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::sync::Mutex;
-use tokio::time::timeout;
+use tokio::sync::broadcast;
+use tokio::sync::RwLock;
+use tokio::time::{Duration, Instant};
 
+use crate::apis::{history, ApiError};
+use crate::blinding::deobfuscate;
+use crate::block_confirmations;
+use crate::commitment::{commitment as coin_commitment, nullifier};
+use crate::confirmations;
 use crate::fp::Fp;
-use crate::hash::hash4;
 use crate::keys::Point;
-use crate::keys::{EphemeralKey, PrivateKey, PublicKey};
+use crate::keys::{EphemeralKey, PrivateKey, PublicKey, ScanningKey, UNTAGGED};
+use crate::metrics::METRICS;
+use crate::rpc::{self, RpcProvider};
 use crate::tree::SparseMerkleTree;
 use crate::u256_to_h160;
 use crate::Coin;
 use crate::Context;
-use crate::GetCoinsResponse;
+use crate::GetCoinsRequest;
+use crate::ListedCoin;
+use crate::SharedContext;
+use crate::SyncProgress;
+use crate::WatchedCoin;
+use crate::{is_pending_spend, is_spendable};
 
-#[allow(dead_code)]
-pub async fn coins(
-    context_coin: Arc<Mutex<Context>>,
-    contract: Contract<Provider<Http>>,
-    priv_key: PrivateKey,
-) -> Result<Json<GetCoinsResponse>, eyre::Report> {
-    let mut my_coins: Vec<Coin> = Vec::new();
-    let mut tree = SparseMerkleTree::new(16);
-    let sent_events = timeout(std::time::Duration::from_secs(5), async {
-        contract
-            .event::<SentFilter>()
-            .from_block(0)
-            .to_block(1000)
-            .address(ValueOrArray::Value(contract.address()))
-            .query()
-            .await
-            .unwrap()
+/// Blocks covered by a single `eth_getLogs` call while scanning.
+pub const DEFAULT_SCAN_CHUNK_SIZE: u64 = 2000;
+
+/// Chunks fetched at the same time while scanning.
+pub const DEFAULT_SCAN_CONCURRENCY: usize = 4;
+
+/// Tries to recognize a `Sent` event as a coin of the wallet of `key`, sent
+/// either to its address or to the single-key address of older wallets,
+/// which only the spending key detects. The coin is either a direct deposit
+/// (plain hints) or a send (hints obfuscated with the shared secret). Coins
+/// carrying a view tag which doesn't match are skipped before anything else
+/// is computed.
+pub fn detect(key: &ScanningKey, sent_event: &SentFilter) -> Result<Option<Coin>> {
+    let ephemeral = EphemeralKey {
+        point: Point {
+            x: Fp::try_from(sent_event.ephemeral.x)?,
+            y: Fp::try_from(sent_event.ephemeral.y)?,
+        },
+    };
+
+    let viewing_key = key.viewing_key();
+    if is_tagged_for(sent_event, viewing_key.view_tag(ephemeral)) {
+        let stealth_priv = key
+            .spending_key()
+            .map(|priv_key| priv_key.with_shared_secret(viewing_key.shared_secret(ephemeral)));
+        let stealth_pub = viewing_key.derive(ephemeral);
+        if let Some(coin) = open(sent_event, ephemeral, stealth_pub, stealth_priv)? {
+            return Ok(Some(coin));
+        }
+    }
+    match key.spending_key() {
+        Some(priv_key) if is_tagged_for(sent_event, priv_key.view_tag(ephemeral)) => {
+            let stealth_priv = priv_key.derive(ephemeral);
+            open(
+                sent_event,
+                ephemeral,
+                stealth_priv.into(),
+                Some(stealth_priv),
+            )
+        }
+        _ => Ok(None),
+    }
+}
+
+fn is_tagged_for(sent_event: &SentFilter, view_tag: u8) -> bool {
+    sent_event.view_tag == UNTAGGED || sent_event.view_tag == view_tag
+}
+
+// The full detection, recomputing the commitment of the coin with
+// `stealth_pub`. Obfuscated hints need the private key of the coin.
+fn open(
+    sent_event: &SentFilter,
+    ephemeral: EphemeralKey,
+    stealth_pub: PublicKey,
+    stealth_priv: Option<PrivateKey>,
+) -> Result<Option<Coin>> {
+    let index: U256 = sent_event.index;
+    let hint_amount = sent_event.hint_amount;
+    let hint_token_address = sent_event.hint_token_address;
+    let commitment = sent_event.commitment;
+    let nullifier = stealth_priv.map(|stealth_priv| nullifier(&stealth_priv, index));
+
+    // Hints out of the field can't be the plain hints of a deposit.
+    let calc_commitment = coin_commitment(hint_amount, &stealth_pub, hint_token_address).ok();
+
+    if calc_commitment == Some(commitment) {
+        tracing::debug!("Detected a deposit at index {}", index);
+        return Ok(Some(Coin {
+            index,
+            uint_token: u256_to_h160(hint_token_address),
+            amount: hint_amount.into(),
+            nullifier,
+            priv_key: stealth_priv,
+            pub_key: stealth_pub,
+            commitment: sent_event.commitment,
+            block_number: None,
+            tx_hash: None,
+            ephemeral: Some(ephemeral),
+        }));
+    }
+
+    // get sends
+    let stealth_priv = match stealth_priv {
+        Some(stealth_priv) => stealth_priv,
+        None => return Ok(None),
+    };
+    let shared_secret = stealth_priv.shared_secret(ephemeral);
+    let amount = deobfuscate(hint_amount, shared_secret)?;
+    let token_address = deobfuscate(hint_token_address, shared_secret)?;
+
+    // Both are field elements, this can't fail.
+    let calc_commitment_obfuscate = coin_commitment(amount, &stealth_pub, token_address)?;
+
+    if commitment == calc_commitment_obfuscate {
+        tracing::debug!("Detected a received coin at index {}", index);
+        return Ok(Some(Coin {
+            index,
+            uint_token: u256_to_h160(token_address),
+            amount: amount.into(),
+            nullifier,
+            priv_key: Some(stealth_priv),
+            pub_key: stealth_pub,
+            commitment,
+            block_number: None,
+            tx_hash: None,
+            ephemeral: Some(ephemeral),
+        }));
+    }
+
+    Ok(None)
+}
+
+/// Recognizes a `Sent` event committed to `pub_key` itself with plain hints,
+/// rather than to a stealth key derived from it. Only these can be told
+/// apart without a private or viewing key, by anyone knowing the address.
+pub fn detect_public(pub_key: &PublicKey, sent_event: &SentFilter) -> Option<WatchedCoin> {
+    let commitment = coin_commitment(
+        sent_event.hint_amount,
+        pub_key,
+        sent_event.hint_token_address,
+    )
+    .ok()?;
+    (commitment == sent_event.commitment).then(|| WatchedCoin {
+        index: sent_event.index,
+        uint_token: u256_to_h160(sent_event.hint_token_address),
+        amount: sent_event.hint_amount.into(),
+        commitment,
+        block_number: None,
+        tx_hash: None,
     })
+}
+
+/// The coins of `sent_events` detectable for `pub_key`, along with the block
+/// and transaction they were created in when known, see `detect_public`.
+pub fn watch<'a>(
+    pub_key: &PublicKey,
+    sent_events: impl IntoIterator<Item = (&'a SentFilter, Option<U64>, Option<H256>)>,
+) -> Vec<WatchedCoin> {
+    sent_events
+        .into_iter()
+        .filter_map(|(sent_event, block_number, tx_hash)| {
+            detect_public(pub_key, sent_event).map(|coin| WatchedCoin {
+                block_number,
+                tx_hash,
+                ..coin
+            })
+        })
+        .collect()
+}
+
+/// Scans the whole chain for the coins detectable for `pub_key`, in chunks
+/// as `scan_chunks_with` does. Whether they were spent can't be told:
+/// nullifiers need the private key.
+pub async fn watch_chain(
+    contract: &Contract<RpcProvider>,
+    pub_key: &PublicKey,
+    chunk_size: u64,
+    concurrency: usize,
+) -> Result<Vec<WatchedCoin>, ApiError> {
+    let head = contract.client().get_block_number().await?;
+    // Nothing but the progress of this scan ever lands in this context.
+    let context = RwLock::new(Context::new(contract.address(), true));
+    scan_chunks_with(
+        &context,
+        Vec::<WatchedCoin>::new(),
+        0,
+        head,
+        chunk_size,
+        concurrency,
+        |from, to| fetch_events(contract, from, to),
+        |coins, sent_events, _| {
+            coins.extend(watch(
+                pub_key,
+                sent_events.iter().map(|(event, block_number, tx_hash)| {
+                    (event, Some(*block_number), Some(*tx_hash))
+                }),
+            ));
+            Ok(coins.len())
+        },
+    )
+    .await
+}
+
+pub struct Scan {
+    pub coins: Vec<Coin>,
+    pub tree: SparseMerkleTree,
+    pub spent_nullifiers: HashSet<U256>,
+    // Spends with fewer than `min_confirmations` as of `head`, their coins
+    // are kept. See `Context::pending_nullifiers`.
+    pub pending_nullifiers: HashMap<U256, U64>,
+    head: Option<U64>,
+    min_confirmations: u64,
+    // Number of `Sent` events, i.e. coins of every wallet.
+    pub scanned_coins: usize,
+    // Number of coins of the wallet, spent ones included.
+    pub owned_coins: usize,
+}
+
+impl Scan {
+    pub fn new() -> Self {
+        Scan {
+            coins: Vec::new(),
+            tree: SparseMerkleTree::new(16),
+            spent_nullifiers: HashSet::new(),
+            pending_nullifiers: HashMap::new(),
+            head: None,
+            min_confirmations: 0,
+            scanned_coins: 0,
+            owned_coins: 0,
+        }
+    }
+
+    /// A scan up to `head` which only counts the spends buried under
+    /// `min_confirmations` blocks as spent.
+    pub fn with_confirmations(head: U64, min_confirmations: u64) -> Self {
+        Scan {
+            head: Some(head),
+            min_confirmations,
+            ..Scan::new()
+        }
+    }
+
+    /// A scan up to `head` picking up where the last sync of `ctx` left off,
+    /// with its coins, tree and spent nullifiers. Its pending spends aren't
+    /// kept, the blocks they were seen in are scanned again.
+    pub fn resume(ctx: &Context, head: U64) -> Self {
+        Scan {
+            coins: ctx.coins.to_vec(),
+            tree: ctx.tree.clone(),
+            spent_nullifiers: ctx.spent_nullifiers.clone(),
+            scanned_coins: ctx.scanned_coins,
+            owned_coins: ctx.owned_coins,
+            ..Scan::with_confirmations(head, ctx.min_confirmations)
+        }
+    }
+
+    /// Inserts the commitments of `sent_events` in the tree and keeps the
+    /// coins that `key` detects, along with the block and transaction
+    /// they were created in when known. Events seen before, e.g. fetched twice by
+    /// overlapping ranges, are skipped, while a different commitment at an
+    /// index already set is an error rather than a corrupted tree. The
+    /// commitments are inserted together, once all of them were checked.
+    pub fn add_sent_events<'a>(
+        &mut self,
+        key: &ScanningKey,
+        sent_events: impl IntoIterator<Item = (&'a SentFilter, Option<U64>, Option<H256>)>,
+    ) -> Result<()> {
+        let mut leaves = HashMap::new();
+        for (sent_event, block_number, tx_hash) in sent_events {
+            let index = sent_event.index.low_u64();
+            let commitment = Fp::try_from(sent_event.commitment)?;
+            match self.tree.leaf(index).or(leaves.get(&index).copied()) {
+                Some(leaf) if leaf == commitment => {
+                    tracing::debug!("Skipping coin {} already scanned", index);
+                    continue;
+                }
+                Some(_) => {
+                    return Err(eyre::Report::msg(format!(
+                        "Two different commitments were scanned at index {}!",
+                        index
+                    )));
+                }
+                None => {
+                    leaves.insert(index, commitment);
+                }
+            }
+            if let Some(coin) = detect(key, sent_event)? {
+                self.owned_coins += 1;
+                if !coin.is_spent(&self.spent_nullifiers) {
+                    self.coins.push(Coin {
+                        block_number,
+                        tx_hash,
+                        ephemeral: None,
+                        ..coin
+                    });
+                }
+            }
+            self.scanned_coins += 1;
+        }
+        self.tree
+            .insert_many(&leaves.into_iter().collect::<Vec<_>>());
+        Ok(())
+    }
+
+    /// Drops the coins spent by `spend_events`, unless the block of the spend
+    /// isn't confirmed yet.
+    pub fn add_spend_events<'a>(
+        &mut self,
+        spend_events: impl IntoIterator<Item = (&'a SpendFilter, Option<U64>)>,
+    ) {
+        for (spend_event, block_number) in spend_events {
+            match (self.head, block_number) {
+                (Some(head), Some(block))
+                    if block_confirmations(head, block) < self.min_confirmations =>
+                {
+                    self.pending_nullifiers.insert(spend_event.nullifier, block);
+                }
+                _ => {
+                    self.pending_nullifiers.remove(&spend_event.nullifier);
+                    self.spent_nullifiers.insert(spend_event.nullifier);
+                }
+            }
+        }
+        let spent_nullifiers = &self.spent_nullifiers;
+        self.coins.retain(|coin| !coin.is_spent(spent_nullifiers));
+    }
+}
+
+impl Default for Scan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Rebuilds the merkle tree out of every `Sent` event and keeps the unspent
+/// coins that `key` detects. Coins of other wallets only end up in the
+/// tree, they can't be spent by this wallet and are never listed.
+pub fn scan(
+    key: &ScanningKey,
+    sent_events: &[SentFilter],
+    spend_events: &[SpendFilter],
+) -> Result<Scan> {
+    let mut scan = Scan::new();
+    scan.add_sent_events(key, sent_events.iter().map(|e| (e, None, None)))?;
+    scan.add_spend_events(spend_events.iter().map(|e| (e, None)));
+    Ok(scan)
+}
+
+// Providers word their log query limits differently, e.g. "query returned
+// more than 10000 results" (Infura) or "block range is too wide" (Alchemy).
+fn is_range_too_large(message: &str) -> bool {
+    let message = message.to_lowercase();
+    [
+        "more than",
+        "too many",
+        "too wide",
+        "too large",
+        "block range",
+        "limit exceeded",
+        "size exceeded",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+/// Scans blocks `0..=target_block` from scratch, see `scan_chunks_from`.
+async fn scan_chunks<F, Fut>(
+    context_coin: &RwLock<Context>,
+    key: &ScanningKey,
+    target_block: U64,
+    chunk_size: u64,
+    concurrency: usize,
+    fetch: F,
+) -> Result<Scan, ApiError>
+where
+    F: FnMut(u64, u64) -> Fut,
+    Fut: Future<Output = Result<(Vec<(SentFilter, U64, H256)>, Vec<(SpendFilter, U64)>), ApiError>>,
+{
+    let min_confirmations = context_coin.read().await.min_confirmations;
+    let scan = Scan::with_confirmations(target_block, min_confirmations);
+    scan_chunks_from(
+        context_coin,
+        key,
+        scan,
+        0,
+        target_block,
+        chunk_size,
+        concurrency,
+        fetch,
+    )
+    .await
+}
+
+/// Carries `scan` on over blocks `from_block..=target_block` with the
+/// detection of `key`, see `scan_chunks_with`.
+#[allow(clippy::too_many_arguments)]
+async fn scan_chunks_from<F, Fut>(
+    context_coin: &RwLock<Context>,
+    key: &ScanningKey,
+    scan: Scan,
+    from_block: u64,
+    target_block: U64,
+    chunk_size: u64,
+    concurrency: usize,
+    fetch: F,
+) -> Result<Scan, ApiError>
+where
+    F: FnMut(u64, u64) -> Fut,
+    Fut: Future<Output = Result<(Vec<(SentFilter, U64, H256)>, Vec<(SpendFilter, U64)>), ApiError>>,
+{
+    scan_chunks_with(
+        context_coin,
+        scan,
+        from_block,
+        target_block,
+        chunk_size,
+        concurrency,
+        fetch,
+        |scan, sent_events, spend_events| {
+            scan.add_sent_events(
+                key,
+                sent_events.iter().map(|(event, block_number, tx_hash)| {
+                    (event, Some(*block_number), Some(*tx_hash))
+                }),
+            )?;
+            scan.add_spend_events(
+                spend_events
+                    .iter()
+                    .map(|(event, block_number)| (event, Some(*block_number))),
+            );
+            Ok(scan.owned_coins)
+        },
+    )
+    .await
+}
+
+/// Carries `scan` on over blocks `from_block..=target_block` in chunks of
+/// `chunk_size` blocks, fetching the events of up to `concurrency`
+/// consecutive chunks at once through `fetch` and reporting the progress in
+/// the context as it goes. `apply` adds the events of a chunk, sent ones
+/// sorted by index, to `scan` and returns the number of coins found so far.
+/// Chunks are applied in block order whatever order their fetches complete
+/// in, so the result doesn't depend on `concurrency`. A chunk the provider
+/// refuses for returning too many logs is retried with half its size.
+#[allow(clippy::too_many_arguments)]
+async fn scan_chunks_with<S, F, Fut, A>(
+    context_coin: &RwLock<Context>,
+    mut scan: S,
+    mut from_block: u64,
+    target_block: U64,
+    chunk_size: u64,
+    concurrency: usize,
+    mut fetch: F,
+    mut apply: A,
+) -> Result<S, ApiError>
+where
+    F: FnMut(u64, u64) -> Fut,
+    Fut: Future<Output = Result<(Vec<(SentFilter, U64, H256)>, Vec<(SpendFilter, U64)>), ApiError>>,
+    A: FnMut(&mut S, &[(SentFilter, U64, H256)], &[(SpendFilter, U64)]) -> Result<usize>,
+{
+    let mut chunk_size = chunk_size.max(1);
+    if from_block > target_block.as_u64() {
+        return Ok(scan);
+    }
+    'window: loop {
+        let mut ranges = Vec::new();
+        let mut start = from_block;
+        while ranges.len() < concurrency.max(1) && start <= target_block.as_u64() {
+            let end = std::cmp::min(start + chunk_size - 1, target_block.as_u64());
+            ranges.push((start, end));
+            start = end + 1;
+        }
+        let results =
+            futures::future::join_all(ranges.iter().map(|&(from, to)| fetch(from, to))).await;
+
+        for ((from, to), result) in ranges.into_iter().zip(results) {
+            let (mut sent_events, spend_events) = match result {
+                Ok(events) => events,
+                Err(ApiError::NodeUnreachable(message))
+                    if chunk_size > 1 && is_range_too_large(&message) =>
+                {
+                    // The chunks after this one are fetched again, smaller.
+                    chunk_size /= 2;
+                    tracing::debug!(
+                        "Provider refused the range, retrying with {} blocks",
+                        chunk_size
+                    );
+                    from_block = from;
+                    continue 'window;
+                }
+                Err(e) => return Err(e),
+            };
+            sent_events.sort_by_key(|(event, _, _)| event.index);
+            let found_coins = apply(&mut scan, &sent_events, &spend_events)?;
+
+            context_coin.write().await.sync_progress = Some(SyncProgress {
+                current_block: U64::from(to),
+                target_block,
+                found_coins,
+            });
+            tracing::debug!("Scanned blocks {}..={} of {}", from, to, target_block);
+
+            if to >= target_block.as_u64() {
+                return Ok(scan);
+            }
+            from_block = to + 1;
+        }
+    }
+}
+
+async fn fetch_events(
+    contract: &Contract<RpcProvider>,
+    from_block: u64,
+    to_block: u64,
+) -> Result<(Vec<(SentFilter, U64, H256)>, Vec<(SpendFilter, U64)>), ApiError> {
+    // Requests time out in the transport, see `rpc::connect_with_timeout`.
+    let sent_events = contract
+        .event::<SentFilter>()
+        .from_block(from_block)
+        .to_block(to_block)
+        .address(ValueOrArray::Value(contract.address()))
+        .query_with_meta()
+        .await
+        .map_err(ApiError::from_contract_call)?
+        .into_iter()
+        .map(|(event, meta)| (event, meta.block_number, meta.transaction_hash))
+        .collect();
+    let spend_events = contract
+        .event::<SpendFilter>()
+        .from_block(from_block)
+        .to_block(to_block)
+        .address(ValueOrArray::Value(contract.address()))
+        .query_with_meta()
+        .await
+        .map_err(ApiError::from_contract_call)?
+        .into_iter()
+        .map(|(event, meta)| (event, meta.block_number))
+        .collect();
+    Ok((sent_events, spend_events))
+}
+
+/// Finds the coin at `index` if `key` detects it, whether it was spent since
+/// or not.
+pub async fn find_coin(
+    contract: &Contract<RpcProvider>,
+    key: &ScanningKey,
+    index: U256,
+    chunk_size: u64,
+    concurrency: usize,
+) -> Result<Option<Coin>, ApiError> {
+    let block_number = contract.client().get_block_number().await?;
+    // Nothing but the progress of this scan ever lands in this context.
+    let context = RwLock::new(Context::new(contract.address(), false));
+    let scan = scan_chunks(
+        &context,
+        key,
+        block_number,
+        chunk_size,
+        concurrency,
+        |from, to| {
+            let events = fetch_events(contract, from, to);
+            async move {
+                let (sent_events, _) = events.await?;
+                // Spends are left out, they would drop a spent coin.
+                let sent_events = sent_events
+                    .into_iter()
+                    .filter(|(event, _, _)| event.index == index)
+                    .collect();
+                Ok((sent_events, vec![]))
+            }
+        },
+    )
     .await?;
-    for sent_event in sent_events {
-        let ephemeral = EphemeralKey {
-            point: Point {
-                x: Fp::try_from(sent_event.ephemeral.x)?,
-                y: Fp::try_from(sent_event.ephemeral.y)?,
-            },
+    Ok(scan.coins.into_iter().next())
+}
+
+/// Broadcasts the coins of `new_coins` that are not among the `known` ones.
+pub fn publish_new_coins(
+    coin_sender: &broadcast::Sender<Coin>,
+    known: &[Coin],
+    new_coins: &[Coin],
+) {
+    for coin in new_coins {
+        if !known.iter().any(|c| c.index == coin.index) {
+            // Sending only fails when nobody is listening.
+            let _ = coin_sender.send(*coin);
+        }
+    }
+}
+
+/// Heads of past syncs remembered to detect reorgs, deeper reorgs roll back
+/// to the oldest of them.
+const RECENT_BLOCKS: usize = 64;
+
+async fn block_hash(client: &RpcProvider, number: U64) -> Result<Option<H256>, ApiError> {
+    Ok(client.get_block(number).await?.and_then(|block| block.hash))
+}
+
+/// Compares the heads of the past syncs with the chain. When some are no
+/// longer part of it, drops the coins created after the last head that still
+/// is, the common ancestor, so that they can't be spent before the next scan
+/// rebuilds the state out of the canonical chain. Returns that ancestor.
+async fn roll_back_reorg(
+    context_coin: &RwLock<Context>,
+    client: &RpcProvider,
+) -> Result<Option<U64>, ApiError> {
+    let recent_blocks = context_coin.read().await.recent_blocks.clone();
+    let mut ancestor = None;
+    let mut reorged = false;
+    for (&number, &hash) in recent_blocks.iter().rev() {
+        if block_hash(client, number).await? == Some(hash) {
+            ancestor = Some(number);
+            break;
+        }
+        reorged = true;
+    }
+    if !reorged {
+        return Ok(None);
+    }
+    // Without a common ancestor, everything after the oldest head goes.
+    let ancestor = ancestor.unwrap_or_else(|| {
+        recent_blocks
+            .keys()
+            .next()
+            .map_or(U64::zero(), |oldest| oldest.saturating_sub(U64::one()))
+    });
+
+    let mut ctx = context_coin.write().await;
+    let before = ctx.coins.len();
+    Arc::make_mut(&mut ctx.coins)
+        .retain(|coin| coin.block_number.is_none_or(|block| block <= ancestor));
+    ctx.pending_nullifiers.retain(|_, block| *block <= ancestor);
+    ctx.recent_blocks.retain(|&number, _| number <= ancestor);
+    ctx.last_synced_block = Some(ancestor);
+    ctx.reorgs_handled += 1;
+    ctx.mark_dirty();
+    tracing::warn!(
+        "Chain reorganized after block {}, dropped {} coins, rescanning",
+        ancestor,
+        before - ctx.coins.len()
+    );
+    Ok(Some(ancestor))
+}
+
+/// Scans the blocks mined since the last sync, updating the coins, the tree
+/// and the spent nullifiers of the context. Coins of blocks a reorg removed
+/// since the last scan are dropped first, and the chain is then scanned
+/// again from the start.
+#[tracing::instrument(skip_all)]
+pub async fn sync_coins(
+    context_coin: SharedContext,
+    contract: Contract<RpcProvider>,
+    key: ScanningKey,
+    coin_sender: broadcast::Sender<Coin>,
+    chunk_size: u64,
+    concurrency: usize,
+) -> Result<(), ApiError> {
+    let client = contract.client();
+    let rolled_back = roll_back_reorg(&context_coin, &client).await?;
+    let block_number = client.get_block_number().await?;
+    let head_hash = block_hash(&client, block_number).await?;
+    let (scan, from_block) = {
+        let ctx = context_coin.read().await;
+        match ctx.last_synced_block {
+            // The blocks of unconfirmed spends are scanned again, in case
+            // they were reorged since. A deeper reorg leaves commitments of
+            // dropped blocks in the tree, which is then rebuilt.
+            Some(last) if rolled_back.is_none() => (
+                Scan::resume(&ctx, block_number),
+                (last.as_u64() + 1).saturating_sub(ctx.min_confirmations),
+            ),
+            _ => (
+                Scan::with_confirmations(block_number, ctx.min_confirmations),
+                0,
+            ),
+        }
+    };
+    let scan = scan_chunks_from(
+        &context_coin,
+        &key,
+        scan,
+        from_block,
+        block_number,
+        chunk_size,
+        concurrency,
+        |from, to| fetch_events(&contract, from, to),
+    )
+    .await;
+    if scan.is_err() {
+        context_coin.write().await.sync_progress = None;
+    }
+    let scan = scan?;
+    tracing::debug!(
+        "Scanned {} coins, {} owned",
+        scan.scanned_coins,
+        scan.owned_coins
+    );
+
+    let mut ctx = context_coin.write().await;
+    // Clients get the coins of the initial sync through `/coins` itself.
+    if ctx.last_synced_block.is_some() {
+        publish_new_coins(&coin_sender, &ctx.coins, &scan.coins);
+    }
+    ctx.coins = Arc::new(scan.coins);
+    ctx.tree = scan.tree;
+    ctx.spent_nullifiers = scan.spent_nullifiers;
+    ctx.pending_nullifiers = scan.pending_nullifiers;
+    ctx.scanned_coins = scan.scanned_coins;
+    ctx.owned_coins = scan.owned_coins;
+    ctx.last_synced_block = Some(block_number);
+    ctx.sync_progress = None;
+    ctx.mark_dirty();
+    if let Some(hash) = head_hash {
+        ctx.recent_blocks.insert(block_number, hash);
+        while ctx.recent_blocks.len() > RECENT_BLOCKS {
+            ctx.recent_blocks.pop_first();
+        }
+    }
+    drop(ctx);
+
+    if let Err(e) = history::reconcile(&context_coin, contract.client().as_ref()).await {
+        tracing::warn!("Cannot reconcile the history: {}", e);
+    }
+
+    Ok(())
+}
+
+/// Keeps the context in sync with the chain, scanning every `interval`. Over
+/// a WebSocket the logs of the contract are subscribed to as well, and a
+/// scan starts as soon as one comes in. When the connection drops, the logs
+/// are subscribed to again once reconnected and the blocks mined in between
+/// are scanned right away.
+pub async fn sync_loop(
+    context_coin: SharedContext,
+    contract: Contract<RpcProvider>,
+    key: ScanningKey,
+    coin_sender: broadcast::Sender<Coin>,
+    interval: Duration,
+    state_file: Option<PathBuf>,
+    chunk_size: u64,
+    concurrency: usize,
+) {
+    let client = contract.client();
+    let filter = Filter::new().address(contract.address());
+    let mut logs = None;
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        // Subscribing before scanning, nothing mined in between is missed.
+        let resubscribed = if rpc::is_pubsub(&client) && logs.is_none() {
+            match client.subscribe_logs(&filter).await {
+                Ok(stream) => logs = Some(stream),
+                Err(e) => tracing::warn!("Cannot subscribe to logs, polling for them: {}", e),
+            }
+            logs.is_some()
+        } else {
+            false
         };
+        if resubscribed {
+            tracing::info!(
+                "Subscribed to logs, catching up from block {:?}",
+                context_coin.read().await.last_synced_block
+            );
+        } else {
+            let closed = match &mut logs {
+                Some(stream) => tokio::select! {
+                    _ = ticker.tick() => false,
+                    log = stream.next() => log.is_none(),
+                },
+                None => {
+                    ticker.tick().await;
+                    false
+                }
+            };
+            if closed {
+                tracing::warn!("Log subscription closed, subscribing again");
+                logs = None;
+                continue;
+            }
+        }
+        // The logs of one transaction come together, a single scan covers
+        // all of them.
+        if let Some(stream) = &mut logs {
+            while let Some(Some(_)) = stream.next().now_or_never() {}
+        }
+        let started = Instant::now();
+        if let Err(e) = sync_coins(
+            context_coin.clone(),
+            contract.clone(),
+            key,
+            coin_sender.clone(),
+            chunk_size,
+            concurrency,
+        )
+        .await
+        {
+            if e.is_node_error() {
+                METRICS.record_rpc_error();
+            }
+            tracing::warn!("Chain sync failed: {}", e);
+            continue;
+        }
+        METRICS.record_sync(started.elapsed());
+        if let Some(state_file) = &state_file {
+            if let Err(e) = context_coin.read().await.save(state_file) {
+                tracing::warn!("Cannot save the state file: {}", e);
+            }
+        }
+    }
+}
 
-        let stealth_priv = priv_key.derive(ephemeral);
-        let stealth_pub: PublicKey = stealth_priv.clone().into();
-        let index: U256 = sent_event.index;
-        let hint_amount = sent_event.hint_amount;
-        let hint_token_address = sent_event.hint_token_address;
-        let u64_index: u64 = index.low_u64();
-        let commitment = Fp::try_from(sent_event.commitment)?;
-        tree.set(u64_index, commitment);
+/// Coins serialized per chunk of a `/coins` response.
+const COINS_PER_CHUNK: usize = 256;
 
-        let calc_commitment = hash4([
-            stealth_pub.point.x,
-            stealth_pub.point.y,
-            Fp::try_from(hint_amount)?,
-            Fp::try_from(hint_token_address)?,
-        ]);
+/// Most coins a page of `/coins` holds, larger limits are clamped to it.
+pub const MAX_COINS_LIMIT: usize = 1000;
 
-        let shared_secret = stealth_priv.shared_secret(ephemeral);
-
-        if commitment == calc_commitment {
-            println!("ITS MINE");
-            my_coins.push(Coin {
-                index,
-                uint_token: u256_to_h160(hint_token_address),
-                amount: sent_event.hint_amount,
-                nullifier: stealth_priv.nullifier(index.low_u32()).into(),
-                priv_key: stealth_priv,
-                pub_key: stealth_pub,
-                commitment: sent_event.commitment,
-            });
+/// A page of the coins of the last sync, as of the head they were synced up
+/// to. It only holds a reference to them, so responding with it neither
+/// copies the coins nor keeps the context locked while the body is streamed.
+pub struct CoinsView {
+    coins: Arc<Vec<Coin>>,
+    memos: Arc<HashMap<U256, String>>,
+    // Copied, only spends of the last few blocks are there.
+    pending_nullifiers: HashMap<U256, U64>,
+    last_synced_block: Option<U64>,
+    min_confirmations: u64,
+    page: Range<usize>,
+}
+
+impl CoinsView {
+    /// Whether coins are left after this page.
+    pub fn has_more(&self) -> bool {
+        self.page.end < self.coins.len()
+    }
+
+    /// The coins of the page along with their depth, those not buried under
+    /// enough blocks yet are marked as pending, and those spent in such
+    /// blocks as pending spends.
+    pub fn listed(&self) -> impl Iterator<Item = ListedCoin> + '_ {
+        self.listed_in(0..self.page.len())
+    }
+
+    // The coins of `range`, relative to the start of the page.
+    fn listed_in(&self, range: Range<usize>) -> impl Iterator<Item = ListedCoin> + '_ {
+        let start = self.page.start;
+        self.coins[start + range.start..start + range.end]
+            .iter()
+            .map(|coin| ListedCoin {
+                coin: *coin,
+                confirmations: confirmations(self.last_synced_block, coin),
+                pending: !is_spendable(self.last_synced_block, self.min_confirmations, coin),
+                pending_spend: is_pending_spend(&self.pending_nullifiers, coin),
+                memo: self.memos.get(&coin.commitment).cloned(),
+            })
+    }
+
+    // The `chunk`-th part of `{"coins":[...],"has_more":...}`.
+    fn chunk(&self, chunk: usize) -> Result<Bytes, serde_json::Error> {
+        let mut json = Vec::new();
+        if chunk == 0 {
+            json.extend_from_slice(b"{\"coins\":[");
+        }
+        let start = chunk * COINS_PER_CHUNK;
+        let end = (start + COINS_PER_CHUNK).min(self.page.len());
+        for (i, coin) in self.listed_in(start..end).enumerate() {
+            if start + i > 0 {
+                json.push(b',');
+            }
+            serde_json::to_writer(&mut json, &coin)?;
         }
+        if end == self.page.len() {
+            json.extend_from_slice(format!("],\"has_more\":{}}}", self.has_more()).as_bytes());
+        }
+        Ok(Bytes::from(json))
+    }
+}
 
-        // get sends
-        let amount = U256::to_string(&(Fp::try_from(hint_amount)? - shared_secret).into());
-        let token_address =
-            U256::to_string(&(Fp::try_from(hint_token_address)? - shared_secret).into());
+impl IntoResponse for CoinsView {
+    fn into_response(self) -> Response {
+        // At least one chunk, for the brackets of an empty list.
+        let chunks = self.page.len().div_ceil(COINS_PER_CHUNK);
+        let body = futures::stream::iter(0..chunks.max(1)).map(move |chunk| self.chunk(chunk));
+        (
+            [(header::CONTENT_TYPE, "application/json")],
+            StreamBody::new(body),
+        )
+            .into_response()
+    }
+}
 
-        let calc_commitment_obfuscate = hash4([
+/// Returns a page of the coins found by the last sync, see `CoinsView`.
+/// The page holds `MAX_COINS_LIMIT` coins at most, also without a limit:
+/// clients page through the rest while `has_more` is set.
+pub async fn coins(
+    Query(req): Query<GetCoinsRequest>,
+    context_coin: SharedContext,
+) -> Result<CoinsView, ApiError> {
+    let ctx = context_coin.read().await;
+    let start = req.offset.min(ctx.coins.len());
+    let limit = req.limit.unwrap_or(MAX_COINS_LIMIT).min(MAX_COINS_LIMIT);
+    let end = start.saturating_add(limit);
+    Ok(CoinsView {
+        coins: ctx.coins.clone(),
+        memos: ctx.memos.clone(),
+        pending_nullifiers: ctx.pending_nullifiers.clone(),
+        last_synced_block: ctx.last_synced_block,
+        min_confirmations: ctx.min_confirmations,
+        page: start..end.min(ctx.coins.len()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::h160_to_u256;
+    use crate::hash::hash4;
+    use bindings::owshen::Point as OwshenPoint;
+
+    fn deposit_event(receiver: PublicKey, amount: U256, token: U256) -> SentFilter {
+        let (ephemeral, stealth_pub, view_tag) =
+            receiver.derive_with_view_tag(&mut rand::thread_rng());
+        let commitment = hash4([
             stealth_pub.point.x,
             stealth_pub.point.y,
-            Fp::from_str(&amount)?,
-            Fp::from_str(&token_address)?,
+            Fp::try_from(amount).unwrap(),
+            Fp::try_from(token).unwrap(),
         ]);
-
-        if commitment == calc_commitment_obfuscate {
-            println!("I HAVE SOMETHING ");
-            my_coins.push(Coin {
-                index,
-                uint_token: u256_to_h160(U256::from_str(&token_address)?),
-                amount: U256::from_str(&amount)?,
-                nullifier: stealth_priv.nullifier(index.low_u32()).into(),
-                priv_key: stealth_priv,
-                pub_key: stealth_pub,
-                commitment: commitment.into(),
-            });
+        SentFilter {
+            ephemeral: OwshenPoint {
+                x: ephemeral.point.x.into(),
+                y: ephemeral.point.y.into(),
+            },
+            index: U256::from(3),
+            timestamp: U256::zero(),
+            hint_amount: amount,
+            hint_token_address: token,
+            commitment: commitment.into(),
+            view_tag,
         }
     }
 
-    for spend_event in contract
-        .event::<SpendFilter>()
-        .from_block(0)
-        .to_block(100)
-        .query()
-        .await?
-    {
-        for _coin in my_coins.clone() {
-            let coin_position = my_coins
+    #[test]
+    fn test_viewing_key_detects_coins() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let other_key = PrivateKey::generate(&mut rand::thread_rng());
+        let event = deposit_event(priv_key.address(), U256::from(10), U256::from(123));
+
+        let viewing_key = ScanningKey::Viewing(priv_key.viewing_key());
+        let viewed = detect(&viewing_key, &event).unwrap().unwrap();
+        assert_eq!(viewed.amount, Amount::from(10));
+        assert_eq!(viewed.index, U256::from(3));
+        assert!(viewed.is_consistent());
+        assert!(detect(&other_key.into(), &event).unwrap().is_none());
+        assert!(detect(&other_key.viewing_key().into(), &event)
+            .unwrap()
+            .is_none());
+
+        // The spending key detects the same coin, and only it can open it.
+        let owned = detect(&priv_key.into(), &event).unwrap().unwrap();
+        assert_eq!(owned.pub_key, viewed.pub_key);
+        let (stealth_priv, spend_nullifier) = owned.spending_key().unwrap();
+        assert_eq!(PublicKey::from(stealth_priv), viewed.pub_key);
+        assert_eq!(viewed.priv_key.map(|key| key.secret), None);
+        assert_eq!(viewed.nullifier, None);
+        assert!(matches!(viewed.spending_key(), Err(ApiError::Forbidden(_))));
+        assert!(!viewed.is_spent(&HashSet::from([spend_nullifier])));
+
+        // Coins of the single-key address need the spending key.
+        let legacy = deposit_event(PublicKey::from(priv_key), U256::from(10), U256::from(123));
+        assert!(detect(&viewing_key, &legacy).unwrap().is_none());
+        assert!(detect(&priv_key.into(), &legacy).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_watch_public_address() {
+        let own = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        let watched = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        // Paid to the address itself rather than to a stealth key of it.
+        let direct = |receiver: PublicKey, index: u64, amount: u64| {
+            let mut event = deposit_event(receiver, U256::from(amount), U256::from(123));
+            event.index = U256::from(index);
+            event.commitment =
+                coin_commitment(U256::from(amount), &receiver, U256::from(123)).unwrap();
+            event
+        };
+        let mut stealth = deposit_event(watched, U256::from(5), U256::from(123));
+        stealth.index = U256::from(2);
+        let events = vec![
+            direct(watched, 0, 10),
+            direct(own, 1, 20),
+            stealth,
+            direct(watched, 3, 30),
+        ];
+
+        let coins = watch(
+            &watched,
+            events
                 .iter()
-                .position(|_coin| _coin.nullifier == spend_event.nullifier);
-            match coin_position {
-                Some(index) => {
-                    my_coins.remove(index);
+                .map(|e| (e, Some(U64::from(e.index.as_u64() + 100)), None)),
+        );
+        let found = coins
+            .iter()
+            .map(|coin| (coin.index.as_u64(), coin.amount))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![(0, Amount::from(10u64)), (3, Amount::from(30u64))]
+        );
+        assert_eq!(coins[0].commitment, events[0].commitment);
+        assert_eq!(coins[1].block_number, Some(U64::from(103)));
+        assert_eq!(coins[0].uint_token, u256_to_h160(U256::from(123)));
+
+        let own_coins = watch(&own, events.iter().map(|e| (e, None, None)));
+        assert_eq!(own_coins.len(), 1);
+        assert_eq!(own_coins[0].index, U256::from(1));
+    }
+
+    #[tokio::test]
+    async fn test_inconsistent_coins_are_dropped() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let mut coins = Vec::new();
+        for i in 0..3 {
+            let mut event = deposit_event(
+                PublicKey::from(priv_key),
+                U256::from(10 + i),
+                U256::from(123),
+            );
+            event.index = U256::from(i);
+            let coin = detect(&priv_key.into(), &event).unwrap().unwrap();
+            assert!(coin.is_consistent());
+            coins.push(coin);
+        }
+        // Opens to more than was committed to.
+        coins[1].amount = Amount::from(1000u64);
+        assert!(!coins[1].is_consistent());
+        let mut foreign_key = coins[2];
+        foreign_key.priv_key = Some(PrivateKey::generate(&mut rand::thread_rng()));
+        assert!(!foreign_key.is_consistent());
+
+        let mut tampered = Context::new(H160::default(), false);
+        tampered.coins = Arc::new(coins.clone());
+        let dir = tempfile::tempdir().unwrap();
+        let state_file = dir.path().join("state.json");
+        tampered.save(&state_file).unwrap();
+
+        let mut ctx = Context::new(H160::default(), false);
+        ctx.load(&state_file, &priv_key.into()).unwrap();
+        let context = Arc::new(RwLock::new(ctx));
+        let listed = coins_of(context.clone()).await;
+        assert_eq!(listed, vec![U256::from(0), U256::from(2)]);
+        let health = crate::apis::health(context).await.unwrap();
+        assert_eq!(health.rejected_coins, 1);
+    }
+
+    async fn coins_of(context: SharedContext) -> Vec<U256> {
+        coins(Query(GetCoinsRequest::default()), context)
+            .await
+            .unwrap()
+            .listed()
+            .map(|listed| listed.coin.index)
+            .collect()
+    }
+
+    #[test]
+    fn test_scan_skips_foreign_coins() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let foreign_key = PrivateKey::generate(&mut rand::thread_rng());
+        let mut events = Vec::new();
+        for (i, owner) in [priv_key, foreign_key, priv_key, foreign_key]
+            .into_iter()
+            .enumerate()
+        {
+            let mut event =
+                deposit_event(PublicKey::from(owner), U256::from(10 + i), U256::from(123));
+            event.index = U256::from(i);
+            events.push(event);
+        }
+        let spent = detect(&priv_key.into(), &events[2]).unwrap().unwrap();
+        let spend_events = vec![SpendFilter {
+            nullifier: spent.nullifier.unwrap(),
+        }];
+
+        let scan = scan(&priv_key.into(), &events, &spend_events).unwrap();
+        assert_eq!(scan.scanned_coins, 4);
+        assert_eq!(scan.owned_coins, 2);
+        assert_eq!(scan.coins.len(), 1);
+        assert_eq!(scan.coins[0].index, U256::from(0));
+        assert_eq!(scan.tree.leaves().len(), 4);
+    }
+
+    #[test]
+    fn test_scanned_coins_are_consistent() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let pub_key = PublicKey::from(priv_key);
+        let token = U256::from(123);
+
+        // A send, with hints obfuscated by the shared secret.
+        let (ephemeral, stealth_pub) = pub_key.derive(&mut rand::thread_rng());
+        let shared_secret = priv_key.derive(ephemeral).shared_secret(ephemeral);
+        let send_event = SentFilter {
+            ephemeral: OwshenPoint {
+                x: ephemeral.point.x.into(),
+                y: ephemeral.point.y.into(),
+            },
+            index: U256::from(4),
+            timestamp: U256::zero(),
+            hint_amount: (Fp::from(25) + shared_secret).into(),
+            hint_token_address: (Fp::try_from(token).unwrap() + shared_secret).into(),
+            commitment: coin_commitment(U256::from(25), &stealth_pub, token).unwrap(),
+            view_tag: UNTAGGED,
+        };
+
+        for event in [deposit_event(pub_key, U256::from(10), token), send_event] {
+            let coin = detect(&priv_key.into(), &event).unwrap().unwrap();
+            assert_eq!(
+                coin_commitment(
+                    coin.amount.into(),
+                    &coin.pub_key,
+                    h160_to_u256(coin.uint_token)
+                )
+                .unwrap(),
+                coin.commitment
+            );
+            assert_eq!(
+                coin.priv_key
+                    .map(|priv_key| nullifier(&priv_key, coin.index)),
+                coin.nullifier
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scan_progress() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let context = Arc::new(RwLock::new(Context::new(H160::default(), false)));
+        let observed = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let scan = scan_chunks(
+            &context,
+            &priv_key.into(),
+            U64::from(9999),
+            2000,
+            1,
+            |from, _to| {
+                let context = context.clone();
+                let observed = observed.clone();
+                async move {
+                    let progress = context.read().await.sync_progress;
+                    observed.lock().unwrap().push(progress);
+                    // One owned coin every other chunk.
+                    let mut event =
+                        deposit_event(PublicKey::from(priv_key), U256::from(10), U256::from(123));
+                    event.index = U256::from(from);
+                    let sent_events = if from % 4000 == 0 {
+                        vec![(event, U64::from(from), H256::from_low_u64_be(from))]
+                    } else {
+                        vec![]
+                    };
+                    Ok((sent_events, vec![]))
                 }
-                None => {}
-            }
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(scan.owned_coins, 3);
+
+        let mut observed = observed.lock().unwrap().clone();
+        observed.push(context.read().await.sync_progress);
+        assert_eq!(observed[0], None);
+        let progress = observed[1..].iter().map(|p| p.unwrap()).collect::<Vec<_>>();
+        assert_eq!(progress.len(), 5);
+        for pair in progress.windows(2) {
+            assert!(pair[0].current_block < pair[1].current_block);
+            assert!(pair[0].found_coins <= pair[1].found_coins);
+        }
+        let last = progress.last().unwrap();
+        assert_eq!(last.current_block, U64::from(9999));
+        assert_eq!(last.target_block, U64::from(9999));
+        assert_eq!(last.found_coins, 3);
+    }
+
+    #[tokio::test]
+    async fn test_scan_chunks() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let context = RwLock::new(Context::new(H160::default(), false));
+
+        let mut ranges = Vec::new();
+        scan_chunks(
+            &context,
+            &priv_key.into(),
+            U64::from(10_500),
+            2000,
+            1,
+            |from, to| {
+                ranges.push((from, to));
+                async { Ok((vec![], vec![])) }
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(ranges.len(), 6);
+        assert_eq!(ranges[0], (0, 1999));
+        assert_eq!(ranges[5], (10_000, 10_500));
+
+        // The provider caps queries at 600 blocks.
+        let mut ranges = Vec::new();
+        scan_chunks(
+            &context,
+            &priv_key.into(),
+            U64::from(1999),
+            2000,
+            1,
+            |from, to| {
+                ranges.push((from, to));
+                let result = if to - from + 1 > 600 {
+                    Err(ApiError::NodeUnreachable(
+                        "query returned more than 10000 results".to_string(),
+                    ))
+                } else {
+                    Ok((vec![], vec![]))
+                };
+                async move { result }
+            },
+        )
+        .await
+        .unwrap();
+        let scanned = ranges
+            .iter()
+            .filter(|(from, to)| to - from + 1 <= 600)
+            .collect::<Vec<_>>();
+        assert_eq!(scanned.len(), 4);
+        assert_eq!(*scanned[0], (0, 499));
+        assert_eq!(*scanned[3], (1500, 1999));
+
+        let result = scan_chunks(
+            &context,
+            &priv_key.into(),
+            U64::from(100),
+            2000,
+            1,
+            |_, _| async {
+                Err::<(Vec<(SentFilter, U64, H256)>, Vec<(SpendFilter, U64)>), _>(
+                    ApiError::NodeUnreachable("connection refused".to_string()),
+                )
+            },
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_scan() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let other = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        let context = RwLock::new(Context::new(H160::default(), false));
+
+        // A coin every 250 blocks, one in three ours, the first one spent.
+        let mut sent_events = Vec::new();
+        for i in 0..40u64 {
+            let receiver = if i % 3 == 0 {
+                PublicKey::from(priv_key)
+            } else {
+                other
+            };
+            let mut event = deposit_event(receiver, U256::from(i + 1), U256::from(123));
+            event.index = U256::from(i);
+            sent_events.push((event, U64::from(i * 250), H256::from_low_u64_be(i)));
+        }
+        let spent = detect(&priv_key.into(), &sent_events[0].0)
+            .unwrap()
+            .unwrap();
+        let spend_events = vec![(
+            SpendFilter {
+                nullifier: spent.nullifier.unwrap(),
+            },
+            U64::from(5_000),
+        )];
+
+        let scan_with = |concurrency: usize| {
+            let (sent_events, spend_events) = (sent_events.clone(), spend_events.clone());
+            scan_chunks(
+                &context,
+                &priv_key.into(),
+                U64::from(9_999),
+                1000,
+                concurrency,
+                move |from, to| {
+                    let in_range = |block: u64| from <= block && block <= to;
+                    let sent = sent_events
+                        .iter()
+                        .filter(|(_, block, _)| in_range(block.as_u64()))
+                        .cloned()
+                        // Logs of a chunk come in any order.
+                        .rev()
+                        .collect::<Vec<_>>();
+                    let spend = spend_events
+                        .iter()
+                        .filter(|(_, block)| in_range(block.as_u64()))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    async move {
+                        // Later chunks complete first.
+                        tokio::time::sleep(Duration::from_millis(10 - from / 1000)).await;
+                        Ok((sent, spend))
+                    }
+                },
+            )
+        };
 
-            println!(
-                "YOU SPEND YOUR DEPOSIT! nullifier:{:?}",
-                spend_event.nullifier
+        let sequential = scan_with(1).await.unwrap();
+        assert_eq!(sequential.owned_coins, 14);
+        assert_eq!(sequential.tree.num_leaves(), 40);
+        assert_eq!(sequential.coins.len(), 13);
+        for concurrency in [2, 4, 16] {
+            let concurrent = scan_with(concurrency).await.unwrap();
+            assert_eq!(concurrent.tree.root(), sequential.tree.root());
+            assert_eq!(concurrent.scanned_coins, sequential.scanned_coins);
+            assert_eq!(
+                serde_json::to_string(&concurrent.coins).unwrap(),
+                serde_json::to_string(&sequential.coins).unwrap()
             );
         }
     }
-    let mut ctx = context_coin.lock().unwrap();
-    ctx.coins = my_coins.clone();
-    ctx.tree = tree;
 
-    Ok(Json(GetCoinsResponse {
-        coins: my_coins.clone(),
-    }))
+    #[tokio::test]
+    async fn test_shallow_spends() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let mut context = Context::new(H160::default(), false);
+        context.min_confirmations = 6;
+        let context = RwLock::new(context);
+
+        let mut event = deposit_event(PublicKey::from(priv_key), U256::from(10), U256::from(123));
+        event.index = U256::zero();
+        let coin = detect(&priv_key.into(), &event).unwrap().unwrap();
+        let spend = SpendFilter {
+            nullifier: coin.nullifier.unwrap(),
+        };
+        let scan_spent_at = |spend_block: u64| {
+            let (event, spend) = (event.clone(), spend.clone());
+            scan_chunks(
+                &context,
+                &priv_key.into(),
+                U64::from(100),
+                1000,
+                1,
+                move |_, _| {
+                    let events = (
+                        vec![(event.clone(), U64::from(10), H256::zero())],
+                        vec![(spend.clone(), U64::from(spend_block))],
+                    );
+                    async move { Ok(events) }
+                },
+            )
+        };
+
+        // 5 confirmations, the spend may still be reorged away.
+        let shallow = scan_spent_at(96).await.unwrap();
+        assert_eq!(shallow.coins.len(), 1);
+        assert!(shallow.spent_nullifiers.is_empty());
+        assert_eq!(
+            shallow.pending_nullifiers,
+            HashMap::from([(coin.nullifier.unwrap(), U64::from(96))])
+        );
+
+        let confirmed = scan_spent_at(95).await.unwrap();
+        assert!(confirmed.coins.is_empty());
+        assert!(coin.is_spent(&confirmed.spent_nullifiers));
+        assert!(confirmed.pending_nullifiers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resumed_scan() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let other = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        let mut context = Context::new(H160::default(), false);
+        context.min_confirmations = 6;
+        let context = RwLock::new(context);
+
+        // A coin every 100 blocks, every other one ours, the first one spent
+        // right before the first sync.
+        let mut sent_events = Vec::new();
+        for i in 0..20u64 {
+            let receiver = if i % 2 == 0 {
+                PublicKey::from(priv_key)
+            } else {
+                other
+            };
+            let mut event = deposit_event(receiver, U256::from(i + 1), U256::from(123));
+            event.index = U256::from(i);
+            sent_events.push((event, U64::from(i * 100), H256::from_low_u64_be(i)));
+        }
+        let spent = detect(&priv_key.into(), &sent_events[0].0)
+            .unwrap()
+            .unwrap();
+        let spend_events = vec![(
+            SpendFilter {
+                nullifier: spent.nullifier.unwrap(),
+            },
+            U64::from(1_497),
+        )];
+        let mut ranges = Vec::new();
+        let mut fetch = |from: u64, to: u64| {
+            ranges.push((from, to));
+            let in_range = |block: &U64| from <= block.as_u64() && block.as_u64() <= to;
+            let sent = sent_events
+                .iter()
+                .filter(|(_, block, _)| in_range(block))
+                .cloned()
+                .collect::<Vec<_>>();
+            let spend = spend_events
+                .iter()
+                .filter(|(_, block)| in_range(block))
+                .cloned()
+                .collect::<Vec<_>>();
+            async move { Ok((sent, spend)) }
+        };
+
+        let first = scan_chunks(
+            &context,
+            &priv_key.into(),
+            U64::from(1_499),
+            500,
+            1,
+            &mut fetch,
+        )
+        .await
+        .unwrap();
+        assert_eq!(first.coins.len(), 8);
+        assert_eq!(first.pending_nullifiers.len(), 1);
+        {
+            let mut ctx = context.write().await;
+            ctx.coins = Arc::new(first.coins);
+            ctx.tree = first.tree;
+            ctx.spent_nullifiers = first.spent_nullifiers;
+            ctx.pending_nullifiers = first.pending_nullifiers;
+            ctx.scanned_coins = first.scanned_coins;
+            ctx.owned_coins = first.owned_coins;
+            ctx.last_synced_block = Some(U64::from(1_499));
+        }
+
+        // Only the blocks since the first sync and its unconfirmed ones are
+        // fetched again.
+        let resumed = Scan::resume(&*context.read().await, U64::from(1_999));
+        let resumed = scan_chunks_from(
+            &context,
+            &priv_key.into(),
+            resumed,
+            1_494,
+            U64::from(1_999),
+            500,
+            1,
+            &mut fetch,
+        )
+        .await
+        .unwrap();
+        let full = scan_chunks(
+            &context,
+            &priv_key.into(),
+            U64::from(1_999),
+            500,
+            1,
+            &mut fetch,
+        )
+        .await
+        .unwrap();
+        assert_eq!(ranges[3..5], [(1_494, 1_993), (1_994, 1_999)]);
+
+        assert_eq!(resumed.tree.root(), full.tree.root());
+        assert_eq!(resumed.scanned_coins, 20);
+        assert_eq!(resumed.owned_coins, 10);
+        assert!(spent.is_spent(&resumed.spent_nullifiers));
+        assert!(resumed.pending_nullifiers.is_empty());
+        assert_eq!(
+            serde_json::to_string(&resumed.coins).unwrap(),
+            serde_json::to_string(&full.coins).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_overlapping_scan() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let other = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        let context = RwLock::new(Context::new(H160::default(), false));
+
+        // A coin every 100 blocks, every other one ours.
+        let mut sent_events = Vec::new();
+        for i in 0..20u64 {
+            let receiver = if i % 2 == 0 {
+                PublicKey::from(priv_key)
+            } else {
+                other
+            };
+            let mut event = deposit_event(receiver, U256::from(i + 1), U256::from(123));
+            event.index = U256::from(i);
+            sent_events.push((event, U64::from(i * 100), H256::from_low_u64_be(i)));
+        }
+
+        // Each chunk also returns the 150 blocks before it.
+        let scan_with = |overlap: u64| {
+            let sent_events = sent_events.clone();
+            scan_chunks(
+                &context,
+                &priv_key.into(),
+                U64::from(1_999),
+                500,
+                2,
+                move |from, to| {
+                    let from = from.saturating_sub(overlap);
+                    let sent = sent_events
+                        .iter()
+                        .filter(|(_, block, _)| from <= block.as_u64() && block.as_u64() <= to)
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    async move { Ok((sent, vec![])) }
+                },
+            )
+        };
+
+        let disjoint = scan_with(0).await.unwrap();
+        let overlapping = scan_with(150).await.unwrap();
+        assert_eq!(overlapping.tree.root(), disjoint.tree.root());
+        assert_eq!(overlapping.tree.num_leaves(), 20);
+        assert_eq!(overlapping.scanned_coins, 20);
+        assert_eq!(overlapping.owned_coins, 10);
+        assert_eq!(
+            serde_json::to_string(&overlapping.coins).unwrap(),
+            serde_json::to_string(&disjoint.coins).unwrap()
+        );
+
+        // Another commitment at an index already scanned.
+        let mut scan = Scan::new();
+        let mut conflicting = deposit_event(other, U256::from(99), U256::from(123));
+        conflicting.index = U256::from(3);
+        let events = [&sent_events[3].0, &conflicting];
+        assert!(scan
+            .add_sent_events(
+                &priv_key.into(),
+                events.into_iter().map(|e| (e, None, None))
+            )
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_coin_provenance() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let context = RwLock::new(Context::new(H160::default(), false));
+        let event = deposit_event(PublicKey::from(priv_key), U256::from(1), U256::from(123));
+        let tx_hash = H256::random();
+
+        let scan = scan_chunks(
+            &context,
+            &priv_key.into(),
+            U64::from(100),
+            1000,
+            1,
+            |_, _| {
+                let sent = vec![(event.clone(), U64::from(42), tx_hash)];
+                async move { Ok((sent, vec![])) }
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(scan.coins.len(), 1);
+        assert_eq!(scan.coins[0].tx_hash, Some(tx_hash));
+        assert_eq!(scan.coins[0].block_number, Some(U64::from(42)));
+
+        // Listed by `/coins`, and unknown for coins of an older state file.
+        let json = serde_json::to_value(scan.coins[0]).unwrap();
+        assert_eq!(json["tx_hash"], serde_json::to_value(tx_hash).unwrap());
+        let mut old = json.as_object().unwrap().clone();
+        old.remove("tx_hash");
+        let restored: Coin = serde_json::from_value(old.into()).unwrap();
+        assert_eq!(restored.tx_hash, None);
+    }
+
+    #[test]
+    fn test_accounts_detect_own_coins() {
+        let entropy = crate::keys::Entropy::generate(&mut rand::thread_rng());
+        let first = entropy.account_key(0);
+        let second = entropy.account_key(1);
+        let mut events = Vec::new();
+        for (i, owner) in [first, second, second].into_iter().enumerate() {
+            let mut event =
+                deposit_event(PublicKey::from(owner), U256::from(10 + i), U256::from(123));
+            event.index = U256::from(i);
+            events.push(event);
+        }
+
+        let first_scan = scan(&first, &events, &[]).unwrap();
+        let second_scan = scan(&second, &events, &[]).unwrap();
+        assert_eq!(first_scan.coins.len(), 1);
+        assert_eq!(first_scan.coins[0].index, U256::from(0));
+        assert_eq!(second_scan.coins.len(), 2);
+        assert!(second_scan
+            .coins
+            .iter()
+            .all(|coin| coin.index > U256::zero()));
+        // Both share the same tree.
+        assert_eq!(first_scan.tree.root(), second_scan.tree.root());
+    }
+
+    #[tokio::test]
+    async fn test_pending_coins() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let mut event = deposit_event(PublicKey::from(priv_key), U256::from(10), U256::from(123));
+        event.index = U256::zero();
+        let mut scan = Scan::new();
+        scan.add_sent_events(&priv_key.into(), [(&event, Some(U64::from(100)), None)])
+            .unwrap();
+        let coin = scan.coins[0];
+        assert_eq!(coin.block_number, Some(U64::from(100)));
+
+        let mut context = Context::new(H160::default(), false);
+        context.min_confirmations = 6;
+        context.coins = Arc::new(scan.coins);
+        let context = Arc::new(RwLock::new(context));
+
+        for (head, confirmations, pending) in [(100, 1, true), (104, 5, true), (105, 6, false)] {
+            context.write().await.last_synced_block = Some(U64::from(head));
+            let listed = coins(Query(GetCoinsRequest::default()), context.clone())
+                .await
+                .unwrap()
+                .listed()
+                .collect::<Vec<_>>();
+            assert_eq!(listed[0].confirmations, Some(confirmations));
+            assert_eq!(listed[0].pending, pending);
+            assert_eq!(
+                context.read().await.ensure_spendable(&coin).is_err(),
+                pending
+            );
+        }
+
+        // Coins restored from state files without blocks stay spendable.
+        let unknown = Coin {
+            block_number: None,
+            ..coin
+        };
+        assert!(context.read().await.is_spendable(&unknown));
+
+        // Spent in a block which may still be reorged, it can't be spent
+        // again in the meantime.
+        context
+            .write()
+            .await
+            .pending_nullifiers
+            .insert(coin.nullifier.unwrap(), U64::from(105));
+        let listed = coins(Query(GetCoinsRequest::default()), context.clone())
+            .await
+            .unwrap()
+            .listed()
+            .collect::<Vec<_>>();
+        assert!(listed[0].pending_spend);
+        assert!(!listed[0].pending);
+        assert!(matches!(
+            context.read().await.ensure_spendable(&coin),
+            Err(ApiError::BadRequest(_))
+        ));
+        assert!(context
+            .read()
+            .await
+            .coin_to_spend(coin.index, None)
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stream_coins() {
+        #[derive(serde::Deserialize)]
+        struct Coins {
+            coins: Vec<ListedCoin>,
+            has_more: bool,
+        }
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let template = detect(
+            &priv_key.into(),
+            &deposit_event(PublicKey::from(priv_key), U256::from(10), U256::from(123)),
+        )
+        .unwrap()
+        .unwrap();
+
+        let mut context = Context::new(H160::default(), false);
+        context.min_confirmations = 6;
+        context.last_synced_block = Some(U64::from(10_000));
+        let context = Arc::new(RwLock::new(context));
+        for count in [0, 1, COINS_PER_CHUNK, 10_000] {
+            let all = (0..count as u64)
+                .map(|i| Coin {
+                    index: U256::from(i),
+                    block_number: Some(U64::from(i)),
+                    ..template
+                })
+                .collect::<Vec<_>>();
+            context.write().await.coins = Arc::new(all.clone());
+
+            let view = coins(Query(GetCoinsRequest::default()), context.clone())
+                .await
+                .unwrap();
+            // Syncs aren't held back by the response.
+            context.write().await.coins = Arc::default();
+            let response = view.into_response();
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let Coins {
+                coins: listed,
+                has_more,
+            } = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!(has_more, count > MAX_COINS_LIMIT);
+            assert_eq!(listed.len(), count.min(MAX_COINS_LIMIT));
+            for (listed, coin) in listed.iter().zip(&all) {
+                assert_eq!(listed.coin.index, coin.index);
+                assert_eq!(listed.coin.nullifier, coin.nullifier);
+                assert_eq!(
+                    listed.confirmations,
+                    Some(10_001 - coin.block_number.unwrap().as_u64())
+                );
+                assert_eq!(
+                    listed.pending,
+                    coin.block_number.unwrap() > U64::from(9_995)
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_coins_pages() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let template = detect(
+            &priv_key.into(),
+            &deposit_event(PublicKey::from(priv_key), U256::from(10), U256::from(123)),
+        )
+        .unwrap()
+        .unwrap();
+        let mut context = Context::new(H160::default(), false);
+        context.coins = Arc::new(
+            (0..2500u64)
+                .map(|i| Coin {
+                    index: U256::from(i),
+                    ..template
+                })
+                .collect(),
+        );
+        let context = Arc::new(RwLock::new(context));
+        let page = |offset: usize, limit: Option<usize>| {
+            let context = context.clone();
+            async move {
+                let view = coins(Query(GetCoinsRequest { offset, limit }), context)
+                    .await
+                    .unwrap();
+                let body = hyper::body::to_bytes(view.into_response().into_body())
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+
+        // Clamped to `MAX_COINS_LIMIT`.
+        let first = page(0, Some(usize::MAX)).await;
+        assert_eq!(first["coins"].as_array().unwrap().len(), MAX_COINS_LIMIT);
+        assert_eq!(first["has_more"], true);
+        let second = page(1000, Some(10)).await;
+        assert_eq!(
+            second["coins"][0]["index"],
+            serde_json::to_value(U256::from(1000)).unwrap()
+        );
+        assert_eq!(second["has_more"], true);
+        let last = page(2000, Some(MAX_COINS_LIMIT)).await;
+        assert_eq!(last["coins"].as_array().unwrap().len(), 500);
+        // Chunks carry on from the offset of the page.
+        assert_eq!(
+            last["coins"][499]["index"],
+            serde_json::to_value(U256::from(2499)).unwrap()
+        );
+        assert_eq!(last["has_more"], false);
+        let past = page(usize::MAX, Some(10)).await;
+        assert_eq!(past["coins"], serde_json::json!([]));
+        assert_eq!(past["has_more"], false);
+
+        // Without a limit the page is as large as it can be.
+        let unlimited = page(0, None).await;
+        assert_eq!(unlimited, first);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_access() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let coin = detect(
+            &priv_key.into(),
+            &deposit_event(PublicKey::from(priv_key), U256::from(10), U256::from(123)),
+        )
+        .unwrap()
+        .unwrap();
+        let context = Arc::new(RwLock::new(Context::new(H160::default(), false)));
+
+        let mut tasks = Vec::new();
+        for i in 0..200 {
+            let context = context.clone();
+            tasks.push(tokio::spawn(async move {
+                if i % 4 == 0 {
+                    let mut ctx = context.write().await;
+                    Arc::make_mut(&mut ctx.coins).push(coin);
+                    ctx.last_synced_block = Some(U64::from(i));
+                } else {
+                    coins(Query(GetCoinsRequest::default()), context)
+                        .await
+                        .unwrap();
+                }
+            }));
+        }
+        tokio::time::timeout(Duration::from_secs(10), futures::future::join_all(tasks))
+            .await
+            .expect("Deadlock while accessing the context!");
+        assert_eq!(context.read().await.coins.len(), 50);
+    }
+
+    #[test]
+    fn test_view_tags() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let event = deposit_event(PublicKey::from(priv_key), U256::from(10), U256::from(123));
+        assert_ne!(event.view_tag, UNTAGGED);
+        assert!(detect(&priv_key.into(), &event).unwrap().is_some());
+
+        // Coins without a tag still go through the full detection.
+        let untagged = SentFilter {
+            view_tag: UNTAGGED,
+            ..event.clone()
+        };
+        assert!(detect(&priv_key.into(), &untagged).unwrap().is_some());
+
+        // A wrong tag means the coin was not meant for this key.
+        let mistagged = SentFilter {
+            view_tag: event.view_tag % 255 + 1,
+            ..event
+        };
+        assert!(detect(&priv_key.into(), &mistagged).unwrap().is_none());
+    }
+
+    // cargo test --release bench_view_tags -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_view_tags() {
+        const COINS: usize = 100_000;
+        const OWNED_EVERY: usize = 1000;
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let foreign_key = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+
+        // Scanning costs the same for any foreign coin, a few hundred distinct
+        // ones are enough to fill the chain.
+        let foreign = (0..256)
+            .map(|_| deposit_event(foreign_key, U256::from(10), U256::from(123)))
+            .collect::<Vec<_>>();
+        let tagged = (0..COINS)
+            .map(|i| {
+                let mut event = if i % OWNED_EVERY == 0 {
+                    deposit_event(PublicKey::from(priv_key), U256::from(10), U256::from(123))
+                } else {
+                    foreign[i % foreign.len()].clone()
+                };
+                event.index = U256::from(i);
+                event
+            })
+            .collect::<Vec<_>>();
+        let untagged = tagged
+            .iter()
+            .map(|event| SentFilter {
+                view_tag: UNTAGGED,
+                ..event.clone()
+            })
+            .collect::<Vec<_>>();
+
+        let mut timings = Vec::new();
+        for (name, events) in [
+            ("without view tags", &untagged),
+            ("with view tags", &tagged),
+        ] {
+            let started = Instant::now();
+            let scan = scan(&priv_key.into(), events, &[]).unwrap();
+            let elapsed = started.elapsed();
+            println!("{} coins {}: {:?}", COINS, name, elapsed);
+            assert_eq!(scan.owned_coins, COINS / OWNED_EVERY);
+            timings.push(elapsed);
+        }
+        assert!(timings[1] < timings[0]);
+    }
 }
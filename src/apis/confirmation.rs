@@ -0,0 +1,34 @@
+use axum::extract::Query;
+use ethers::prelude::*;
+use std::sync::Arc;
+use tokio::time::Duration;
+
+use crate::apis::{history, ApiError};
+use crate::rpc::RpcProvider;
+use crate::transaction::wait_for_confirmation;
+use crate::SharedContext;
+use crate::{GetConfirmationRequest, GetConfirmationResponse, TxStatus};
+
+// Kept below usual HTTP client timeouts, clients retry while the transaction
+// is still pending.
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(20);
+
+pub async fn confirmation(
+    Query(req): Query<GetConfirmationRequest>,
+    provider: Arc<RpcProvider>,
+    context: SharedContext,
+) -> Result<GetConfirmationResponse, ApiError> {
+    let confirmation = wait_for_confirmation(&provider, req.tx_hash, CONFIRMATION_TIMEOUT).await?;
+    if let Some(nullifier) = req.nullifier {
+        let status = match &confirmation {
+            None => TxStatus::Pending,
+            Some(c) if c.success => TxStatus::Confirmed,
+            Some(_) => TxStatus::Failed,
+        };
+        history::attach_tx(&context, nullifier, req.tx_hash, status).await;
+    }
+    confirmation.ok_or(ApiError::NotFound(format!(
+        "Transaction {:?} is not mined yet!",
+        req.tx_hash
+    )))
+}
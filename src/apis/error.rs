@@ -0,0 +1,228 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json, Response};
+use ethers::contract::ContractError;
+use ethers::providers::{Middleware, MiddlewareError, ProviderError};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::apis::json_rpc::{INTERNAL_ERROR, INVALID_PARAMS};
+use crate::keys::ParseKeyError;
+use crate::rpc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    NotFound(String),
+    // No coin of the wallet at the index a spend asked for.
+    CoinNotFound(String),
+    Forbidden(String),
+    InsufficientFunds(String),
+    InsufficientGas(String),
+    TooManyRequests(String),
+    NodeUnreachable(String),
+    NodeTimeout(String),
+    Internal(String),
+}
+
+/// JSON body of a failed request, `code` is stable and meant for clients to
+/// branch on, `message` is for humans.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiErrorBody {
+    pub code: String,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::CoinNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::InsufficientFunds(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::InsufficientGas(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::NodeUnreachable(_) => StatusCode::BAD_GATEWAY,
+            ApiError::NodeTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::CoinNotFound(_) => "coin_not_found",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::InsufficientFunds(_) => "insufficient_funds",
+            ApiError::InsufficientGas(_) => "insufficient_gas",
+            ApiError::TooManyRequests(_) => "too_many_requests",
+            ApiError::NodeUnreachable(_) => "node_unreachable",
+            ApiError::NodeTimeout(_) => "node_timeout",
+            ApiError::Internal(_) => "internal",
+        }
+    }
+
+    /// Code of the error in a JSON-RPC response, one of the server errors
+    /// the spec leaves to implementations for those it doesn't define.
+    pub fn json_rpc_code(&self) -> i64 {
+        match self {
+            ApiError::BadRequest(_) => INVALID_PARAMS,
+            ApiError::Internal(_) => INTERNAL_ERROR,
+            ApiError::NodeUnreachable(_) => -32000,
+            ApiError::NotFound(_) => -32001,
+            ApiError::InsufficientFunds(_) => -32002,
+            ApiError::InsufficientGas(_) => -32003,
+            ApiError::Unauthorized(_) => -32004,
+            ApiError::Forbidden(_) => -32005,
+            ApiError::TooManyRequests(_) => -32006,
+            ApiError::NodeTimeout(_) => -32007,
+            ApiError::CoinNotFound(_) => -32008,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::BadRequest(msg)
+            | ApiError::Unauthorized(msg)
+            | ApiError::NotFound(msg)
+            | ApiError::CoinNotFound(msg)
+            | ApiError::Forbidden(msg)
+            | ApiError::InsufficientFunds(msg)
+            | ApiError::InsufficientGas(msg)
+            | ApiError::TooManyRequests(msg)
+            | ApiError::NodeUnreachable(msg)
+            | ApiError::NodeTimeout(msg)
+            | ApiError::Internal(msg) => msg,
+        }
+    }
+
+    /// A failed request to the node, `NodeTimeout` when it didn't answer in
+    /// time and `NodeUnreachable` otherwise.
+    pub fn from_node<E: MiddlewareError>(e: E) -> Self {
+        let timeout = e.as_provider_error().is_some_and(rpc::is_timeout);
+        Self::node_error(e.to_string(), timeout)
+    }
+
+    /// `from_node` for the contract calls which failed without reverting.
+    pub fn from_contract_call<M: Middleware>(e: ContractError<M>) -> Self {
+        let timeout = e
+            .as_middleware_error()
+            .and_then(|e| e.as_provider_error())
+            .is_some_and(rpc::is_timeout);
+        Self::node_error(e.to_string(), timeout)
+    }
+
+    fn node_error(message: String, timeout: bool) -> Self {
+        if timeout {
+            ApiError::NodeTimeout(message)
+        } else {
+            ApiError::NodeUnreachable(message)
+        }
+    }
+
+    /// Whether the node failed rather than the request, see `from_node`.
+    pub fn is_node_error(&self) -> bool {
+        matches!(
+            self,
+            ApiError::NodeUnreachable(_) | ApiError::NodeTimeout(_)
+        )
+    }
+
+    pub fn body(&self) -> ApiErrorBody {
+        ApiErrorBody {
+            code: self.code().to_string(),
+            message: self.message().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(msg) => write!(f, "Bad request: {}", msg),
+            ApiError::Unauthorized(msg) => write!(f, "Unauthorized: {}", msg),
+            ApiError::NotFound(msg) => write!(f, "Not found: {}", msg),
+            ApiError::CoinNotFound(msg) => write!(f, "Coin not found: {}", msg),
+            ApiError::Forbidden(msg) => write!(f, "Forbidden: {}", msg),
+            ApiError::InsufficientFunds(msg) => write!(f, "Insufficient funds: {}", msg),
+            ApiError::InsufficientGas(msg) => write!(f, "Insufficient gas: {}", msg),
+            ApiError::TooManyRequests(msg) => write!(f, "Too many requests: {}", msg),
+            ApiError::NodeUnreachable(msg) => write!(f, "Node unreachable: {}", msg),
+            ApiError::NodeTimeout(msg) => write!(f, "Node timed out: {}", msg),
+            ApiError::Internal(msg) => write!(f, "Internal server error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl From<eyre::Report> for ApiError {
+    fn from(e: eyre::Report) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+impl From<ParseKeyError> for ApiError {
+    fn from(e: ParseKeyError) -> Self {
+        ApiError::BadRequest(e.to_string())
+    }
+}
+
+impl From<ProviderError> for ApiError {
+    fn from(e: ProviderError) -> Self {
+        ApiError::from_node(e)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status_code(), Json(self.body())).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_codes() {
+        let msg = || "error".to_string();
+        let cases = [
+            (ApiError::BadRequest(msg()), StatusCode::BAD_REQUEST),
+            (ApiError::Unauthorized(msg()), StatusCode::UNAUTHORIZED),
+            (ApiError::NotFound(msg()), StatusCode::NOT_FOUND),
+            (ApiError::CoinNotFound(msg()), StatusCode::NOT_FOUND),
+            (ApiError::Forbidden(msg()), StatusCode::FORBIDDEN),
+            (
+                ApiError::InsufficientFunds(msg()),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                ApiError::InsufficientGas(msg()),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                ApiError::TooManyRequests(msg()),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (ApiError::NodeUnreachable(msg()), StatusCode::BAD_GATEWAY),
+            (ApiError::NodeTimeout(msg()), StatusCode::GATEWAY_TIMEOUT),
+            (ApiError::Internal(msg()), StatusCode::INTERNAL_SERVER_ERROR),
+        ];
+        for (err, status) in cases {
+            assert_eq!(err.status_code(), status);
+            assert_eq!(err.clone().into_response().status(), status);
+        }
+    }
+
+    #[test]
+    fn test_error_body() {
+        let body = ApiError::InsufficientFunds("missing 10".to_string()).body();
+        assert_eq!(body.code, "insufficient_funds");
+        assert_eq!(body.message, "missing 10");
+    }
+}
@@ -0,0 +1,83 @@
+use axum::extract::Query;
+use bindings::owshen::Owshen;
+use ethers::prelude::*;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::apis::send::prepare_send;
+use crate::apis::withdraw::prepare_withdraw;
+use crate::apis::ApiError;
+use crate::rpc::RpcProvider;
+use crate::transaction::{check_gas_limit, gas_cost, send_call, withdraw_call, GasOverrides};
+use crate::SharedContext;
+use crate::{GetEstimateResponse, GetEstimateWithdrawRequest, GetSendRequest, GetWithdrawRequest};
+
+// Overridden gas prices are reported as they are, rather than the node's.
+async fn estimate_call(
+    call: ContractCall<RpcProvider, ()>,
+    provider: &RpcProvider,
+    overrides: GasOverrides,
+) -> Result<GetEstimateResponse, ApiError> {
+    let gas = call
+        .estimate_gas()
+        .await
+        .map_err(|e| match e.decode_revert::<String>() {
+            Some(reason) => ApiError::BadRequest(format!("Transaction reverts: {}", reason)),
+            None => ApiError::from_contract_call(e),
+        })?;
+    if let Some(gas_limit) = overrides.gas_limit {
+        check_gas_limit(gas_limit, gas)?;
+    }
+    let gas_price = match overrides.gas_price.or(overrides.max_fee) {
+        Some(gas_price) => gas_price,
+        None => provider.get_gas_price().await?,
+    };
+    Ok(GetEstimateResponse {
+        gas,
+        gas_price,
+        cost: gas_cost(gas, gas_price)?,
+    })
+}
+
+pub async fn estimate_withdraw(
+    Query(req): Query<GetEstimateWithdrawRequest>,
+    context: SharedContext,
+    owshen: Owshen<RpcProvider>,
+    provider: Arc<RpcProvider>,
+    params: PathBuf,
+) -> Result<GetEstimateResponse, ApiError> {
+    let withdrawal = prepare_withdraw(
+        Query(GetWithdrawRequest {
+            index: req.index,
+            address: req.address,
+            desire_amount: req.desire_amount,
+            to: req.to,
+            relayer_fee: req.relayer_fee,
+            decimals: req.decimals,
+            token: None,
+            gas: req.gas,
+        }),
+        context.clone(),
+        context,
+        params,
+        false,
+    )
+    .await?;
+    estimate_call(
+        withdraw_call(&owshen, &withdrawal),
+        &provider,
+        withdrawal.gas,
+    )
+    .await
+}
+
+pub async fn estimate_send(
+    Query(req): Query<GetSendRequest>,
+    context: SharedContext,
+    owshen: Owshen<RpcProvider>,
+    provider: Arc<RpcProvider>,
+    params: PathBuf,
+) -> Result<GetEstimateResponse, ApiError> {
+    let sending = prepare_send(Query(req), context.clone(), context, params).await?;
+    estimate_call(send_call(&owshen, &sending), &provider, sending.gas).await
+}
@@ -0,0 +1,30 @@
+use axum::extract::Query;
+use axum::http::header;
+use axum::response::IntoResponse;
+use std::sync::Arc;
+
+use crate::apis::ApiError;
+use crate::export::{rows, to_csv, ExportFormat};
+use crate::{GetExportRequest, SharedContext, TokenInfo};
+
+/// Serves the coins, and optionally the history, as a CSV attachment.
+pub async fn export(
+    Query(req): Query<GetExportRequest>,
+    context: SharedContext,
+    tokens: Arc<Vec<TokenInfo>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let rows = rows(&*context.read().await, &tokens, req.token, req.history);
+    let body = match req.format {
+        ExportFormat::Csv => to_csv(&rows)?,
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"owshen.csv\"",
+            ),
+        ],
+        body,
+    ))
+}
@@ -0,0 +1,292 @@
+//! Feeds arbitrary input to the requests which carry keys and amounts, both
+//! as a query string and as a JSON body, then to the parsing the handlers do
+//! on their fields. Whatever the input, the outcome must be a typed error,
+//! never a panic.
+//!
+//! Every run replays `SEEDS` then `FUZZ_ITERATIONS` random cases per request
+//! type, `OWSHEN_FUZZ_ITERATIONS` runs more, e.g.
+//! `OWSHEN_FUZZ_ITERATIONS=1000000 cargo test fuzz --release`.
+
+use axum::extract::Query;
+use axum::http::Uri;
+use ethers::types::{H160, U256};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::de::DeserializeOwned;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::address_book::AddressBook;
+use crate::amount::parse_request_amount;
+use crate::apis::send::Receiver;
+use crate::apis::ApiError;
+use crate::fp::Fp;
+use crate::keys::{PrivateKey, PublicKey};
+use crate::{Context, GetSendRequest, GetStealthRequest, GetWithdrawRequest};
+
+const FUZZ_ITERATIONS: usize = 2000;
+
+lazy_static! {
+    // A wallet without coins, for the checks the handlers make on it.
+    static ref CONTEXT: Context = Context::new(H160::default(), false);
+}
+
+const FIELDS: &[&str] = &[
+    "index",
+    "address",
+    "new_amount",
+    "desire_amount",
+    "to",
+    "relayer_fee",
+    "receiver_address",
+    "decimals",
+    "token",
+    "receiver_stealth_x",
+    "receiver_stealth_y",
+    "receiver_ephemeral_x",
+    "receiver_ephemeral_y",
+    "receiver_view_tag",
+    "gas_price",
+    "max_fee",
+    "priority_fee",
+    "gas_limit",
+];
+
+// Values near the edges of what the fields accept.
+const DICTIONARY: &[&str] = &[
+    "",
+    "0",
+    "1",
+    "-1",
+    "00",
+    "0x",
+    "0x0",
+    "0X1",
+    "1.",
+    ".5",
+    "1.5",
+    "1e18",
+    "77",
+    "78",
+    "255",
+    "256",
+    "18446744073709551616",
+    "0xffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+    "0x1ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+    "21888242871839275222246405745257275088548364400416034343698204186575808495617",
+    "115792089237316195423570985008687907853269984665640564039457584007913129639936",
+    "OoOo",
+    "OoOo2",
+    "OoOo3",
+    "OoOoV",
+    "true",
+    "null",
+    "[]",
+    "é",
+    "\u{0}",
+];
+
+// Inputs which need care, `{address}` stands for a valid address.
+const SEEDS: &[&str] = &[
+    "",
+    "index=0x&address=OoOo&new_amount=1",
+    "index=0&address={address}&new_amount=1.5&desire_amount=1.5&decimals=255",
+    "index=0&address={address}&new_amount=1&desire_amount=1&decimals=256",
+    "index=0x1ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff&address={address}&new_amount=1&desire_amount=1",
+    "index=-1&address={address}&new_amount=1&desire_amount=1",
+    "index=0&address={address}&new_amount=99999999999999999999999999999999999999999999999999999999999999999999999999999999&desire_amount=0x&decimals=0",
+    "index=0&address={address}&new_amount=0.00000000000000000000000000000000000000000000000000000000000000000000000000001&desire_amount=1.&decimals=77",
+    "index=0&address={address}&new_amount=1&receiver_stealth_x=0&receiver_stealth_y=1&receiver_ephemeral_x=0&receiver_ephemeral_y=1",
+    "index=0&address={address}&new_amount=1&receiver_stealth_x=-1&receiver_stealth_y=-1&receiver_ephemeral_x=1",
+    "index=0&address={address}&new_amount=1&receiver_address=OoOo3%C3%A9&receiver_view_tag=256",
+    "index=0&address={address}&new_amount=1&desire_amount=1&gas_limit=0&max_fee=1&priority_fee=2",
+    "index=0&address={address}&desire_amount=1&to=0x0000000000000000000000000000000000000001&relayer_fee=1.5&decimals=0",
+    "index=0&index=1&address={address}&address={address}",
+    "address=OoOo3%00000000000000000000000000000000000000000000000000000000000000000",
+    "address=%FF%FE",
+];
+
+// The parsing `/send` does before proving.
+fn parse_send(req: GetSendRequest) -> Result<(), ApiError> {
+    CONTEXT.check_index(req.index)?;
+    req.gas.validate()?;
+    parse_request_amount(&req.new_amount, req.decimals)?;
+    Receiver::from_request(&req, &AddressBook::default())?;
+    Ok(())
+}
+
+// The parsing `/withdraw` does before proving.
+fn parse_withdraw(req: GetWithdrawRequest) -> Result<(), ApiError> {
+    CONTEXT.check_index(req.index)?;
+    req.gas.validate()?;
+    parse_request_amount(&req.desire_amount, req.decimals)?;
+    if let Some(fee) = &req.relayer_fee {
+        parse_request_amount(fee, req.decimals)?;
+    }
+    Ok(())
+}
+
+fn parse_stealth(req: GetStealthRequest) -> Result<(), ApiError> {
+    req.address.derive_with_view_tag(&mut rand::thread_rng());
+    Ok(())
+}
+
+fn encode(value: &str) -> String {
+    value.bytes().map(|b| format!("%{:02X}", b)).collect()
+}
+
+fn query(fields: &[(String, String)]) -> String {
+    fields
+        .iter()
+        .map(|(name, value)| format!("{}={}", encode(name), encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// The fields as a JSON object, numbers left unquoted when they parse as one.
+fn json(fields: &[(String, String)]) -> String {
+    let object = fields
+        .iter()
+        .map(|(name, value)| {
+            let value = serde_json::from_str::<serde_json::Number>(value)
+                .map(serde_json::Value::Number)
+                .unwrap_or(serde_json::Value::String(value.clone()));
+            (name.clone(), value)
+        })
+        .collect::<serde_json::Map<_, _>>();
+    serde_json::Value::Object(object).to_string()
+}
+
+/// Values which parse, for the random cases to get past the first field.
+fn valid_values() -> Vec<String> {
+    let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+    let pub_key = PublicKey::from(priv_key);
+    let (ephemeral, stealth) = pub_key.derive(&mut rand::thread_rng());
+    let coordinate = |fp: Fp| Into::<U256>::into(fp).to_string();
+    vec![
+        pub_key.to_string(),
+        coordinate(stealth.point.x),
+        coordinate(stealth.point.y),
+        coordinate(ephemeral.point.x),
+        coordinate(ephemeral.point.y),
+        "0x10".to_string(),
+        format!("{:?}", H160::repeat_byte(1)),
+        "18".to_string(),
+        "0.25".to_string(),
+        "12345".to_string(),
+    ]
+}
+
+fn random_value(rng: &mut StdRng, valid: &[String]) -> String {
+    match rng.gen_range(0..4) {
+        0 => DICTIONARY[rng.gen_range(0..DICTIONARY.len())].to_string(),
+        1 => valid[rng.gen_range(0..valid.len())].clone(),
+        2 => {
+            // A valid value with a byte changed, added or cut off.
+            let mut bytes = valid[rng.gen_range(0..valid.len())].clone().into_bytes();
+            let at = rng.gen_range(0..=bytes.len());
+            match rng.gen_range(0..3) {
+                0 if at < bytes.len() => bytes[at] = rng.gen(),
+                1 => bytes.insert(at, rng.gen()),
+                _ => bytes.truncate(at),
+            }
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+        _ => {
+            let bytes = (0..rng.gen_range(0..80))
+                .map(|_| rng.gen::<u8>())
+                .collect::<Vec<_>>();
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+    }
+}
+
+fn random_fields(rng: &mut StdRng, valid: &[String]) -> Vec<(String, String)> {
+    (0..rng.gen_range(0..=FIELDS.len()))
+        .map(|_| {
+            let name = if rng.gen_ratio(1, 20) {
+                random_value(rng, valid)
+            } else {
+                FIELDS[rng.gen_range(0..FIELDS.len())].to_string()
+            };
+            (name, random_value(rng, valid))
+        })
+        .collect()
+}
+
+/// Deserializes `query` as `T` through the extractor of the routes, then
+/// `json` as the JSON-RPC methods do, and parses whatever deserialized.
+fn check<T: DeserializeOwned>(query: &str, json: &str, parse: fn(T) -> Result<(), ApiError>) {
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        let uri: Uri = format!("/?{}", query).parse().unwrap();
+        if let Ok(Query(req)) = Query::<T>::try_from_uri(&uri) {
+            let _ = parse(req);
+        }
+        if let Ok(req) = serde_json::from_str::<T>(json) {
+            let _ = parse(req);
+        }
+        // Not even an object.
+        let _ = serde_json::from_slice::<T>(query.as_bytes());
+    }));
+    assert!(
+        outcome.is_ok(),
+        "{} panicked on {:?} / {}",
+        std::any::type_name::<T>(),
+        query,
+        json
+    );
+}
+
+fn check_all(query: &str, json: &str) {
+    check(query, json, parse_send);
+    check(query, json, parse_withdraw);
+    check(query, json, parse_stealth);
+}
+
+// Splits a seed back into its fields, for its JSON form.
+fn form_fields(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+fn iterations() -> usize {
+    std::env::var("OWSHEN_FUZZ_ITERATIONS")
+        .ok()
+        .and_then(|iterations| iterations.parse().ok())
+        .unwrap_or(FUZZ_ITERATIONS)
+}
+
+#[test]
+fn test_fuzz_seeds() {
+    let address = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng())).to_string();
+    for seed in SEEDS {
+        let query = seed.replace("{address}", &address);
+        let fields = form_fields(&query);
+        check_all(&query, &json(&fields));
+    }
+
+    // The seeds do parse as far as they are meant to.
+    let valid = format!("index=0&address={}&new_amount=1.5&decimals=6", address);
+    let uri: Uri = format!("/?{}", valid).parse().unwrap();
+    let Query(req) = Query::<GetSendRequest>::try_from_uri(&uri).unwrap();
+    assert!(parse_request_amount(&req.new_amount, req.decimals).is_ok());
+    let uri: Uri = format!("/?{}&decimals=256", valid).parse().unwrap();
+    assert!(Query::<GetSendRequest>::try_from_uri(&uri).is_err());
+}
+
+#[test]
+fn test_fuzz_requests() {
+    let valid = valid_values();
+    let seed = rand::thread_rng().gen();
+    let mut rng = StdRng::seed_from_u64(seed);
+    for _ in 0..iterations() {
+        let fields = random_fields(&mut rng, &valid);
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+            check_all(&query(&fields), &json(&fields))
+        }));
+        // The seed replays the whole run.
+        assert!(outcome.is_ok(), "Fuzzing failed with seed {}", seed);
+    }
+}
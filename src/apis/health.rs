@@ -0,0 +1,16 @@
+use crate::apis::ApiError;
+use crate::GetHealthResponse;
+use crate::SharedContext;
+
+pub async fn health(context: SharedContext) -> Result<GetHealthResponse, ApiError> {
+    let ctx = context.read().await;
+    Ok(GetHealthResponse {
+        synced: ctx.last_synced_block.is_some(),
+        last_synced_block: ctx.last_synced_block,
+        scanned_coins: ctx.scanned_coins,
+        owned_coins: ctx.owned_coins,
+        rejected_coins: ctx.rejected_coins,
+        reorgs_handled: ctx.reorgs_handled,
+        nullifiers_tracked: ctx.spent_nullifiers.len() + ctx.pending_nullifiers.len(),
+    })
+}
@@ -0,0 +1,241 @@
+use axum::extract::Query;
+use ethers::prelude::*;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+
+use crate::amount::Amount;
+use crate::apis::ApiError;
+use crate::rpc::RpcProvider;
+use crate::Context;
+use crate::SharedContext;
+use crate::{GetHistoryRequest, GetHistoryResponse, HistoryEntry, HistoryKind, TxStatus};
+
+const DEFAULT_HISTORY_LIMIT: usize = 20;
+const MAX_HISTORY_LIMIT: usize = 100;
+
+/// Seconds a proof is held for its transaction to be reported. Until then
+/// its coin can't be spent again, see `Context::used_nullifiers`.
+pub const PROOF_EXPIRY: u64 = 60 * 60;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl HistoryEntry {
+    pub fn new(kind: HistoryKind, nullifier: U256, token: H160, amount: Amount) -> Self {
+        HistoryEntry {
+            nullifier,
+            tx_hash: None,
+            kind,
+            token,
+            amount,
+            timestamp: now(),
+            status: TxStatus::Pending,
+        }
+    }
+}
+
+/// Records a spend the wallet has built a proof for. A coin can only be spent
+/// once, so a new proof for the same coin replaces its pending entry. The
+/// entry expires when no transaction is reported within `PROOF_EXPIRY`.
+pub async fn record(context: &RwLock<Context>, entry: HistoryEntry) {
+    let mut ctx = context.write().await;
+    ctx.history
+        .retain(|e| e.nullifier != entry.nullifier || e.status != TxStatus::Pending);
+    ctx.history.push(entry);
+    ctx.mark_dirty();
+}
+
+/// Links the transaction spending `nullifier` to its history entry.
+pub async fn attach_tx(
+    context: &RwLock<Context>,
+    nullifier: U256,
+    tx_hash: TxHash,
+    status: TxStatus,
+) {
+    let mut ctx = context.write().await;
+    if let Some(entry) = ctx
+        .history
+        .iter_mut()
+        .rev()
+        .find(|e| e.nullifier == nullifier)
+    {
+        entry.tx_hash = Some(tx_hash);
+        entry.status = status;
+        ctx.mark_dirty();
+    }
+}
+
+// A pending entry whose coin shows up as spent on chain went through, even if
+// its transaction was never reported.
+fn mark_spent(history: &mut [HistoryEntry], spent_nullifiers: &HashSet<U256>) {
+    for entry in history.iter_mut() {
+        if entry.status == TxStatus::Pending && spent_nullifiers.contains(&entry.nullifier) {
+            entry.status = TxStatus::Confirmed;
+        }
+    }
+}
+
+/// Expires the proofs older than `PROOF_EXPIRY` as of `now`. A proof the
+/// client never reported a transaction for, and whose coin isn't being spent
+/// on chain either, was most likely never broadcast.
+pub fn expire_unbroadcast(ctx: &mut Context, now: u64) {
+    for entry in ctx.history.iter_mut() {
+        if entry.status == TxStatus::Pending
+            && entry.tx_hash.is_none()
+            && entry.timestamp.saturating_add(PROOF_EXPIRY) <= now
+            && !ctx.pending_nullifiers.contains_key(&entry.nullifier)
+        {
+            entry.status = TxStatus::Expired;
+        }
+    }
+}
+
+/// Updates the pending entries from the spent nullifiers and the receipts of
+/// their transactions, and expires the ones never broadcast.
+pub async fn reconcile(context: &RwLock<Context>, provider: &RpcProvider) -> Result<(), ApiError> {
+    let pending = {
+        let mut ctx = context.write().await;
+        let spent_nullifiers = ctx.spent_nullifiers.clone();
+        mark_spent(&mut ctx.history, &spent_nullifiers);
+        expire_unbroadcast(&mut ctx, now());
+        ctx.mark_dirty();
+        ctx.history
+            .iter()
+            .filter(|e| e.status == TxStatus::Pending)
+            .filter_map(|e| e.tx_hash)
+            .collect::<Vec<_>>()
+    };
+    for tx_hash in pending {
+        if let Some(receipt) = provider.get_transaction_receipt(tx_hash).await? {
+            if receipt.block_number.is_none() {
+                continue;
+            }
+            let status = if receipt.status == Some(U64::from(1)) {
+                TxStatus::Confirmed
+            } else {
+                TxStatus::Failed
+            };
+            let mut ctx = context.write().await;
+            for entry in ctx.history.iter_mut() {
+                if entry.tx_hash == Some(tx_hash) {
+                    entry.status = status;
+                }
+            }
+            ctx.mark_dirty();
+        }
+    }
+    Ok(())
+}
+
+/// Lists the history, most recent first.
+pub async fn history(
+    Query(req): Query<GetHistoryRequest>,
+    context: SharedContext,
+) -> Result<GetHistoryResponse, ApiError> {
+    let limit = req.limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+    if limit == 0 || limit > MAX_HISTORY_LIMIT {
+        return Err(ApiError::BadRequest(format!(
+            "Limit should be between 1 and {}!",
+            MAX_HISTORY_LIMIT
+        )));
+    }
+    let ctx = context.read().await;
+    Ok(GetHistoryResponse {
+        entries: ctx
+            .history
+            .iter()
+            .rev()
+            .skip(req.offset)
+            .take(limit)
+            .cloned()
+            .collect(),
+        total: ctx.history.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_send_appends_one_entry() {
+        let context = Arc::new(RwLock::new(Context::new(H160::default(), false)));
+        let nullifier = U256::from(123);
+        let token = H160::from_low_u64_be(1);
+
+        record(
+            &context,
+            HistoryEntry::new(HistoryKind::Send, nullifier, token, Amount::from(10)),
+        )
+        .await;
+        // Proving the same coin again replaces the pending entry.
+        record(
+            &context,
+            HistoryEntry::new(HistoryKind::Send, nullifier, token, Amount::from(10)),
+        )
+        .await;
+        let tx_hash = TxHash::from_low_u64_be(7);
+        attach_tx(&context, nullifier, tx_hash, TxStatus::Confirmed).await;
+
+        let mut ctx = context.write().await;
+        mark_spent(&mut ctx.history, &HashSet::from([nullifier]));
+
+        let history = &ctx.history;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].kind, HistoryKind::Send);
+        assert_eq!(history[0].tx_hash, Some(tx_hash));
+        assert_eq!(history[0].status, TxStatus::Confirmed);
+    }
+
+    #[tokio::test]
+    async fn test_history_pagination() {
+        let context = Arc::new(RwLock::new(Context::new(H160::default(), false)));
+        for i in 0..5 {
+            record(
+                &context,
+                HistoryEntry::new(
+                    HistoryKind::Withdraw,
+                    U256::from(i),
+                    H160::default(),
+                    Amount::from(i),
+                ),
+            )
+            .await;
+        }
+        mark_spent(
+            &mut context.write().await.history,
+            &HashSet::from([U256::from(1)]),
+        );
+
+        let page = history(
+            Query(GetHistoryRequest {
+                offset: 1,
+                limit: Some(2),
+            }),
+            context.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(page.total, 5);
+        assert_eq!(
+            page.entries.iter().map(|e| e.nullifier).collect::<Vec<_>>(),
+            vec![U256::from(3), U256::from(2)]
+        );
+        assert_eq!(context.read().await.history[1].status, TxStatus::Confirmed);
+
+        let req = GetHistoryRequest {
+            offset: 0,
+            limit: Some(0),
+        };
+        assert!(matches!(
+            history(Query(req), context).await,
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+}
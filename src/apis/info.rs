@@ -1,25 +1,444 @@
-use axum::Json;
+use axum::body::Bytes;
+use axum::extract::Query;
+use axum::http::header;
+use axum::response::IntoResponse;
 use ethers::abi::Abi;
-use ethers::types::H160;
+use ethers::providers::Middleware;
+use ethers::types::{H160, H256, U256};
+use ethers::utils::keccak256;
+use eyre::Result;
+use serde::Serialize;
+use serde_json::value::RawValue;
+use std::path::Path;
+use std::sync::Arc;
 
-use crate::{keys::PublicKey, GetInfoResponse, TokenInfo};
+use crate::apis::ApiError;
+use crate::proof;
+use crate::rpc::RpcProvider;
+use crate::transaction::resolve_from;
+use crate::SharedContext;
+use crate::{keys::PublicKey, GetAbiResponse, GetInfoRequest, TokenInfo};
 
-pub async fn info(
+/// Bodies of `/info` and `/abi`. They mostly describe the wallet and its
+/// contracts, nothing of which changes while the wallet runs. The ABIs, the
+/// bulk of them, are encoded once at startup instead of on every request,
+/// see `Abis`. The state of the tree follows the sync, see `TreeState`, and
+/// the gas account is read when there is a node to ask, see `GasState`. The
+/// proving key is described once as well, see `CircuitInfo`.
+#[derive(Clone, Debug)]
+pub struct InfoCache {
+    wallet: Arc<WalletInfo>,
+    abis: Arc<Abis>,
+    abi: Bytes,
+    circuit: Option<Arc<CircuitInfo>>,
+    provider: Option<Arc<RpcProvider>>,
+}
+
+/// The fields of `GetInfoResponse` describing the wallet and its contracts.
+#[derive(Debug, Serialize)]
+struct WalletInfo {
     address: PublicKey,
     dive_contract: H160,
     owshen_contract: H160,
     token_contracts: Vec<TokenInfo>,
-    owshen_abi: Abi,
-    erc20_abi: Abi,
     is_test: bool,
-) -> Result<Json<GetInfoResponse>, eyre::Report> {
-    Ok(Json(GetInfoResponse {
-        address,
-        dive_contract,
-        erc20_abi,
-        owshen_contract,
-        owshen_abi,
-        token_contracts,
-        is_test,
-    }))
+}
+
+/// The ABIs of `GetInfoResponse`, already encoded. Left out for
+/// `/info?abi=false`, for clients which already have them.
+#[derive(Debug, Serialize)]
+struct Abis {
+    owshen_abi: Box<RawValue>,
+    erc20_abi: Box<RawValue>,
+}
+
+impl InfoCache {
+    pub fn new(
+        address: PublicKey,
+        dive_contract: H160,
+        owshen_contract: H160,
+        token_contracts: Vec<TokenInfo>,
+        owshen_abi: Abi,
+        erc20_abi: Abi,
+        is_test: bool,
+    ) -> Result<Self> {
+        let abis = Abis {
+            owshen_abi: serde_json::value::to_raw_value(&owshen_abi)?,
+            erc20_abi: serde_json::value::to_raw_value(&erc20_abi)?,
+        };
+        let abi = GetAbiResponse {
+            owshen_abi,
+            erc20_abi,
+        };
+        Ok(InfoCache {
+            wallet: Arc::new(WalletInfo {
+                address,
+                dive_contract,
+                owshen_contract,
+                token_contracts,
+                is_test,
+            }),
+            abis: Arc::new(abis),
+            abi: Bytes::from(serde_json::to_vec(&abi)?),
+            circuit: None,
+            provider: None,
+        })
+    }
+
+    /// Also reports the gas account of `provider` and its balance.
+    pub fn with_gas_account(self, provider: Arc<RpcProvider>) -> Self {
+        InfoCache {
+            provider: Some(provider),
+            ..self
+        }
+    }
+
+    /// Also describes the proving key, for verifiers to check that the
+    /// proofs of the wallet are made for the circuit they expect.
+    pub fn with_circuit(self, circuit: CircuitInfo) -> Self {
+        InfoCache {
+            circuit: Some(Arc::new(circuit)),
+            ..self
+        }
+    }
+}
+
+/// The body of `/info`, each section flattened into a single object.
+#[derive(Serialize)]
+struct InfoBody<'a> {
+    #[serde(flatten)]
+    wallet: &'a WalletInfo,
+    #[serde(flatten)]
+    abis: Option<&'a Abis>,
+    #[serde(flatten)]
+    circuit: Option<&'a CircuitInfo>,
+    #[serde(flatten)]
+    tree: TreeState,
+    #[serde(flatten)]
+    gas: Option<GasState>,
+}
+
+/// The circuit of the wallet and its proving key, identified by the
+/// keccak256 hash of its content like `/version` does. Read once at
+/// startup, the hash of a proving key of tens of megabytes isn't worth
+/// computing per request.
+#[derive(Debug, Serialize)]
+pub struct CircuitInfo {
+    circuit_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    params_hash: Option<H256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_public_inputs: Option<u32>,
+}
+
+impl CircuitInfo {
+    /// Leaves out what can't be read from `params` rather than failing, the
+    /// wallet may start before the proving key is downloaded.
+    pub fn read(params: &Path) -> Self {
+        let zkey = std::fs::read(params)
+            .map_err(|e| tracing::warn!("Cannot read {:?}: {}", params, e))
+            .ok();
+        let num_public_inputs = zkey.as_ref().and_then(|zkey| {
+            proof::num_public_inputs(zkey)
+                .map_err(|e| tracing::warn!("Cannot read the header of {:?}: {}", params, e))
+                .ok()
+        });
+        CircuitInfo {
+            circuit_name: proof::CIRCUIT_NAME.to_string(),
+            params_hash: zkey.map(|zkey| H256::from(keccak256(zkey))),
+            num_public_inputs,
+        }
+    }
+}
+
+// Cloning `Bytes` only bumps a reference count.
+fn json(body: Bytes) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/json")], body)
+}
+
+/// The fields of `GetInfoResponse` which follow the sync.
+#[derive(Serialize)]
+struct TreeState {
+    tree_root: String,
+    num_commitments: u64,
+}
+
+impl TreeState {
+    // The tree keeps its root up to date as leaves are set, nothing is
+    // hashed here.
+    async fn read(context: &SharedContext) -> Self {
+        let ctx = context.read().await;
+        TreeState {
+            tree_root: format!("{:#x}", U256::from(ctx.tree.root())),
+            num_commitments: ctx.tree.num_leaves() as u64,
+        }
+    }
+}
+
+/// The account paying for the transactions, the first one unlocked on the
+/// node, and its native balance. Fetched on every request: the balance goes
+/// down with each transaction.
+#[derive(Serialize)]
+struct GasState {
+    gas_account: H160,
+    gas_balance: U256,
+}
+
+impl GasState {
+    async fn read(provider: &RpcProvider) -> Result<Self> {
+        let gas_account = resolve_from(provider, None).await?;
+        let gas_balance = provider.get_balance(gas_account, None).await?;
+        Ok(GasState {
+            gas_account,
+            gas_balance,
+        })
+    }
+}
+
+/// The JSON body of `/info`, also the result of `owshen_getInfo`.
+pub async fn info_body(
+    req: &GetInfoRequest,
+    cache: &InfoCache,
+    context: &SharedContext,
+) -> Result<Bytes, ApiError> {
+    let gas = match &cache.provider {
+        // The rest of `/info` doesn't need the node, it stays available.
+        Some(provider) => GasState::read(provider)
+            .await
+            .map_err(|e| tracing::warn!("Cannot read the gas account: {}", e))
+            .ok(),
+        None => None,
+    };
+    let body = InfoBody {
+        wallet: &cache.wallet,
+        abis: req.abi.unwrap_or(true).then_some(&*cache.abis),
+        circuit: cache.circuit.as_deref(),
+        tree: TreeState::read(context).await,
+        gas,
+    };
+    Ok(Bytes::from(serde_json::to_vec(&body)?))
+}
+
+/// Describes the wallet and its contracts, ABIs included unless `abi=false`,
+/// along with the root and size of the tree and the gas account.
+#[tracing::instrument(skip_all)]
+pub async fn info(
+    Query(req): Query<GetInfoRequest>,
+    cache: InfoCache,
+    context: SharedContext,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(json(info_body(&req, &cache, &context).await?))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn abi(cache: InfoCache) -> impl IntoResponse {
+    json(cache.abi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fp::Fp;
+    use crate::keys::PrivateKey;
+    use crate::paths::Paths;
+    use crate::rpc;
+    use crate::{Context, GetInfoResponse};
+    use ethers::utils::Ganache;
+    use tokio::sync::RwLock;
+
+    fn context() -> SharedContext {
+        Arc::new(RwLock::new(Context::new(H160::default(), false)))
+    }
+
+    async fn get_info(req: GetInfoRequest, cache: &InfoCache, context: &SharedContext) -> Bytes {
+        let response = info(Query(req), cache.clone(), context.clone())
+            .await
+            .into_response();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        hyper::body::to_bytes(response.into_body()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_info_cache() {
+        let address = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        let token = TokenInfo {
+            token_address: H160::from_low_u64_be(3),
+            symbol: "WETH".to_string(),
+            decimals: 18,
+        };
+        let cache = InfoCache::new(
+            address,
+            H160::from_low_u64_be(1),
+            H160::from_low_u64_be(2),
+            vec![token],
+            Abi::default(),
+            Abi::default(),
+            true,
+        )
+        .unwrap();
+
+        let context = context();
+        let mut bodies = Vec::new();
+        for _ in 0..3 {
+            bodies.push(get_info(GetInfoRequest { abi: None }, &cache, &context).await);
+        }
+        assert!(bodies.iter().all(|body| body == &bodies[0]));
+
+        let info: GetInfoResponse = serde_json::from_slice(&bodies[0]).unwrap();
+        assert_eq!(info.address, address);
+        assert_eq!(info.owshen_contract, H160::from_low_u64_be(2));
+        assert_eq!(info.token_contracts[0].symbol, "WETH");
+        assert!(info.is_test);
+        assert_eq!(info.owshen_abi, Some(Abi::default()));
+    }
+
+    #[tokio::test]
+    async fn test_info_without_abi() {
+        let address = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        let cache = InfoCache::new(
+            address,
+            H160::from_low_u64_be(1),
+            H160::from_low_u64_be(2),
+            vec![],
+            Abi::default(),
+            Abi::default(),
+            false,
+        )
+        .unwrap();
+
+        let body = get_info(GetInfoRequest { abi: Some(false) }, &cache, &context()).await;
+        let fields: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(fields.get("owshen_abi").is_none());
+        assert!(fields.get("erc20_abi").is_none());
+        assert!(fields.get("owshen_contract").is_some());
+        assert!(fields.get("tree_root").is_some());
+        let full = get_info(GetInfoRequest { abi: None }, &cache, &context()).await;
+        assert!(body.len() < full.len());
+
+        let response = abi(cache).await.into_response();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let abis: GetAbiResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(abis.owshen_abi, Abi::default());
+    }
+
+    #[tokio::test]
+    async fn test_info_tree_state() {
+        let address = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        let cache = InfoCache::new(
+            address,
+            H160::from_low_u64_be(1),
+            H160::from_low_u64_be(2),
+            vec![],
+            Abi::default(),
+            Abi::default(),
+            false,
+        )
+        .unwrap();
+        let context = context();
+        let tree_state = |body: Bytes| {
+            let info: GetInfoResponse = serde_json::from_slice(&body).unwrap();
+            (info.tree_root.unwrap(), info.num_commitments.unwrap())
+        };
+
+        let empty = tree_state(get_info(GetInfoRequest { abi: None }, &cache, &context).await);
+        assert_eq!(empty.1, 0);
+        assert_eq!(
+            empty.0,
+            format!("{:#x}", U256::from(context.read().await.tree.root()))
+        );
+
+        context.write().await.tree.set(0, Fp::from(7));
+        context.write().await.tree.set(1, Fp::from(8));
+        let (root, num_commitments) =
+            tree_state(get_info(GetInfoRequest { abi: Some(false) }, &cache, &context).await);
+        assert_eq!(num_commitments, 2);
+        assert_ne!(root, empty.0);
+        assert_eq!(
+            root,
+            format!("{:#x}", U256::from(context.read().await.tree.root()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_info_gas_account() {
+        let port = 8563u16;
+        let _ganache = Ganache::new().port(port).spawn();
+        let provider = Arc::new(
+            rpc::connect(&format!("http://localhost:{}", port))
+                .await
+                .unwrap(),
+        );
+        let address = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        let cache = InfoCache::new(
+            address,
+            H160::from_low_u64_be(1),
+            H160::from_low_u64_be(2),
+            vec![],
+            Abi::default(),
+            Abi::default(),
+            true,
+        )
+        .unwrap();
+        let context = context();
+
+        // Without a node to ask, nothing is reported.
+        let info: GetInfoResponse =
+            serde_json::from_slice(&get_info(GetInfoRequest { abi: None }, &cache, &context).await)
+                .unwrap();
+        assert_eq!(info.gas_account, None);
+
+        let cache = cache.with_gas_account(provider.clone());
+        let info: GetInfoResponse = serde_json::from_slice(
+            &get_info(GetInfoRequest { abi: Some(false) }, &cache, &context).await,
+        )
+        .unwrap();
+        let accounts = provider.get_accounts().await.unwrap();
+        assert_eq!(info.gas_account, Some(accounts[0]));
+        let balance = info.gas_balance.unwrap();
+        assert!(!balance.is_zero());
+        assert_eq!(
+            balance,
+            provider.get_balance(accounts[0], None).await.unwrap()
+        );
+        assert!(info.tree_root.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_info_circuit() {
+        let address = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        let cache = InfoCache::new(
+            address,
+            H160::from_low_u64_be(1),
+            H160::from_low_u64_be(2),
+            vec![],
+            Abi::default(),
+            Abi::default(),
+            false,
+        )
+        .unwrap();
+        let params = Paths::default().params;
+        let cache = cache.with_circuit(CircuitInfo::read(&params));
+        let context = context();
+
+        for abi in [None, Some(false)] {
+            let info: GetInfoResponse =
+                serde_json::from_slice(&get_info(GetInfoRequest { abi }, &cache, &context).await)
+                    .unwrap();
+            assert_eq!(info.circuit_name.as_deref(), Some("coin_withdraw"));
+            assert_eq!(
+                info.params_hash,
+                Some(H256::from(keccak256(std::fs::read(&params).unwrap())))
+            );
+            // The four outputs of `coin_withdraw.circom`.
+            assert_eq!(info.num_public_inputs, Some(4));
+            assert!(info.tree_root.is_some());
+        }
+
+        // A proving key which can't be read is left out.
+        let missing = CircuitInfo::read(Path::new("/nonexistent/coin_withdraw.zkey"));
+        assert_eq!(missing.params_hash, None);
+        let fields = serde_json::to_value(&missing).unwrap();
+        assert_eq!(fields["circuit_name"], "coin_withdraw");
+        assert!(fields.get("num_public_inputs").is_none());
+    }
 }
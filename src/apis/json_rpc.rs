@@ -0,0 +1,384 @@
+use axum::body::Bytes;
+use axum::extract::Query;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json, Response};
+use bindings::owshen::Owshen;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::apis::{self, ApiError, InfoCache};
+use crate::metrics;
+use crate::rate_limit::RateLimiter;
+use crate::rpc::RpcProvider;
+use crate::{GetCoinsRequest, GetInfoRequest, GetSendRequest, GetWithdrawRequest, SharedContext};
+
+// Error codes defined by the JSON-RPC 2.0 spec.
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// What the methods of `/rpc` dispatch to, the same the REST routes get.
+#[derive(Clone)]
+pub struct JsonRpcState {
+    pub context: SharedContext,
+    pub info: InfoCache,
+    pub params: PathBuf,
+    pub is_test: bool,
+    // To package the withdrawals for a relayer, see `RelayRequest`.
+    pub owshen: Owshen<RpcProvider>,
+    // Shared by `owshen_send` and `owshen_withdraw`, reads are not limited.
+    pub spend_limiter: Arc<RateLimiter>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonRpcRequest {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Option<Value>,
+    // Absent for notifications, which get no response. A `null` id is
+    // still an id.
+    #[serde(default, deserialize_with = "present")]
+    id: Option<Value>,
+}
+
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    /// The `code` of the `ApiError` behind it, see `ApiErrorBody`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl JsonRpcError {
+    fn new(code: i64, message: String) -> Self {
+        JsonRpcError {
+            code,
+            message,
+            data: None,
+        }
+    }
+}
+
+impl From<ApiError> for JsonRpcError {
+    fn from(e: ApiError) -> Self {
+        JsonRpcError {
+            code: e.json_rpc_code(),
+            message: e.message().to_string(),
+            data: Some(Value::String(e.code().to_string())),
+        }
+    }
+}
+
+/// Carries either a `result` or an `error`, never both.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JsonRpcResponse {
+    pub jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<JsonRpcError>,
+    pub id: Value,
+}
+
+impl JsonRpcResponse {
+    fn new(id: Value, result: Result<Value, JsonRpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            result,
+            error,
+            id,
+        }
+    }
+}
+
+// Parameters are given by name, either as an object or as an array holding
+// it, with the fields of the query of the matching REST route.
+fn parse_params<T: DeserializeOwned>(params: Option<Value>) -> Result<T, JsonRpcError> {
+    let params = match params {
+        None => Value::Object(Default::default()),
+        Some(Value::Array(mut params)) if params.len() == 1 => params.remove(0),
+        Some(params) => params,
+    };
+    serde_json::from_value(params)
+        .map_err(|e| JsonRpcError::new(INVALID_PARAMS, format!("Invalid params: {}", e)))
+}
+
+// Same as `respond` for the REST routes.
+fn result<T: Serialize>(result: Result<T, ApiError>) -> Result<Value, JsonRpcError> {
+    if result.as_ref().is_err_and(ApiError::is_node_error) {
+        metrics::METRICS.record_rpc_error();
+    }
+    serde_json::to_value(result?)
+        .map_err(|e| JsonRpcError::new(INTERNAL_ERROR, format!("Cannot encode result: {}", e)))
+}
+
+fn acquire(limiter: &RateLimiter) -> Result<(), JsonRpcError> {
+    limiter.acquire().map_err(|retry_after| {
+        let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        ApiError::TooManyRequests(format!("Retry in {} seconds!", secs)).into()
+    })
+}
+
+async fn call(
+    state: &JsonRpcState,
+    method: &str,
+    params: Option<Value>,
+) -> Result<Value, JsonRpcError> {
+    let context = state.context.clone();
+    match method {
+        "owshen_getInfo" => {
+            let req: GetInfoRequest = parse_params(params)?;
+            let body = apis::info_body(&req, &state.info, &context).await?;
+            serde_json::from_slice(&body).map_err(|e| {
+                JsonRpcError::new(INTERNAL_ERROR, format!("Cannot decode info: {}", e))
+            })
+        }
+        "owshen_getCoins" => {
+            let req: GetCoinsRequest = parse_params(params)?;
+            let view = apis::coins(Query(req), context).await?;
+            result(Ok(serde_json::json!({
+                "coins": view.listed().collect::<Vec<_>>(),
+                "has_more": view.has_more(),
+            })))
+        }
+        "owshen_send" => {
+            let req: GetSendRequest = parse_params(params)?;
+            acquire(&state.spend_limiter)?;
+            result(apis::send(Query(req), context.clone(), context, state.params.clone()).await)
+        }
+        "owshen_withdraw" => {
+            let req: GetWithdrawRequest = parse_params(params)?;
+            acquire(&state.spend_limiter)?;
+            result(
+                apis::withdraw(
+                    Query(req),
+                    context.clone(),
+                    context,
+                    state.params.clone(),
+                    state.is_test,
+                )
+                .await
+                .and_then(|withdrawal| withdrawal.with_relay(&state.owshen)),
+            )
+        }
+        _ => Err(JsonRpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method {}!", method),
+        )),
+    }
+}
+
+async fn handle(state: &JsonRpcState, request: Value) -> Option<JsonRpcResponse> {
+    let request = match serde_json::from_value::<JsonRpcRequest>(request) {
+        Ok(request) if request.jsonrpc == "2.0" => request,
+        Ok(request) => {
+            return Some(JsonRpcResponse::new(
+                request.id.unwrap_or_default(),
+                Err(JsonRpcError::new(
+                    INVALID_REQUEST,
+                    "Only JSON-RPC 2.0 is supported!".to_string(),
+                )),
+            ))
+        }
+        Err(e) => {
+            return Some(JsonRpcResponse::new(
+                Value::Null,
+                Err(JsonRpcError::new(
+                    INVALID_REQUEST,
+                    format!("Invalid request: {}", e),
+                )),
+            ))
+        }
+    };
+    let result = call(state, &request.method, request.params).await;
+    request.id.map(|id| JsonRpcResponse::new(id, result))
+}
+
+/// JSON-RPC 2.0 flavour of the REST routes, for clients built on Ethereum
+/// tooling: `owshen_getInfo`, `owshen_getCoins`, `owshen_send` and
+/// `owshen_withdraw`. Batches are answered in order, one call after the
+/// other.
+#[tracing::instrument(skip_all)]
+pub async fn json_rpc(body: Bytes, state: JsonRpcState) -> Response {
+    let request = match serde_json::from_slice::<Value>(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            return Json(JsonRpcResponse::new(
+                Value::Null,
+                Err(JsonRpcError::new(
+                    PARSE_ERROR,
+                    format!("Parse error: {}", e),
+                )),
+            ))
+            .into_response()
+        }
+    };
+    match request {
+        Value::Array(batch) if !batch.is_empty() => {
+            let mut responses = Vec::new();
+            for request in batch {
+                responses.extend(handle(&state, request).await);
+            }
+            if responses.is_empty() {
+                StatusCode::NO_CONTENT.into_response()
+            } else {
+                Json(responses).into_response()
+            }
+        }
+        request => match handle(&state, request).await {
+            Some(response) => Json(response).into_response(),
+            None => StatusCode::NO_CONTENT.into_response(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::{PrivateKey, PublicKey};
+    use crate::rpc;
+    use crate::Context;
+    use ethers::abi::Abi;
+    use ethers::types::H160;
+    use tokio::sync::RwLock;
+
+    async fn state(watch_only: bool) -> JsonRpcState {
+        let address = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        JsonRpcState {
+            context: Arc::new(RwLock::new(Context::new(H160::default(), watch_only))),
+            info: InfoCache::new(
+                address,
+                H160::from_low_u64_be(1),
+                H160::from_low_u64_be(2),
+                vec![],
+                Abi::default(),
+                Abi::default(),
+                false,
+            )
+            .unwrap(),
+            params: PathBuf::new(),
+            is_test: false,
+            // Only encodes calldata, the node is never queried.
+            owshen: Owshen::new(
+                H160::from_low_u64_be(1),
+                Arc::new(rpc::connect("http://localhost:8545").await.unwrap()),
+            ),
+            spend_limiter: Arc::new(RateLimiter::per_minute(5)),
+        }
+    }
+
+    async fn post(state: &JsonRpcState, body: &str) -> (StatusCode, Value) {
+        let response = json_rpc(Bytes::from(body.to_string()), state.clone()).await;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    fn error_code(response: &Value) -> i64 {
+        assert!(response.get("result").is_none());
+        response["error"]["code"].as_i64().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_json_rpc_call() {
+        let state = state(false).await;
+        let (status, response) = post(
+            &state,
+            r#"{"jsonrpc":"2.0","method":"owshen_getInfo","params":{"abi":false},"id":1}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response["jsonrpc"], "2.0");
+        assert_eq!(response["id"], 1);
+        assert!(response.get("error").is_none());
+        assert_eq!(
+            response["result"]["owshen_contract"],
+            format!("{:?}", H160::from_low_u64_be(2))
+        );
+        assert!(response["result"].get("owshen_abi").is_none());
+        assert_eq!(response["result"]["num_commitments"], 0);
+
+        // Params by position, and ids of any type
+        let (_, response) = post(
+            &state,
+            r#"{"jsonrpc":"2.0","method":"owshen_getCoins","params":[{}],"id":"coins"}"#,
+        )
+        .await;
+        assert_eq!(response["id"], "coins");
+        assert_eq!(response["result"]["coins"], serde_json::json!([]));
+        assert_eq!(response["result"]["has_more"], false);
+    }
+
+    #[tokio::test]
+    async fn test_json_rpc_errors() {
+        let state = state(true).await;
+        let (status, response) = post(&state, r#"{"jsonrpc":"2.0","method":"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(error_code(&response), PARSE_ERROR);
+        assert_eq!(response["id"], Value::Null);
+
+        let (_, response) = post(&state, r#"{"jsonrpc":"1.0","method":"x","id":7}"#).await;
+        assert_eq!(error_code(&response), INVALID_REQUEST);
+        assert_eq!(response["id"], 7);
+
+        let (_, response) =
+            post(&state, r#"{"jsonrpc":"2.0","method":"eth_call","id":null}"#).await;
+        assert_eq!(error_code(&response), METHOD_NOT_FOUND);
+        assert_eq!(response["id"], Value::Null);
+        assert_eq!(response["jsonrpc"], "2.0");
+
+        let (_, response) = post(
+            &state,
+            r#"{"jsonrpc":"2.0","method":"owshen_send","params":{"index":"0x0"},"id":2}"#,
+        )
+        .await;
+        assert_eq!(error_code(&response), INVALID_PARAMS);
+
+        // Errors of the handlers keep their REST code as data.
+        let send = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "owshen_send",
+            "params": {
+                "index": "0x0",
+                "new_amount": "1",
+                "address": PublicKey::from(PrivateKey::generate(&mut rand::thread_rng())),
+            },
+            "id": 3,
+        });
+        let (_, response) = post(&state, &send.to_string()).await;
+        let forbidden = ApiError::Forbidden(String::new());
+        assert_eq!(error_code(&response), forbidden.json_rpc_code());
+        assert_eq!(response["error"]["data"], forbidden.code());
+
+        // Only the calls of a batch are answered, not its notifications.
+        let batch = r#"[
+            {"jsonrpc":"2.0","method":"owshen_getCoins"},
+            {"jsonrpc":"2.0","method":"owshen_nope","id":4}
+        ]"#;
+        let (_, response) = post(&state, batch).await;
+        let responses = response.as_array().unwrap();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0]["id"], 4);
+        assert_eq!(error_code(&responses[0]), METHOD_NOT_FOUND);
+
+        let (status, _) = post(&state, r#"{"jsonrpc":"2.0","method":"owshen_getCoins"}"#).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, response) = post(&state, "[]").await;
+        assert_eq!(error_code(&response), INVALID_REQUEST);
+    }
+}
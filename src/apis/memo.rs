@@ -0,0 +1,158 @@
+use axum::extract::{Json, Query};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::apis::ApiError;
+use crate::SharedContext;
+use crate::{GetMemoRequest, GetMemoResponse, PostMemoRequest};
+
+/// Longest memo accepted, in characters.
+pub const MAX_MEMO_LENGTH: usize = 64;
+
+/// Trims `memo` and refuses the ones too long or holding control
+/// characters, e.g. newlines which would break exports. `None` for an
+/// empty memo.
+pub fn sanitize_memo(memo: &str) -> Result<Option<String>, ApiError> {
+    let memo = memo.trim();
+    if memo.chars().count() > MAX_MEMO_LENGTH {
+        return Err(ApiError::BadRequest(format!(
+            "Memos are at most {} characters long!",
+            MAX_MEMO_LENGTH
+        )));
+    }
+    if memo.chars().any(char::is_control) {
+        return Err(ApiError::BadRequest(
+            "Memos cannot contain control characters!".to_string(),
+        ));
+    }
+    Ok(Some(memo.to_string()).filter(|memo| !memo.is_empty()))
+}
+
+/// The memo of `commitment`, or all of them.
+pub async fn memo(
+    Query(req): Query<GetMemoRequest>,
+    context: SharedContext,
+) -> Result<GetMemoResponse, ApiError> {
+    let memos = context.read().await.memos.clone();
+    Ok(GetMemoResponse {
+        memos: memos
+            .iter()
+            .filter(|(commitment, _)| req.commitment.is_none_or(|c| c == **commitment))
+            .map(|(commitment, memo)| (*commitment, memo.clone()))
+            .collect(),
+    })
+}
+
+/// Labels the coin of `commitment`, the state file is written right away
+/// rather than on the next sync.
+pub async fn set_memo(
+    Json(req): Json<PostMemoRequest>,
+    context: SharedContext,
+    state_path: PathBuf,
+) -> Result<GetMemoResponse, ApiError> {
+    let memo = sanitize_memo(&req.memo)?;
+    let mut ctx = context.write().await;
+    let memos = Arc::make_mut(&mut ctx.memos);
+    match &memo {
+        Some(memo) => memos.insert(req.commitment, memo.clone()),
+        None => memos.remove(&req.commitment),
+    };
+    ctx.save(&state_path)?;
+    Ok(GetMemoResponse {
+        memos: memo
+            .map(|memo| (req.commitment, memo))
+            .into_iter()
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::apis::coins;
+    use crate::keys::{PrivateKey, PublicKey};
+    use crate::{Coin, Context, GetCoinsRequest};
+    use ethers::types::{H160, U256};
+    use tokio::sync::RwLock;
+
+    #[test]
+    fn test_sanitize_memo() {
+        assert_eq!(
+            sanitize_memo("  salary ").unwrap(),
+            Some("salary".to_string())
+        );
+        assert_eq!(sanitize_memo(" ").unwrap(), None);
+        assert!(sanitize_memo("refund\nto: someone").is_err());
+        assert!(sanitize_memo(&"é".repeat(MAX_MEMO_LENGTH)).is_ok());
+        assert!(sanitize_memo(&"a".repeat(MAX_MEMO_LENGTH + 1)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_memo_on_coins() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let coin = |index: u64| Coin {
+            index: U256::from(index),
+            uint_token: H160::default(),
+            amount: Amount::from(10u64),
+            priv_key: Some(priv_key),
+            pub_key: PublicKey::from(priv_key),
+            nullifier: Some(U256::from(1000 + index)),
+            commitment: U256::from(2000 + index),
+            block_number: None,
+            tx_hash: None,
+            ephemeral: None,
+        };
+        let mut ctx = Context::new(H160::default(), false);
+        ctx.coins = Arc::new(vec![coin(0), coin(1)]);
+        let context = Arc::new(RwLock::new(ctx));
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("state.json");
+
+        let set = |memo: &str| PostMemoRequest {
+            commitment: U256::from(2001),
+            memo: memo.to_string(),
+        };
+        let set_response = set_memo(Json(set(" salary")), context.clone(), state_path.clone())
+            .await
+            .unwrap();
+        assert_eq!(set_response.memos[&U256::from(2001)], "salary");
+        assert!(
+            set_memo(Json(set("a\u{0}b")), context.clone(), state_path.clone())
+                .await
+                .is_err()
+        );
+
+        let listed = coins(Query(GetCoinsRequest::default()), context.clone())
+            .await
+            .unwrap()
+            .listed()
+            .map(|coin| coin.memo)
+            .collect::<Vec<_>>();
+        assert_eq!(listed, vec![None, Some("salary".to_string())]);
+        let all = memo(Query(GetMemoRequest { commitment: None }), context.clone())
+            .await
+            .unwrap();
+        assert_eq!(all.memos.len(), 1);
+        let other = GetMemoRequest {
+            commitment: Some(U256::from(2000)),
+        };
+        assert!(memo(Query(other), context.clone())
+            .await
+            .unwrap()
+            .memos
+            .is_empty());
+
+        // Persisted along with the rest of the state
+        let mut restored = Context::new(H160::default(), false);
+        restored
+            .load(&state_path, &priv_key.viewing_key().into())
+            .unwrap();
+        assert_eq!(restored.memos[&U256::from(2001)], "salary");
+
+        set_memo(Json(set("")), context.clone(), state_path)
+            .await
+            .unwrap();
+        assert!(context.read().await.memos.is_empty());
+    }
+}
@@ -0,0 +1,20 @@
+use axum::http::header;
+use axum::response::IntoResponse;
+
+use crate::apis::ApiError;
+use crate::metrics::{Gauges, METRICS};
+use crate::SharedContext;
+
+/// Returns the wallet metrics in the Prometheus text format.
+pub async fn metrics(context: SharedContext) -> Result<impl IntoResponse, ApiError> {
+    let ctx = context.read().await;
+    let gauges = Gauges {
+        scanned_coins: ctx.scanned_coins,
+        owned_coins: ctx.owned_coins,
+        last_synced_block: ctx.last_synced_block.map(|block| block.as_u64()),
+    };
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        METRICS.render(&gauges),
+    ))
+}
@@ -1,11 +1,52 @@
+mod address_book;
+mod address_qr;
+mod balances;
 mod coins;
+mod confirmation;
+mod error;
+mod estimate;
+mod export;
+#[cfg(test)]
+mod fuzz;
+mod health;
+mod history;
 mod info;
+mod json_rpc;
+mod memo;
+mod metrics;
+mod nullifier;
+mod query;
 mod send;
+mod send_multi;
 mod stealth;
+mod sync_status;
+mod version;
 mod withdraw;
+mod ws;
 
-pub use coins::coins;
-pub use info::info;
+pub use address_book::{address_book_add, address_book_list, address_book_remove};
+pub use address_qr::address_qr;
+pub use balances::balances;
+pub use coins::{
+    coins, detect, find_coin, sync_coins, sync_loop, watch_chain, CoinsView,
+    DEFAULT_SCAN_CHUNK_SIZE, DEFAULT_SCAN_CONCURRENCY,
+};
+pub use confirmation::confirmation;
+pub use error::ApiError;
+pub use estimate::{estimate_send, estimate_withdraw};
+pub use export::export;
+pub use health::health;
+pub use history::history;
+pub use info::{abi, info, info_body, CircuitInfo, InfoCache};
+pub use json_rpc::{json_rpc, JsonRpcState};
+pub use memo::{memo, set_memo};
+pub use metrics::metrics;
+pub use nullifier::nullifier;
+pub use query::ApiQuery;
 pub use send::send;
+pub use send_multi::send_multi;
 pub use stealth::stealth;
+pub use sync_status::sync_status;
+pub use version::{build_info, version};
 pub use withdraw::withdraw;
+pub use ws::ws;
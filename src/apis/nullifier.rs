@@ -0,0 +1,70 @@
+use axum::extract::Path;
+use ethers::prelude::*;
+
+use crate::apis::ApiError;
+use crate::GetNullifierResponse;
+use crate::SharedContext;
+
+// The nullifier of a coin is published on-chain as soon as the coin is spent,
+// but revealing it *before* that lets whoever sees it link the future spend
+// transaction to this wallet. This endpoint is therefore only mounted when the
+// wallet runs with `--debug`.
+pub async fn nullifier(
+    Path(index): Path<u64>,
+    context: SharedContext,
+) -> Result<GetNullifierResponse, ApiError> {
+    let index = U256::from(index);
+    let coins = context.read().await.coins.clone();
+    let coin = coins
+        .iter()
+        .find(|coin| coin.index == index)
+        .ok_or(ApiError::NotFound(format!("No coin with index {}", index)))?;
+
+    // The nullifier the spend of the coin reveals, see `Coin::spending_key`.
+    let (_, nullifier) = coin.spending_key()?;
+    Ok(GetNullifierResponse { index, nullifier })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::keys::{PrivateKey, PublicKey};
+    use crate::Coin;
+    use crate::Context;
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    // That the nullifier is the one the spend publishes is checked against a
+    // node, see `test_nullifier_matches_spend` of the wallet.
+    #[tokio::test]
+    async fn test_nullifier_needs_spending_key() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let coin = Coin {
+            index: U256::from(7),
+            uint_token: H160::default(),
+            amount: Amount::from(100),
+            priv_key: None,
+            pub_key: PublicKey::from(priv_key),
+            nullifier: None,
+            commitment: U256::default(),
+            block_number: None,
+            tx_hash: None,
+            ephemeral: None,
+        };
+        let context = Arc::new(RwLock::new(Context {
+            coins: Arc::new(vec![coin]),
+            ..Context::new(H160::default(), true)
+        }));
+
+        // Detected with a viewing key.
+        assert!(matches!(
+            nullifier(Path(7), context.clone()).await,
+            Err(ApiError::Forbidden(_))
+        ));
+        assert!(matches!(
+            nullifier(Path(8), context).await,
+            Err(ApiError::NotFound(_))
+        ));
+    }
+}
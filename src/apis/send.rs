@@ -1,138 +1,592 @@
 use axum::extract::Query;
-use axum::response::Json;
 use ethers::prelude::*;
-use std::str::FromStr;
-use std::sync::Arc;
-use std::sync::Mutex;
+use std::path::{Path, PathBuf};
 
+use crate::address_book::AddressBook;
+use crate::amount::Amount;
+use crate::apis::{history, ApiError};
+use crate::blinding::Blinding;
+use crate::commitment::commitment;
 use crate::fp::Fp;
 use crate::h160_to_u256;
-use crate::hash::hash4;
 use crate::keys::Point;
-use crate::keys::PublicKey;
+use crate::keys::{EphemeralKey, PublicKey, UNTAGGED};
 use crate::proof::prove;
 use crate::proof::Proof;
-use crate::Context;
+use crate::transaction::GasOverrides;
+use crate::tree::SparseMerkleTree;
+use crate::Coin;
 use crate::GetSendRequest;
 use crate::GetSendResponse;
-use crate::PARAMS_FILE;
+use crate::SharedContext;
+use crate::{HistoryEntry, HistoryKind};
 
+impl GetSendRequest {
+    /// Sends `new_amount` out of the coin at `index`, the change going back to
+    /// `address`. The receiver is left for the caller to set.
+    pub fn new(index: U256, address: PublicKey, new_amount: String) -> Self {
+        GetSendRequest {
+            index,
+            new_amount,
+            receiver_address: String::new(),
+            address,
+            decimals: None,
+            token: None,
+            receiver_stealth_x: None,
+            receiver_stealth_y: None,
+            receiver_ephemeral_x: None,
+            receiver_ephemeral_y: None,
+            receiver_view_tag: None,
+            gas: GasOverrides::default(),
+        }
+    }
+}
+
+impl GetSendResponse {
+    pub fn history_entry(&self) -> HistoryEntry {
+        HistoryEntry::new(
+            HistoryKind::Send,
+            self.nullifier,
+            self.token,
+            self.obfuscated_receiver_amount.into(),
+        )
+    }
+}
+
+/// Where the receiver's coin of a send goes.
+#[derive(Debug, Clone, Copy)]
+pub enum Receiver {
+    /// A fresh stealth key is derived for the address.
+    Address(PublicKey),
+    /// A stealth key already derived elsewhere, e.g. through `/stealth`, which
+    /// is proven against as it is.
+    Stealth {
+        stealth: PublicKey,
+        ephemeral: EphemeralKey,
+        view_tag: u8,
+    },
+}
+
+impl Receiver {
+    /// The stealth key of `req` when it carries one, the resolved
+    /// `receiver_address` otherwise.
+    pub fn from_request(
+        req: &GetSendRequest,
+        address_book: &AddressBook,
+    ) -> Result<Self, ApiError> {
+        let point = |x: Option<Fp>, y: Option<Fp>, name: &str| match (x, y) {
+            (Some(x), Some(y)) => {
+                let point = Point { x, y };
+                point.validate().map_err(|e| {
+                    ApiError::BadRequest(format!("Invalid receiver {}: {}", name, e))
+                })?;
+                Ok(Some(point))
+            }
+            (None, None) => Ok(None),
+            _ => Err(ApiError::BadRequest(format!(
+                "Both coordinates of the receiver {} are needed!",
+                name
+            ))),
+        };
+        let stealth = point(req.receiver_stealth_x, req.receiver_stealth_y, "stealth")?;
+        let ephemeral = point(
+            req.receiver_ephemeral_x,
+            req.receiver_ephemeral_y,
+            "ephemeral",
+        )?;
+        match (stealth, ephemeral) {
+            (Some(stealth), Some(ephemeral)) => {
+                if !req.receiver_address.is_empty() {
+                    return Err(ApiError::BadRequest(
+                        "Either a receiver address or a stealth key is expected, not both!"
+                            .to_string(),
+                    ));
+                }
+                Ok(Receiver::Stealth {
+                    stealth: PublicKey {
+                        point: stealth,
+                        view: None,
+                    },
+                    ephemeral: EphemeralKey { point: ephemeral },
+                    view_tag: req.receiver_view_tag.unwrap_or(UNTAGGED),
+                })
+            }
+            (None, None) => Ok(Receiver::Address(
+                address_book.resolve(&req.receiver_address)?,
+            )),
+            _ => Err(ApiError::BadRequest(
+                "A receiver stealth key needs its ephemeral key!".to_string(),
+            )),
+        }
+    }
+
+    /// The ephemeral key, stealth key and view tag of the receiver's coin.
+    pub fn output(&self, blinding: &Blinding) -> (EphemeralKey, PublicKey, u8) {
+        match self {
+            Receiver::Address(pub_key) => blinding.derive(pub_key),
+            Receiver::Stealth {
+                stealth,
+                ephemeral,
+                view_tag,
+            } => (*ephemeral, *stealth, *view_tag),
+        }
+    }
+}
+
+/// Builds the proof and commitments for sending `send_amount` out of `coin` to
+/// `receiver`, returning the change to `address_pub_key`.
+pub fn build_send(
+    coin: &Coin,
+    merkle_root: &SparseMerkleTree,
+    root_block: Option<U64>,
+    send_amount: Amount,
+    receiver: Receiver,
+    address_pub_key: PublicKey,
+    params: &Path,
+    blinding: &Blinding,
+) -> Result<GetSendResponse, ApiError> {
+    let (priv_key, nullifier) = coin.spending_key()?;
+    let index = coin.index;
+    let amount = coin.amount;
+    let remaining_amount = amount.checked_sub(send_amount).ok_or_else(|| {
+        ApiError::InsufficientFunds(format!(
+            "Requested amount {} exceeds the coin amount {}!",
+            send_amount, amount
+        ))
+    })?;
+
+    let u32_index: u32 = index.low_u32();
+    let u64_index: u64 = index.low_u64();
+    // get merkle proof
+    let merkle_proof = merkle_root.get(u64_index);
+
+    let (address_ephemeral, address_stealth_pub_key, address_view_tag) =
+        blinding.derive(&address_pub_key);
+    let (receiver_address_ephemeral, receiver_address_stealth_pub_key, receiver_view_tag) =
+        receiver.output(blinding);
+
+    let obfuscated_remaining_amount: U256 = remaining_amount.into();
+    let hint_token_address = h160_to_u256(coin.uint_token);
+
+    // calc commitment one -> its for receiver
+    let u256_calc_send_commitment = commitment(
+        send_amount.into(),
+        &receiver_address_stealth_pub_key,
+        hint_token_address,
+    )?;
+
+    // calc commitment two -> its for sender
+    let u256_calc_sender_commitment = commitment(
+        remaining_amount.into(),
+        &address_stealth_pub_key,
+        hint_token_address,
+    )?;
+
+    let proof: std::result::Result<Proof, eyre::Error> = prove(
+        params,
+        u32_index,
+        hint_token_address,
+        amount.into(),
+        send_amount.into(),
+        obfuscated_remaining_amount,
+        receiver_address_stealth_pub_key,
+        address_stealth_pub_key,
+        priv_key.secret,
+        merkle_proof.proof.try_into().unwrap(),
+    );
+
+    match proof {
+        Ok(proof) => Ok(GetSendResponse {
+            proof,
+            token: coin.uint_token,
+            amount,
+            nullifier,
+            obfuscated_receiver_amount: send_amount.into(),
+            obfuscated_sender_amount: obfuscated_remaining_amount,
+            receiver_commitment: u256_calc_send_commitment,
+            sender_commitment: u256_calc_sender_commitment,
+            sender_ephemeral: address_ephemeral.point,
+            receiver_ephemeral: receiver_address_ephemeral.point,
+            sender_view_tag: address_view_tag,
+            receiver_view_tag,
+            root: merkle_root.root().into(),
+            root_block,
+            gas: GasOverrides::default(),
+        }),
+        Err(e) => Err(ApiError::Internal(format!(
+            "Something wrong while creating proof for send: {}",
+            e
+        ))),
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(index = %req.index, new_amount = %req.new_amount, receiver_address = %req.receiver_address)
+)]
 pub async fn send(
     Query(req): Query<GetSendRequest>,
-    context_send: Arc<Mutex<Context>>,
-    context_tree_send: Arc<Mutex<Context>>,
-) -> Result<Json<GetSendResponse>, eyre::Report> {
+    context_send: SharedContext,
+    context_tree_send: SharedContext,
+    params: PathBuf,
+) -> Result<GetSendResponse, ApiError> {
+    let send = prepare_send(Query(req), context_send.clone(), context_tree_send, params).await?;
+    history::record(&context_send, send.history_entry()).await;
+    Ok(send)
+}
+
+/// Builds the proof of a send without recording it, e.g. for estimates.
+pub async fn prepare_send(
+    Query(req): Query<GetSendRequest>,
+    context_send: SharedContext,
+    context_tree_send: SharedContext,
+    params: PathBuf,
+) -> Result<GetSendResponse, ApiError> {
+    if context_send.read().await.watch_only {
+        return Err(ApiError::Forbidden(
+            "Watch-only wallets cannot send!".to_string(),
+        ));
+    }
+
     let index = req.index;
-    let new_amount = req.new_amount;
-    let receiver_address = req.receiver_address;
-    let address = req.address;
-
-    let coins = context_send.lock().unwrap().coins.clone();
-    let merkle_root = context_tree_send.lock().unwrap().tree.clone();
-    // Find a coin with the specified index
-    let filtered_coin = coins.iter().find(|coin| coin.index == index);
-
-    match filtered_coin {
-        Some(coin) => {
-            let u32_index: u32 = index.low_u32();
-            let u64_index: u64 = index.low_u64();
-            // get merkle proof
-            let merkle_proof = merkle_root.get(u64_index);
-
-            let address_pub_key = PublicKey::from_str(&address)?;
-            let (address_ephemeral, address_stealth_pub_key) =
-                address_pub_key.derive(&mut rand::thread_rng());
-
-            let receiver_address_pub_key = PublicKey::from_str(&receiver_address)?;
-            let (receiver_address_ephemeral, receiver_address_stealth_pub_key) =
-                receiver_address_pub_key.derive(&mut rand::thread_rng());
-
-            let amount: U256 = coin.amount;
-            let str_amount: String = U256::to_string(&amount);
-
-            let str_amount_num: i64 = str_amount.parse()?;
-            let new_amount_num: i64 = new_amount.parse()?;
-
-            let send_amount = U256::from_str(&new_amount)?;
-
-            let min = str_amount_num - new_amount_num;
-
-            let remaining_amount = min.to_string();
-
-            let obfuscated_remaining_amount = amount - new_amount_num;
-            let hint_token_address = h160_to_u256(coin.uint_token);
-
-            // calc commitment one -> its for receiver
-            let calc_send_commitment = hash4([
-                receiver_address_stealth_pub_key.point.x,
-                receiver_address_stealth_pub_key.point.y,
-                Fp::from_str(&new_amount)?,
-                Fp::try_from(hint_token_address)?,
-            ]);
-
-            let u256_calc_send_commitment = calc_send_commitment.into();
-
-            // calc commitment two -> its for sender
-            let calc_sender_commitment: Fp = hash4([
-                address_stealth_pub_key.point.x,
-                address_stealth_pub_key.point.y,
-                Fp::from_str(&remaining_amount)?,
-                Fp::try_from(hint_token_address)?,
-            ]);
-
-            let u256_calc_sender_commitment = calc_sender_commitment.into();
-
-            let proof: std::result::Result<Proof, eyre::Error> = prove(
-                PARAMS_FILE,
-                u32_index,
-                hint_token_address,
-                amount,
-                new_amount_num.into(),
-                obfuscated_remaining_amount,
-                receiver_address_stealth_pub_key,
-                address_stealth_pub_key,
-                coin.priv_key.secret,
-                merkle_proof.proof.try_into().unwrap(),
-            );
-
-            match proof {
-                Ok(proof) => Ok(Json(GetSendResponse {
-                    proof,
-                    token: coin.uint_token,
-                    amount,
-                    nullifier: coin.nullifier,
-                    obfuscated_receiver_amount: send_amount,
-                    obfuscated_sender_amount: obfuscated_remaining_amount,
-                    receiver_commitment: u256_calc_send_commitment,
-                    sender_commitment: u256_calc_sender_commitment,
-                    sender_ephemeral: address_ephemeral.point,
-                    receiver_ephemeral: receiver_address_ephemeral.point,
-                })),
-                Err(_e) => Err(eyre::Report::msg(
-                    "Something wrong while creating proof for send",
-                )),
-            }
+    let gas = req.gas;
+    gas.validate()?;
+    let new_amount = req.new_amount.clone();
+    let decimals = req.decimals;
+    let address_pub_key = req.address;
+
+    let (coin, send_amount) = {
+        let ctx = context_send.read().await;
+        let coin = ctx.coin_to_spend(index, req.token)?;
+        ctx.ensure_precision(coin.uint_token, &new_amount, decimals)?;
+        (
+            coin,
+            ctx.parse_amount(coin.uint_token, &new_amount, decimals)?,
+        )
+    };
+    let (merkle_root, root_block) = {
+        let ctx = context_tree_send.read().await;
+        (ctx.tree.clone(), ctx.last_synced_block)
+    };
+
+    let (receiver, blinding) = {
+        let ctx = context_send.read().await;
+        (
+            Receiver::from_request(&req, &ctx.address_book)?,
+            ctx.blinding.clone(),
+        )
+    };
+
+    let send = build_send(
+        &coin,
+        &merkle_root,
+        root_block,
+        send_amount,
+        receiver,
+        address_pub_key,
+        &params,
+        &blinding,
+    )?;
+    Ok(GetSendResponse { gas, ..send })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apis::detect;
+    use crate::keys::PrivateKey;
+    use crate::paths::Paths;
+    use crate::{Context, TxStatus};
+    use bindings::owshen::{Point as OwshenPoint, SentFilter};
+    use ff::Field;
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    // A coin of `priv_key` at index 0, matching its commitment.
+    fn coin(priv_key: PrivateKey, amount: u64, token: H160) -> Coin {
+        let pub_key = PublicKey::from(priv_key);
+        let amount = Amount::from(amount);
+        Coin {
+            index: U256::zero(),
+            uint_token: token,
+            amount,
+            priv_key: Some(priv_key),
+            pub_key,
+            nullifier: Some(priv_key.nullifier(0).into()),
+            commitment: commitment(amount.into(), &pub_key, h160_to_u256(token)).unwrap(),
+            block_number: None,
+            tx_hash: None,
+            ephemeral: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watch_only_cannot_send() {
+        let context = Arc::new(RwLock::new(Context::new(H160::default(), true)));
+        let req = GetSendRequest {
+            receiver_address: PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()))
+                .to_string(),
+            ..GetSendRequest::new(
+                U256::from(0),
+                PublicKey::from(PrivateKey::generate(&mut rand::thread_rng())),
+                "1".to_string(),
+            )
+        };
+        assert!(matches!(
+            send(Query(req), context.clone(), context, PathBuf::default()).await,
+            Err(ApiError::Forbidden(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_coin() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let token = H160::repeat_byte(1);
+        let mut context = Context::new(H160::default(), false);
+        context.coins = Arc::new(vec![coin(priv_key, 100, token)]);
+        let context = Arc::new(RwLock::new(context));
+        let req = |index: u64, token: Option<H160>| GetSendRequest {
+            receiver_address: PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()))
+                .to_string(),
+            token,
+            ..GetSendRequest::new(
+                U256::from(index),
+                PublicKey::from(priv_key),
+                "1".to_string(),
+            )
+        };
+
+        let err = send(
+            Query(req(1, None)),
+            context.clone(),
+            context.clone(),
+            PathBuf::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, ApiError::CoinNotFound(_)), "{}", err);
+        assert_eq!(err.status_code(), axum::http::StatusCode::NOT_FOUND);
+
+        // The coin exists but holds another token.
+        let other = Some(H160::repeat_byte(2));
+        let err = send(
+            Query(req(0, other)),
+            context.clone(),
+            context.clone(),
+            PathBuf::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, ApiError::BadRequest(_)), "{}", err);
+
+        // Neither got as far as the history.
+        assert!(context.read().await.history.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cannot_send_spent_coin() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let (ephemeral, _) = PublicKey::from(priv_key).derive(&mut rand::thread_rng());
+        let token = H160::repeat_byte(1);
+        let nullifier = U256::from(1);
+        let mut context = Context::new(H160::default(), false);
+        context.coins = Arc::new(vec![Coin {
+            nullifier: Some(nullifier),
+            ephemeral: Some(ephemeral),
+            ..coin(priv_key.derive(ephemeral), 100, token)
+        }]);
+        // A proof spending the coin was handed out, its transaction isn't
+        // mined yet.
+        context.history.push(HistoryEntry::new(
+            HistoryKind::Send,
+            nullifier,
+            token,
+            Amount::from(10u64),
+        ));
+
+        // The guard survives a restart.
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("state.json");
+        context.save(&state).unwrap();
+        let mut restored = Context::new(H160::default(), false);
+        restored.load(&state, &priv_key.into()).unwrap();
+        let context = Arc::new(RwLock::new(restored));
+
+        let req = || GetSendRequest {
+            receiver_address: PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()))
+                .to_string(),
+            ..GetSendRequest::new(U256::from(0), priv_key.address(), "1".to_string())
+        };
+        let err = send(
+            Query(req()),
+            context.clone(),
+            context.clone(),
+            PathBuf::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, ApiError::BadRequest(_)), "{}", err);
+
+        // Once mined, the spend is known from the chain.
+        {
+            let mut ctx = context.write().await;
+            ctx.history[0].status = TxStatus::Confirmed;
+            ctx.spent_nullifiers.insert(nullifier);
+        }
+        let err = send(
+            Query(req()),
+            context.clone(),
+            context.clone(),
+            PathBuf::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, ApiError::BadRequest(_)), "{}", err);
+        assert_eq!(context.read().await.history.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_appends_one_entry() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let (ephemeral, _) = PublicKey::from(priv_key).derive(&mut rand::thread_rng());
+        let coin = coin(priv_key.derive(ephemeral), 100, H160::from_low_u64_be(1));
+        let nullifier = coin.nullifier.unwrap();
+        let mut context = Context::new(H160::default(), false);
+        context.coins = Arc::new(vec![coin]);
+        context.tree.set(0, Fp::try_from(coin.commitment).unwrap());
+        let context = Arc::new(RwLock::new(context));
+
+        let req = || GetSendRequest {
+            receiver_address: PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()))
+                .to_string(),
+            ..GetSendRequest::new(U256::from(0), priv_key.address(), "40".to_string())
+        };
+        let send_coin = || {
+            send(
+                Query(req()),
+                context.clone(),
+                context.clone(),
+                Paths::default().params,
+            )
+        };
+        let sent = send_coin().await.unwrap();
+        {
+            let ctx = context.read().await;
+            assert_eq!(ctx.history.len(), 1);
+            assert_eq!(ctx.history[0].kind, HistoryKind::Send);
+            assert_eq!(ctx.history[0].nullifier, nullifier);
+            assert_eq!(sent.nullifier, nullifier);
+            assert_eq!(ctx.history[0].tx_hash, None);
         }
-        None => {
-            println!("No coin with index {} found", index);
-            Ok(Json(GetSendResponse {
-                proof: Proof::default(),
-                token: H160::default(),
-                amount: U256::default(),
-                nullifier: U256::default(),
-                obfuscated_receiver_amount: U256::default(),
-                obfuscated_sender_amount: U256::default(),
-                receiver_commitment: U256::default(),
-                sender_commitment: U256::default(),
-                sender_ephemeral: Point {
-                    x: Fp::default(),
-                    y: Fp::default(),
-                },
-                receiver_ephemeral: Point {
-                    x: Fp::default(),
-                    y: Fp::default(),
-                },
-            }))
+        // The coin is held by the proof until its transaction is reported.
+        assert!(matches!(send_coin().await, Err(ApiError::BadRequest(_))));
+        assert_eq!(context.read().await.history.len(), 1);
+
+        // The proof was never broadcast, the coin is freed once it expires.
+        {
+            let mut ctx = context.write().await;
+            let now = ctx.history[0].timestamp + history::PROOF_EXPIRY;
+            history::expire_unbroadcast(&mut ctx, now - 1);
+            assert_eq!(ctx.history[0].status, TxStatus::Pending);
+            history::expire_unbroadcast(&mut ctx, now);
+            assert_eq!(ctx.history[0].status, TxStatus::Expired);
         }
+        let sent = send_coin().await.unwrap();
+        let tx_hash = TxHash::from_low_u64_be(7);
+        history::attach_tx(&context, sent.nullifier, tx_hash, TxStatus::Pending).await;
+        let mut ctx = context.write().await;
+        assert_eq!(ctx.history.len(), 2);
+        assert_eq!(ctx.history[1].tx_hash, Some(tx_hash));
+
+        // Broadcast proofs don't expire, their receipts settle them.
+        let later = ctx.history[1].timestamp + 2 * history::PROOF_EXPIRY;
+        history::expire_unbroadcast(&mut ctx, later);
+        assert_eq!(ctx.history[1].status, TxStatus::Pending);
+    }
+
+    #[test]
+    fn test_send_to_stealth() {
+        let mut rng = rand::thread_rng();
+        let receiver_priv_key = PrivateKey::generate(&mut rng);
+        // Derived by the receiver's side, as `/stealth` would.
+        let (ephemeral, stealth, view_tag) =
+            PublicKey::from(receiver_priv_key).derive_with_view_tag(&mut rng);
+        let req = GetSendRequest {
+            receiver_stealth_x: Some(stealth.point.x),
+            receiver_stealth_y: Some(stealth.point.y),
+            receiver_ephemeral_x: Some(ephemeral.point.x),
+            receiver_ephemeral_y: Some(ephemeral.point.y),
+            receiver_view_tag: Some(view_tag),
+            ..GetSendRequest::new(
+                U256::from(0),
+                PublicKey::from(receiver_priv_key),
+                "30".to_string(),
+            )
+        };
+        let receiver = Receiver::from_request(&req, &AddressBook::default()).unwrap();
+
+        let priv_key = PrivateKey::generate(&mut rng);
+        let (coin_ephemeral, _) = PublicKey::from(priv_key).derive(&mut rng);
+        let token = H160::from_low_u64_be(1);
+        let coin = coin(priv_key.derive(coin_ephemeral), 100, token);
+        let mut tree = SparseMerkleTree::new(16);
+        tree.set(0, Fp::try_from(coin.commitment).unwrap());
+
+        let send = build_send(
+            &coin,
+            &tree,
+            None,
+            Amount::from(30u64),
+            receiver,
+            PublicKey::from(priv_key),
+            &Paths::default().params,
+            &Blinding::default(),
+        )
+        .unwrap();
+        assert_eq!(send.receiver_ephemeral, ephemeral.point);
+        assert_eq!(send.receiver_view_tag, view_tag);
+
+        let sent = SentFilter {
+            ephemeral: OwshenPoint {
+                x: send.receiver_ephemeral.x.into(),
+                y: send.receiver_ephemeral.y.into(),
+            },
+            index: U256::from(1),
+            timestamp: U256::zero(),
+            hint_amount: send.obfuscated_receiver_amount,
+            hint_token_address: h160_to_u256(send.token),
+            commitment: send.receiver_commitment,
+            view_tag: send.receiver_view_tag,
+        };
+        let received = detect(&receiver_priv_key.into(), &sent).unwrap().unwrap();
+        assert_eq!(received.amount, Amount::from(30u64));
+        assert_eq!(received.pub_key, stealth);
+
+        let off_curve = GetSendRequest {
+            receiver_stealth_y: Some(stealth.point.y + Fp::ONE),
+            ..req.clone()
+        };
+        assert!(matches!(
+            Receiver::from_request(&off_curve, &AddressBook::default()),
+            Err(ApiError::BadRequest(_))
+        ));
+        let identity = GetSendRequest {
+            receiver_ephemeral_x: Some(Fp::ZERO),
+            receiver_ephemeral_y: Some(Fp::ONE),
+            ..req.clone()
+        };
+        assert!(matches!(
+            Receiver::from_request(&identity, &AddressBook::default()),
+            Err(ApiError::BadRequest(_))
+        ));
+        let without_ephemeral = GetSendRequest {
+            receiver_ephemeral_x: None,
+            receiver_ephemeral_y: None,
+            ..req
+        };
+        assert!(matches!(
+            Receiver::from_request(&without_ephemeral, &AddressBook::default()),
+            Err(ApiError::BadRequest(_))
+        ));
     }
 }
@@ -0,0 +1,184 @@
+use axum::extract::Query;
+use ethers::prelude::*;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use crate::amount::Amount;
+use crate::apis::send::{build_send, Receiver};
+use crate::apis::{history, ApiError};
+use crate::Coin;
+use crate::SharedContext;
+use crate::{GetMultiSendRequest, GetMultiSendResponse, SendPart};
+
+/// Greedily picks unspent coins of `token`, largest first, until their sum
+/// covers `target`. The last picked coin only contributes what is still missing.
+pub fn select_coins(
+    coins: &[Coin],
+    spent_nullifiers: &HashSet<U256>,
+    token: H160,
+    target: Amount,
+) -> Result<Vec<SendPart>, ApiError> {
+    if target.is_zero() {
+        return Err(ApiError::BadRequest(
+            "Amount should be greater than zero!".to_string(),
+        ));
+    }
+
+    let mut candidates: Vec<&Coin> = coins
+        .iter()
+        .filter(|coin| coin.uint_token == token && !coin.is_spent(spent_nullifiers))
+        .collect();
+    candidates.sort_by(|a, b| b.amount.cmp(&a.amount));
+
+    let mut parts = Vec::new();
+    let mut remaining = target;
+    for coin in candidates {
+        if remaining.is_zero() {
+            break;
+        }
+        let amount = std::cmp::min(coin.amount, remaining);
+        parts.push(SendPart {
+            index: coin.index,
+            amount,
+        });
+        remaining = remaining
+            .checked_sub(amount)
+            .expect("never picks more than what is missing");
+    }
+
+    if !remaining.is_zero() {
+        return Err(ApiError::InsufficientFunds(format!(
+            "Insufficient balance: missing {} of token {:?}!",
+            remaining, token
+        )));
+    }
+    Ok(parts)
+}
+
+// The circuit spends a single coin per proof, and the contract checks every
+// proof against its latest root. Proofs for several coins can therefore not be
+// built upfront: only the first part is proven here, the remaining parts have
+// to be sent through `/send` one by one once the previous one is mined.
+pub async fn send_multi(
+    Query(req): Query<GetMultiSendRequest>,
+    context: SharedContext,
+    params: PathBuf,
+) -> Result<GetMultiSendResponse, ApiError> {
+    let (coins, merkle_root, root_block, spent_nullifiers, watch_only, address_book, blinding) = {
+        let ctx = context.read().await;
+        (
+            // Pending coins can't be picked.
+            ctx.coins
+                .iter()
+                .filter(|coin| ctx.is_spendable(coin))
+                .copied()
+                .collect::<Vec<_>>(),
+            ctx.tree.clone(),
+            ctx.last_synced_block,
+            ctx.used_nullifiers(),
+            ctx.watch_only,
+            ctx.address_book.clone(),
+            ctx.blinding.clone(),
+        )
+    };
+    if watch_only {
+        return Err(ApiError::Forbidden(
+            "Watch-only wallets cannot send!".to_string(),
+        ));
+    }
+
+    let target = {
+        let ctx = context.read().await;
+        ctx.ensure_precision(req.token, &req.new_amount, req.decimals)?;
+        ctx.parse_amount(req.token, &req.new_amount, req.decimals)?
+    };
+    let parts = select_coins(&coins, &spent_nullifiers, req.token, target)?;
+
+    let first = &parts[0];
+    let coin = coins
+        .iter()
+        .find(|coin| coin.index == first.index)
+        .ok_or(ApiError::Internal("Selected coin disappeared!".to_string()))?;
+    let send = build_send(
+        coin,
+        &merkle_root,
+        root_block,
+        first.amount,
+        Receiver::Address(address_book.resolve(&req.receiver_address)?),
+        req.address,
+        &params,
+        &blinding,
+    )?;
+    history::record(&context, send.history_entry()).await;
+
+    Ok(GetMultiSendResponse { parts, send })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::{PrivateKey, PublicKey};
+
+    fn coin(index: u64, amount: u64, token: H160) -> Coin {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        Coin {
+            index: U256::from(index),
+            uint_token: token,
+            amount: Amount::from(amount),
+            priv_key: Some(priv_key),
+            pub_key: PublicKey::from(priv_key),
+            nullifier: Some(priv_key.nullifier(index as u32).into()),
+            commitment: U256::default(),
+            block_number: None,
+            tx_hash: None,
+            ephemeral: None,
+        }
+    }
+
+    #[test]
+    fn test_select_two_coins() {
+        let token = H160::from_low_u64_be(1);
+        let coins = vec![coin(0, 50, token), coin(1, 60, token)];
+        let parts = select_coins(&coins, &HashSet::new(), token, Amount::from(100)).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].index, U256::from(1));
+        assert_eq!(parts[0].amount, Amount::from(60));
+        assert_eq!(parts[1].index, U256::from(0));
+        assert_eq!(parts[1].amount, Amount::from(40));
+    }
+
+    #[test]
+    fn test_select_three_coins() {
+        let token = H160::from_low_u64_be(1);
+        let other_token = H160::from_low_u64_be(2);
+        let coins = vec![
+            coin(0, 30, token),
+            coin(1, 1000, other_token),
+            coin(2, 30, token),
+            coin(3, 30, token),
+        ];
+        let parts = select_coins(&coins, &HashSet::new(), token, Amount::from(80)).unwrap();
+        assert_eq!(parts.len(), 3);
+        assert!(parts.iter().all(|p| p.index != U256::from(1)));
+        let total = parts
+            .iter()
+            .try_fold(Amount::zero(), |acc, p| acc.checked_add(p.amount));
+        assert_eq!(total, Some(Amount::from(80)));
+    }
+
+    #[test]
+    fn test_select_insufficient_balance() {
+        let token = H160::from_low_u64_be(1);
+        let coins = vec![coin(0, 30, token), coin(1, 60, token)];
+        let spent = HashSet::from([coins[1].nullifier.unwrap()]);
+        assert!(select_coins(&coins, &HashSet::new(), token, Amount::from(90)).is_ok());
+        assert!(matches!(
+            select_coins(&coins, &HashSet::new(), token, Amount::from(91)),
+            Err(ApiError::InsufficientFunds(_))
+        ));
+        assert!(matches!(
+            select_coins(&coins, &spent, token, Amount::from(31)),
+            Err(ApiError::InsufficientFunds(_))
+        ));
+    }
+}
@@ -1,16 +1,125 @@
-use axum::{extract::Query, Json};
-use std::str::FromStr;
+use axum::extract::Query;
 
-use crate::keys::PublicKey;
+use crate::apis::ApiError;
 use crate::{GetStealthRequest, GetStealthResponse};
 
-pub async fn stealth(
-    Query(req): Query<GetStealthRequest>,
-) -> Result<Json<GetStealthResponse>, eyre::Report> {
-    let pub_key = PublicKey::from_str(&req.address)?;
-    let (ephemeral, address) = pub_key.derive(&mut rand::thread_rng());
-    Ok(Json(GetStealthResponse {
+#[tracing::instrument(skip_all, fields(address = %req.address))]
+pub async fn stealth(Query(req): Query<GetStealthRequest>) -> Result<GetStealthResponse, ApiError> {
+    let (ephemeral, address, view_tag) = req.address.derive_with_view_tag(&mut rand::thread_rng());
+    Ok(GetStealthResponse {
         address: address.point,
         ephemeral: ephemeral.point,
-    }))
+        view_tag,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apis::error::ApiErrorBody;
+    use crate::apis::ApiQuery;
+    use crate::keys::{PrivateKey, PublicKey};
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use axum::routing::get;
+    use axum::Router;
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id};
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+    type RecordedSpan = (String, Vec<(String, String)>);
+
+    #[derive(Clone, Default)]
+    struct SpanRecorder(Arc<Mutex<Vec<RecordedSpan>>>);
+
+    struct FieldVisitor<'a>(&'a mut Vec<(String, String)>);
+
+    impl<'a> Visit for FieldVisitor<'a> {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0
+                .push((field.name().to_string(), format!("{:?}", value)));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for SpanRecorder {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+            let mut fields = Vec::new();
+            attrs.record(&mut FieldVisitor(&mut fields));
+            self.0
+                .lock()
+                .unwrap()
+                .push((attrs.metadata().name().to_string(), fields));
+        }
+    }
+
+    #[test]
+    fn test_stealth_span() {
+        let recorder = SpanRecorder::default();
+        let subscriber = tracing_subscriber::registry().with(recorder.clone());
+        let address = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            futures::executor::block_on(stealth(Query(GetStealthRequest { address }))).unwrap();
+        });
+        let address = address.to_string();
+
+        let spans = recorder.0.lock().unwrap();
+        assert!(spans.iter().any(|(name, fields)| {
+            name == "stealth" && fields == &vec![("address".to_string(), address.clone())]
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_stealth() {
+        let pub_key = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        let resp = stealth(Query(GetStealthRequest { address: pub_key }))
+            .await
+            .unwrap();
+        assert!(resp.address.is_on_curve());
+        assert_ne!(resp.view_tag, crate::keys::UNTAGGED);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_address() {
+        use tower::ServiceExt;
+
+        let app = Router::new().route(
+            "/stealth",
+            get(|ApiQuery(req): ApiQuery<GetStealthRequest>| async move {
+                crate::respond(stealth(Query(req)).await)
+            }),
+        );
+        let request = |query: String| {
+            app.clone().oneshot(
+                Request::get(format!("/stealth?{}", query))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+        let pub_key = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        let response = request(format!("address={}", pub_key)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Refused before reaching the handler.
+        let identity = format!("address=OoOo3{}", "0".repeat(64));
+        for query in ["address=OoOo".to_string(), identity, String::new()] {
+            let response = request(query.clone()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", query);
+        }
+        let body = hyper::body::to_bytes(
+            request("address=OoOo".to_string())
+                .await
+                .unwrap()
+                .into_body(),
+        )
+        .await
+        .unwrap();
+        // With the body of the errors of the handlers.
+        let body: ApiErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.code, "bad_request");
+        assert!(body.message.contains("Invalid key length!"));
+    }
 }
@@ -0,0 +1,12 @@
+use crate::apis::ApiError;
+use crate::GetSyncStatusResponse;
+use crate::SharedContext;
+
+pub async fn sync_status(context: SharedContext) -> Result<GetSyncStatusResponse, ApiError> {
+    let ctx = context.read().await;
+    Ok(GetSyncStatusResponse {
+        syncing: ctx.sync_progress.is_some(),
+        progress: ctx.sync_progress,
+        last_synced_block: ctx.last_synced_block,
+    })
+}
@@ -0,0 +1,45 @@
+use ethers::types::H256;
+use ethers::utils::keccak256;
+use std::path::Path;
+
+use crate::apis::ApiError;
+use crate::GetVersionResponse;
+
+/// Describes the running build. The proving key is identified by the
+/// keccak256 hash of its content, `None` when it can't be read.
+pub fn build_info(params: &Path) -> GetVersionResponse {
+    GetVersionResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_commit: env!("OWSHEN_GIT_COMMIT").to_string(),
+        target: env!("OWSHEN_TARGET").to_string(),
+        params_hash: std::fs::read(params)
+            .ok()
+            .map(|params| H256::from(keccak256(params))),
+    }
+}
+
+pub async fn version(build_info: GetVersionResponse) -> Result<GetVersionResponse, ApiError> {
+    Ok(build_info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_build_info() {
+        let info = build_info(Path::new("/nonexistent/coin_withdraw.zkey"));
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert!(!info.git_commit.is_empty());
+        assert!(!info.target.is_empty());
+        assert_eq!(info.params_hash, None);
+
+        let mut params = tempfile::NamedTempFile::new().unwrap();
+        params.write_all(b"zkey").unwrap();
+        assert_eq!(
+            build_info(params.path()).params_hash,
+            Some(H256::from(keccak256(b"zkey")))
+        );
+    }
+}
@@ -1,102 +1,521 @@
 use axum::extract::Query;
-use axum::response::Json;
 use ethers::prelude::*;
-use std::str::FromStr;
-use std::sync::Arc;
-use std::sync::Mutex;
+use std::path::PathBuf;
 
+use crate::amount::{format_request_amount, parse_request_amount, Amount};
+use crate::apis::{history, ApiError};
+use crate::commitment::commitment;
 use crate::fp::Fp;
 use crate::h160_to_u256;
-use crate::hash::hash4;
-use crate::keys::Point;
-use crate::keys::PublicKey;
+use crate::keys::{Point, PublicKey};
 use crate::proof::prove;
 use crate::proof::Proof;
-use crate::Context;
+use crate::transaction::GasOverrides;
 use crate::GetWithdrawRequest;
 use crate::GetWithdrawResponse;
-use crate::PARAMS_FILE;
+use crate::SharedContext;
+use crate::{HistoryEntry, HistoryKind};
 
+impl GetWithdrawRequest {
+    /// Withdraws `desire_amount` out of the coin at `index` to `to`, the change
+    /// going back to `address`.
+    pub fn new(index: U256, address: PublicKey, desire_amount: String, to: H160) -> Self {
+        GetWithdrawRequest {
+            index,
+            address,
+            desire_amount,
+            to,
+            relayer_fee: None,
+            decimals: None,
+            token: None,
+            gas: GasOverrides::default(),
+        }
+    }
+}
+
+// Splits a coin into the withdrawn amount and the change that remains in the
+// wallet.
+fn split_amount(
+    coin_amount: Amount,
+    desire_amount: &str,
+    decimals: Option<u8>,
+) -> Result<(Amount, Amount), ApiError> {
+    let withdraw_amount = parse_request_amount(desire_amount, decimals)?;
+    let remaining_amount = coin_amount.checked_sub(withdraw_amount).ok_or_else(|| {
+        ApiError::InsufficientFunds(format!(
+            "Requested amount {} exceeds the coin amount {}!",
+            format_request_amount(withdraw_amount, decimals),
+            format_request_amount(coin_amount, decimals)
+        ))
+    })?;
+    Ok((withdraw_amount, remaining_amount))
+}
+
+// The withdrawn coin is committed to `to` and `fee` in place of a public key,
+// so that neither can be changed without invalidating the proof. The contract
+// recomputes the same commitment out of its arguments.
+fn withdrawal_key(to: H160, fee: Amount) -> Result<PublicKey, ApiError> {
+    let coordinate = |value: U256| {
+        Fp::from_u256_checked(value)
+            .map_err(|e| ApiError::BadRequest(format!("Invalid withdrawal: {}", e)))
+    };
+    Ok(PublicKey {
+        point: Point {
+            x: coordinate(h160_to_u256(to))?,
+            y: coordinate(fee.into())?,
+        },
+        view: None,
+    })
+}
+
+// Spans never carry the wallet's keys, only the request parameters.
+#[tracing::instrument(skip_all, fields(index = %req.index, desire_amount = %req.desire_amount))]
 pub async fn withdraw(
     Query(req): Query<GetWithdrawRequest>,
-    context_withdraw: Arc<Mutex<Context>>,
-    context_tree: Arc<Mutex<Context>>,
-) -> Result<Json<GetWithdrawResponse>, eyre::Report> {
+    context_withdraw: SharedContext,
+    context_tree: SharedContext,
+    params: PathBuf,
+    is_test: bool,
+) -> Result<GetWithdrawResponse, ApiError> {
+    let withdrawal = prepare_withdraw(
+        Query(req),
+        context_withdraw.clone(),
+        context_tree,
+        params,
+        is_test,
+    )
+    .await?;
+    history::record(
+        &context_withdraw,
+        HistoryEntry::new(
+            HistoryKind::Withdraw,
+            withdrawal.nullifier,
+            withdrawal.token,
+            withdrawal.withdrawn_amount,
+        ),
+    )
+    .await;
+    Ok(withdrawal)
+}
+
+/// Builds the proof of a withdrawal without recording it, e.g. for estimates.
+pub async fn prepare_withdraw(
+    Query(req): Query<GetWithdrawRequest>,
+    context_withdraw: SharedContext,
+    context_tree: SharedContext,
+    params: PathBuf,
+    is_test: bool,
+) -> Result<GetWithdrawResponse, ApiError> {
+    if context_withdraw.read().await.watch_only {
+        return Err(ApiError::Forbidden(
+            "Watch-only wallets cannot withdraw!".to_string(),
+        ));
+    }
+
     let index = req.index;
-    let coins = context_withdraw.lock().unwrap().coins.clone();
-    let address = req.address;
-    let merkle_root = context_tree.lock().unwrap().tree.clone();
-    // Find a coin with the specified index
-    let filtered_coin = coins.iter().find(|coin| coin.index == index);
-    match filtered_coin {
-        Some(coin) => {
-            let u32_index: u32 = index.low_u32();
-            let u64_index: u64 = index.low_u64();
-            // get merkle proof
-            let merkle_proof = merkle_root.get(u64_index);
-            let pub_key = PublicKey::from_str(&address)?;
-            let (ephemeral, stealth_pub_key) = pub_key.derive(&mut rand::thread_rng());
-
-            let amount: U256 = coin.amount;
-
-            let new_amount_num: i64 = req.desire_amount.parse()?;
-
-            let obfuscated_remaining_amount = amount - new_amount_num;
-
-            let min: U256 = amount - new_amount_num;
-            let remaining_amount = min.to_string();
-
-            let hint_token_address = h160_to_u256(coin.uint_token);
-
-            let calc_commitment = hash4([
-                stealth_pub_key.point.x,
-                stealth_pub_key.point.y,
-                Fp::from_str(&remaining_amount)?,
-                Fp::try_from(hint_token_address)?,
-            ]);
-            let u256_calc_commitment = calc_commitment.into();
-
-            let proof: std::result::Result<Proof, eyre::Error> = prove(
-                PARAMS_FILE,
-                u32_index,
-                hint_token_address,
-                amount,
-                new_amount_num.into(),
-                obfuscated_remaining_amount,
-                PublicKey::null(),
-                stealth_pub_key,
-                coin.priv_key.secret,
-                merkle_proof.proof.try_into().unwrap(),
+    let gas = req.gas;
+    gas.validate()?;
+    let (coin, decimals, relayer_fee) = {
+        let ctx = context_withdraw.read().await;
+        let coin = ctx.coin_to_spend(index, req.token)?;
+        ctx.ensure_precision(coin.uint_token, &req.desire_amount, req.decimals)?;
+        let relayer_fee = match &req.relayer_fee {
+            Some(fee) => ctx.parse_amount(coin.uint_token, fee, req.decimals)?,
+            None => Amount::from(0),
+        };
+        (
+            coin,
+            ctx.amount_decimals(coin.uint_token, req.decimals)?,
+            relayer_fee,
+        )
+    };
+    let (priv_key, nullifier) = coin.spending_key()?;
+    let pub_key = req.address;
+    let (merkle_root, root_block) = {
+        let ctx = context_tree.read().await;
+        (ctx.tree.clone(), ctx.last_synced_block)
+    };
+
+    let u32_index: u32 = index.low_u32();
+    let u64_index: u64 = index.low_u64();
+    // get merkle proof
+    let merkle_proof = merkle_root.get(u64_index);
+    let (ephemeral, stealth_pub_key, view_tag) =
+        context_withdraw.read().await.blinding.derive(&pub_key);
+
+    let amount = coin.amount;
+
+    let (withdraw_amount, remaining_amount) = split_amount(amount, &req.desire_amount, decimals)?;
+    if relayer_fee > withdraw_amount {
+        return Err(ApiError::BadRequest(format!(
+            "Relayer fee {} exceeds the withdrawn amount {}!",
+            format_request_amount(relayer_fee, decimals),
+            format_request_amount(withdraw_amount, decimals)
+        )));
+    }
+
+    let obfuscated_remaining_amount: U256 = remaining_amount.into();
+
+    let hint_token_address = h160_to_u256(coin.uint_token);
+
+    // The change goes back to the wallet as a new coin
+    let u256_calc_commitment = commitment(
+        remaining_amount.into(),
+        &stealth_pub_key,
+        hint_token_address,
+    )?;
+
+    let proof: std::result::Result<Proof, eyre::Error> = prove(
+        params,
+        u32_index,
+        hint_token_address,
+        amount.into(),
+        withdraw_amount.into(),
+        obfuscated_remaining_amount,
+        withdrawal_key(req.to, relayer_fee)?,
+        stealth_pub_key,
+        priv_key.secret,
+        merkle_proof.proof.try_into().unwrap(),
+    );
+    match proof {
+        Ok(proof) => Ok(GetWithdrawResponse {
+            proof,
+            token: coin.uint_token,
+            amount: coin.amount,
+            obfuscated_remaining_amount,
+            nullifier,
+            commitment: u256_calc_commitment,
+            ephemeral: ephemeral.point,
+            view_tag,
+            root: merkle_root.root().into(),
+            root_block,
+            remaining_amount: if is_test {
+                Some(remaining_amount)
+            } else {
+                None
+            },
+            to: req.to,
+            withdrawn_amount: withdraw_amount,
+            relayer_fee,
+            gas,
+            relay: None,
+        }),
+        Err(e) => Err(ApiError::Internal(format!(
+            "Something wrong while creating proof for withdraw: {}",
+            e
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blinding::Blinding;
+    use crate::keys::PrivateKey;
+    use crate::paths::Paths;
+    use crate::Coin;
+    use crate::Context;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    // A coin of `priv_key` at index 0, matching its commitment.
+    fn coin(priv_key: PrivateKey, amount: u64, token: H160) -> Coin {
+        let pub_key = PublicKey::from(priv_key);
+        let amount = Amount::from(amount);
+        Coin {
+            index: U256::zero(),
+            uint_token: token,
+            amount,
+            priv_key: Some(priv_key),
+            pub_key,
+            nullifier: Some(priv_key.nullifier(0).into()),
+            commitment: commitment(amount.into(), &pub_key, h160_to_u256(token)).unwrap(),
+            block_number: None,
+            tx_hash: None,
+            ephemeral: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watch_only_cannot_withdraw() {
+        let context = Arc::new(RwLock::new(Context::new(H160::default(), true)));
+        let req = GetWithdrawRequest::new(
+            U256::from(0),
+            PublicKey::from(PrivateKey::generate(&mut rand::thread_rng())),
+            "1".to_string(),
+            H160::default(),
+        );
+        assert!(matches!(
+            withdraw(
+                Query(req),
+                context.clone(),
+                context,
+                PathBuf::default(),
+                false
+            )
+            .await,
+            Err(ApiError::Forbidden(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cannot_withdraw_spent_coin() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let nullifier: U256 = priv_key.nullifier(0).into();
+        let context = Arc::new(RwLock::new(Context {
+            coins: Arc::new(vec![coin(priv_key, 10, H160::default())]),
+            spent_nullifiers: HashSet::from([nullifier]),
+            ..Context::new(H160::default(), false)
+        }));
+        let req = GetWithdrawRequest::new(
+            U256::from(0),
+            PublicKey::from(priv_key),
+            "1".to_string(),
+            H160::default(),
+        );
+        assert!(matches!(
+            withdraw(
+                Query(req),
+                context.clone(),
+                context,
+                PathBuf::default(),
+                false
+            )
+            .await,
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cannot_withdraw_pending_coin() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let context = Arc::new(RwLock::new(Context {
+            coins: Arc::new(vec![Coin {
+                block_number: Some(U64::from(10)),
+                ..coin(priv_key, 10, H160::default())
+            }]),
+            last_synced_block: Some(U64::from(11)),
+            min_confirmations: 6,
+            ..Context::new(H160::default(), false)
+        }));
+        let req = GetWithdrawRequest::new(
+            U256::from(0),
+            PublicKey::from(priv_key),
+            "1".to_string(),
+            H160::default(),
+        );
+        match withdraw(
+            Query(req),
+            context.clone(),
+            context,
+            PathBuf::default(),
+            false,
+        )
+        .await
+        {
+            Err(ApiError::BadRequest(msg)) => assert!(msg.contains("2 of the 6 confirmations")),
+            other => panic!("Unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_excess_precision() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let usdc = H160::repeat_byte(6);
+        let context = Arc::new(RwLock::new(Context {
+            coins: Arc::new(vec![coin(priv_key, 10_000_000, usdc)]),
+            token_decimals: HashMap::from([(usdc, 6)]),
+            ..Context::new(H160::default(), false)
+        }));
+        let req = GetWithdrawRequest {
+            decimals: Some(6),
+            ..GetWithdrawRequest::new(
+                U256::from(0),
+                PublicKey::from(priv_key),
+                "1.0000001".to_string(),
+                H160::default(),
+            )
+        };
+        match withdraw(
+            Query(req),
+            context.clone(),
+            context.clone(),
+            PathBuf::default(),
+            false,
+        )
+        .await
+        {
+            Err(ApiError::BadRequest(msg)) => assert!(msg.contains("at most 6 fractional digits")),
+            other => panic!("Unexpected result {:?}", other.map(|_| ())),
+        }
+        assert!(context.read().await.history.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_index_beyond_the_tree() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let context = Arc::new(RwLock::new(Context::new(H160::default(), false)));
+        let capacity = U256::from(4u64.pow(16));
+        let modulus = U256::from_str_radix(&<Fp as ff::PrimeField>::MODULUS[2..], 16).unwrap();
+        for (index, valid) in [
+            (capacity - 1, true),
+            (capacity, false),
+            // Indices whose low bits are those of a valid one.
+            (capacity + 1, false),
+            (U256::from(u32::MAX) + 2, false),
+            (modulus - 1, false),
+            (modulus, false),
+        ] {
+            let req = GetWithdrawRequest::new(
+                index,
+                PublicKey::from(priv_key),
+                "1".to_string(),
+                H160::default(),
+            );
+            let result = withdraw(
+                Query(req),
+                context.clone(),
+                context.clone(),
+                PathBuf::default(),
+                false,
+            )
+            .await;
+            // Valid indices of unknown coins aren't found.
+            assert_eq!(
+                !matches!(result, Err(ApiError::BadRequest(_))),
+                valid,
+                "{}",
+                index
             );
-            match proof {
-                Ok(proof) => Ok(Json(GetWithdrawResponse {
-                    proof,
-                    token: coin.uint_token,
-                    amount: coin.amount,
-                    obfuscated_remaining_amount,
-                    nullifier: coin.nullifier,
-                    commitment: u256_calc_commitment,
-                    ephemeral: ephemeral.point,
-                })),
-                Err(_e) => Err(eyre::Report::msg(
-                    "Something wrong while creating proof for withdraw",
-                )),
-            }
         }
-        None => {
-            println!("No coin with index {} found", index);
-            Ok(Json(GetWithdrawResponse {
-                proof: Proof::default(),
-                token: H160::default(),
-                amount: U256::default(),
-                obfuscated_remaining_amount: U256::default(),
-                nullifier: U256::default(),
-                commitment: U256::default(),
-                ephemeral: Point {
-                    x: Fp::default(),
-                    y: Fp::default(),
-                },
-            }))
+    }
+
+    #[tokio::test]
+    async fn test_change_is_blinded_by_context() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let (coin_ephemeral, _) = PublicKey::from(priv_key).derive(&mut rand::thread_rng());
+        let token = H160::from_low_u64_be(1);
+        let coin = coin(priv_key.derive(coin_ephemeral), 100, token);
+        let mut context = Context {
+            coins: Arc::new(vec![coin]),
+            blinding: Blinding::seeded(7),
+            ..Context::new(H160::default(), false)
+        };
+        context.tree.set(0, Fp::try_from(coin.commitment).unwrap());
+        let context = Arc::new(RwLock::new(context));
+
+        let req = GetWithdrawRequest::new(
+            U256::from(0),
+            priv_key.address(),
+            "40".to_string(),
+            H160::default(),
+        );
+        let withdrawal = withdraw(
+            Query(req),
+            context.clone(),
+            context,
+            Paths::default().params,
+            false,
+        )
+        .await
+        .unwrap();
+
+        // The change is drawn from the seeded blinding, as the next output of
+        // a fresh one with the same seed.
+        let (ephemeral, stealth_pub_key, view_tag) =
+            Blinding::seeded(7).derive(&priv_key.address());
+        assert_eq!(withdrawal.ephemeral, ephemeral.point);
+        assert_eq!(withdrawal.view_tag, view_tag);
+        assert_eq!(
+            withdrawal.commitment,
+            commitment(
+                Amount::from(60u64).into(),
+                &stealth_pub_key,
+                h160_to_u256(token)
+            )
+            .unwrap()
+        );
+        assert_eq!(withdrawal.withdrawn_amount, Amount::from(40u64));
+        assert_eq!(withdrawal.relayer_fee, Amount::zero());
+    }
+
+    #[tokio::test]
+    async fn test_relayer_fee_within_the_withdrawal() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let context = Arc::new(RwLock::new(Context {
+            coins: Arc::new(vec![coin(priv_key, 100, H160::default())]),
+            ..Context::new(H160::default(), false)
+        }));
+        let req = GetWithdrawRequest {
+            relayer_fee: Some("41".to_string()),
+            ..GetWithdrawRequest::new(
+                U256::from(0),
+                priv_key.address(),
+                "40".to_string(),
+                H160::repeat_byte(1),
+            )
+        };
+        match withdraw(
+            Query(req),
+            context.clone(),
+            context,
+            PathBuf::default(),
+            false,
+        )
+        .await
+        {
+            Err(ApiError::BadRequest(msg)) => assert!(msg.contains("exceeds the withdrawn amount")),
+            other => panic!("Unexpected result {:?}", other.map(|_| ())),
         }
     }
+
+    #[test]
+    fn test_withdrawal_key_binds_receiver_and_fee() {
+        let to = H160::repeat_byte(1);
+        let key = withdrawal_key(to, Amount::from(5)).unwrap();
+        assert_eq!(key.point.x, Fp::try_from(h160_to_u256(to)).unwrap());
+        assert_eq!(key.point.y, Fp::from(5));
+        assert_ne!(
+            key.point,
+            withdrawal_key(to, Amount::from(6)).unwrap().point
+        );
+        assert_ne!(
+            key.point,
+            withdrawal_key(H160::repeat_byte(2), Amount::from(5))
+                .unwrap()
+                .point
+        );
+    }
+
+    #[test]
+    fn test_split_amount() {
+        let coin_amount = Amount::from(100);
+        assert_eq!(
+            split_amount(coin_amount, "100", None).unwrap(),
+            (Amount::from(100), Amount::zero())
+        );
+        assert_eq!(
+            split_amount(coin_amount, "30", None).unwrap(),
+            (Amount::from(30), Amount::from(70))
+        );
+        assert!(matches!(
+            split_amount(coin_amount, "101", None),
+            Err(ApiError::InsufficientFunds(_))
+        ));
+        assert!(matches!(
+            split_amount(coin_amount, "-1", None),
+            Err(ApiError::BadRequest(_))
+        ));
+
+        // Whole tokens of a 2 decimals token
+        assert_eq!(
+            split_amount(coin_amount, "0.3", Some(2)).unwrap(),
+            (Amount::from(30), Amount::from(70))
+        );
+        assert!(matches!(
+            split_amount(coin_amount, "0.305", Some(2)),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
 }
@@ -0,0 +1,107 @@
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::response::Response;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{interval_at, Duration, Instant};
+
+use crate::Coin;
+
+// Keeps idle connections from being closed by proxies and browsers.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Upgrades to a WebSocket that pushes every newly detected `Coin` as JSON.
+pub async fn ws(ws: WebSocketUpgrade, coin_sender: broadcast::Sender<Coin>) -> Response {
+    // Subscribe before the upgrade so that no coin is missed in between.
+    let coin_receiver = coin_sender.subscribe();
+    ws.on_upgrade(move |socket| push_coins(socket, coin_receiver))
+}
+
+async fn push_coins(mut socket: WebSocket, mut coin_receiver: broadcast::Receiver<Coin>) {
+    let mut heartbeat = interval_at(Instant::now() + HEARTBEAT_INTERVAL, HEARTBEAT_INTERVAL);
+    loop {
+        tokio::select! {
+            coin = coin_receiver.recv() => match coin {
+                Ok(coin) => {
+                    let msg = match serde_json::to_string(&coin) {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            tracing::error!("Cannot serialize coin: {}", e);
+                            continue;
+                        }
+                    };
+                    if socket.send(Message::Text(msg)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("WebSocket client lagged behind, {} coins skipped", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = heartbeat.tick() => {
+                if socket.send(Message::Ping(vec![])).await.is_err() {
+                    break;
+                }
+            }
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                _ => {}
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::apis::coins::publish_new_coins;
+    use crate::keys::{PrivateKey, PublicKey};
+    use axum::{routing::get, Router};
+    use ethers::types::{H160, U256};
+    use futures::StreamExt;
+    use std::net::SocketAddr;
+    use tokio_tungstenite::{connect_async, tungstenite::Message as ClientMessage};
+
+    #[tokio::test]
+    async fn test_ws_pushes_new_coins() {
+        let (coin_sender, _) = broadcast::channel::<Coin>(16);
+        let sender = coin_sender.clone();
+        let app = Router::new().route(
+            "/ws",
+            get(move |upgrade: WebSocketUpgrade| async move { ws(upgrade, sender).await }),
+        );
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let (mut client, _) = connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let coin = Coin {
+            index: U256::from(3),
+            uint_token: H160::from_low_u64_be(1),
+            amount: Amount::from(10),
+            priv_key: Some(priv_key),
+            pub_key: PublicKey::from(priv_key),
+            nullifier: Some(U256::from(5)),
+            commitment: U256::from(7),
+            block_number: None,
+            tx_hash: None,
+            ephemeral: None,
+        };
+        publish_new_coins(&coin_sender, &[], &[coin]);
+
+        loop {
+            match client.next().await.unwrap().unwrap() {
+                ClientMessage::Text(msg) => {
+                    let pushed: Coin = serde_json::from_str(&msg).unwrap();
+                    assert_eq!(pushed.index, coin.index);
+                    assert_eq!(pushed.amount, coin.amount);
+                    break;
+                }
+                _ => continue,
+            }
+        }
+    }
+}
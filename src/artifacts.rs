@@ -0,0 +1,137 @@
+use ethers::abi::Abi;
+use ethers::types::Bytes;
+use eyre::Result;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::hash::PoseidonVariant;
+use crate::proof::PARAMS_FILE_NAME;
+
+/// First bytes of the proving keys written by snarkjs.
+const ZKEY_MAGIC: &[u8; 4] = b"zkey";
+
+/// The Poseidon contract deployed along with a test config, read from
+/// `--artifacts-dir` when it holds one for the variant. The directory mirrors
+/// `src/assets`: `poseidon4.abi` and `poseidon4.evm` for `poseidon4`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoseidonArtifacts {
+    pub variant: PoseidonVariant,
+    pub abi: String,
+    pub bytecode: String,
+}
+
+impl PoseidonArtifacts {
+    pub fn bundled(variant: PoseidonVariant) -> Self {
+        PoseidonArtifacts {
+            variant,
+            abi: variant.abi().to_string(),
+            bytecode: variant.bytecode().to_string(),
+        }
+    }
+
+    /// The artifacts of `variant` in `dir`, the bundled ones when it has
+    /// neither of them. Either file alone is refused, the ABI and the
+    /// bytecode of different builds may not match.
+    pub fn load(dir: Option<&Path>, variant: PoseidonVariant) -> Result<Self> {
+        let dir = match dir {
+            Some(dir) => dir,
+            None => return Ok(Self::bundled(variant)),
+        };
+        let abi_path = dir.join(format!("{}.abi", variant));
+        let bytecode_path = dir.join(format!("{}.evm", variant));
+        match (abi_path.is_file(), bytecode_path.is_file()) {
+            (false, false) => Ok(Self::bundled(variant)),
+            (true, true) => {
+                let abi = std::fs::read_to_string(&abi_path)?;
+                let bytecode = std::fs::read_to_string(&bytecode_path)?.trim().to_string();
+                validate_abi(&abi)
+                    .map_err(|e| eyre::Report::msg(format!("Invalid {:?}: {}", abi_path, e)))?;
+                Bytes::from_str(&bytecode).map_err(|e| {
+                    eyre::Report::msg(format!("Invalid {:?}: {}", bytecode_path, e))
+                })?;
+                tracing::info!("Using the {} contract of {:?}", variant, dir);
+                Ok(PoseidonArtifacts {
+                    variant,
+                    abi,
+                    bytecode,
+                })
+            }
+            (true, false) | (false, true) => Err(eyre::Report::msg(format!(
+                "{:?} needs both {:?} and {:?}!",
+                dir, abi_path, bytecode_path
+            ))),
+        }
+    }
+}
+
+fn validate_abi(abi: &str) -> Result<()> {
+    let abi = serde_json::from_str::<Abi>(abi)?;
+    if !abi.functions.contains_key("poseidon") {
+        return Err(eyre::Report::msg("no `poseidon` function"));
+    }
+    Ok(())
+}
+
+/// The proving key in `dir`, if it has one.
+pub fn params(dir: &Path) -> Result<Option<PathBuf>> {
+    let params = dir.join(PARAMS_FILE_NAME);
+    if !params.is_file() {
+        return Ok(None);
+    }
+    let mut magic = [0u8; 4];
+    std::fs::File::open(&params)?
+        .read_exact(&mut magic)
+        .ok()
+        .filter(|_| &magic == ZKEY_MAGIC)
+        .ok_or(eyre::Report::msg(format!(
+            "{:?} is not a snarkjs proving key!",
+            params
+        )))?;
+    Ok(Some(params))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_artifacts_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let variant = PoseidonVariant::Poseidon4;
+        assert_eq!(
+            PoseidonArtifacts::load(Some(dir.path()), variant).unwrap(),
+            PoseidonArtifacts::bundled(variant)
+        );
+        assert_eq!(params(dir.path()).unwrap(), None);
+
+        // Same contract, told apart by the formatting of its files.
+        let abi: serde_json::Value = serde_json::from_str(variant.abi()).unwrap();
+        let abi = serde_json::to_string(&abi).unwrap();
+        let bytecode = variant.bytecode().trim().trim_start_matches("0x");
+        std::fs::write(dir.path().join("poseidon4.abi"), &abi).unwrap();
+        std::fs::write(dir.path().join("poseidon4.evm"), format!("{}\n", bytecode)).unwrap();
+        let zkey = dir.path().join(PARAMS_FILE_NAME);
+        std::fs::write(&zkey, b"zkey\x01\x00\x00\x00").unwrap();
+
+        let loaded = PoseidonArtifacts::load(Some(dir.path()), variant).unwrap();
+        assert_ne!(loaded, PoseidonArtifacts::bundled(variant));
+        assert_eq!(loaded.abi, abi);
+        assert_eq!(loaded.bytecode, bytecode);
+        assert_eq!(params(dir.path()).unwrap(), Some(zkey.clone()));
+
+        // Artifacts which don't parse are refused rather than deployed.
+        std::fs::write(dir.path().join("poseidon4.evm"), "0xnothex").unwrap();
+        assert!(PoseidonArtifacts::load(Some(dir.path()), variant).is_err());
+        std::fs::write(dir.path().join("poseidon4.abi"), "[]").unwrap();
+        std::fs::write(dir.path().join("poseidon4.evm"), bytecode).unwrap();
+        let err = PoseidonArtifacts::load(Some(dir.path()), variant)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("poseidon"), "{}", err);
+        std::fs::remove_file(dir.path().join("poseidon4.abi")).unwrap();
+        assert!(PoseidonArtifacts::load(Some(dir.path()), variant).is_err());
+        std::fs::write(&zkey, "{}").unwrap();
+        assert!(params(dir.path()).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use axum::body::Body;
+use axum::http::{header, HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::sync::Arc;
+
+use crate::apis::ApiError;
+
+// Compares every byte whatever the first mismatch, so that response times
+// don't leak how much of a guessed token is right.
+fn token_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn bearer_token(request: &Request<Body>) -> Option<&str> {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+/// Middleware answering 401 unless the request carries
+/// `Authorization: Bearer <token>`. Without a token everything goes through.
+pub async fn authenticate(
+    token: Option<Arc<String>>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let token = match token {
+        Some(token) => token,
+        None => return next.run(request).await,
+    };
+    match bearer_token(&request) {
+        Some(given) if token_matches(&token, given) => next.run(request).await,
+        given => {
+            tracing::warn!(
+                "Rejecting {} request to {}",
+                if given.is_some() {
+                    "wrongly authenticated"
+                } else {
+                    "unauthenticated"
+                },
+                request.uri().path()
+            );
+            (
+                [(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"))],
+                ApiError::Unauthorized("A valid API token is required!".to_string()),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use tower::ServiceExt;
+
+    fn app(token: Option<&str>) -> Router {
+        let token = token.map(|t| Arc::new(t.to_string()));
+        Router::new().route(
+            "/send",
+            get(|| async { "proof" }).layer(middleware::from_fn(move |request, next| {
+                authenticate(token.clone(), request, next)
+            })),
+        )
+    }
+
+    fn request(authorization: Option<&str>) -> Request<Body> {
+        let mut request = Request::get("/send");
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let protected = app(Some("s3cret"));
+        for authorization in [None, Some("Bearer wrong!"), Some("s3cret"), Some("Bearer ")] {
+            let response = protected
+                .clone()
+                .oneshot(request(authorization))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        }
+        let response = protected
+            .oneshot(request(Some("Bearer s3cret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Without a configured token the endpoint stays open.
+        let response = app(None).oneshot(request(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_token_matches() {
+        assert!(token_matches("s3cret", "s3cret"));
+        assert!(!token_matches("s3cret", "s3cre"));
+        assert!(!token_matches("s3cret", "s3creT"));
+        assert!(!token_matches("", "s3cret"));
+    }
+}
@@ -0,0 +1,153 @@
+use ethers::types::U256;
+use eyre::Result;
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::commitment::commitment;
+use crate::fp::Fp;
+use crate::keys::{PrivateKey, PublicKey};
+use crate::proof::{prove, Proof};
+use crate::tree::SparseMerkleTree;
+
+/// Latencies of the proofs generated by `bench_proof`.
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    latencies: Vec<Duration>,
+}
+
+impl BenchReport {
+    pub fn new(mut latencies: Vec<Duration>) -> Self {
+        latencies.sort();
+        BenchReport { latencies }
+    }
+
+    pub fn min(&self) -> Duration {
+        self.latencies.first().copied().unwrap_or_default()
+    }
+
+    pub fn max(&self) -> Duration {
+        self.latencies.last().copied().unwrap_or_default()
+    }
+
+    pub fn median(&self) -> Duration {
+        let n = self.latencies.len();
+        match n {
+            0 => Duration::ZERO,
+            _ if n % 2 == 1 => self.latencies[n / 2],
+            _ => (self.latencies[n / 2 - 1] + self.latencies[n / 2]) / 2,
+        }
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.latencies.len() {
+            0 => Duration::ZERO,
+            n => self.latencies.iter().sum::<Duration>() / n as u32,
+        }
+    }
+
+    /// Proofs per second, one after the other.
+    pub fn throughput(&self) -> f64 {
+        let total = self.latencies.iter().sum::<Duration>().as_secs_f64();
+        if total > 0.0 {
+            self.latencies.len() as f64 / total
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Proofs: {}", self.latencies.len())?;
+        writeln!(f, "Min: {:.3}s", self.min().as_secs_f64())?;
+        writeln!(f, "Median: {:.3}s", self.median().as_secs_f64())?;
+        writeln!(f, "Max: {:.3}s", self.max().as_secs_f64())?;
+        writeln!(f, "Mean: {:.3}s", self.mean().as_secs_f64())?;
+        write!(f, "Throughput: {:.3} proofs/s", self.throughput())
+    }
+}
+
+// Withdraws 40 out of a coin of 100 sitting alone in a fresh tree, as the
+// wallet would do for a coin of its own.
+fn synthetic_withdraw<P: AsRef<Path>>(params: P, index: u32) -> Result<Proof> {
+    let mut rng = rand::thread_rng();
+    let priv_key = PrivateKey::generate(&mut rng);
+    let (ephemeral, stealth_pub_key) = PublicKey::from(priv_key).derive(&mut rng);
+    let stealth_priv_key = priv_key.derive(ephemeral);
+    let token = U256::from(1);
+    let amount = U256::from(100);
+    let withdraw_amount = U256::from(40);
+    let remaining_amount = amount - withdraw_amount;
+
+    let mut tree = SparseMerkleTree::new(16);
+    tree.set(
+        index as u64,
+        Fp::try_from(commitment(amount, &stealth_pub_key, token)?)?,
+    );
+    let (_, change_pub_key) = PublicKey::from(priv_key).derive(&mut rng);
+
+    prove(
+        params,
+        index,
+        token,
+        amount,
+        withdraw_amount,
+        remaining_amount,
+        PublicKey::null(),
+        change_pub_key,
+        stealth_priv_key.secret,
+        tree.get(index as u64).proof.try_into().map_err(|_| {
+            eyre::Report::msg("The merkle proof doesn't match the circuit's depth!")
+        })?,
+    )
+}
+
+/// Generates `iterations` withdraw proofs over synthetic inputs, without
+/// touching the chain. Returns their latencies and the last proof.
+pub fn bench_proof<P: AsRef<Path>>(params: P, iterations: usize) -> Result<(BenchReport, Proof)> {
+    if iterations == 0 {
+        return Err(eyre::Report::msg("At least one iteration is needed!"));
+    }
+    let mut latencies = Vec::with_capacity(iterations);
+    let mut proof = Proof::default();
+    for i in 0..iterations {
+        let started = Instant::now();
+        proof = synthetic_withdraw(params.as_ref(), i as u32)?;
+        latencies.push(started.elapsed());
+        tracing::info!("Proof {}/{} took {:?}", i + 1, iterations, latencies[i]);
+    }
+    Ok((BenchReport::new(latencies), proof))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paths::Paths;
+    use crate::proof::verify;
+
+    #[test]
+    fn test_bench_report() {
+        let report = BenchReport::new([4, 1, 3, 2].into_iter().map(Duration::from_secs).collect());
+        assert_eq!(report.min(), Duration::from_secs(1));
+        assert_eq!(report.max(), Duration::from_secs(4));
+        assert_eq!(report.median(), Duration::from_millis(2500));
+        assert_eq!(report.mean(), Duration::from_millis(2500));
+        assert_eq!(report.throughput(), 0.4);
+
+        let report = BenchReport::new(vec![Duration::from_secs(3)]);
+        assert_eq!(report.median(), Duration::from_secs(3));
+        assert_eq!(BenchReport::new(vec![]).throughput(), 0.0);
+    }
+
+    #[test]
+    fn test_bench_proof() {
+        let params = Paths::default().params;
+        let (report, proof) = bench_proof(&params, 1).unwrap();
+        assert!(report.min() > Duration::ZERO);
+        assert_eq!(report.min(), report.max());
+        assert!(verify(&params, &proof).unwrap());
+
+        assert!(bench_proof(&params, 0).is_err());
+    }
+}
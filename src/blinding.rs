@@ -0,0 +1,111 @@
+use ethers::types::U256;
+use eyre::Result;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::sync::{Arc, Mutex};
+
+use crate::fp::Fp;
+use crate::keys::{EphemeralKey, PublicKey};
+
+/// Source of the randomness blinding the outputs of a spend.
+///
+/// Every output goes to a one-time stealth key `pk + s·G`, where `r·G` is a
+/// fresh ephemeral key published along the coin and `s = hash(r·pk)` the
+/// secret it shares with the receiver. The commitment `hash(stealth, amount,
+/// token)` hides the amount behind the stealth key, while the hint published
+/// next to it is either the plain amount, as for deposits and the outputs
+/// this wallet builds, or the amount obfuscated as `amount + s'` in the
+/// field (see `deobfuscate`), `s'` being the secret shared with the stealth
+/// key itself. Knowing `r` reveals all of it, so outside of tests it must
+/// never be predictable.
+#[derive(Clone, Debug, Default)]
+pub enum Blinding {
+    /// The thread RNG, seeded from the OS.
+    #[default]
+    Secure,
+    /// Reproducible outputs, only offered in test mode.
+    Seeded(Arc<Mutex<StdRng>>),
+}
+
+impl Blinding {
+    pub fn seeded(seed: u64) -> Self {
+        Blinding::Seeded(Arc::new(Mutex::new(StdRng::seed_from_u64(seed))))
+    }
+
+    /// Draws the ephemeral key of an output to `pub_key`, see
+    /// `PublicKey::derive_with_view_tag`.
+    pub fn derive(&self, pub_key: &PublicKey) -> (EphemeralKey, PublicKey, u8) {
+        match self {
+            Blinding::Secure => pub_key.derive_with_view_tag(&mut rand::thread_rng()),
+            Blinding::Seeded(rng) => pub_key.derive_with_view_tag(&mut *rng.lock().unwrap()),
+        }
+    }
+}
+
+pub fn deobfuscate(hint: U256, shared_secret: Fp) -> Result<U256> {
+    Ok((Fp::try_from(hint)? - shared_secret).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apis::detect;
+    use crate::commitment::commitment;
+    use crate::keys::PrivateKey;
+    use bindings::owshen::{Point as OwshenPoint, SentFilter};
+
+    // Blinds a hint with the shared secret of its coin, as other wallets do.
+    fn obfuscate(value: U256, shared_secret: Fp) -> Result<U256> {
+        Ok((Fp::from_u256_checked(value)? + shared_secret).into())
+    }
+
+    #[test]
+    fn test_seeded_blinding() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let pub_key = PublicKey::from(priv_key);
+        let amount = U256::from(1234);
+        let token = U256::from(7);
+
+        let output = |blinding: &Blinding| {
+            let (ephemeral, stealth_pub, view_tag) = blinding.derive(&pub_key);
+            let shared_secret = priv_key.derive(ephemeral).shared_secret(ephemeral);
+            (
+                ephemeral,
+                stealth_pub,
+                view_tag,
+                obfuscate(amount, shared_secret).unwrap(),
+                obfuscate(token, shared_secret).unwrap(),
+            )
+        };
+        let (ephemeral, stealth_pub, view_tag, hint_amount, hint_token) =
+            output(&Blinding::seeded(42));
+        let again = output(&Blinding::seeded(42));
+        assert_eq!(again.0.point, ephemeral.point);
+        assert_eq!(again.1, stealth_pub);
+        assert_eq!(
+            (again.2, again.3, again.4),
+            (view_tag, hint_amount, hint_token)
+        );
+        assert_ne!(output(&Blinding::seeded(43)).3, hint_amount);
+        assert_ne!(output(&Blinding::Secure).3, hint_amount);
+        assert_ne!(hint_amount, amount);
+
+        let sent = SentFilter {
+            ephemeral: OwshenPoint {
+                x: ephemeral.point.x.into(),
+                y: ephemeral.point.y.into(),
+            },
+            index: U256::from(5),
+            timestamp: U256::zero(),
+            hint_amount,
+            hint_token_address: hint_token,
+            commitment: commitment(amount, &stealth_pub, token).unwrap(),
+            view_tag,
+        };
+        let coin = detect(&priv_key.into(), &sent).unwrap().unwrap();
+        assert_eq!(U256::from(coin.amount), amount);
+
+        let shared_secret = priv_key.derive(ephemeral).shared_secret(ephemeral);
+        assert_eq!(deobfuscate(hint_amount, shared_secret).unwrap(), amount);
+    }
+}
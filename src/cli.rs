@@ -0,0 +1,326 @@
+use axum::extract::Query;
+use bindings::owshen::Owshen;
+use ethers::prelude::*;
+use eyre::Result;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::{broadcast, RwLock};
+use tokio::time::Duration;
+
+use crate::apis;
+use crate::export::{self, ExportFormat};
+use crate::keys::PrivateKey;
+use crate::paths::Paths;
+use crate::proof;
+use crate::relayer::{self, RelayRequest};
+use crate::rpc::RpcProvider;
+use crate::transaction::{
+    broadcast_once, ensure_gas, resolve_from, resolve_tx_type, send_call, wait_for_confirmation,
+    withdraw_call, GasOverrides, TxType, BROADCAST_ATTEMPTS, BROADCAST_RETRY_DELAY,
+};
+use crate::{
+    Config, Context, ExportOpt, GetConfirmationResponse, GetSendRequest, GetWithdrawRequest,
+    NullifierOpt, SendOpt, SharedContext, TokenInfo, WatchOpt, WithdrawOpt,
+};
+
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(120);
+
+// Scans the chain once, the CLI has no background sync to rely on. Amounts
+// in whole `tokens` take their decimals, as they do on the server.
+async fn sync_context(
+    provider: Arc<RpcProvider>,
+    config: &Config,
+    priv_key: PrivateKey,
+    tokens: &[TokenInfo],
+) -> Result<SharedContext> {
+    let mut context = Context::new(config.owshen_contract_address, false);
+    context.set_token_decimals(tokens);
+    let context = Arc::new(RwLock::new(context));
+    let contract = Contract::new(
+        config.owshen_contract_address,
+        config.owshen_contract_abi.clone(),
+        provider,
+    );
+    let (coin_sender, _) = broadcast::channel(1);
+    apis::sync_coins(
+        context.clone(),
+        contract,
+        priv_key.into(),
+        coin_sender,
+        apis::DEFAULT_SCAN_CHUNK_SIZE,
+        apis::DEFAULT_SCAN_CONCURRENCY,
+    )
+    .await?;
+    Ok(context)
+}
+
+async fn ensure_coin(context: &SharedContext, index: U256) -> Result<()> {
+    if !context.read().await.coins.iter().any(|c| c.index == index) {
+        return Err(eyre::Report::msg(format!(
+            "No coin with index {} found!",
+            index
+        )));
+    }
+    Ok(())
+}
+
+// Transactions are sent from `from`, or the first account unlocked on the
+// node, and awaited until mined, or only printed when `dry_run` is set. A
+// failed broadcast is retried without risking a second inclusion, see
+// `broadcast_once`.
+async fn broadcast(
+    call: ContractCall<RpcProvider, ()>,
+    provider: &RpcProvider,
+    dry_run: bool,
+    tx_type: Option<TxType>,
+    gas: &GasOverrides,
+    from: Option<H160>,
+) -> Result<Option<GetConfirmationResponse>> {
+    gas.validate()?;
+    if dry_run {
+        let calldata = call
+            .calldata()
+            .ok_or(eyre::Report::msg("Cannot encode the transaction!"))?;
+        println!("Calldata: {}", calldata);
+        return Ok(None);
+    }
+
+    let from = resolve_from(provider, from).await?;
+    let tx_type = resolve_tx_type(provider, tx_type).await?;
+    let mut call = with_tx_type!(call, tx_type).from(from);
+    gas.apply(&mut call.tx)?;
+    ensure_gas(provider, &call, from).await?;
+    let tx_hash = broadcast_once(
+        provider,
+        call.tx,
+        BROADCAST_ATTEMPTS,
+        BROADCAST_RETRY_DELAY,
+        |tx| async move {
+            let pending = provider.send_transaction(tx, None).await?;
+            Ok::<_, ProviderError>(pending.tx_hash())
+        },
+    )
+    .await?;
+    println!("Transaction hash: {:?}", tx_hash);
+    confirm(provider, tx_hash).await.map(Some)
+}
+
+// The relayer pays for the gas, the wallet only follows the transaction.
+async fn relay(
+    url: &str,
+    request: RelayRequest,
+    provider: &RpcProvider,
+) -> Result<Option<GetConfirmationResponse>> {
+    let tx_hash = relayer::submit(url, &request).await?;
+    println!("Transaction hash: {:?} (relayed)", tx_hash);
+    confirm(provider, tx_hash).await.map(Some)
+}
+
+async fn confirm(provider: &RpcProvider, tx_hash: TxHash) -> Result<GetConfirmationResponse> {
+    let confirmation = wait_for_confirmation(provider, tx_hash, CONFIRMATION_TIMEOUT)
+        .await?
+        .ok_or(eyre::Report::msg(format!(
+            "Transaction {:?} was not mined in time!",
+            tx_hash
+        )))?;
+    if !confirmation.success {
+        return Err(eyre::Report::msg(format!(
+            "Transaction reverted in block {}: {}",
+            confirmation.block_number,
+            confirmation
+                .revert_reason
+                .unwrap_or("unknown reason".to_string())
+        )));
+    }
+    println!("Confirmed in block {}", confirmation.block_number);
+    Ok(confirmation)
+}
+
+pub async fn withdraw(
+    provider: Arc<RpcProvider>,
+    config: &Config,
+    priv_key: PrivateKey,
+    tokens: &[TokenInfo],
+    paths: &Paths,
+    opt: &WithdrawOpt,
+) -> Result<Option<GetConfirmationResponse>> {
+    let params = opt.params.clone().unwrap_or(paths.params.clone());
+    proof::ensure_params(&params)?;
+    let context = sync_context(provider.clone(), config, priv_key, tokens).await?;
+    let index = U256::from(opt.index);
+    ensure_coin(&context, index).await?;
+
+    println!("Generating proof...");
+    let withdrawal = apis::withdraw(
+        Query(GetWithdrawRequest {
+            decimals: opt.decimals,
+            relayer_fee: opt.relayer_fee.clone(),
+            ..GetWithdrawRequest::new(index, priv_key.address(), opt.amount.clone(), opt.to)
+        }),
+        context.clone(),
+        context,
+        params,
+        false,
+    )
+    .await?;
+
+    let owshen = Owshen::new(config.owshen_contract_address, provider.clone());
+    if let (Some(url), false) = (&opt.relayer, opt.dry_run) {
+        let request = RelayRequest::withdraw(&owshen, &withdrawal)?;
+        return relay(url, request, &provider).await;
+    }
+    broadcast(
+        withdraw_call(&owshen, &withdrawal),
+        &provider,
+        opt.dry_run,
+        opt.tx_type,
+        &opt.gas,
+        opt.from,
+    )
+    .await
+}
+
+pub async fn send(
+    provider: Arc<RpcProvider>,
+    config: &Config,
+    priv_key: PrivateKey,
+    tokens: &[TokenInfo],
+    paths: &Paths,
+    opt: &SendOpt,
+) -> Result<Option<GetConfirmationResponse>> {
+    let params = opt.params.clone().unwrap_or(paths.params.clone());
+    proof::ensure_params(&params)?;
+    let context = sync_context(provider.clone(), config, priv_key, tokens).await?;
+    let index = U256::from(opt.index);
+    ensure_coin(&context, index).await?;
+
+    println!("Generating proof...");
+    let send = apis::send(
+        Query(GetSendRequest {
+            receiver_address: opt.receiver.to_string(),
+            decimals: opt.decimals,
+            ..GetSendRequest::new(index, priv_key.address(), opt.amount.clone())
+        }),
+        context.clone(),
+        context,
+        params,
+    )
+    .await?;
+
+    let owshen = Owshen::new(config.owshen_contract_address, provider.clone());
+    broadcast(
+        send_call(&owshen, &send),
+        &provider,
+        opt.dry_run,
+        opt.tx_type,
+        &opt.gas,
+        opt.from,
+    )
+    .await
+}
+
+/// Derives the nullifier of a coin of the wallet, spent or not, and with
+/// `check` whether it is spent on-chain.
+pub async fn nullifier(
+    provider: Arc<RpcProvider>,
+    config: &Config,
+    priv_key: PrivateKey,
+    opt: &NullifierOpt,
+) -> Result<(U256, Option<bool>)> {
+    let contract = Contract::new(
+        config.owshen_contract_address,
+        config.owshen_contract_abi.clone(),
+        provider.clone(),
+    );
+    let index = U256::from(opt.index);
+    let coin = apis::find_coin(
+        &contract,
+        &priv_key.into(),
+        index,
+        apis::DEFAULT_SCAN_CHUNK_SIZE,
+        apis::DEFAULT_SCAN_CONCURRENCY,
+    )
+    .await?
+    .ok_or(eyre::Report::msg(format!(
+        "No coin with index {} found!",
+        index
+    )))?;
+    let (_, nullifier) = coin.spending_key()?;
+    println!("Nullifier: {:?}", H256::from_uint(&nullifier));
+
+    if !opt.check {
+        return Ok((nullifier, None));
+    }
+    let owshen = Owshen::new(config.owshen_contract_address, provider);
+    let spent = owshen.is_spent(nullifier).call().await?;
+    println!("{}", if spent { "Spent" } else { "Unspent" });
+    Ok((nullifier, Some(spent)))
+}
+
+/// Writes the coins of the wallet, and with `history` the spends recorded in
+/// the state file of the wallet, to `out` or the standard output.
+pub async fn export(
+    provider: Arc<RpcProvider>,
+    config: &Config,
+    priv_key: PrivateKey,
+    tokens: &[TokenInfo],
+    state: &Path,
+    opt: &ExportOpt,
+) -> Result<usize> {
+    let context = sync_context(provider, config, priv_key, tokens).await?;
+    if opt.history && state.exists() {
+        let mut saved = Context::new(config.owshen_contract_address, false);
+        saved.load(state, &priv_key.into())?;
+        context.write().await.history = saved.history;
+    }
+    let rows = export::rows(&*context.read().await, tokens, opt.token, opt.history);
+    let body = match opt.format {
+        ExportFormat::Csv => export::to_csv(&rows)?,
+    };
+    match &opt.out {
+        Some(out) => {
+            std::fs::write(out, body)?;
+            println!("Exported {} rows to {:?}", rows.len(), out);
+        }
+        None => std::io::stdout().write_all(&body)?,
+    }
+    Ok(rows.len())
+}
+
+/// Lists the coins paid to the watched address itself, the only ones it
+/// gives away. Nothing can be spent: there is no key to spend with.
+pub async fn watch(provider: Arc<RpcProvider>, config: &Config, opt: &WatchOpt) -> Result<usize> {
+    let contract = Contract::new(
+        config.owshen_contract_address,
+        config.owshen_contract_abi.clone(),
+        provider,
+    );
+    println!("Watching {}, read-only", opt.address);
+    let coins = apis::watch_chain(
+        &contract,
+        &opt.address,
+        apis::DEFAULT_SCAN_CHUNK_SIZE,
+        apis::DEFAULT_SCAN_CONCURRENCY,
+    )
+    .await?;
+    for coin in coins.iter() {
+        println!(
+            "Coin {}: {} of {:?}, commitment {:#x}, block {}, transaction {}",
+            coin.index,
+            coin.amount,
+            coin.uint_token,
+            coin.commitment,
+            coin.block_number
+                .map_or("unknown".to_string(), |block| block.to_string()),
+            coin.tx_hash
+                .map_or("unknown".to_string(), |tx_hash| format!("{:?}", tx_hash))
+        );
+    }
+    println!(
+        "{} coin(s) detectable, coins sent to stealth addresses of {} aren't",
+        coins.len(),
+        opt.address
+    );
+    Ok(coins.len())
+}
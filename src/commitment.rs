@@ -0,0 +1,65 @@
+//! Pure recomputation of the values stored with every coin, so that they can be
+//! checked independently of the chain scan.
+
+use ethers::types::U256;
+use eyre::Result;
+
+use crate::fp::Fp;
+use crate::hash::hash4;
+use crate::keys::{PrivateKey, PublicKey};
+
+/// Commitment of a coin, as stored in the Owshen merkle tree:
+///
+/// `poseidon4(pub_key.x, pub_key.y, amount, token)`
+///
+/// where `pub_key` is the stealth public key of the coin and `token` the token
+/// address, left-padded to 256 bits. Fails for an `amount` or `token` not
+/// below the field modulus rather than committing to a wrapped value.
+pub fn commitment(amount: U256, pub_key: &PublicKey, token: U256) -> Result<U256> {
+    Ok(hash4([
+        pub_key.point.x,
+        pub_key.point.y,
+        Fp::from_u256_checked(amount)?,
+        Fp::from_u256_checked(token)?,
+    ])
+    .into())
+}
+
+/// Nullifier revealed when spending the coin at `index`:
+///
+/// `poseidon4(priv_key.secret, index, 0, 0)`
+///
+/// where `priv_key` is the stealth private key of the coin. Only the lowest 32
+/// bits of `index` are used, like in the circuit.
+pub fn nullifier(priv_key: &PrivateKey, index: U256) -> U256 {
+    priv_key.nullifier(index.low_u32()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ff::PrimeField;
+
+    #[test]
+    fn test_commitment_input_order() {
+        let pub_key = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        let amount = U256::from(10);
+        let token = U256::from(20);
+        let expected: U256 =
+            hash4([pub_key.point.x, pub_key.point.y, Fp::from(10), Fp::from(20)]).into();
+        assert_eq!(commitment(amount, &pub_key, token).unwrap(), expected);
+        assert_ne!(
+            commitment(amount, &pub_key, token).unwrap(),
+            commitment(token, &pub_key, amount).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_commitment_out_of_field() {
+        let pub_key = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        let modulus = U256::from_str_radix(&Fp::MODULUS[2..], 16).unwrap();
+        assert!(commitment(modulus - 1, &pub_key, U256::from(20)).is_ok());
+        assert!(commitment(modulus, &pub_key, U256::from(20)).is_err());
+        assert!(commitment(U256::from(10), &pub_key, modulus + 1).is_err());
+    }
+}
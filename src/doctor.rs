@@ -0,0 +1,339 @@
+use colored::Colorize;
+use ethers::providers::Middleware;
+use ethers::types::H160;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task;
+
+use crate::apis;
+use crate::bench;
+use crate::hash::check_poseidon;
+use crate::proof;
+use crate::rpc::{self, RpcProvider};
+use crate::{code_hash, empty_root_mismatch, missing_from_abi, Config, Wallet};
+
+/// One line of the checklist printed by `owshen doctor`.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    /// What was found when the check passed, what went wrong otherwise.
+    pub result: Result<String, String>,
+}
+
+impl Check {
+    fn new<S: Into<String>>(name: S, result: Result<String, String>) -> Self {
+        Check {
+            name: name.into(),
+            result,
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.result {
+            Ok(found) => write!(f, "{} {}: {}", "✓".bright_green(), self.name, found),
+            Err(problem) => write!(f, "{} {}: {}", "✗".bright_red(), self.name, problem),
+        }
+    }
+}
+
+fn load_config(path: &Path) -> Result<Config, String> {
+    let config =
+        std::fs::read_to_string(path).map_err(|e| format!("cannot read {:?}: {}", path, e))?;
+    serde_json::from_str(&config).map_err(|e| format!("cannot parse {:?}: {}", path, e))
+}
+
+async fn connect(endpoint: &str, timeout: Duration) -> Result<(RpcProvider, String), String> {
+    let provider = rpc::connect_with_timeout(endpoint, timeout)
+        .await
+        .map_err(|e| format!("cannot connect to {}: {}", endpoint, e))?;
+    // Connecting over HTTP doesn't reach the node yet.
+    let head = provider
+        .get_block_number()
+        .await
+        .map_err(|e| format!("{} doesn't answer: {}", endpoint, e))?;
+    Ok((provider, format!("{} is at block {}", endpoint, head)))
+}
+
+async fn has_code(provider: &RpcProvider, address: H160) -> Result<String, String> {
+    match provider.get_code(address, None).await {
+        Ok(code) if code.is_empty() => Err(format!("no contract is deployed at {:?}", address)),
+        Ok(code) => Ok(format!("{} bytes of code at {:?}", code.len(), address)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// Same checks as `check_contract`, reported rather than enforced.
+async fn check_owshen(provider: &RpcProvider, config: &Config) -> Result<String, String> {
+    let found = has_code(provider, config.owshen_contract_address).await?;
+    let missing = missing_from_abi(&config.owshen_contract_abi);
+    if !missing.is_empty() {
+        return Err(format!(
+            "the stored Owshen ABI lacks {}",
+            missing.join(", ")
+        ));
+    }
+    if let Some(expected) = config.owshen_code_hash {
+        let actual = code_hash(provider, config.owshen_contract_address)
+            .await
+            .map_err(|e| e.to_string())?;
+        if actual != expected {
+            return Err(format!(
+                "the code at {:?} hashes to {:?} instead of {:?}",
+                config.owshen_contract_address, actual, expected
+            ));
+        }
+    }
+    if let Some(problem) = empty_root_mismatch(provider, config.owshen_contract_address)
+        .await
+        .map_err(|e| e.to_string())?
+    {
+        return Err(problem);
+    }
+    Ok(found)
+}
+
+async fn check_params(params: PathBuf) -> Result<String, String> {
+    proof::ensure_params(&params).map_err(|e| e.to_string())?;
+    let vkey = task::spawn_blocking({
+        let params = params.clone();
+        move || proof::export_verification_key(params)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
+    let vkey: serde_json::Value =
+        serde_json::from_reader(std::fs::File::open(vkey.path()).map_err(|e| e.to_string())?)
+            .map_err(|e| format!("unreadable verification key: {}", e))?;
+    let hash = apis::build_info(&params)
+        .params_hash
+        .ok_or(format!("cannot hash {:?}", params))?;
+    Ok(format!(
+        "{:?} ({} public inputs, hash {:?})",
+        params, vkey["nPublic"], hash
+    ))
+}
+
+async fn check_proof(params: PathBuf) -> Result<String, String> {
+    let started = Instant::now();
+    let verified = task::spawn_blocking(move || {
+        let (_, proof) = bench::bench_proof(&params, 1)?;
+        proof::verify(&params, &proof)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
+    if verified {
+        Ok(format!(
+            "generated and verified in {:.1}s",
+            started.elapsed().as_secs_f64()
+        ))
+    } else {
+        Err("the generated proof doesn't verify".to_string())
+    }
+}
+
+/// Goes through everything a spend needs, from the wallet file to a proof
+/// verified against the proving key. A failure doesn't stop the checks
+/// which don't depend on it, those which do are reported as failed.
+pub async fn doctor(
+    wallet_path: &Path,
+    config_path: &Path,
+    params: PathBuf,
+    rpc_timeout: Duration,
+) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let wallet = match Wallet::load(wallet_path) {
+        Ok(Some(wallet)) => Ok(wallet),
+        Ok(None) => Err(format!("not initialized at {:?}", wallet_path)),
+        Err(e) => Err(format!("cannot load {:?}: {}", wallet_path, e)),
+    };
+    checks.push(Check::new(
+        "Wallet",
+        wallet
+            .as_ref()
+            .map(|wallet| {
+                format!(
+                    "{} account(s) in {:?}",
+                    wallet.accounts().len(),
+                    wallet_path
+                )
+            })
+            .map_err(Clone::clone),
+    ));
+
+    let config = load_config(config_path);
+    checks.push(Check::new(
+        "Config",
+        config
+            .as_ref()
+            .map(|config| format!("{:?} in {:?}", config.name, config_path))
+            .map_err(Clone::clone),
+    ));
+
+    let node = match &config {
+        Ok(config) => connect(&config.endpoint, rpc_timeout).await,
+        Err(_) => Err("skipped, the config didn't load".to_string()),
+    };
+    checks.push(Check::new(
+        "Node",
+        node.as_ref()
+            .map(|(_, found)| found.clone())
+            .map_err(Clone::clone),
+    ));
+
+    match (&node, &config) {
+        (Ok((provider, _)), Ok(config)) => {
+            let chain_id = match provider.get_chainid().await {
+                Ok(actual) => match wallet.as_ref().ok().and_then(|wallet| wallet.chain_id) {
+                    Some(expected) if expected != actual => Err(format!(
+                        "the wallet was initialized on chain {} but the node is on chain {}",
+                        expected, actual
+                    )),
+                    Some(_) => Ok(actual.to_string()),
+                    None if wallet.is_ok() => {
+                        Ok(format!("{}, the wallet doesn't record one", actual))
+                    }
+                    None => Err(format!("{}, skipped, the wallet didn't load", actual)),
+                },
+                Err(e) => Err(e.to_string()),
+            };
+            checks.push(Check::new("Chain id", chain_id));
+            checks.push(Check::new(
+                "Owshen contract",
+                check_owshen(provider, config).await,
+            ));
+            if let Some(poseidon) = config.poseidon_contract_address {
+                checks.push(Check::new(
+                    "Poseidon contract",
+                    check_poseidon(
+                        Arc::new(provider.clone()),
+                        poseidon,
+                        config.poseidon_variant,
+                    )
+                    .await
+                    .map(|_| format!("{} at {:?}", config.poseidon_variant, poseidon))
+                    .map_err(|e| e.to_string()),
+                ));
+            }
+            checks.push(Check::new(
+                "DIVE contract",
+                has_code(provider, config.dive_contract_address).await,
+            ));
+            if let Ok(wallet) = &wallet {
+                for token in wallet.token_contracts.iter() {
+                    checks.push(Check::new(
+                        format!("{} contract", token.symbol),
+                        has_code(provider, token.token_address).await,
+                    ));
+                }
+            }
+        }
+        _ => {
+            for name in ["Chain id", "Owshen contract", "DIVE contract"] {
+                checks.push(Check::new(
+                    name,
+                    Err("skipped, the node is unreachable".to_string()),
+                ));
+            }
+        }
+    }
+
+    let params_check = check_params(params.clone()).await;
+    let params_ok = params_check.is_ok();
+    checks.push(Check::new("Proving key", params_check));
+    checks.push(Check::new(
+        "Sample proof",
+        if params_ok {
+            check_proof(params).await
+        } else {
+            Err("skipped, the proving key is unusable".to_string())
+        },
+    ));
+
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::artifacts::PoseidonArtifacts;
+    use crate::hash::PoseidonVariant;
+    use crate::paths::Paths;
+    use crate::transaction::TxType;
+    use crate::{initialize_config, initialize_wallet, ExistingContracts};
+    use ethers::utils::Ganache;
+
+    #[tokio::test]
+    async fn test_doctor() {
+        let port = 8561u16;
+        let endpoint = format!("http://localhost:{}", port);
+        let _ganache = Ganache::new().port(port).spawn();
+
+        let dir = tempfile::tempdir().unwrap();
+        let wallet_path = dir.path().join("wallet.json");
+        let config_path = dir.path().join("config.json");
+        let wallet = initialize_wallet(
+            endpoint.clone(),
+            None,
+            true,
+            Some(TxType::Legacy),
+            1,
+            None,
+            rpc::DEFAULT_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        std::fs::write(&wallet_path, serde_json::to_string(&wallet).unwrap()).unwrap();
+        // As `owshen init --test` sets it up.
+        let config = initialize_config(
+            endpoint,
+            "doctor".to_string(),
+            true,
+            Some(TxType::Legacy),
+            &PoseidonArtifacts::bundled(PoseidonVariant::default()),
+            ExistingContracts::default(),
+            None,
+            rpc::DEFAULT_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        std::fs::write(&config_path, serde_json::to_string(&config).unwrap()).unwrap();
+
+        let params = Paths::default().params;
+        let checks = doctor(&wallet_path, &config_path, params, rpc::DEFAULT_TIMEOUT).await;
+        for check in checks.iter() {
+            assert!(check.passed(), "{}", check);
+        }
+        let names = checks.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        assert!(names.contains(&"Sample proof"));
+        assert!(names.contains(&"Poseidon contract"));
+        assert!(names.contains(&"WETH contract"));
+
+        // Dependent checks fail along with what they rely on.
+        let checks = doctor(
+            &dir.path().join("missing.json"),
+            &config_path,
+            dir.path().join("missing.zkey"),
+            rpc::DEFAULT_TIMEOUT,
+        )
+        .await;
+        let failed = checks
+            .iter()
+            .filter(|c| !c.passed())
+            .map(|c| c.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            failed,
+            vec!["Wallet", "Chain id", "Proving key", "Sample proof"]
+        );
+    }
+}
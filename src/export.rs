@@ -0,0 +1,206 @@
+use ethers::types::H160;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::amount::{format_amount, Amount};
+use crate::{Context, HistoryKind, TokenInfo, TxStatus};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = eyre::Report;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(eyre::Report::msg("Expected `csv`!")),
+        }
+    }
+}
+
+/// One line of an export, either a coin or a history entry. Fields which
+/// don't apply to the kind of record are left empty.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportRow {
+    /// `coin`, `send` or `withdraw`.
+    pub record: String,
+    pub index: Option<u64>,
+    /// Symbol of the token, or its address when the wallet doesn't know it.
+    pub token: String,
+    /// In whole tokens for known tokens, in the smallest unit otherwise.
+    pub amount: String,
+    pub commitment: Option<String>,
+    pub nullifier: String,
+    pub tx_hash: Option<String>,
+    pub timestamp: Option<u64>,
+    /// `spent`/`unspent` for coins, the transaction status for history.
+    pub status: String,
+}
+
+fn token_and_amount(tokens: &[TokenInfo], token: H160, amount: Amount) -> (String, String) {
+    match tokens.iter().find(|t| t.token_address == token) {
+        Some(info) => (info.symbol.clone(), format_amount(amount, info.decimals)),
+        None => (format!("{:?}", token), amount.to_string()),
+    }
+}
+
+/// The coins of `ctx`, and its history when `history` is set, optionally
+/// restricted to a single token.
+pub fn rows(
+    ctx: &Context,
+    tokens: &[TokenInfo],
+    token: Option<H160>,
+    history: bool,
+) -> Vec<ExportRow> {
+    let wanted = |t: H160| token.is_none_or(|token| token == t);
+    let mut rows = ctx
+        .coins
+        .iter()
+        .filter(|coin| wanted(coin.uint_token))
+        .map(|coin| {
+            let (token, amount) = token_and_amount(tokens, coin.uint_token, coin.amount);
+            ExportRow {
+                record: "coin".to_string(),
+                index: Some(coin.index.as_u64()),
+                token,
+                amount,
+                commitment: Some(format!("{:#x}", coin.commitment)),
+                nullifier: coin
+                    .nullifier
+                    .map(|nullifier| format!("{:#x}", nullifier))
+                    .unwrap_or_default(),
+                tx_hash: None,
+                timestamp: None,
+                status: if coin.is_spent(&ctx.spent_nullifiers) {
+                    "spent"
+                } else {
+                    "unspent"
+                }
+                .to_string(),
+            }
+        })
+        .collect::<Vec<_>>();
+    if history {
+        rows.extend(
+            ctx.history
+                .iter()
+                .filter(|entry| wanted(entry.token))
+                .map(|entry| {
+                    let (token, amount) = token_and_amount(tokens, entry.token, entry.amount);
+                    ExportRow {
+                        record: match entry.kind {
+                            HistoryKind::Send => "send",
+                            HistoryKind::Withdraw => "withdraw",
+                        }
+                        .to_string(),
+                        index: None,
+                        token,
+                        amount,
+                        commitment: None,
+                        nullifier: format!("{:#x}", entry.nullifier),
+                        tx_hash: entry.tx_hash.map(|hash| format!("{:?}", hash)),
+                        timestamp: Some(entry.timestamp),
+                        status: match entry.status {
+                            TxStatus::Pending => "pending",
+                            TxStatus::Confirmed => "confirmed",
+                            TxStatus::Failed => "failed",
+                            TxStatus::Expired => "expired",
+                        }
+                        .to_string(),
+                    }
+                }),
+        );
+    }
+    rows
+}
+
+/// Writes `rows` after a header row, quoting the fields which need it.
+pub fn to_csv(rows: &[ExportRow]) -> Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::{PrivateKey, PublicKey};
+    use crate::{Coin, HistoryEntry};
+    use ethers::types::U256;
+    use std::sync::Arc;
+
+    fn coin(index: u64, amount: u64, token: H160) -> Coin {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        Coin {
+            index: U256::from(index),
+            uint_token: token,
+            amount: Amount::from(amount),
+            priv_key: Some(priv_key),
+            pub_key: PublicKey::from(priv_key),
+            nullifier: Some(U256::from(1000 + index)),
+            commitment: U256::from(2000 + index),
+            block_number: None,
+            tx_hash: None,
+            ephemeral: None,
+        }
+    }
+
+    #[test]
+    fn test_export_csv() {
+        let dive = H160::from_low_u64_be(1);
+        let unknown = H160::from_low_u64_be(2);
+        let tokens = vec![TokenInfo {
+            token_address: dive,
+            // Needs quoting
+            symbol: "DIVE, \"test\"".to_string(),
+            decimals: 2,
+        }];
+        let mut ctx = Context::new(H160::default(), false);
+        ctx.coins = Arc::new(vec![
+            coin(0, 150, dive),
+            coin(1, 7, unknown),
+            coin(2, 5, dive),
+        ]);
+        ctx.spent_nullifiers.insert(U256::from(1002));
+        ctx.history.push(HistoryEntry::new(
+            HistoryKind::Send,
+            U256::from(1002),
+            dive,
+            Amount::from(5u64),
+        ));
+
+        let csv = to_csv(&rows(&ctx, &tokens, None, false)).unwrap();
+        let text = String::from_utf8(csv.clone()).unwrap();
+        assert!(text.starts_with(
+            "record,index,token,amount,commitment,nullifier,tx_hash,timestamp,status\n"
+        ));
+        assert!(text.contains("\"DIVE, \"\"test\"\"\",1.5,"));
+
+        let parsed = csv::Reader::from_reader(csv.as_slice())
+            .deserialize()
+            .collect::<Result<Vec<ExportRow>, _>>()
+            .unwrap();
+        assert_eq!(parsed, rows(&ctx, &tokens, None, false));
+        let exported = parsed
+            .iter()
+            .map(|row| (row.index.unwrap(), row.status.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(exported, vec![(0, "unspent"), (1, "unspent"), (2, "spent")]);
+        assert_eq!(parsed[1].token, format!("{:?}", unknown));
+        assert_eq!(parsed[1].amount, "7");
+
+        let filtered = rows(&ctx, &tokens, Some(dive), true);
+        assert_eq!(filtered.len(), 3);
+        assert!(filtered.iter().all(|row| row.token == tokens[0].symbol));
+        assert_eq!(filtered[2].record, "send");
+        assert_eq!(filtered[2].amount, "0.05");
+        assert_eq!(filtered[2].status, "pending");
+    }
+}
@@ -50,6 +50,19 @@ impl Serialize for Fp {
 }
 
 impl Fp {
+    /// Converts `value` into a field element, refusing values at or above the
+    /// modulus instead of reducing them like `TryFrom<U256>` does.
+    pub fn from_u256_checked(value: U256) -> Result<Self, eyre::Report> {
+        let modulus = U256::from_str_radix(&Fp::MODULUS[2..], 16)?;
+        if value >= modulus {
+            return Err(eyre::Report::msg(format!(
+                "{} is not below the field modulus!",
+                value
+            )));
+        }
+        Fp::try_from(value)
+    }
+
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, eyre::Report> {
         Ok(Fp::from_str(
             &BigUint::from_bytes_le(bytes)
@@ -85,3 +98,26 @@ impl<'de> Visitor<'de> for FpStr {
         Fp::from_str_vartime(s).ok_or(de::Error::invalid_value(de::Unexpected::Str(s), &self))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_u256_checked() {
+        let modulus = U256::from_dec_str(
+            "21888242871839275222246405745257275088548364400416034343698204186575808495617",
+        )
+        .unwrap();
+        let below: U256 = Fp::from_u256_checked(modulus - 1).unwrap().into();
+        assert_eq!(below, modulus - 1);
+        assert_eq!(Fp::from_u256_checked(modulus - 1).unwrap(), -Fp::ONE);
+        assert!(Fp::from_u256_checked(modulus).is_err());
+        assert!(Fp::from_u256_checked(modulus + 1).is_err());
+        assert!(Fp::from_u256_checked(U256::MAX).is_err());
+        assert_eq!(Fp::from_u256_checked(U256::zero()).unwrap(), Fp::ZERO);
+
+        // The unchecked conversion silently wraps around.
+        assert_eq!(Fp::try_from(modulus + 1).unwrap(), Fp::ONE);
+    }
+}
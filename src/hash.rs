@@ -1,16 +1,107 @@
+use ethers::abi::Abi;
+use ethers::contract::Contract;
+use ethers::types::{H160, U256};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
 use crate::fp::Fp;
 use crate::poseidon::poseidon;
-
+use crate::rpc::RpcProvider;
 
 pub fn hash4(vals: [Fp; 4]) -> Fp {
     poseidon(vals)
 }
 
+/// Input hashed on both sides by `check_poseidon`.
+const TEST_VECTOR: [u64; 4] = [1, 2, 3, 4];
+
+/// Poseidon contracts bundled with the wallet, the Owshen contract hashes
+/// its tree and commitments with the one it was deployed with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PoseidonVariant {
+    /// Width 5 (4 inputs), the parameters of circomlib and of `hash4`.
+    #[default]
+    Poseidon4,
+}
+
+impl PoseidonVariant {
+    pub fn abi(&self) -> &'static str {
+        match self {
+            PoseidonVariant::Poseidon4 => include_str!("assets/poseidon4.abi"),
+        }
+    }
+
+    pub fn bytecode(&self) -> &'static str {
+        match self {
+            PoseidonVariant::Poseidon4 => include_str!("assets/poseidon4.evm"),
+        }
+    }
+}
+
+impl FromStr for PoseidonVariant {
+    type Err = eyre::Report;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "poseidon4" => Ok(PoseidonVariant::Poseidon4),
+            _ => Err(eyre::Report::msg("Expected `poseidon4`!")),
+        }
+    }
+}
+
+impl fmt::Display for PoseidonVariant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoseidonVariant::Poseidon4 => write!(f, "poseidon4"),
+        }
+    }
+}
+
+/// Refuses a Poseidon contract which doesn't hash like `hash4`: the roots
+/// it computes would never match the proofs the wallet generates.
+pub async fn check_poseidon(
+    provider: Arc<RpcProvider>,
+    address: H160,
+    variant: PoseidonVariant,
+) -> Result<()> {
+    let abi = serde_json::from_str::<Abi>(variant.abi())?;
+    let contract = Contract::new(address, abi, provider);
+    let on_chain: U256 = contract
+        .method_hash(
+            ethers::utils::id("poseidon(uint256[4])"),
+            (TEST_VECTOR.map(U256::from),),
+        )?
+        .call()
+        .await
+        .map_err(|e| {
+            eyre::Report::msg(format!(
+                "Cannot hash with the {} contract at {:?}: {}",
+                variant, address, e
+            ))
+        })?;
+    let off_chain: U256 = hash4(TEST_VECTOR.map(Fp::from)).into();
+    if on_chain != off_chain {
+        return Err(eyre::Report::msg(format!(
+            "The {} contract at {:?} hashes {:?} to {} instead of {}!",
+            variant, address, TEST_VECTOR, on_chain, off_chain
+        )));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::fp::Fp;
-    use crate::hash::hash4;
+    use super::*;
+    use crate::deploy;
+    use crate::rpc;
+    use crate::transaction::TxType;
+    use ethers::providers::Middleware;
+    use ethers::utils::Ganache;
     use ff::PrimeField;
+
     #[test]
     fn poseidon_hash() {
         let out: Fp = hash4([Fp::from(0), Fp::from(0), Fp::from(0), Fp::from(0)]);
@@ -20,4 +111,97 @@ mod tests {
         .unwrap();
         assert_eq!(out, expected);
     }
+
+    #[derive(Deserialize)]
+    struct Vector {
+        inputs: [String; 4],
+        expected: String,
+    }
+
+    // Hashes the inputs of `vectors`, a JSON array of `{"inputs": [...],
+    // "expected": ...}` with field elements in decimal, as the vectors of
+    // circomlib are. Returns how many matched, or the first which didn't.
+    fn check_vectors(vectors: &str) -> Result<usize, String> {
+        let vectors: Vec<Vector> =
+            serde_json::from_str(vectors).map_err(|e| format!("Invalid vectors: {}", e))?;
+        let parse =
+            |s: &String| Fp::from_str_vartime(s).ok_or(format!("{:?} is not a field element", s));
+        for (i, vector) in vectors.iter().enumerate() {
+            let inputs = [
+                parse(&vector.inputs[0])?,
+                parse(&vector.inputs[1])?,
+                parse(&vector.inputs[2])?,
+                parse(&vector.inputs[3])?,
+            ];
+            let actual = hash4(inputs);
+            if actual != parse(&vector.expected)? {
+                return Err(format!(
+                    "Vector #{} {:?} hashes to {} instead of {}",
+                    i,
+                    vector.inputs,
+                    Into::<U256>::into(actual),
+                    vector.expected
+                ));
+            }
+        }
+        Ok(vectors.len())
+    }
+
+    #[test]
+    fn test_poseidon_vectors() {
+        let bundled = include_str!("assets/poseidon4_vectors.json");
+        assert_eq!(check_vectors(bundled), Ok(7));
+
+        // More vectors, e.g. exported from the reference of the circuit.
+        if let Ok(path) = std::env::var("OWSHEN_POSEIDON_VECTORS") {
+            let vectors = std::fs::read_to_string(&path).unwrap();
+            if let Err(e) = check_vectors(&vectors) {
+                panic!("{}: {}", path, e);
+            }
+        }
+
+        let wrong = r#"[
+            {"inputs": ["0", "0", "0", "0"], "expected": "2351654555892372227640888372176282444150254868378439619268573230312091195718"},
+            {"inputs": ["1", "2", "3", "4"], "expected": "1"}
+        ]"#;
+        let err = check_vectors(wrong).unwrap_err();
+        assert!(
+            err.starts_with("Vector #1 [\"1\", \"2\", \"3\", \"4\"]"),
+            "{}",
+            err
+        );
+        assert!(err.ends_with("instead of 1"), "{}", err);
+        let out_of_field = r#"[{"inputs": ["0", "0", "0", "21888242871839275222246405745257275088548364400416034343698204186575808495617"], "expected": "0"}]"#;
+        assert!(check_vectors(out_of_field).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_poseidon() {
+        let port = 8562u16;
+        let _ganache = Ganache::new().port(port).spawn();
+        let provider = Arc::new(
+            rpc::connect(&format!("http://localhost:{}", port))
+                .await
+                .unwrap(),
+        );
+        let from = provider.get_accounts().await.unwrap()[0];
+
+        let variant = PoseidonVariant::from_str("poseidon4").unwrap();
+        assert_eq!(variant.to_string(), "poseidon4");
+        assert!(PoseidonVariant::from_str("poseidon6").is_err());
+        let poseidon = deploy(
+            provider.clone(),
+            variant.abi(),
+            variant.bytecode(),
+            TxType::Legacy,
+            from,
+        )
+        .await;
+        check_poseidon(provider.clone(), poseidon.address(), variant)
+            .await
+            .unwrap();
+
+        // Not a Poseidon contract
+        assert!(check_poseidon(provider, from, variant).await.is_err());
+    }
 }
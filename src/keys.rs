@@ -4,8 +4,9 @@ use bip39::Mnemonic;
 
 use ff::{Field, PrimeField, PrimeFieldBits};
 use num_bigint::{BigUint, RandBigInt};
-use num_traits::{Num, Zero};
-use rand::Rng;
+use num_traits::Zero;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use serde::{de, de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
 use std::fmt::Display;
@@ -32,6 +33,8 @@ lazy_static! {
         "2736030358979909402780800718157159386076813972158567259200215660948447373041"
     )
     .unwrap();
+    // `ORDER` as a scalar, to multiply points with.
+    static ref ORDER_FP: Fp = Fp::from_str_vartime(&ORDER.to_string()).unwrap();
     pub static ref G: Point = Point {
         x: Fp::from_str_vartime(
             "995203441582195749578291179787384436505546430278305826713579947235728471134"
@@ -52,6 +55,28 @@ impl Point {
         let y2 = self.y * self.y;
         *A * x2 + y2 == Fp::ONE + *D * x2 * y2
     }
+
+    /// Whether the point lies in the prime-order subgroup generated by `BASE`,
+    /// where all keys live. Points of the full curve carry a cofactor of 8
+    /// and would leak the low bits of the secrets they are multiplied with.
+    pub fn is_in_subgroup(&self) -> bool {
+        self.is_on_curve() && *self * *ORDER_FP == *INF
+    }
+
+    /// Checks a point coming from outside of the wallet before it is used as
+    /// a key: on the curve, in the subgroup and not the identity.
+    pub fn validate(&self) -> Result<(), ParseKeyError> {
+        if !self.is_on_curve() {
+            return Err(ParseKeyError::NotOnCurve);
+        }
+        if *self == *INF {
+            return Err(ParseKeyError::Identity);
+        }
+        if !self.is_in_subgroup() {
+            return Err(ParseKeyError::NotInSubgroup);
+        }
+        Ok(())
+    }
 }
 
 impl Neg for Point {
@@ -106,6 +131,10 @@ pub struct PrivateKey {
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct PublicKey {
     pub point: Point,
+    // Key of the secret shared with the stealth keys derived from an address,
+    // see `ViewingKey`. Single-key addresses of older wallets and stealth keys
+    // have none.
+    pub view: Option<Point>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Copy, PartialEq)]
@@ -113,15 +142,53 @@ pub struct Entropy {
     pub value: [u8; 16],
 }
 
+/// Key that lets its holder detect the incoming coins of a wallet, but not
+/// spend them.
+///
+/// Addresses carry a spending key `S = s·G` and a viewing key `V = v·G`. A
+/// coin goes to the stealth key `S + h·G`, where `h` hashes the secret `r·V`
+/// shared with the receiver, so `v` is enough to recognize it and recompute
+/// its stealth key. Its private key `s + h`, and so its nullifier and spend
+/// proofs, also need `s`, which `v` is hashed out of. Coins sent to the
+/// single-key address of an older wallet can't be detected with it.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ViewingKey {
+    pub secret: Fp,
+    pub spend: Point,
+}
+
+/// Key the chain is scanned with. Both detect the coins of a wallet, only the
+/// spending key opens them.
+#[derive(Debug, Clone, Copy)]
+pub enum ScanningKey {
+    Spending(PrivateKey),
+    Viewing(ViewingKey),
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct EphemeralKey {
     pub point: Point,
 }
 
+/// View tag of coins whose sender didn't compute one, such coins always go
+/// through the full detection.
+pub const UNTAGGED: u8 = 0;
+
+/// One byte published along a coin so that receivers can discard most foreign
+/// coins after a single scalar multiplication, instead of deriving the stealth
+/// key and recomputing the commitment. It is domain separated from the hash
+/// the stealth key is derived from, which leaks nothing of it. Real tags are
+/// never `UNTAGGED`.
+pub fn view_tag(shared_secret: Point) -> u8 {
+    let hash = hash4([shared_secret.x, shared_secret.y, 1.into(), 0.into()]);
+    hash.to_repr().as_ref()[0] % 255 + 1
+}
+
 impl From<PrivateKey> for PublicKey {
     fn from(sk: PrivateKey) -> Self {
         Self {
             point: *BASE * sk.secret,
+            view: None,
         }
     }
 }
@@ -141,12 +208,25 @@ impl PublicKey {
     }
 
     pub fn derive<R: Rng>(&self, rng: &mut R) -> (EphemeralKey, PublicKey) {
+        let (ephemeral, pub_key, _) = self.derive_with_view_tag(rng);
+        (ephemeral, pub_key)
+    }
+
+    /// `derive`, also returning the view tag to publish along the coin.
+    pub fn derive_with_view_tag<R: Rng>(&self, rng: &mut R) -> (EphemeralKey, PublicKey, u8) {
         let r = Fp::random(rng);
         let ephemeral = *BASE * r;
-        let shared_secret = self.point * r;
+        let shared_secret = self.view.unwrap_or(self.point) * r;
         let shared_secret_hash = hash4([shared_secret.x, shared_secret.y, 0.into(), 0.into()]);
         let pub_key = self.point + *BASE * shared_secret_hash;
-        (EphemeralKey { point: ephemeral }, Self { point: pub_key })
+        (
+            EphemeralKey { point: ephemeral },
+            Self {
+                point: pub_key,
+                view: None,
+            },
+            view_tag(shared_secret),
+        )
     }
 
     pub fn null() -> Self {
@@ -155,6 +235,7 @@ impl PublicKey {
                 x: 0.into(),
                 y: 0.into(),
             },
+            view: None,
         }
     }
 }
@@ -177,16 +258,36 @@ impl Entropy {
             value: mnemonic.to_entropy().try_into().unwrap(),
         }
     }
+
+    /// Key of the account at `index` of the mnemonic. The index is used as the
+    /// BIP-39 passphrase, account 0 (no passphrase) is the historical wallet key.
+    pub fn account_key(&self, index: u32) -> PrivateKey {
+        let mnemonic: Mnemonic = Mnemonic::from_entropy(&self.value).unwrap();
+        let passphrase = if index == 0 {
+            String::new()
+        } else {
+            format!("owshen/account/{}", index)
+        };
+        let seed = mnemonic.to_seed(&passphrase);
+        PrivateKey {
+            secret: Fp::from_bytes(&seed).unwrap(),
+        }
+    }
 }
 
 impl PrivateKey {
-    pub fn generate<R: Rng>(_rng: &mut R) -> Self {
-        let rnd = rand::thread_rng().gen_biguint_range(&BigUint::zero(), &*ORDER);
+    pub fn generate<R: Rng>(rng: &mut R) -> Self {
+        let rnd = rng.gen_biguint_range(&BigUint::zero(), &*ORDER);
         Self {
             secret: Fp::from_str_vartime(rnd.to_string().as_str()).unwrap(),
         }
     }
 
+    /// Deterministic `generate`, the same seed always gives the same key.
+    pub fn generate_from_seed(seed: [u8; 32]) -> Self {
+        Self::generate(&mut StdRng::from_seed(seed))
+    }
+
     pub fn to_mnemonic(&self) -> Result<String, bip39::Error> {
         let secret_bytes: Vec<u8> = self.secret.to_repr().as_ref().to_vec();
         let mnemonic: Mnemonic = Mnemonic::from_entropy(&secret_bytes)?;
@@ -196,13 +297,22 @@ impl PrivateKey {
         Ok(phrase)
     }
 
+    /// View tag a coin sent to this key with `eph` carries.
+    pub fn view_tag(&self, eph: EphemeralKey) -> u8 {
+        view_tag(eph.point * self.secret)
+    }
+
     pub fn shared_secret(&self, eph: EphemeralKey) -> Fp {
         let shared_secret = eph.point * self.secret;
         hash4([shared_secret.x, shared_secret.y, 0.into(), 0.into()])
     }
 
     pub fn derive(&self, eph: EphemeralKey) -> Self {
-        let shared_secret = self.shared_secret(eph);
+        self.with_shared_secret(self.shared_secret(eph))
+    }
+
+    /// The stealth key of a coin sharing `shared_secret` with this key.
+    pub fn with_shared_secret(&self, shared_secret: Fp) -> Self {
         let secret = BigUint::from_bytes_le(self.secret.to_repr().as_ref());
         let shared_secret = BigUint::from_bytes_le(shared_secret.to_repr().as_ref());
         let stealth_secret = Fp::from_str_vartime(
@@ -215,62 +325,275 @@ impl PrivateKey {
             secret: stealth_secret,
         }
     }
+
+    /// The stealth key of the coin sent to `stealth_pub` with `eph`, through
+    /// the address or, as by older wallets, the single key.
+    pub fn stealth_key(&self, eph: EphemeralKey, stealth_pub: &PublicKey) -> Option<Self> {
+        [
+            self.with_shared_secret(self.viewing_key().shared_secret(eph)),
+            self.derive(eph),
+        ]
+        .into_iter()
+        .find(|stealth_priv| PublicKey::from(*stealth_priv) == *stealth_pub)
+    }
+
     pub fn decrypt(&self, cipher: Cipher) -> Point {
         cipher.b - cipher.a * self.secret
     }
     pub fn nullifier(&self, index: u32) -> Fp {
         hash4([self.secret, Fp::from(index as u64), 0.into(), 0.into()])
     }
+
+    /// The secret of the viewing key is hashed out of the spending secret,
+    /// domain separated from the nullifiers.
+    pub fn viewing_key(&self) -> ViewingKey {
+        ViewingKey {
+            secret: hash4([self.secret, 0.into(), 0.into(), 1.into()]),
+            spend: *BASE * self.secret,
+        }
+    }
+
+    /// The address coins are sent to, with both the spending and the viewing
+    /// key. `PublicKey::from` gives the single-key address of older wallets.
+    pub fn address(&self) -> PublicKey {
+        self.viewing_key().public_key()
+    }
+}
+
+impl ViewingKey {
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey {
+            point: self.spend,
+            view: Some(*BASE * self.secret),
+        }
+    }
+
+    /// View tag a coin sent to the address with `eph` carries.
+    pub fn view_tag(&self, eph: EphemeralKey) -> u8 {
+        view_tag(eph.point * self.secret)
+    }
+
+    pub fn shared_secret(&self, eph: EphemeralKey) -> Fp {
+        let shared_secret = eph.point * self.secret;
+        hash4([shared_secret.x, shared_secret.y, 0.into(), 0.into()])
+    }
+
+    /// The stealth key of a coin sent to the address with `eph`. Its private
+    /// key is `PrivateKey::with_shared_secret` of the spending key.
+    pub fn derive(&self, eph: EphemeralKey) -> PublicKey {
+        PublicKey {
+            point: self.spend + *BASE * self.shared_secret(eph),
+            view: None,
+        }
+    }
+}
+
+impl ScanningKey {
+    pub fn viewing_key(&self) -> ViewingKey {
+        match self {
+            ScanningKey::Spending(priv_key) => priv_key.viewing_key(),
+            ScanningKey::Viewing(viewing_key) => *viewing_key,
+        }
+    }
+
+    pub fn spending_key(&self) -> Option<&PrivateKey> {
+        match self {
+            ScanningKey::Spending(priv_key) => Some(priv_key),
+            ScanningKey::Viewing(_) => None,
+        }
+    }
+
+    /// The address of the wallet.
+    pub fn address(&self) -> PublicKey {
+        self.viewing_key().public_key()
+    }
+}
+
+impl From<PrivateKey> for ScanningKey {
+    fn from(priv_key: PrivateKey) -> Self {
+        ScanningKey::Spending(priv_key)
+    }
+}
+
+impl From<ViewingKey> for ScanningKey {
+    fn from(viewing_key: ViewingKey) -> Self {
+        ScanningKey::Viewing(viewing_key)
+    }
 }
 
 impl From<Entropy> for PrivateKey {
     fn from(entropy: Entropy) -> Self {
-        let mnemonic: Mnemonic = Mnemonic::from_entropy(&entropy.value).unwrap();
-        let seed = mnemonic.to_seed("");
-        let secret = Fp::from_bytes(&seed).unwrap();
-        PrivateKey { secret }
+        entropy.account_key(0)
+    }
+}
+
+/// Error returned when parsing a textual key representation fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseKeyError {
+    InvalidLength,
+    InvalidPrefix,
+    InvalidHex,
+    NotInField,
+    NotOnCurve,
+    NotInSubgroup,
+    Identity,
+}
+
+impl Display for ParseKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseKeyError::InvalidLength => write!(f, "Invalid key length!"),
+            ParseKeyError::InvalidPrefix => write!(f, "Invalid Owshen address prefix!"),
+            ParseKeyError::InvalidHex => write!(f, "Invalid hex encoding!"),
+            ParseKeyError::NotInField => write!(f, "Value is not a valid field element!"),
+            ParseKeyError::NotOnCurve => write!(f, "Invalid point!"),
+            ParseKeyError::NotInSubgroup => write!(f, "Point is not in the prime-order subgroup!"),
+            ParseKeyError::Identity => write!(f, "Point is the identity!"),
+        }
+    }
+}
+
+impl std::error::Error for ParseKeyError {}
+
+// Decodes exactly 64 hex characters (big-endian) into a field element.
+fn fp_from_hex(s: &str) -> Result<Fp, ParseKeyError> {
+    if s.len() != 64 {
+        return Err(ParseKeyError::InvalidLength);
+    }
+    if !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ParseKeyError::InvalidHex);
+    }
+    let mut repr = <Fp as PrimeField>::Repr::default();
+    for (i, byte) in repr.as_mut().iter_mut().rev().enumerate() {
+        *byte =
+            u8::from_str_radix(&s[2 * i..2 * i + 2], 16).map_err(|_| ParseKeyError::InvalidHex)?;
+    }
+    Option::<Fp>::from(Fp::from_repr(repr)).ok_or(ParseKeyError::NotInField)
+}
+
+fn fp_to_hex(f: &mut std::fmt::Formatter, val: &Fp) -> std::fmt::Result {
+    for byte in val.to_repr().as_ref().iter().rev() {
+        write!(f, "{:02x}", byte)?;
+    }
+    Ok(())
+}
+
+// Decodes a point out of `2` (even y) or `3` (odd y) followed by the 64-digit
+// big-endian hex of the x coordinate, `y` being recovered from the curve
+// equation.
+fn point_from_str(s: &str) -> Result<Point, ParseKeyError> {
+    let is_odd = match &s[..1] {
+        "3" => true,
+        "2" => false,
+        _ => return Err(ParseKeyError::InvalidPrefix),
+    };
+    let x = fp_from_hex(&s[1..])?;
+    let div =
+        Option::<Fp>::from((*D * x * x - Fp::ONE).invert()).ok_or(ParseKeyError::NotOnCurve)?;
+    let mut y = Option::<Fp>::from(((*A * x * x - Fp::ONE) * div).sqrt())
+        .ok_or(ParseKeyError::NotOnCurve)?;
+    if Into::<bool>::into(y.is_odd()) != is_odd {
+        y = -y;
     }
+    let point = Point { x, y };
+    point.validate()?;
+    Ok(point)
 }
 
+fn point_to_str(f: &mut std::fmt::Formatter, point: &Point) -> std::fmt::Result {
+    let is_odd: bool = point.y.is_odd().into();
+    write!(f, "{}", if is_odd { "3" } else { "2" })?;
+    fp_to_hex(f, &point.x)
+}
+
+/// The canonical address encoding is `OoOo` followed by the spending key, then
+/// by the viewing key if any, each as `2` (even y) or `3` (odd y) followed by
+/// the 64-digit big-endian hex of the x coordinate.
 impl FromStr for PublicKey {
-    type Err = eyre::Report;
+    type Err = ParseKeyError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.len() != 69 || !s.starts_with("OoOo") {
-            return Err(eyre::Report::msg("Invalid Owshen address!"));
+        if !s.is_ascii() || (s.len() != 69 && s.len() != 134) {
+            return Err(ParseKeyError::InvalidLength);
         }
-        if let Some(x) = Fp::from_str_vartime(&BigUint::from_str_radix(&s[5..], 16)?.to_string()) {
-            let is_odd = if &s[4..5] == "3" {
-                true
-            } else if &s[4..5] == "2" {
-                false
-            } else {
-                return Err(eyre::Report::msg("Invalid Owshen address!"));
-            };
-            let div = Option::<Fp>::from((*D * x * x - Fp::ONE).invert())
-                .ok_or(eyre::Report::msg("Invalid point!"))?;
-            let mut y = Option::<Fp>::from(((*A * x * x - Fp::ONE) * div).sqrt())
-                .ok_or(eyre::Report::msg("Invalid point!"))?;
-            if Into::<bool>::into(y.is_odd()) != is_odd {
-                y = -y;
-            }
-            Ok(Self {
-                point: Point { x, y },
-            })
-        } else {
-            Err(eyre::Report::msg("Invalid Owshen address!"))
+        if !s.starts_with("OoOo") {
+            return Err(ParseKeyError::InvalidPrefix);
         }
+        let view = match s.len() {
+            134 => Some(point_from_str(&s[69..])?),
+            _ => None,
+        };
+        Ok(Self {
+            point: point_from_str(&s[4..69])?,
+            view,
+        })
+    }
+}
+
+impl TryFrom<&str> for PublicKey {
+    type Error = ParseKeyError;
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
     }
 }
 
 impl Display for PublicKey {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "OoOo")?;
-        let is_odd: bool = self.point.y.is_odd().into();
-        write!(f, "{}", if is_odd { "3" } else { "2" })?;
-        for byte in self.point.x.to_repr().as_ref().iter().rev() {
-            write!(f, "{:02x}", byte)?;
+        point_to_str(f, &self.point)?;
+        match &self.view {
+            Some(view) => point_to_str(f, view),
+            None => Ok(()),
         }
-        Ok(())
+    }
+}
+
+/// Private keys are encoded as `0x` followed by the 64-digit big-endian hex
+/// of the secret scalar.
+impl FromStr for PrivateKey {
+    type Err = ParseKeyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.is_ascii() || s.len() != 66 {
+            return Err(ParseKeyError::InvalidLength);
+        }
+        if !s.starts_with("0x") {
+            return Err(ParseKeyError::InvalidPrefix);
+        }
+        Ok(Self {
+            secret: fp_from_hex(&s[2..])?,
+        })
+    }
+}
+
+impl Display for PrivateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "0x")?;
+        fp_to_hex(f, &self.secret)
+    }
+}
+
+/// Viewing keys are encoded as `OoOoV` followed by the 64-digit big-endian
+/// hex of the scalar, then by the spending key as in addresses.
+impl FromStr for ViewingKey {
+    type Err = ParseKeyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.is_ascii() || s.len() != 134 {
+            return Err(ParseKeyError::InvalidLength);
+        }
+        if !s.starts_with("OoOoV") {
+            return Err(ParseKeyError::InvalidPrefix);
+        }
+        Ok(Self {
+            secret: fp_from_hex(&s[5..69])?,
+            spend: point_from_str(&s[69..])?,
+        })
+    }
+}
+
+impl Display for ViewingKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "OoOoV")?;
+        fp_to_hex(f, &self.secret)?;
+        point_to_str(f, &self.spend)
     }
 }
 
@@ -301,11 +624,12 @@ impl<'de> Visitor<'de> for PublicKeyStr {
         write!(formatter, "expecting a string")
     }
 
+    // The reason is kept, requests carrying addresses are refused with it.
     fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
     where
         E: de::Error,
     {
-        PublicKey::from_str(s).map_err(|_| de::Error::invalid_value(de::Unexpected::Str(s), &self))
+        PublicKey::try_from(s).map_err(|e| E::custom(format!("invalid address {:?}: {}", s, e)))
     }
 }
 
@@ -328,6 +652,60 @@ mod tests {
         assert_eq!(PublicKey::from(stealth_priv_key), stealth_pub_key);
     }
 
+    #[test]
+    fn test_viewing_key_stealth() {
+        let master_priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let master_pub_key: PublicKey = master_priv_key.into();
+
+        // Through the viewing key of a two-key address.
+        let viewing_key = master_priv_key.viewing_key();
+        let (stealth_eph, stealth_pub_key) =
+            master_priv_key.address().derive(&mut rand::thread_rng());
+        assert_eq!(viewing_key.derive(stealth_eph), stealth_pub_key);
+        let stealth_priv_key =
+            master_priv_key.with_shared_secret(viewing_key.shared_secret(stealth_eph));
+        assert_eq!(PublicKey::from(stealth_priv_key), stealth_pub_key);
+        assert_ne!(
+            master_priv_key.derive(stealth_eph).secret,
+            stealth_priv_key.secret
+        );
+
+        // Either kind is found again from the ephemeral key.
+        let stealth_key = |eph, stealth_pub| {
+            master_priv_key
+                .stealth_key(eph, &stealth_pub)
+                .map(|key| key.secret)
+        };
+        assert_eq!(
+            stealth_key(stealth_eph, stealth_pub_key),
+            Some(stealth_priv_key.secret)
+        );
+        let (legacy_eph, legacy_pub_key) = master_pub_key.derive(&mut rand::thread_rng());
+        assert_eq!(
+            stealth_key(legacy_eph, legacy_pub_key),
+            Some(master_priv_key.derive(legacy_eph).secret)
+        );
+        assert_eq!(stealth_key(legacy_eph, stealth_pub_key), None);
+    }
+
+    #[test]
+    fn test_view_tag() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let other_key = PrivateKey::generate(&mut rand::thread_rng());
+        let mut foreign_matches = 0;
+        for _ in 0..64 {
+            let (eph, _, tag) =
+                PublicKey::from(priv_key).derive_with_view_tag(&mut rand::thread_rng());
+            assert_ne!(tag, UNTAGGED);
+            assert_eq!(priv_key.view_tag(eph), tag);
+            if other_key.view_tag(eph) == tag {
+                foreign_matches += 1;
+            }
+        }
+        // One in 255 foreign coins is expected to match.
+        assert!(foreign_matches < 8);
+    }
+
     #[test]
     fn test_encrypt() {
         let priv_key = PrivateKey {
@@ -354,11 +732,186 @@ mod tests {
         assert_eq!(*G, *G + *INF);
     }
 
+    #[test]
+    fn test_validate_point() {
+        let pub_key = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        assert!(pub_key.point.is_in_subgroup());
+        assert_eq!(pub_key.point.validate(), Ok(()));
+
+        let off_curve = Point {
+            x: pub_key.point.x,
+            y: pub_key.point.y + Fp::ONE,
+        };
+        assert!(!off_curve.is_on_curve());
+        assert!(!off_curve.is_in_subgroup());
+        assert_eq!(off_curve.validate(), Err(ParseKeyError::NotOnCurve));
+
+        assert!(INF.is_in_subgroup());
+        assert_eq!(INF.validate(), Err(ParseKeyError::Identity));
+        // The generator of the full curve, of order 8 times the subgroup's.
+        assert!(G.is_on_curve());
+        assert!(!G.is_in_subgroup());
+        assert_eq!(G.validate(), Err(ParseKeyError::NotInSubgroup));
+        let low_order = Point {
+            x: 0.into(),
+            y: -Fp::ONE,
+        };
+        assert_eq!(low_order.validate(), Err(ParseKeyError::NotInSubgroup));
+
+        // Both decode from an address.
+        let identity = PublicKey {
+            point: *INF,
+            view: None,
+        }
+        .to_string();
+        assert_eq!(PublicKey::from_str(&identity), Err(ParseKeyError::Identity));
+        assert_eq!(
+            PublicKey::from_str(
+                &PublicKey {
+                    point: *G,
+                    view: None
+                }
+                .to_string()
+            ),
+            Err(ParseKeyError::NotInSubgroup)
+        );
+    }
+
     #[test]
     fn test_add() {
         assert_eq!((*G + *G) + *G, *G + (*G + *G));
     }
 
+    #[test]
+    fn test_public_key_round_trip() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let pub_key = PublicKey::from(PrivateKey::generate(&mut rng));
+            assert_eq!(PublicKey::from_str(&pub_key.to_string()).unwrap(), pub_key);
+            assert_eq!(
+                PublicKey::try_from(pub_key.to_string().as_str()),
+                Ok(pub_key)
+            );
+            let address = PrivateKey::generate(&mut rng).address();
+            assert_eq!(address.to_string().len(), 134);
+            assert_eq!(PublicKey::from_str(&address.to_string()).unwrap(), address);
+        }
+        assert_eq!(
+            PublicKey::try_from("OoOo"),
+            Err(ParseKeyError::InvalidLength)
+        );
+    }
+
+    #[test]
+    fn test_private_key_round_trip() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let priv_key = PrivateKey::generate(&mut rng);
+            let parsed = PrivateKey::from_str(&priv_key.to_string()).unwrap();
+            assert_eq!(parsed.secret, priv_key.secret);
+        }
+    }
+
+    #[test]
+    fn test_viewing_key() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let viewing_key = priv_key.viewing_key();
+        assert_eq!(viewing_key.public_key(), priv_key.address());
+        assert_eq!(viewing_key.spend, PublicKey::from(priv_key).point);
+        assert_ne!(viewing_key.secret, priv_key.secret);
+        let parsed = ViewingKey::from_str(&viewing_key.to_string()).unwrap();
+        assert_eq!(parsed.secret, viewing_key.secret);
+        assert_eq!(parsed.spend, viewing_key.spend);
+        assert!(ViewingKey::from_str(&priv_key.address().to_string()).is_err());
+    }
+
+    #[test]
+    fn test_viewing_key_cannot_spend() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let viewing_key = priv_key.viewing_key();
+        let (eph, stealth_pub_key, tag) = priv_key
+            .address()
+            .derive_with_view_tag(&mut rand::thread_rng());
+        assert_eq!(viewing_key.view_tag(eph), tag);
+        assert_eq!(viewing_key.derive(eph), stealth_pub_key);
+
+        // Everything the viewing key holds, used as a spending key, opens
+        // neither the stealth key nor the nullifier of the coin.
+        let stealth_priv_key = priv_key.with_shared_secret(viewing_key.shared_secret(eph));
+        let nullifier = stealth_priv_key.nullifier(0);
+        let view_secret = PrivateKey {
+            secret: viewing_key.secret,
+        };
+        for guess in [
+            view_secret.derive(eph),
+            view_secret.with_shared_secret(viewing_key.shared_secret(eph)),
+            PrivateKey {
+                secret: viewing_key.shared_secret(eph),
+            },
+        ] {
+            assert_ne!(PublicKey::from(guess), stealth_pub_key);
+            assert_ne!(guess.nullifier(0), nullifier);
+        }
+        assert_eq!(PublicKey::from(stealth_priv_key), stealth_pub_key);
+    }
+
+    #[test]
+    fn test_malformed_keys() {
+        let valid = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng())).to_string();
+        assert_eq!(PublicKey::from_str(""), Err(ParseKeyError::InvalidLength));
+        assert_eq!(
+            PublicKey::from_str(&valid.replace("OoOo", "XoOo")),
+            Err(ParseKeyError::InvalidPrefix)
+        );
+        assert_eq!(
+            PublicKey::from_str(&format!("OoOo4{}", &valid[5..])),
+            Err(ParseKeyError::InvalidPrefix)
+        );
+        assert_eq!(
+            PublicKey::from_str(&format!("OoOo2{}", "g".repeat(64))),
+            Err(ParseKeyError::InvalidHex)
+        );
+        assert_eq!(
+            PublicKey::from_str(&format!("OoOo2{}", "f".repeat(64))),
+            Err(ParseKeyError::NotInField)
+        );
+        assert_eq!(
+            PublicKey::from_str(&format!("OoOo2{}é", &valid[5..67])),
+            Err(ParseKeyError::InvalidLength)
+        );
+        assert_eq!(
+            PrivateKey::from_str("0x1234").map(|k| k.secret),
+            Err(ParseKeyError::InvalidLength)
+        );
+    }
+
+    #[test]
+    fn test_account_keys() {
+        let entropy = Entropy::generate(&mut rand::thread_rng());
+        let main: PrivateKey = entropy.into();
+        assert_eq!(entropy.account_key(0).secret, main.secret);
+        assert_eq!(entropy.account_key(1).secret, entropy.account_key(1).secret);
+        assert_ne!(entropy.account_key(1).secret, main.secret);
+        assert_ne!(entropy.account_key(1).secret, entropy.account_key(2).secret);
+    }
+
+    #[test]
+    fn test_generate_from_seed() {
+        let seed = [7u8; 32];
+        let priv_key = PrivateKey::generate_from_seed(seed);
+        assert_eq!(
+            PublicKey::from(priv_key),
+            PublicKey::from(PrivateKey::generate_from_seed(seed))
+        );
+        assert_ne!(
+            PublicKey::from(priv_key),
+            PublicKey::from(PrivateKey::generate_from_seed([8u8; 32]))
+        );
+
+        let secret = BigUint::from_bytes_le(priv_key.secret.to_repr().as_ref());
+        assert!(secret < *ORDER);
+    }
+
     #[test]
     fn test_mul() {
         let g5_sum = *G + *G + *G + *G + *G;
@@ -0,0 +1,313 @@
+use eyre::Result;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::keys::{Entropy, PrivateKey};
+use crate::{Account, Wallet};
+
+/// Keeps the spending keys of the accounts, by label.
+pub trait KeyStore {
+    /// The key of `account`, `None` when the store doesn't hold one.
+    fn load_private_key(&self, account: &str) -> Result<Option<PrivateKey>>;
+
+    /// Keeps `priv_key` as the key of `account`, replacing the previous one.
+    fn store_private_key(&self, account: &str, priv_key: PrivateKey) -> Result<()>;
+
+    /// Keeps the entropy of the mnemonic the accounts derive from, so that
+    /// the wallet can still be recovered from the store.
+    fn store_entropy(&self, entropy: Entropy) -> Result<()>;
+}
+
+/// Where `--keystore` keeps the keys.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyStoreKind {
+    /// Along with the rest of the wallet file.
+    #[default]
+    File,
+    /// In the keychain of the OS, the wallet file holds no secret.
+    Keyring,
+}
+
+impl FromStr for KeyStoreKind {
+    type Err = eyre::Report;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(KeyStoreKind::File),
+            "keyring" => Ok(KeyStoreKind::Keyring),
+            _ => Err(eyre::Report::msg("Expected `file` or `keyring`!")),
+        }
+    }
+}
+
+impl KeyStoreKind {
+    /// The store of the keys of the wallet at `wallet_path`.
+    pub fn open(self, wallet_path: &Path) -> Box<dyn KeyStore> {
+        match self {
+            KeyStoreKind::File => Box::new(FileKeyStore::new(wallet_path)),
+            KeyStoreKind::Keyring => Box::new(KeyringKeyStore::new(wallet_path)),
+        }
+    }
+}
+
+/// The accounts listed in the wallet file. `PRIVATE_KEY_VAR` is left out,
+/// its key isn't stored anywhere.
+pub struct FileKeyStore {
+    path: PathBuf,
+}
+
+impl FileKeyStore {
+    pub fn new(path: &Path) -> Self {
+        FileKeyStore {
+            path: path.to_path_buf(),
+        }
+    }
+
+    fn wallet(&self) -> Result<Wallet> {
+        Wallet::load_with_key(&self.path, None)?
+            .ok_or(eyre::Report::msg(format!("No wallet at {:?}!", self.path)))
+    }
+}
+
+impl KeyStore for FileKeyStore {
+    fn load_private_key(&self, account: &str) -> Result<Option<PrivateKey>> {
+        Ok(self
+            .wallet()?
+            .accounts()
+            .into_iter()
+            .find(|a| a.label == account)
+            .map(|a| a.priv_key))
+    }
+
+    fn store_private_key(&self, account: &str, priv_key: PrivateKey) -> Result<()> {
+        let mut wallet = self.wallet()?;
+        // Keyless files derive no account from their entropy, listing them
+        // keeps the ones which aren't replaced.
+        wallet.accounts = wallet.accounts();
+        match wallet.accounts.iter_mut().find(|a| a.label == account) {
+            Some(listed) => listed.priv_key = priv_key,
+            None => wallet.accounts.push(Account {
+                label: account.to_string(),
+                priv_key,
+            }),
+        }
+        wallet.save(&self.path)
+    }
+
+    fn store_entropy(&self, entropy: Entropy) -> Result<()> {
+        let mut wallet = self.wallet()?;
+        wallet.entropy = Some(entropy);
+        wallet.save(&self.path)
+    }
+}
+
+const KEYRING_SERVICE: &str = "owshen";
+
+// Entry of the mnemonic, named apart from the `accountN` labels of the keys.
+const KEYRING_MNEMONIC: &str = "#mnemonic";
+
+/// The keychain of the OS: the Secret Service on Linux, the Keychain on
+/// macOS and the Credential Manager on Windows. Entries are named after the
+/// wallet file, several wallets don't share their accounts.
+pub struct KeyringKeyStore {
+    wallet_path: PathBuf,
+}
+
+impl KeyringKeyStore {
+    pub fn new(wallet_path: &Path) -> Self {
+        KeyringKeyStore {
+            wallet_path: wallet_path.to_path_buf(),
+        }
+    }
+
+    fn entry(&self, account: &str) -> Result<keyring::Entry> {
+        let user = format!("{}#{}", self.wallet_path.to_string_lossy(), account);
+        Ok(keyring::Entry::new(KEYRING_SERVICE, &user)?)
+    }
+}
+
+impl KeyStore for KeyringKeyStore {
+    fn load_private_key(&self, account: &str) -> Result<Option<PrivateKey>> {
+        match self.entry(account)?.get_password() {
+            Ok(priv_key) => PrivateKey::from_str(&priv_key).map(Some).map_err(|e| {
+                eyre::Report::msg(format!(
+                    "Invalid private key in the keyring for {}: {}",
+                    account, e
+                ))
+            }),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn store_private_key(&self, account: &str, priv_key: PrivateKey) -> Result<()> {
+        Ok(self.entry(account)?.set_password(&priv_key.to_string())?)
+    }
+
+    // Kept as the phrase, the way it was shown when the wallet was created.
+    fn store_entropy(&self, entropy: Entropy) -> Result<()> {
+        let mnemonic = entropy
+            .to_mnemonic()
+            .map_err(|e| eyre::Report::msg(format!("Invalid entropy: {}", e)))?;
+        Ok(self.entry(KEYRING_MNEMONIC)?.set_password(&mnemonic)?)
+    }
+}
+
+/// Moves the keys of `wallet` to `keystore`, leaving only what isn't secret
+/// in the wallet. The mnemonic moves too, the store then holds its only copy.
+pub fn move_keys(wallet: &mut Wallet, keystore: &dyn KeyStore) -> Result<()> {
+    for account in wallet.accounts() {
+        keystore.store_private_key(&account.label, account.priv_key)?;
+    }
+    if let Some(entropy) = wallet.entropy {
+        keystore.store_entropy(entropy)?;
+    }
+    wallet.entropy = None;
+    wallet.accounts = Vec::new();
+    Ok(())
+}
+
+/// The account `selector` picks, by index or label, with its key from
+/// `keystore`. Wallets whose keys are kept elsewhere don't list their
+/// accounts, which are then named as `derive_accounts` does. Keys the store
+/// doesn't hold are looked up in the wallet, e.g. the one of
+/// `PRIVATE_KEY_VAR`.
+pub fn select_account(
+    wallet: &Wallet,
+    selector: Option<&str>,
+    keystore: &dyn KeyStore,
+) -> Result<Account> {
+    let label = if wallet.accounts().is_empty() {
+        match selector {
+            None => "account0".to_string(),
+            Some(selector) => match selector.parse::<usize>() {
+                Ok(index) => format!("account{}", index),
+                Err(_) => selector.to_string(),
+            },
+        }
+    } else {
+        wallet.account(selector)?.label
+    };
+    match keystore.load_private_key(&label)? {
+        Some(priv_key) => Ok(Account { label, priv_key }),
+        None => wallet.account(selector),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{derive_accounts, WALLET_VERSION};
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct MockKeyStore {
+        keys: RefCell<HashMap<String, PrivateKey>>,
+        entropy: RefCell<Option<Entropy>>,
+    }
+
+    impl KeyStore for MockKeyStore {
+        fn load_private_key(&self, account: &str) -> Result<Option<PrivateKey>> {
+            Ok(self.keys.borrow().get(account).copied())
+        }
+
+        fn store_private_key(&self, account: &str, priv_key: PrivateKey) -> Result<()> {
+            self.keys.borrow_mut().insert(account.to_string(), priv_key);
+            Ok(())
+        }
+
+        fn store_entropy(&self, entropy: Entropy) -> Result<()> {
+            *self.entropy.borrow_mut() = Some(entropy);
+            Ok(())
+        }
+    }
+
+    fn wallet(accounts: u32) -> Wallet {
+        let entropy = Entropy::generate(&mut rand::thread_rng());
+        Wallet {
+            version: WALLET_VERSION,
+            entropy: Some(entropy),
+            token_contracts: vec![],
+            chain_id: None,
+            accounts: derive_accounts(&entropy, accounts),
+        }
+    }
+
+    #[test]
+    fn test_keystore_kind() {
+        assert_eq!(KeyStoreKind::default(), KeyStoreKind::File);
+        assert_eq!(
+            KeyStoreKind::from_str("keyring").unwrap(),
+            KeyStoreKind::Keyring
+        );
+        assert!(KeyStoreKind::from_str("kms").is_err());
+    }
+
+    #[test]
+    fn test_file_keystore() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wallet.json");
+        let keystore = FileKeyStore::new(&path);
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        assert!(keystore.load_private_key("account0").is_err());
+        assert!(keystore.store_private_key("account0", priv_key).is_err());
+
+        let wallet = wallet(2);
+        wallet.save(&path).unwrap();
+        let secret = |account| {
+            keystore
+                .load_private_key(account)
+                .unwrap()
+                .map(|key| key.secret)
+        };
+        assert_eq!(secret("account1"), Some(wallet.accounts[1].priv_key.secret));
+        assert_eq!(secret("savings"), None);
+
+        keystore.store_private_key("account1", priv_key).unwrap();
+        keystore.store_private_key("savings", priv_key).unwrap();
+        let stored = Wallet::load_with_key(&path, None).unwrap().unwrap();
+        assert_eq!(stored.accounts.len(), 3);
+        assert_eq!(
+            stored.accounts[0].priv_key.secret,
+            wallet.accounts[0].priv_key.secret
+        );
+        assert_eq!(secret("account1"), Some(priv_key.secret));
+        assert_eq!(secret("savings"), Some(priv_key.secret));
+        assert_eq!(stored.entropy, wallet.entropy);
+    }
+
+    #[test]
+    fn test_select_account() {
+        let keystore = MockKeyStore::default();
+        let mut wallet = wallet(2);
+        let entropy = wallet.entropy;
+        let keys = wallet.accounts();
+
+        // Nothing stored, the keys of the wallet are used.
+        let account = select_account(&wallet, Some("1"), &keystore).unwrap();
+        assert_eq!(account.priv_key.secret, keys[1].priv_key.secret);
+
+        move_keys(&mut wallet, &keystore).unwrap();
+        assert!(wallet.accounts().is_empty());
+        // The mnemonic isn't lost, the store keeps it.
+        assert_eq!(wallet.entropy, None);
+        assert_eq!(*keystore.entropy.borrow(), entropy);
+        assert_eq!(keystore.keys.borrow().len(), 2);
+        let selected = |selector| select_account(&wallet, selector, &keystore).unwrap();
+        assert_eq!(selected(None).priv_key.secret, keys[0].priv_key.secret);
+        assert_eq!(selected(Some("1")).priv_key.secret, keys[1].priv_key.secret);
+        assert_eq!(selected(Some("account1")).label, "account1");
+        assert!(select_account(&wallet, Some("2"), &keystore).is_err());
+
+        // A key given in the environment is still used.
+        let env_key = PrivateKey::generate(&mut rand::thread_rng());
+        let wallet = wallet.with_private_key(Some(env_key));
+        assert_eq!(
+            select_account(&wallet, None, &keystore)
+                .unwrap()
+                .priv_key
+                .secret,
+            env_key.secret
+        );
+    }
+}
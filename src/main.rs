@@ -1,41 +1,85 @@
+#[macro_use]
+mod transaction;
+
+mod address_book;
+mod amount;
 mod apis;
+mod artifacts;
+mod auth;
+mod bench;
+mod blinding;
+mod cli;
+mod commitment;
+mod doctor;
+mod export;
 mod fp;
 mod hash;
 mod keys;
+mod keystore;
+mod metrics;
+mod paths;
 mod poseidon;
 mod proof;
+mod qr;
+mod rate_limit;
+mod relayer;
+mod rpc;
 mod tree;
 
+use address_book::AddressBook;
+use amount::Amount;
+use apis::{ApiError, ApiQuery};
+use artifacts::PoseidonArtifacts;
 use axum::{
-    // body::Bytes,
-    body::Body,
-    extract::{self, Query},
-    http::{Response, StatusCode},
-    response::{Html, IntoResponse, Json},
-    routing::{get, get_service},
+    body::{Body, Bytes},
+    extract::{self, ws::WebSocketUpgrade, Query},
+    http::{Request, StatusCode},
+    middleware,
+    response::{Html, IntoResponse, Json, Response},
+    routing::{get, get_service, post},
     Router,
 };
 use bindings::owshen::{Owshen, Point as OwshenPoint};
 use bindings::simple_erc_20::SimpleErc20;
 use bip39::Mnemonic;
+use blinding::Blinding;
 use colored::Colorize;
 use ethers::prelude::*;
+use export::ExportFormat;
 use eyre::Result;
+use fp::Fp;
+use hash::{check_poseidon, PoseidonVariant};
 use keys::Point;
-use keys::{PrivateKey, PublicKey};
+use keys::{EphemeralKey, PrivateKey, PublicKey, ScanningKey, ViewingKey};
+use keystore::KeyStoreKind;
+use paths::Paths;
 use proof::Proof;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rate_limit::{RateLimit, RateLimiter};
+use relayer::RelayRequest;
+use rpc::RpcProvider;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::io::Write;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::{fs::read_to_string, process::Command};
 use structopt::StructOpt;
-use tokio::fs::File;
+use tokio::sync::{broadcast, Notify, RwLock};
 use tokio::task;
-use tokio_util::codec::{BytesCodec, FramedRead};
+use tokio::time::Duration;
+use tower::ServiceExt;
 use tower_http::cors::CorsLayer;
-use tower_http::services::ServeFile;
+use tower_http::services::{ServeDir, ServeFile};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::prelude::*;
+use transaction::{resolve_from, resolve_tx_type, GasOverrides, TxType};
 use tree::SparseMerkleTree;
 use webbrowser;
 
@@ -43,6 +87,7 @@ use webbrowser;
 extern crate lazy_static;
 
 const GOERLI_ENDPOINT: &str = "https://ethereum-goerli.publicnode.com";
+const TEST_SEED: [u8; 32] = [0u8; 32];
 // Initialize wallet, TODO: let secret be derived from a BIP-39 mnemonic code
 #[derive(StructOpt, Debug)]
 pub struct InitOpt {
@@ -54,6 +99,36 @@ pub struct InitOpt {
     mnemonic: Option<Mnemonic>,
     #[structopt(long)]
     test: bool,
+    #[structopt(
+        long,
+        default_value = "1",
+        help = "Number of accounts to derive from the mnemonic"
+    )]
+    accounts: u32,
+    #[structopt(
+        long,
+        help = "Transaction type (legacy/eip1559), detected from the chain if omitted"
+    )]
+    tx_type: Option<TxType>,
+    #[structopt(
+        long,
+        help = "Account paying for the transactions, the first one unlocked on the node by default"
+    )]
+    from: Option<H160>,
+    #[structopt(
+        long,
+        default_value = "file",
+        help = "Where to keep the keys (file/keyring), keyring leaves no secret in the wallet file"
+    )]
+    keystore: KeyStoreKind,
+}
+
+// A zero period would make `tokio::time::interval` panic.
+fn parse_sync_interval(s: &str) -> Result<u64, String> {
+    match s.parse::<u64>().map_err(|e| e.to_string())? {
+        0 => Err("The sync interval should be at least a second!".to_string()),
+        secs => Ok(secs),
+    }
 }
 
 // Open web wallet interface
@@ -69,6 +144,103 @@ pub struct WalletOpt {
     test: bool,
     #[structopt(long)]
     config: Option<PathBuf>,
+    #[structopt(long, help = "Enable debug endpoints")]
+    debug: bool,
+    #[structopt(long, help = "Run a watch-only wallet from a viewing key")]
+    viewing_key: Option<ViewingKey>,
+    #[structopt(
+        long,
+        default_value = "10",
+        parse(try_from_str = parse_sync_interval),
+        help = "Seconds between two chain scans"
+    )]
+    sync_interval: u64,
+    #[structopt(
+        long,
+        help = "File persisting the synced state between runs, defaults to state.json in the data directory"
+    )]
+    state_file: Option<PathBuf>,
+    #[structopt(
+        long,
+        default_value = "info",
+        help = "Log level (trace/debug/info/warn/error)"
+    )]
+    log_level: tracing::Level,
+    #[structopt(
+        long,
+        default_value = "2000",
+        help = "Blocks fetched per log query while scanning the chain"
+    )]
+    scan_chunk_size: u64,
+    #[structopt(
+        long,
+        default_value = "4",
+        help = "Block chunks fetched concurrently while scanning the chain"
+    )]
+    scan_concurrency: usize,
+    #[structopt(
+        long,
+        help = "Path of the proving key, defaults to $OWSHEN_PARAMS or the bundled one"
+    )]
+    params: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Start even if the endpoint is not on the chain the wallet was initialized on"
+    )]
+    force_chain: bool,
+    #[structopt(
+        long,
+        help = "Start even if the Owshen contract doesn't look like the one the wallet expects"
+    )]
+    force_contract: bool,
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "Proofs per minute allowed on a spend endpoint, e.g. /send=10 (default 5)"
+    )]
+    rate_limit: Vec<RateLimit>,
+    #[structopt(
+        long,
+        default_value = "120",
+        help = "Seconds given to in-flight proofs to finish on shutdown"
+    )]
+    shutdown_timeout: u64,
+    #[structopt(
+        long,
+        default_value = "6",
+        help = "Blocks a coin must be buried under before it can be spent, or a spend before its coin is dropped"
+    )]
+    min_confirmations: u64,
+    #[structopt(
+        long,
+        help = "Index or label of the account to use, the first one by default"
+    )]
+    account: Option<String>,
+    #[structopt(long, help = "Expose Prometheus metrics on /metrics")]
+    metrics: bool,
+    #[structopt(
+        long,
+        help = "Token required as `Authorization: Bearer <token>` by the spend and write endpoints"
+    )]
+    api_token: Option<String>,
+    #[structopt(long, help = "Also require the API token on read-only endpoints")]
+    auth_all: bool,
+    #[structopt(
+        long,
+        help = "Seed the randomness of the spend outputs, for reproducible tests (needs --test)"
+    )]
+    blinding_seed: Option<u64>,
+    #[structopt(
+        long,
+        help = "Don't open the web interface in a browser, only print its address"
+    )]
+    no_browser: bool,
+    #[structopt(
+        long,
+        default_value = "file",
+        help = "Where the keys are kept (file/keyring)"
+    )]
+    keystore: KeyStoreKind,
 }
 #[derive(StructOpt, Debug)]
 pub struct ConfigOpt {
@@ -80,11 +252,326 @@ pub struct ConfigOpt {
     config: Option<PathBuf>,
     #[structopt(long)]
     test: bool,
+    #[structopt(
+        long,
+        help = "Transaction type (legacy/eip1559), detected from the chain if omitted"
+    )]
+    tx_type: Option<TxType>,
+    #[structopt(long, help = "Use an already deployed Owshen contract")]
+    owshen_address: Option<H160>,
+    #[structopt(long, help = "Use an already deployed DIVE token")]
+    dive_address: Option<H160>,
+    #[structopt(long, help = "Use an already deployed Poseidon contract")]
+    poseidon_address: Option<H160>,
+    #[structopt(
+        long,
+        default_value = "poseidon4",
+        help = "Poseidon contract to deploy, it must match the circuit"
+    )]
+    poseidon_variant: PoseidonVariant,
+    #[structopt(
+        long,
+        help = "Account paying for the transactions, the first one unlocked on the node by default"
+    )]
+    from: Option<H160>,
 }
 
 // Show wallet info
 #[derive(StructOpt, Debug)]
-pub struct InfoOpt {}
+pub struct InfoOpt {
+    #[structopt(long, help = "Also print the address as a QR code")]
+    qr: bool,
+    #[structopt(
+        long,
+        help = "Index or label of the account to use, the first one by default"
+    )]
+    account: Option<String>,
+    #[structopt(
+        long,
+        default_value = "file",
+        help = "Where the keys are kept (file/keyring)"
+    )]
+    keystore: KeyStoreKind,
+}
+
+// Manage the labelled receivers of the address book
+#[derive(StructOpt, Debug)]
+pub enum AddressBookOpt {
+    Add {
+        #[structopt(long)]
+        label: String,
+        #[structopt(long)]
+        address: String,
+        #[structopt(long, help = "Replace the address of an existing label")]
+        replace: bool,
+    },
+    List,
+    Remove {
+        #[structopt(long)]
+        label: String,
+    },
+}
+
+// Send a coin to another Owshen address
+#[derive(StructOpt, Debug)]
+pub struct SendOpt {
+    #[structopt(long)]
+    db: Option<PathBuf>,
+    #[structopt(long)]
+    config: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Index or label of the account to use, the first one by default"
+    )]
+    account: Option<String>,
+    #[structopt(
+        long,
+        default_value = "file",
+        help = "Where the keys are kept (file/keyring)"
+    )]
+    keystore: KeyStoreKind,
+    #[structopt(long, help = "Index of the coin to spend")]
+    index: u64,
+    #[structopt(
+        long,
+        help = "Amount to send, in the token's smallest unit unless --decimals is given"
+    )]
+    amount: String,
+    #[structopt(
+        long,
+        help = "Decimals of the token, to give the amount in whole tokens"
+    )]
+    decimals: Option<u8>,
+    #[structopt(long, help = "Owshen address of the receiver")]
+    receiver: PublicKey,
+    #[structopt(long, help = "Print the calldata instead of broadcasting")]
+    dry_run: bool,
+    #[structopt(long)]
+    tx_type: Option<TxType>,
+    #[structopt(flatten)]
+    gas: GasOverrides,
+    #[structopt(
+        long,
+        help = "Account paying for the transactions, the first one unlocked on the node by default"
+    )]
+    from: Option<H160>,
+    #[structopt(
+        long,
+        help = "Path of the proving key, defaults to $OWSHEN_PARAMS or the bundled one"
+    )]
+    params: Option<PathBuf>,
+}
+
+// Withdraw a coin to an Ethereum address
+#[derive(StructOpt, Debug)]
+pub struct WithdrawOpt {
+    #[structopt(long)]
+    db: Option<PathBuf>,
+    #[structopt(long)]
+    config: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Index or label of the account to use, the first one by default"
+    )]
+    account: Option<String>,
+    #[structopt(
+        long,
+        default_value = "file",
+        help = "Where the keys are kept (file/keyring)"
+    )]
+    keystore: KeyStoreKind,
+    #[structopt(long, help = "Index of the coin to spend")]
+    index: u64,
+    #[structopt(
+        long,
+        help = "Amount to withdraw, in the token's smallest unit unless --decimals is given"
+    )]
+    amount: String,
+    #[structopt(
+        long,
+        help = "Decimals of the token, to give the amount in whole tokens"
+    )]
+    decimals: Option<u8>,
+    #[structopt(long, help = "Ethereum address receiving the tokens")]
+    to: H160,
+    #[structopt(long, help = "Print the calldata instead of broadcasting")]
+    dry_run: bool,
+    #[structopt(
+        long,
+        help = "URL of a relayer submitting the transaction, so that no account of yours pays its gas"
+    )]
+    relayer: Option<String>,
+    #[structopt(
+        long,
+        help = "Part of the amount paid to the relayer, in the same unit as the amount"
+    )]
+    relayer_fee: Option<String>,
+    #[structopt(long)]
+    tx_type: Option<TxType>,
+    #[structopt(flatten)]
+    gas: GasOverrides,
+    #[structopt(
+        long,
+        help = "Account paying for the transactions, the first one unlocked on the node by default"
+    )]
+    from: Option<H160>,
+    #[structopt(
+        long,
+        help = "Path of the proving key, defaults to $OWSHEN_PARAMS or the bundled one"
+    )]
+    params: Option<PathBuf>,
+}
+
+// Print the viewing key of the wallet for watch-only usage
+#[derive(StructOpt, Debug)]
+pub struct ExportViewingKeyOpt {
+    #[structopt(long)]
+    db: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Index or label of the account to use, the first one by default"
+    )]
+    account: Option<String>,
+    #[structopt(
+        long,
+        default_value = "file",
+        help = "Where the keys are kept (file/keyring)"
+    )]
+    keystore: KeyStoreKind,
+}
+
+// Print the nullifier of a coin, e.g. to audit whether it was spent
+#[derive(StructOpt, Debug)]
+pub struct NullifierOpt {
+    #[structopt(long)]
+    db: Option<PathBuf>,
+    #[structopt(long)]
+    config: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Index or label of the account to use, the first one by default"
+    )]
+    account: Option<String>,
+    #[structopt(
+        long,
+        default_value = "file",
+        help = "Where the keys are kept (file/keyring)"
+    )]
+    keystore: KeyStoreKind,
+    #[structopt(long, help = "Index of the coin")]
+    index: u64,
+    #[structopt(long, help = "Also check on-chain whether the coin is spent")]
+    check: bool,
+}
+
+// List the coins detectable for someone else's address, read-only
+#[derive(StructOpt, Debug)]
+pub struct WatchOpt {
+    #[structopt(long)]
+    config: Option<PathBuf>,
+    #[structopt(long, help = "Owshen address to watch, no private key is needed")]
+    address: PublicKey,
+}
+
+// Switch the node of an existing setup, e.g. to another RPC provider
+#[derive(StructOpt, Debug)]
+pub struct SetEndpointOpt {
+    #[structopt(help = "URL of the new node, on the chain of the wallet")]
+    endpoint: String,
+    #[structopt(long)]
+    db: Option<PathBuf>,
+    #[structopt(long)]
+    config: Option<PathBuf>,
+}
+
+// Export the coins, and optionally the history, e.g. for accounting
+#[derive(StructOpt, Debug)]
+pub struct ExportOpt {
+    #[structopt(long)]
+    db: Option<PathBuf>,
+    #[structopt(long)]
+    config: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Index or label of the account to use, the first one by default"
+    )]
+    account: Option<String>,
+    #[structopt(
+        long,
+        default_value = "file",
+        help = "Where the keys are kept (file/keyring)"
+    )]
+    keystore: KeyStoreKind,
+    #[structopt(
+        long,
+        default_value = "csv",
+        help = "Output format, only `csv` for now"
+    )]
+    format: ExportFormat,
+    #[structopt(long, help = "File to write, the standard output by default")]
+    out: Option<PathBuf>,
+    #[structopt(long, help = "Only export the coins and history of this token")]
+    token: Option<H160>,
+    #[structopt(
+        long,
+        help = "Also export the sends and withdrawals made by this wallet"
+    )]
+    history: bool,
+}
+
+// Time the generation of withdraw proofs over synthetic inputs
+#[derive(StructOpt, Debug)]
+pub struct BenchProofOpt {
+    #[structopt(long, default_value = "10", help = "Proofs to generate")]
+    iterations: usize,
+    #[structopt(long, help = "Path of the proving key")]
+    params: Option<PathBuf>,
+}
+
+// Check the wallet, the node, the contracts and the proving pipeline
+#[derive(StructOpt, Debug)]
+pub struct DoctorOpt {
+    #[structopt(long)]
+    db: Option<PathBuf>,
+    #[structopt(long)]
+    config: Option<PathBuf>,
+    #[structopt(long, help = "Path of the proving key")]
+    params: Option<PathBuf>,
+}
+
+// Print the build information
+#[derive(StructOpt, Debug)]
+pub struct VersionOpt {
+    #[structopt(long, help = "Path of the proving key to identify")]
+    params: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(version = concat!(env!("CARGO_PKG_VERSION"), " (", env!("OWSHEN_GIT_COMMIT"), ")"))]
+struct Opt {
+    #[structopt(
+        long,
+        global = true,
+        help = "Directory of the wallet, its state and logs, defaults to $OWSHEN_HOME or ~/.owshen"
+    )]
+    data_dir: Option<PathBuf>,
+    #[structopt(
+        long,
+        global = true,
+        default_value = "30",
+        help = "Seconds the node gets to answer a request"
+    )]
+    rpc_timeout: u64,
+    #[structopt(
+        long,
+        global = true,
+        help = "Directory of a Poseidon contract (poseidon4.abi, poseidon4.evm) and proving key \
+                (coin_withdraw_0001.zkey) to use instead of the bundled ones"
+    )]
+    artifacts_dir: Option<PathBuf>,
+    #[structopt(subcommand)]
+    command: OwshenCliOpt,
+}
 
 #[derive(StructOpt, Debug)]
 enum OwshenCliOpt {
@@ -92,89 +579,498 @@ enum OwshenCliOpt {
     Info(InfoOpt),
     Wallet(WalletOpt),
     Config(ConfigOpt),
+    ExportViewingKey(ExportViewingKeyOpt),
+    Send(SendOpt),
+    Withdraw(WithdrawOpt),
+    Nullifier(NullifierOpt),
+    Export(ExportOpt),
+    BenchProof(BenchProofOpt),
+    Version(VersionOpt),
+    AddressBook(AddressBookOpt),
+    Doctor(DoctorOpt),
+    Watch(WatchOpt),
+    SetEndpoint(SetEndpointOpt),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetInfoRequest {
+    // Pass `false` to leave the ABIs out, see `/abi`.
+    abi: Option<bool>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GetInfoResponse {
     address: PublicKey,
-    erc20_abi: Abi,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    erc20_abi: Option<Abi>,
     dive_contract: H160,
     owshen_contract: H160,
-    owshen_abi: Abi,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    owshen_abi: Option<Abi>,
     token_contracts: Vec<TokenInfo>,
     is_test: bool,
+    // Always set by `/info`, see `apis::info`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tree_root: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    num_commitments: Option<u64>,
+    // Set by `/info` unless the node can't tell, see `apis::info`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gas_account: Option<H160>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gas_balance: Option<U256>,
+    // The proving key, set by `/info` when it has been read, see
+    // `apis::CircuitInfo`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    circuit_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    params_hash: Option<H256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    num_public_inputs: Option<u32>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
-pub struct GetCoinsResponse {
-    coins: Vec<Coin>,
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetAbiResponse {
+    owshen_abi: Abi,
+    erc20_abi: Abi,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetVersionResponse {
+    version: String,
+    git_commit: String,
+    target: String,
+    // keccak256 of the proving key
+    params_hash: Option<H256>,
+}
+
+// An item of the `coins` array `/coins` responds with, see `apis::CoinsView`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ListedCoin {
+    #[serde(flatten)]
+    pub coin: Coin,
+    // `None` for coins of unknown depth, these are spendable.
+    pub confirmations: Option<u64>,
+    // Not buried under enough blocks to be spent yet.
+    pub pending: bool,
+    // Spent in a block not buried under enough blocks yet, the spend may
+    // still be reorged. See `Context::pending_nullifiers`.
+    #[serde(default)]
+    pub pending_spend: bool,
+    // Label set through `/memo`, local to the wallet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+// A coin found by `owshen watch`, see `apis::watch_chain`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchedCoin {
+    pub index: U256,
+    pub uint_token: H160,
+    pub amount: Amount,
+    pub commitment: U256,
+    pub block_number: Option<U64>,
+    pub tx_hash: Option<H256>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetHealthResponse {
+    synced: bool,
+    last_synced_block: Option<U64>,
+    scanned_coins: usize,
+    owned_coins: usize,
+    // Coins of the state file dropped for not matching their commitment,
+    // scanned ones always match it, see `apis::coins::detect`.
+    rejected_coins: usize,
+    reorgs_handled: u64,
+    // Spent nullifiers, confirmed or not.
+    nullifiers_tracked: usize,
+}
+
+// Progress of a running chain scan.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SyncProgress {
+    current_block: U64,
+    target_block: U64,
+    found_coins: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetSyncStatusResponse {
+    syncing: bool,
+    progress: Option<SyncProgress>,
+    last_synced_block: Option<U64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetNullifierResponse {
+    index: U256,
+    nullifier: U256,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GetStealthRequest {
-    address: String,
+    address: PublicKey,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GetStealthResponse {
     address: Point,
     ephemeral: Point,
+    // To pass along the deposit, see `keys::view_tag`.
+    view_tag: u8,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GetWithdrawRequest {
     index: U256,
-    pub address: String,
+    pub address: PublicKey,
     pub desire_amount: String,
+    // Receiver of the withdrawn tokens, bound by the proof.
+    pub to: H160,
+    // Part of `desire_amount` paid to whoever submits the transaction, e.g. a
+    // relayer, in the same unit. Bound by the proof too.
+    #[serde(default)]
+    pub relayer_fee: Option<String>,
+    // When given, amounts are in whole tokens instead of the token's smallest
+    // unit, with this many decimals unless the wallet knows the token's.
+    #[serde(default)]
+    pub decimals: Option<u8>,
+    // When given, the coin at `index` must hold this token.
+    #[serde(default)]
+    pub token: Option<H160>,
+    // Overrides of the gas the transaction would be sent with.
+    #[serde(flatten)]
+    pub gas: GasOverrides,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GetWithdrawResponse {
     proof: Proof,
     pub token: H160,
-    pub amount: U256,
+    pub amount: Amount,
     pub obfuscated_remaining_amount: U256,
     pub nullifier: U256,
     pub commitment: U256,
     pub ephemeral: Point,
+    pub view_tag: u8,
+    // Root of the tree the proof was generated against, and the last synced
+    // block that tree reflects.
+    pub root: U256,
+    pub root_block: Option<U64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_amount: Option<Amount>,
+    // What the proof lets the contract pay out: `withdrawn_amount`, of which
+    // `relayer_fee` goes to the sender of the transaction and the rest to `to`.
+    pub to: H160,
+    pub withdrawn_amount: Amount,
+    pub relayer_fee: Amount,
+    // The overrides of the request, to send the transaction with.
+    #[serde(default, skip_serializing_if = "GasOverrides::is_empty")]
+    pub gas: GasOverrides,
+    // The transaction packaged for a relayer, see `RelayRequest`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay: Option<RelayRequest>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GetSendRequest {
     index: U256,
     pub new_amount: String,
+    // Left empty when sending to `receiver_stealth_*`.
+    #[serde(default)]
     pub receiver_address: String,
-    pub address: String,
+    pub address: PublicKey,
+    // When given, amounts are in whole tokens instead of the token's smallest
+    // unit, with this many decimals unless the wallet knows the token's.
+    #[serde(default)]
+    pub decimals: Option<u8>,
+    // When given, the coin at `index` must hold this token.
+    #[serde(default)]
+    pub token: Option<H160>,
+    // Stealth key and ephemeral key of the receiver derived by someone else,
+    // e.g. through `/stealth`, in the decimal coordinates of `Point`.
+    #[serde(default)]
+    pub receiver_stealth_x: Option<Fp>,
+    #[serde(default)]
+    pub receiver_stealth_y: Option<Fp>,
+    #[serde(default)]
+    pub receiver_ephemeral_x: Option<Fp>,
+    #[serde(default)]
+    pub receiver_ephemeral_y: Option<Fp>,
+    #[serde(default)]
+    pub receiver_view_tag: Option<u8>,
+    // Overrides of the gas the transaction would be sent with.
+    #[serde(flatten)]
+    pub gas: GasOverrides,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GetSendResponse {
     proof: Proof,
     pub token: H160,
-    pub amount: U256,
+    pub amount: Amount,
     pub nullifier: U256,
     pub receiver_commitment: U256,
     pub sender_commitment: U256,
     pub sender_ephemeral: Point,
     pub receiver_ephemeral: Point,
+    pub sender_view_tag: u8,
+    pub receiver_view_tag: u8,
     pub obfuscated_receiver_amount: U256,
     pub obfuscated_sender_amount: U256,
+    // Root of the tree the proof was generated against, and the last synced
+    // block that tree reflects.
+    pub root: U256,
+    pub root_block: Option<U64>,
+    // The overrides of the request, to send the transaction with.
+    #[serde(default, skip_serializing_if = "GasOverrides::is_empty")]
+    pub gas: GasOverrides,
+}
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetConfirmationRequest {
+    pub tx_hash: TxHash,
+    // Nullifier of the spent coin, links the transaction to its history entry.
+    #[serde(default)]
+    pub nullifier: Option<U256>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryKind {
+    Send,
+    Withdraw,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TxStatus {
+    Pending,
+    Confirmed,
+    Failed,
+    // The proof was never reported as broadcast, see `history::PROOF_EXPIRY`.
+    Expired,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HistoryEntry {
+    nullifier: U256,
+    // Unknown until the client reports the broadcast transaction.
+    tx_hash: Option<TxHash>,
+    kind: HistoryKind,
+    token: H160,
+    amount: Amount,
+    timestamp: u64,
+    status: TxStatus,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PostAddressBookAddRequest {
+    label: String,
+    address: String,
+    // An existing label is only replaced when asked for.
+    #[serde(default)]
+    replace: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetMemoRequest {
+    // All the memos when omitted.
+    commitment: Option<U256>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PostMemoRequest {
+    commitment: U256,
+    // An empty memo removes the label.
+    memo: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetMemoResponse {
+    memos: BTreeMap<U256, String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PostAddressBookRemoveRequest {
+    label: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetAddressBookResponse {
+    entries: BTreeMap<String, PublicKey>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct GetCoinsRequest {
+    #[serde(default)]
+    offset: usize,
+    // As many as a page holds when unset, see `apis::coins`.
+    limit: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetHistoryRequest {
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetExportRequest {
+    #[serde(default)]
+    format: ExportFormat,
+    token: Option<H160>,
+    #[serde(default)]
+    history: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetBalancesRequest {
+    token: Option<H160>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TokenBalance {
+    address: H160,
+    // `None` for tokens the wallet doesn't know.
+    symbol: Option<String>,
+    decimals: Option<u8>,
+    // In the smallest unit of the token.
+    confirmed: Amount,
+    // Coins not buried under enough blocks to be spent yet.
+    pending: Amount,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetBalancesResponse {
+    balances: Vec<TokenBalance>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetHistoryResponse {
+    entries: Vec<HistoryEntry>,
+    total: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GetConfirmationResponse {
+    pub tx_hash: TxHash,
+    pub block_number: U64,
+    pub success: bool,
+    pub revert_reason: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetEstimateWithdrawRequest {
+    index: U256,
+    pub address: PublicKey,
+    pub desire_amount: String,
+    pub to: H160,
+    #[serde(default)]
+    pub relayer_fee: Option<String>,
+    // When given, amounts are in whole tokens instead of the token's smallest
+    // unit, with this many decimals unless the wallet knows the token's.
+    #[serde(default)]
+    pub decimals: Option<u8>,
+    // Overrides of the gas the transaction would be sent with.
+    #[serde(flatten)]
+    pub gas: GasOverrides,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetEstimateResponse {
+    pub gas: U256,
+    pub gas_price: U256,
+    pub cost: U256,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetMultiSendRequest {
+    pub token: H160,
+    pub new_amount: String,
+    pub receiver_address: String,
+    pub address: PublicKey,
+    // When given, amounts are in whole tokens instead of the token's smallest
+    // unit, with this many decimals unless the wallet knows the token's.
+    #[serde(default)]
+    pub decimals: Option<u8>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SendPart {
+    pub index: U256,
+    pub amount: Amount,
 }
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetMultiSendResponse {
+    pub parts: Vec<SendPart>,
+    pub send: GetSendResponse,
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Coin {
     pub index: U256,
     pub uint_token: H160,
-    pub amount: U256,
-    pub priv_key: PrivateKey,
+    pub amount: Amount,
+    // Coins detected with a viewing key can't be opened, see `ViewingKey`.
+    pub priv_key: Option<PrivateKey>,
     pub pub_key: PublicKey,
-    pub nullifier: U256,
+    pub nullifier: Option<U256>,
     pub commitment: U256,
+    // Block and transaction the coin was created in, unknown for coins of
+    // older state files.
+    #[serde(default)]
+    pub block_number: Option<U64>,
+    #[serde(default)]
+    pub tx_hash: Option<H256>,
+    // Key the coin was sent with. State files don't keep the stealth key,
+    // it is derived again from this one when they are loaded.
+    #[serde(default)]
+    pub ephemeral: Option<EphemeralKey>,
+}
+
+impl Coin {
+    /// Whether the amount, token and key of the coin hash to its on-chain
+    /// commitment. The circuit recomputes it the same way, a coin failing
+    /// this can't be spent.
+    pub fn is_consistent(&self) -> bool {
+        self.priv_key
+            .is_none_or(|priv_key| PublicKey::from(priv_key) == self.pub_key)
+            && commitment::commitment(
+                self.amount.into(),
+                &self.pub_key,
+                h160_to_u256(self.uint_token),
+            )
+            .is_ok_and(|commitment| commitment == self.commitment)
+    }
+
+    /// Whether the nullifier of the coin is among `nullifiers`. Coins whose
+    /// nullifier is unknown are never found spent.
+    pub fn is_spent(&self, nullifiers: &HashSet<U256>) -> bool {
+        self.nullifier
+            .is_some_and(|nullifier| nullifiers.contains(&nullifier))
+    }
+
+    /// The stealth key the coin is spent with, and the nullifier the spend
+    /// reveals.
+    pub fn spending_key(&self) -> Result<(PrivateKey, U256), ApiError> {
+        match (self.priv_key, self.nullifier) {
+            (Some(priv_key), Some(nullifier)) => Ok((priv_key, nullifier)),
+            _ => Err(ApiError::Forbidden(format!(
+                "Coin with index {} was detected with a viewing key, it can't be spent!",
+                self.index
+            ))),
+        }
+    }
 }
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Send {
     pub index: U256,
     pub token_address: H160,
-    pub amount: U256,
+    pub amount: Amount,
     pub commitment: U256,
 }
 
@@ -182,11 +1078,224 @@ pub struct Send {
 pub struct TokenInfo {
     token_address: H160,
     symbol: String,
+    // Wallets created before decimals were recorded only held 18 decimals
+    // test tokens.
+    #[serde(default = "default_decimals")]
+    decimals: u8,
 }
+
+fn default_decimals() -> u8 {
+    18
+}
+/// Schema version of the wallet files written by this build.
+const WALLET_VERSION: u32 = 1;
+
+/// Spending key taking precedence over the ones of the wallet file, which
+/// then doesn't need to hold any secret.
+const PRIVATE_KEY_VAR: &str = "OWSHEN_PRIVATE_KEY";
+
+/// Parses the value of `PRIVATE_KEY_VAR`, unset and empty alike mean no key.
+fn private_key_from(value: Option<&str>) -> Result<Option<PrivateKey>> {
+    match value.map(str::trim).filter(|value| !value.is_empty()) {
+        None => Ok(None),
+        Some(value) => PrivateKey::from_str(value).map(Some).map_err(|e| {
+            eyre::Report::msg(format!("Invalid private key in {}: {}", PRIVATE_KEY_VAR, e))
+        }),
+    }
+}
+
+fn private_key_from_env() -> Result<Option<PrivateKey>> {
+    match std::env::var_os(PRIVATE_KEY_VAR) {
+        None => Ok(None),
+        Some(value) => private_key_from(Some(value.to_str().ok_or(eyre::Report::msg(format!(
+            "{} is not valid unicode!",
+            PRIVATE_KEY_VAR
+        )))?)),
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct Wallet {
-    entropy: Entropy,
+    // Files predating the version field are version 0.
+    #[serde(default)]
+    version: u32,
+    // Left out when the key comes from `PRIVATE_KEY_VAR`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    entropy: Option<Entropy>,
     token_contracts: Vec<TokenInfo>,
+    // Wallets created before chain ids were recorded don't have one.
+    #[serde(default)]
+    chain_id: Option<U256>,
+    // Wallets created before accounts existed only use the first key of
+    // their mnemonic.
+    #[serde(default)]
+    accounts: Vec<Account>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Account {
+    label: String,
+    priv_key: PrivateKey,
+}
+
+impl Wallet {
+    fn accounts(&self) -> Vec<Account> {
+        if self.accounts.is_empty() {
+            self.entropy
+                .map(|entropy| derive_accounts(&entropy, 1))
+                .unwrap_or_default()
+        } else {
+            self.accounts.clone()
+        }
+    }
+
+    /// Selects an account by index or label, the first one by default.
+    fn account(&self, selector: Option<&str>) -> Result<Account> {
+        let accounts = self.accounts();
+        if accounts.is_empty() {
+            return Err(eyre::Report::msg(format!(
+                "The wallet holds no key, set {}!",
+                PRIVATE_KEY_VAR
+            )));
+        }
+        let account = match selector {
+            None => accounts.first(),
+            Some(selector) => match selector.parse::<usize>() {
+                Ok(index) => accounts.get(index),
+                Err(_) => accounts.iter().find(|a| a.label == selector),
+            },
+        };
+        account.cloned().ok_or(eyre::Report::msg(format!(
+            "No account {} in the wallet!",
+            selector.unwrap_or_default()
+        )))
+    }
+
+    /// Adds `token` to the tokens of the wallet, which are kept sorted by
+    /// symbol then address. A token whose address is already listed is
+    /// refused.
+    fn add_token(&mut self, token: TokenInfo) -> Result<()> {
+        if let Some(listed) = self
+            .token_contracts
+            .iter()
+            .find(|t| t.token_address == token.token_address)
+        {
+            return Err(eyre::Report::msg(format!(
+                "Token {:?} is already in the wallet as {}!",
+                token.token_address, listed.symbol
+            )));
+        }
+        self.token_contracts.push(token);
+        sort_tokens(&mut self.token_contracts);
+        Ok(())
+    }
+
+    /// Replaces the accounts of the file with the single `priv_key`, if any.
+    /// Only in memory, the wallet file is never written back.
+    fn with_private_key(mut self, priv_key: Option<PrivateKey>) -> Self {
+        if let Some(priv_key) = priv_key {
+            self.accounts = vec![Account {
+                label: "env".to_string(),
+                priv_key,
+            }];
+        }
+        self
+    }
+
+    /// Parses a wallet file, upgrading older schemas to `WALLET_VERSION`.
+    /// Files of a newer schema are refused: their fields may not mean what
+    /// this build expects.
+    fn from_json(json: &str) -> Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(json)?;
+        let version = match value.get("version") {
+            None => 0,
+            Some(version) => version
+                .as_u64()
+                .and_then(|version| u32::try_from(version).ok())
+                .ok_or(eyre::Report::msg(format!(
+                    "Invalid wallet version {}!",
+                    version
+                )))?,
+        };
+        if version > WALLET_VERSION {
+            return Err(eyre::Report::msg(format!(
+                "The wallet file has schema version {} but this build only supports up to version {}, upgrade owshen to open it!",
+                version, WALLET_VERSION
+            )));
+        }
+        let mut wallet: Wallet = serde_json::from_value(value).map_err(|e| {
+            eyre::Report::msg(format!(
+                "Invalid wallet file (schema version {}): {}",
+                version, e
+            ))
+        })?;
+        wallet.migrate().map_err(|e| {
+            eyre::Report::msg(format!(
+                "Invalid wallet file (schema version {}): {}",
+                version, e
+            ))
+        })?;
+        Ok(wallet)
+    }
+
+    fn migrate(&mut self) -> Result<()> {
+        if self.version == 0 {
+            // Version 1 lists the accounts, version 0 wallets only used the
+            // first key of their mnemonic.
+            if self.accounts.is_empty() {
+                let entropy = self
+                    .entropy
+                    .ok_or(eyre::Report::msg("missing field `entropy`"))?;
+                self.accounts = derive_accounts(&entropy, 1);
+            }
+            self.version = 1;
+        }
+        // Files written by hand may list a token twice, the first entry is
+        // kept.
+        let mut seen = HashSet::new();
+        self.token_contracts
+            .retain(|token| seen.insert(token.token_address));
+        sort_tokens(&mut self.token_contracts);
+        Ok(())
+    }
+
+    /// Loads the wallet at `path`, `None` when there is no wallet yet. The
+    /// key in `PRIVATE_KEY_VAR`, if set, overrides the ones of the file.
+    fn load(path: &Path) -> Result<Option<Self>> {
+        Wallet::load_with_key(path, private_key_from_env()?)
+    }
+
+    fn load_with_key(path: &Path, priv_key: Option<PrivateKey>) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        Wallet::from_json(&std::fs::read_to_string(path)?)
+            .map(|wallet| Some(wallet.with_private_key(priv_key)))
+            .map_err(|e| eyre::Report::msg(format!("Cannot load {:?}: {}", path, e)))
+    }
+
+    /// Writes the wallet to `path` atomically, a crash halfway through would
+    /// otherwise lose the keys.
+    fn save(&self, path: &Path) -> Result<()> {
+        write_atomic(path, serde_json::to_string(self)?.as_bytes())
+    }
+}
+
+fn sort_tokens(tokens: &mut [TokenInfo]) {
+    tokens.sort_by(|a, b| {
+        a.symbol
+            .cmp(&b.symbol)
+            .then(a.token_address.cmp(&b.token_address))
+    });
+}
+
+fn derive_accounts(entropy: &Entropy, count: u32) -> Vec<Account> {
+    (0..count)
+        .map(|index| Account {
+            label: format!("account{}", index),
+            priv_key: entropy.account_key(index),
+        })
+        .collect()
 }
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct Config {
@@ -196,6 +1305,15 @@ struct Config {
     owshen_contract_address: H160,
     owshen_contract_abi: Abi,
     erc20_abi: Abi,
+    // keccak256 of the Owshen contract code, older configs don't record it.
+    #[serde(default)]
+    owshen_code_hash: Option<H256>,
+    #[serde(default)]
+    poseidon_variant: PoseidonVariant,
+    // Hasher of the Owshen contract, unknown when an existing Owshen
+    // contract was configured without it and in older configs.
+    #[serde(default)]
+    poseidon_contract_address: Option<H160>,
 }
 
 impl Default for Config {
@@ -207,73 +1325,489 @@ impl Default for Config {
             owshen_contract_address: H160::default(),
             owshen_contract_abi: Abi::default(),
             erc20_abi: Abi::default(),
+            owshen_code_hash: None,
+            poseidon_variant: PoseidonVariant::default(),
+            poseidon_contract_address: None,
         }
     }
 }
 
+/// The wallet state as handed to the handlers, the sync loop and the proving
+/// tasks.
+pub type SharedContext = Arc<RwLock<Context>>;
+
+// Shared as a `SharedContext`, with a `tokio` lock so waiting for it
+// never blocks a runtime thread. Handlers only read it, the sync loop is the
+// only writer. Guards must not be held across an `.await` (or a proof
+// generation): copy what is needed out of the context and drop the guard.
 pub struct Context {
-    coins: Vec<Coin>,
+    owshen_contract: H160,
+    // Replaced as a whole by every sync, so that `/coins` can keep serving
+    // the previous ones without copying them.
+    coins: Arc<Vec<Coin>>,
     tree: SparseMerkleTree,
+    spent_nullifiers: HashSet<U256>,
+    // Nullifiers spent in blocks not buried under `min_confirmations` yet,
+    // with those blocks. Their coins are kept until then, a reorg may still
+    // undo the spend.
+    pending_nullifiers: HashMap<U256, U64>,
+    watch_only: bool,
+    // Block up to which the chain has been scanned, `None` before the first sync.
+    last_synced_block: Option<U64>,
+    scanned_coins: usize,
+    owned_coins: usize,
+    // Coins of the state file dropped for not matching their commitment,
+    // scanned ones always match it, see `apis::coins::detect`.
+    rejected_coins: usize,
+    // Progress of the scan in flight, `None` between two scans.
+    sync_progress: Option<SyncProgress>,
+    // Sends and withdrawals made through this wallet, oldest first.
+    history: Vec<HistoryEntry>,
+    // Persisted on its own, it doesn't depend on the synced contract.
+    address_book: AddressBook,
+    // Blocks a coin must be buried under before it can be spent.
+    min_confirmations: u64,
+    // Randomness of the outputs built by `/send` and `/withdraw`.
+    blinding: Blinding,
+    // Hashes of the last synced heads, to notice when the chain reorganized
+    // under them.
+    recent_blocks: BTreeMap<U64, H256>,
+    reorgs_handled: u64,
+    // Local labels of coins, sent or received, by commitment. Shared with
+    // `/coins` like the coins.
+    memos: Arc<HashMap<U256, String>>,
+    // Decimals of the tokens of the wallet, amounts of the others aren't
+    // checked against them.
+    token_decimals: HashMap<H160, u8>,
+    // Changed since the last save, an atomic since saving only needs a
+    // read guard.
+    dirty: AtomicBool,
 }
 
-const PARAMS_FILE: &str = "contracts/circuits/coin_withdraw_0001.zkey";
+// Serializable state of a `Context`, lets the wallet serve its coins right
+// after a restart instead of waiting for the first chain scan.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ContextSnapshot {
+    owshen_contract: H160,
+    coins: Vec<Coin>,
+    spent_nullifiers: HashSet<U256>,
+    #[serde(default)]
+    pending_nullifiers: HashMap<U256, U64>,
+    leaves: Vec<(u64, Fp)>,
+    last_synced_block: Option<U64>,
+    #[serde(default)]
+    scanned_coins: usize,
+    #[serde(default)]
+    owned_coins: usize,
+    #[serde(default)]
+    rejected_coins: usize,
+    #[serde(default)]
+    history: Vec<HistoryEntry>,
+    #[serde(default)]
+    memos: HashMap<U256, String>,
+}
 
-fn u256_to_h160(u256: U256) -> H160 {
-    let mut bytes: [u8; 32] = [0u8; 32];
-    u256.to_big_endian(&mut bytes);
-    let address_bytes: &[u8] = &bytes[12..32]; // Taking the last 20 bytes for ethereum address
-    H160::from_slice(address_bytes)
+/// Number of blocks mined on top of the one `coin` was created in, itself
+/// included, as of `head`.
+pub fn confirmations(head: Option<U64>, coin: &Coin) -> Option<u64> {
+    Some(block_confirmations(head?, coin.block_number?))
 }
 
-fn h160_to_u256(h160_val: H160) -> U256 {
-    let mut bytes = [0u8; 32];
-    bytes[12..32].copy_from_slice(h160_val.as_bytes());
+/// Whether `coin` is buried under `min_confirmations` blocks as of `head`.
+/// Coins of unknown blocks are, they predate the confirmations.
+pub fn is_spendable(head: Option<U64>, min_confirmations: u64, coin: &Coin) -> bool {
+    confirmations(head, coin).is_none_or(|confirmations| confirmations >= min_confirmations)
+}
 
-    U256::from_big_endian(&bytes)
+/// Whether `coin` is spent in a block not buried under enough blocks yet,
+/// i.e. its nullifier is among `pending_nullifiers`.
+pub fn is_pending_spend(pending_nullifiers: &HashMap<U256, U64>, coin: &Coin) -> bool {
+    coin.nullifier
+        .is_some_and(|nullifier| pending_nullifiers.contains_key(&nullifier))
 }
 
-fn handle_error<T: IntoResponse>(result: Result<T, eyre::Report>) -> impl IntoResponse {
-    match result {
-        Ok(a) => a.into_response(),
-        Err(e) => {
-            let error_message = format!("Internal server error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_message)).into_response()
+/// Number of blocks mined on top of `block`, itself included, as of `head`.
+pub fn block_confirmations(head: U64, block: U64) -> u64 {
+    (head.as_u64() + 1).saturating_sub(block.as_u64())
+}
+
+impl Context {
+    pub fn new(owshen_contract: H160, watch_only: bool) -> Self {
+        Context {
+            owshen_contract,
+            coins: Arc::default(),
+            tree: SparseMerkleTree::new(16),
+            spent_nullifiers: HashSet::new(),
+            pending_nullifiers: HashMap::new(),
+            watch_only,
+            last_synced_block: None,
+            scanned_coins: 0,
+            owned_coins: 0,
+            rejected_coins: 0,
+            sync_progress: None,
+            history: vec![],
+            address_book: AddressBook::default(),
+            min_confirmations: 0,
+            blinding: Blinding::Secure,
+            recent_blocks: BTreeMap::new(),
+            reorgs_handled: 0,
+            memos: Arc::default(),
+            token_decimals: HashMap::new(),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Notes that the persisted state changed, for `save_if_dirty`.
+    pub fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Number of blocks mined on top of the one `coin` was created in,
+    /// itself included, as of the last sync.
+    pub fn confirmations(&self, coin: &Coin) -> Option<u64> {
+        confirmations(self.last_synced_block, coin)
+    }
+
+    pub fn is_spendable(&self, coin: &Coin) -> bool {
+        is_spendable(self.last_synced_block, self.min_confirmations, coin)
+    }
+
+    /// Whether `coin` is spent in a block which may still be reorged, see
+    /// `pending_nullifiers`.
+    pub fn is_pending_spend(&self, coin: &Coin) -> bool {
+        is_pending_spend(&self.pending_nullifiers, coin)
+    }
+
+    /// Nullifiers which can't be spent again: spent on chain, or revealed by a
+    /// proof of the wallet whose transaction is still pending. The latter are
+    /// kept in the history, which is saved with the state, so that a restart
+    /// doesn't forget them before the spend is seen on chain.
+    pub fn used_nullifiers(&self) -> HashSet<U256> {
+        self.history
+            .iter()
+            .filter(|entry| entry.status == TxStatus::Pending)
+            .map(|entry| entry.nullifier)
+            .chain(self.spent_nullifiers.iter().copied())
+            .collect()
+    }
+
+    /// Refuses indices beyond the leaves of the tree, no coin can be there
+    /// and the proofs only take the low bits of the index.
+    pub fn check_index(&self, index: U256) -> Result<(), ApiError> {
+        if index >= U256::from(self.tree.capacity()) {
+            return Err(ApiError::BadRequest(format!(
+                "Invalid coin index: {} is beyond the {} leaves of the tree!",
+                index,
+                self.tree.capacity()
+            )));
+        }
+        Ok(())
+    }
+
+    /// The coin at `index` if the wallet owns it and can spend it now. When
+    /// `token` is given, the coin must hold that token.
+    pub fn coin_to_spend(&self, index: U256, token: Option<H160>) -> Result<Coin, ApiError> {
+        self.check_index(index)?;
+        let coin =
+            self.coins
+                .iter()
+                .find(|coin| coin.index == index)
+                .ok_or(ApiError::CoinNotFound(format!(
+                    "The wallet has no coin with index {}!",
+                    index
+                )))?;
+        if coin.is_spent(&self.used_nullifiers()) {
+            return Err(ApiError::BadRequest(format!(
+                "Coin with index {} has already been spent!",
+                index
+            )));
+        }
+        if let Some(token) = token.filter(|token| *token != coin.uint_token) {
+            return Err(ApiError::BadRequest(format!(
+                "Coin with index {} holds token {:?}, not {:?}!",
+                index, coin.uint_token, token
+            )));
+        }
+        self.ensure_spendable(coin)?;
+        Ok(*coin)
+    }
+
+    /// Records the decimals of `tokens`, see `amount_decimals`.
+    pub fn set_token_decimals(&mut self, tokens: &[TokenInfo]) {
+        self.token_decimals = tokens
+            .iter()
+            .map(|token| (token.token_address, token.decimals))
+            .collect();
+    }
+
+    /// The decimals a request amount of `token` is in: none for the smallest
+    /// unit, else the ones the client gave. When the wallet knows the token,
+    /// they must be its own, an amount scaled by others would be off by
+    /// orders of magnitude.
+    pub fn amount_decimals(
+        &self,
+        token: H160,
+        decimals: Option<u8>,
+    ) -> Result<Option<u8>, ApiError> {
+        match (decimals, self.token_decimals.get(&token)) {
+            (Some(decimals), Some(&token_decimals)) if decimals != token_decimals => {
+                Err(ApiError::BadRequest(format!(
+                    "Token {:?} has {} decimals, not {}!",
+                    token, token_decimals, decimals
+                )))
+            }
+            _ => Ok(decimals),
+        }
+    }
+
+    /// Parses a request amount of `token`, see `amount_decimals`.
+    pub fn parse_amount(
+        &self,
+        token: H160,
+        amount: &str,
+        decimals: Option<u8>,
+    ) -> Result<Amount, ApiError> {
+        amount::parse_request_amount(amount, self.amount_decimals(token, decimals)?)
+    }
+
+    /// Refuses `amount` if it is more precise than `token`, see
+    /// `amount::check_precision`, or in other decimals than the token's.
+    pub fn ensure_precision(
+        &self,
+        token: H160,
+        amount: &str,
+        decimals: Option<u8>,
+    ) -> Result<(), ApiError> {
+        let decimals = self.amount_decimals(token, decimals)?;
+        match self.token_decimals.get(&token) {
+            Some(token_decimals) => amount::check_precision(amount, decimals, *token_decimals),
+            None => Ok(()),
+        }
+    }
+
+    pub fn ensure_spendable(&self, coin: &Coin) -> Result<(), ApiError> {
+        if self.is_pending_spend(coin) {
+            return Err(ApiError::BadRequest(format!(
+                "Coin with index {} is spent by a transaction awaiting its confirmations!",
+                coin.index
+            )));
+        }
+        if self.is_spendable(coin) {
+            return Ok(());
+        }
+        Err(ApiError::BadRequest(format!(
+            "Coin with index {} has {} of the {} confirmations required!",
+            coin.index,
+            self.confirmations(coin).unwrap_or_default(),
+            self.min_confirmations
+        )))
+    }
+
+    /// The state to save. The stealth keys of the coins are left out, the
+    /// state file holds no secret: `restore` derives them again.
+    pub fn snapshot(&self) -> ContextSnapshot {
+        ContextSnapshot {
+            owshen_contract: self.owshen_contract,
+            coins: self
+                .coins
+                .iter()
+                .map(|coin| Coin {
+                    priv_key: None,
+                    ..*coin
+                })
+                .collect(),
+            spent_nullifiers: self.spent_nullifiers.clone(),
+            pending_nullifiers: self.pending_nullifiers.clone(),
+            leaves: self.tree.leaves(),
+            last_synced_block: self.last_synced_block,
+            scanned_coins: self.scanned_coins,
+            owned_coins: self.owned_coins,
+            rejected_coins: self.rejected_coins,
+            history: self.history.clone(),
+            memos: self.memos.as_ref().clone(),
+        }
+    }
+
+    /// Restores the state saved by `snapshot`, deriving the stealth keys of
+    /// the coins with `key`.
+    pub fn restore(&mut self, snapshot: ContextSnapshot, key: &ScanningKey) -> Result<()> {
+        if snapshot.owshen_contract != self.owshen_contract {
+            return Err(eyre::Report::msg(format!(
+                "Snapshot was taken for contract {:?}, expected {:?}!",
+                snapshot.owshen_contract, self.owshen_contract
+            )));
+        }
+        // Coins of older state files don't know their ephemeral key, the
+        // chain is then scanned again.
+        let open = |coin: Coin| match key.spending_key() {
+            Some(priv_key) => coin
+                .ephemeral
+                .and_then(|eph| priv_key.stealth_key(eph, &coin.pub_key))
+                .map(|stealth_priv| Coin {
+                    priv_key: Some(stealth_priv),
+                    ..coin
+                })
+                .ok_or(eyre::Report::msg(format!(
+                    "Cannot derive the key of coin {}!",
+                    coin.index
+                ))),
+            None => Ok(coin),
+        };
+        let coins = snapshot
+            .coins
+            .into_iter()
+            .map(open)
+            .collect::<Result<Vec<_>>>()?;
+        let mut tree = SparseMerkleTree::new(self.tree.depth());
+        tree.insert_many(&snapshot.leaves);
+        // State files are as trustworthy as the disk they are on.
+        let (coins, rejected): (Vec<Coin>, Vec<Coin>) =
+            coins.into_iter().partition(Coin::is_consistent);
+        for coin in rejected.iter() {
+            tracing::warn!(
+                "Dropping coin {} of the state file, it doesn't match its commitment",
+                coin.index
+            );
+        }
+        self.coins = Arc::new(coins);
+        self.rejected_coins = snapshot.rejected_coins + rejected.len();
+        self.tree = tree;
+        self.spent_nullifiers = snapshot.spent_nullifiers;
+        self.pending_nullifiers = snapshot.pending_nullifiers;
+        self.last_synced_block = snapshot.last_synced_block;
+        self.scanned_coins = snapshot.scanned_coins;
+        self.owned_coins = snapshot.owned_coins;
+        self.history = snapshot.history;
+        self.memos = Arc::new(snapshot.memos);
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_atomic(path, serde_json::to_string(&self.snapshot())?.as_bytes())?;
+        self.dirty.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Saves the state unless nothing changed since the last save. Returns
+    /// whether it was written.
+    pub fn save_if_dirty(&self, path: &Path) -> Result<bool> {
+        if !self.dirty.load(Ordering::Relaxed) {
+            return Ok(false);
         }
+        self.save(path)?;
+        Ok(true)
+    }
+
+    pub fn load(&mut self, path: &Path, key: &ScanningKey) -> Result<()> {
+        let snapshot: ContextSnapshot = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        self.restore(snapshot, key)
     }
 }
 
-async fn serve_index(test: bool) -> impl IntoResponse {
-    let app_dir_path = std::env::var("APPDIR").unwrap_or_else(|_| "".to_string());
-    let index_path = if test {
-        "client/build/index.html".to_string()
-    } else {
-        format!("{}/usr/share/owshen/client/index.html", app_dir_path)
+/// Replaces `path` with `contents` through a file written and synced next to
+/// it, then renamed over it: a crash leaves either the previous contents or
+/// the new ones, never part of them.
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    write_atomic_with(path, |file| file.write_all(contents))
+}
+
+// `write_atomic` with the contents written by `write`, the temporary file is
+// removed when it fails.
+fn write_atomic_with<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&mut std::fs::File) -> std::io::Result<()>,
+{
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
     };
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    write(file.as_file_mut())?;
+    file.as_file().sync_all()?;
+    file.persist(path)?;
+    Ok(())
+}
 
-    println!("index path {}", index_path);
+fn u256_to_h160(u256: U256) -> H160 {
+    let mut bytes: [u8; 32] = [0u8; 32];
+    u256.to_big_endian(&mut bytes);
+    let address_bytes: &[u8] = &bytes[12..32]; // Taking the last 20 bytes for ethereum address
+    H160::from_slice(address_bytes)
+}
+
+fn h160_to_u256(h160_val: H160) -> U256 {
+    let mut bytes = [0u8; 32];
+    bytes[12..32].copy_from_slice(h160_val.as_bytes());
+
+    U256::from_big_endian(&bytes)
+}
+
+fn respond<T: Serialize>(result: Result<T, ApiError>) -> impl IntoResponse {
+    if result.as_ref().is_err_and(ApiError::is_node_error) {
+        metrics::METRICS.record_rpc_error();
+    }
+    match result {
+        Ok(a) => Json(a).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn serve_index(index_path: PathBuf) -> impl IntoResponse {
+    tracing::debug!("index path {:?}", index_path);
     match read_to_string(index_path) {
-        Ok(contents) => Html(contents),
-        Err(_) => Html("<h1>Error: Unable to read the index file</h1>".to_string()),
+        Ok(contents) => Html(contents).into_response(),
+        Err(_) => (
+            StatusCode::NOT_FOUND,
+            Html("<h1>Error: Unable to read the index file</h1>".to_string()),
+        )
+            .into_response(),
     }
 }
 
-async fn serve_file(file_path: PathBuf) -> impl IntoResponse {
-    if let Ok(file) = File::open(file_path).await {
-        let stream = FramedRead::new(file, BytesCodec::new());
+// Maps a requested static file to a path inside `root`. Anything but a plain
+// relative path is refused, as is a file resolving outside of `root` once
+// symlinks are followed.
+fn resolve_static_path(root: &Path, file: &str) -> Option<PathBuf> {
+    let file = Path::new(file);
+    if !file.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    let root = root.canonicalize().ok()?;
+    let path = root.join(file).canonicalize().ok()?;
+    path.starts_with(&root).then_some(path)
+}
 
-        Response::new(Body::wrap_stream(stream))
-    } else {
-        Response::builder()
-            .status(StatusCode::INTERNAL_SERVER_ERROR)
-            .body(Body::from("File not found"))
-            .unwrap()
+// `ServeDir` sets the content types and answers range requests, a file is
+// only handed to it once it is known to stay in `root`.
+async fn serve_file(root: PathBuf, file: String, mut request: Request<Body>) -> Response {
+    if resolve_static_path(&root, &file).is_none() {
+        tracing::debug!("Refusing static file {:?}", file);
+        return StatusCode::NOT_FOUND.into_response();
     }
+    // `ServeDir` looks the path up relative to `root`.
+    let path = request.uri().path().strip_prefix("/static").unwrap_or("/");
+    *request.uri_mut() = path.parse().unwrap_or_default();
+    match ServeDir::new(root).oneshot(request).await {
+        Ok(response) => response.into_response(),
+        Err(infallible) => match infallible {},
+    }
+}
+
+fn static_files(static_files_path: PathBuf) -> Router {
+    Router::new().route(
+        "/static/*file",
+        get(
+            move |extract::Path(file): extract::Path<String>, request: Request<Body>| {
+                serve_file(static_files_path.clone(), file, request)
+            },
+        ),
+    )
 }
 
 async fn serve_wallet(
-    provider: Arc<Provider<Http>>,
+    provider: Arc<RpcProvider>,
     _port: u16,
-    priv_key: PrivateKey,
+    scanning_key: ScanningKey,
     pub_key: PublicKey,
     owshen_contract: H160,
     dive_contract: H160,
@@ -281,100 +1815,401 @@ async fn serve_wallet(
     erc20_abi: Abi,
     token_contracts: Vec<TokenInfo>,
     test: bool,
+    debug: bool,
+    watch_only: bool,
+    sync_interval: Duration,
+    paths: Paths,
+    scan_chunk_size: u64,
+    scan_concurrency: usize,
+    rate_limits: Vec<RateLimit>,
+    shutdown_timeout: Duration,
+    min_confirmations: u64,
+    metrics: bool,
+    api_token: Option<String>,
+    auth_all: bool,
+    blinding: Blinding,
+    no_browser: bool,
 ) -> Result<()> {
-    let tree: SparseMerkleTree = SparseMerkleTree::new(16);
-    let context = Arc::new(Mutex::new(Context {
-        coins: vec![],
-        tree,
-    }));
-
-    let info_addr: PublicKey = pub_key.clone();
-    let coins_owshen_abi = abi.clone();
+    if !watch_only {
+        if let Err(e) = proof::ensure_params(&paths.params) {
+            tracing::warn!("Sending and withdrawing will fail: {}", e);
+        }
+    }
+    let mut context = Context::new(owshen_contract, watch_only);
+    if paths.state.exists() {
+        if let Err(e) = context.load(&paths.state, &scanning_key) {
+            tracing::warn!("Ignoring state file {:?}: {}", paths.state, e);
+        }
+    }
+    context.address_book = AddressBook::load(&paths.address_book)?;
+    context.min_confirmations = min_confirmations;
+    context.blinding = blinding;
+    context.set_token_decimals(&token_contracts);
+    let context = Arc::new(RwLock::new(context));
+    let _state_guard = StateGuard {
+        context: context.clone(),
+        state_file: paths.state.clone(),
+    };
+
+    let tokens_export = Arc::new(token_contracts.clone());
+    let tokens_balances = tokens_export.clone();
+    let info_cache = apis::InfoCache::new(
+        pub_key,
+        dive_contract,
+        owshen_contract,
+        token_contracts,
+        abi.clone(),
+        erc20_abi,
+        test,
+    )?
+    .with_gas_account(provider.clone())
+    .with_circuit(apis::CircuitInfo::read(&paths.params));
+    let abi_cache = info_cache.clone();
+    let context_info = context.clone();
+    let qr_addr = pub_key;
+    let coins_owshen_abi = abi;
     let coins_owshen_address = owshen_contract.clone();
     let context_coin = context.clone();
+    let context_health = context.clone();
+    let context_sync_status = context.clone();
+    let context_history = context.clone();
+    let context_export = context.clone();
+    let context_balances = context.clone();
+    let context_confirmation = context.clone();
+    let context_address_book = context.clone();
+    let context_address_book_add = context.clone();
+    let context_address_book_remove = context.clone();
+    let address_book_path = paths.address_book.clone();
+    let address_book_path_remove = paths.address_book.clone();
+    let context_memo = context.clone();
+    let context_set_memo = context.clone();
+    let state_path_memo = paths.state.clone();
     let context_tree = context.clone();
     let context_tree_send = context.clone();
     let context_withdraw = context.clone();
     let context_send = context.clone();
+    let context_send_multi = context.clone();
+    let build_info = apis::build_info(&paths.params);
+    let params_withdraw = paths.params.clone();
+    let params_send = paths.params.clone();
+    let params_send_multi = paths.params.clone();
+    let params_estimate_withdraw = paths.params.clone();
+    let params_estimate_send = paths.params.clone();
+    let context_nullifier = context.clone();
+    let context_metrics = context.clone();
+    let context_estimate_withdraw = context.clone();
+    let context_estimate_send = context.clone();
+    let owshen = Owshen::new(owshen_contract, provider.clone());
+    let owshen_estimate_send = owshen.clone();
+    let owshen_withdraw = owshen.clone();
+    let provider_estimate_withdraw = provider.clone();
+    let provider_estimate_send = provider.clone();
+    let provider_confirmation = provider.clone();
     let contract = Contract::new(coins_owshen_address, coins_owshen_abi, provider);
-    let contract_clone = contract.clone();
+    let (coin_sender, _) = broadcast::channel::<Coin>(64);
+    let coin_sender_ws = coin_sender.clone();
 
-    let app_dir_path = std::env::var("APPDIR").unwrap_or_else(|_| "".to_string());
-    let root_files_path = format!("{}/usr/share/owshen/client", app_dir_path);
-    let static_files_path = format!("{}/usr/share/owshen/client/static", app_dir_path);
+    tokio::spawn(apis::sync_loop(
+        context.clone(),
+        contract,
+        scanning_key,
+        coin_sender,
+        sync_interval,
+        Some(paths.state.clone()),
+        scan_chunk_size,
+        scan_concurrency,
+    ));
 
-    let app = Router::new()
-        .route("/", get(move || serve_index(test)))
+    // Proof generation is CPU bound, each spend endpoint gets its own bucket.
+    // Estimates generate the same proofs, they draw from the bucket of the
+    // spend they estimate.
+    let spend_limiter = |endpoint: &str| {
+        Arc::new(RateLimiter::per_minute(rate_limit::per_minute(
+            &rate_limits,
+            endpoint,
+        )))
+    };
+    let spend_limit = |limiter: &Arc<RateLimiter>| {
+        let limiter = limiter.clone();
+        middleware::from_fn(move |request, next| rate_limit::limit(limiter.clone(), request, next))
+    };
+    let withdraw_limiter = spend_limiter("/withdraw");
+    let send_limiter = spend_limiter("/send");
+
+    // Spending and writing always take the token when there is one, reading
+    // only with `auth_all`.
+    let authenticate = |protected: bool| {
+        let token = api_token
+            .clone()
+            .filter(|_| protected || auth_all)
+            .map(Arc::new);
+        middleware::from_fn(move |request, next| auth::authenticate(token.clone(), request, next))
+    };
+
+    let rpc_state = apis::JsonRpcState {
+        context: context.clone(),
+        info: info_cache.clone(),
+        params: paths.params.clone(),
+        is_test: test,
+        owshen: owshen.clone(),
+        spend_limiter: spend_limiter("/rpc"),
+    };
+
+    let index_path = if test {
+        PathBuf::from("client/build/index.html")
+    } else {
+        paths.client.join("index.html")
+    };
+
+    let mut api = Router::new()
         .route(
-            "/static/*file",
-            get(|params: extract::Path<String>| async move {
-                let file_path = PathBuf::from(static_files_path).join(params.as_str());
-                println!("file path {:?}", file_path);
-                serve_file(file_path).await
+            "/coins",
+            get(move |ApiQuery(req): ApiQuery<GetCoinsRequest>| async move {
+                apis::coins(Query(req), context_coin).await
             }),
         )
         .route(
-            "/manifest.json",
-            get_service(ServeFile::new(format!("{}/manifest.json", root_files_path))),
+            "/health",
+            get(move || async move { respond(apis::health(context_health).await) }),
         )
         .route(
-            "/asset-manifest.json",
-            get_service(ServeFile::new(format!(
-                "{}/asset-manifest.json",
-                root_files_path
-            ))),
+            "/sync_status",
+            get(move || async move { respond(apis::sync_status(context_sync_status).await) }),
         )
         .route(
-            "/robots.txt",
-            get_service(ServeFile::new(format!("{}/robots.txt", root_files_path))),
+            "/ws",
+            get(move |ws: WebSocketUpgrade| async move { apis::ws(ws, coin_sender_ws).await }),
         )
         .route(
-            "/coins",
-            get(move || async move {
-                handle_error(apis::coins(context_coin, contract_clone, priv_key).await)
+            "/withdraw",
+            get(
+                move |ApiQuery(req): ApiQuery<GetWithdrawRequest>| async move {
+                    respond(
+                        apis::withdraw(
+                            Query(req),
+                            context_withdraw,
+                            context_tree,
+                            params_withdraw,
+                            test,
+                        )
+                        .await
+                        .and_then(|withdrawal| withdrawal.with_relay(&owshen_withdraw)),
+                    )
+                },
+            )
+            .layer(spend_limit(&withdraw_limiter))
+            .layer(authenticate(true)),
+        )
+        .route(
+            "/send",
+            get(move |ApiQuery(req): ApiQuery<GetSendRequest>| async move {
+                respond(apis::send(Query(req), context_send, context_tree_send, params_send).await)
+            })
+            .layer(spend_limit(&send_limiter))
+            .layer(authenticate(true)),
+        )
+        .route(
+            "/send_multi",
+            get(
+                move |ApiQuery(req): ApiQuery<GetMultiSendRequest>| async move {
+                    respond(
+                        apis::send_multi(Query(req), context_send_multi, params_send_multi).await,
+                    )
+                },
+            )
+            .layer(spend_limit(&spend_limiter("/send_multi")))
+            .layer(authenticate(true)),
+        )
+        .route(
+            "/estimate/withdraw",
+            get(
+                move |ApiQuery(req): ApiQuery<GetEstimateWithdrawRequest>| async move {
+                    respond(
+                        apis::estimate_withdraw(
+                            Query(req),
+                            context_estimate_withdraw,
+                            owshen,
+                            provider_estimate_withdraw,
+                            params_estimate_withdraw,
+                        )
+                        .await,
+                    )
+                },
+            )
+            .layer(spend_limit(&withdraw_limiter))
+            .layer(authenticate(true)),
+        )
+        .route(
+            "/estimate/send",
+            get(move |ApiQuery(req): ApiQuery<GetSendRequest>| async move {
+                respond(
+                    apis::estimate_send(
+                        Query(req),
+                        context_estimate_send,
+                        owshen_estimate_send,
+                        provider_estimate_send,
+                        params_estimate_send,
+                    )
+                    .await,
+                )
+            })
+            .layer(spend_limit(&send_limiter))
+            .layer(authenticate(true)),
+        )
+        .route(
+            "/confirmation",
+            get(
+                move |ApiQuery(req): ApiQuery<GetConfirmationRequest>| async move {
+                    respond(
+                        apis::confirmation(Query(req), provider_confirmation, context_confirmation)
+                            .await,
+                    )
+                },
+            )
+            // Writes the history when given a nullifier, a failed entry
+            // frees its coin to be spent again.
+            .layer(authenticate(true)),
+        )
+        .route(
+            "/stealth",
+            get(|ApiQuery(req): ApiQuery<GetStealthRequest>| async move {
+                respond(apis::stealth(Query(req)).await)
             }),
         )
         .route(
-            "/withdraw",
+            "/address_book",
+            get(
+                move || async move { respond(apis::address_book_list(context_address_book).await) },
+            ),
+        )
+        .route(
+            "/address_book/add",
+            post(
+                move |extract::Json(req): extract::Json<PostAddressBookAddRequest>| async move {
+                    respond(
+                        apis::address_book_add(
+                            Json(req),
+                            context_address_book_add,
+                            address_book_path,
+                        )
+                        .await,
+                    )
+                },
+            )
+            .layer(authenticate(true)),
+        )
+        .route(
+            "/address_book/remove",
+            post(
+                move |extract::Json(req): extract::Json<PostAddressBookRemoveRequest>| async move {
+                    respond(
+                        apis::address_book_remove(
+                            Json(req),
+                            context_address_book_remove,
+                            address_book_path_remove,
+                        )
+                        .await,
+                    )
+                },
+            )
+            .layer(authenticate(true)),
+        )
+        .route(
+            "/memo",
+            get(move |ApiQuery(req): ApiQuery<GetMemoRequest>| async move {
+                respond(apis::memo(Query(req), context_memo).await)
+            })
+            .merge(
+                post(
+                    move |extract::Json(req): extract::Json<PostMemoRequest>| async move {
+                        respond(apis::set_memo(Json(req), context_set_memo, state_path_memo).await)
+                    },
+                )
+                .layer(authenticate(true)),
+            ),
+        )
+        .route(
+            "/history",
             get(
-                move |extract::Query(req): extract::Query<GetWithdrawRequest>| async move {
-                    handle_error(apis::withdraw(Query(req), context_withdraw, context_tree).await)
+                move |ApiQuery(req): ApiQuery<GetHistoryRequest>| async move {
+                    respond(apis::history(Query(req), context_history).await)
                 },
             ),
         )
         .route(
-            "/send",
+            "/balances",
             get(
-                move |extract::Query(req): extract::Query<GetSendRequest>| async move {
-                    handle_error(apis::send(Query(req), context_send, context_tree_send).await)
+                move |ApiQuery(req): ApiQuery<GetBalancesRequest>| async move {
+                    respond(apis::balances(Query(req), context_balances, tokens_balances).await)
                 },
             ),
         )
         .route(
-            "/stealth",
+            "/export",
             get(
-                |extract::Query(req): extract::Query<GetStealthRequest>| async move {
-                    handle_error(apis::stealth(Query(req)).await)
+                move |ApiQuery(req): ApiQuery<GetExportRequest>| async move {
+                    apis::export(Query(req), context_export, tokens_export).await
                 },
             ),
         )
+        .route(
+            "/address_qr",
+            get(move || async move { apis::address_qr(qr_addr).await }),
+        )
+        .route(
+            "/version",
+            get(move || async move { respond(apis::version(build_info).await) }),
+        )
         .route(
             "/info",
-            get(move || async move {
-                handle_error(
-                    apis::info(
-                        info_addr,
-                        dive_contract,
-                        owshen_contract,
-                        token_contracts,
-                        abi,
-                        erc20_abi,
-                        test,
-                    )
-                    .await,
-                )
+            get(move |ApiQuery(req): ApiQuery<GetInfoRequest>| async move {
+                apis::info(Query(req), info_cache, context_info).await
             }),
         )
+        .route(
+            "/abi",
+            get(move || async move { apis::abi(abi_cache).await }),
+        )
+        .route(
+            "/rpc",
+            post(move |body: Bytes| async move { apis::json_rpc(body, rpc_state).await })
+                .layer(authenticate(true)),
+        );
+
+    if metrics {
+        api = api.route(
+            "/metrics",
+            get(move || async move { apis::metrics(context_metrics).await }),
+        );
+    }
+
+    if debug {
+        api = api.route(
+            "/coins/:index/nullifier",
+            get(move |index: extract::Path<u64>| async move {
+                respond(apis::nullifier(index, context_nullifier).await)
+            })
+            .layer(authenticate(true)),
+        );
+    }
+
+    // The web client's own files are never behind the token.
+    let app = Router::new()
+        .route("/", get(move || serve_index(index_path)))
+        .merge(static_files(paths.client.join("static")))
+        .route(
+            "/manifest.json",
+            get_service(ServeFile::new(paths.client.join("manifest.json"))),
+        )
+        .route(
+            "/asset-manifest.json",
+            get_service(ServeFile::new(paths.client.join("asset-manifest.json"))),
+        )
+        .route(
+            "/robots.txt",
+            get_service(ServeFile::new(paths.client.join("robots.txt"))),
+        )
+        .merge(api.route_layer(authenticate(false)))
         .layer(CorsLayer::permissive());
 
     let addr = SocketAddr::from(([127, 0, 0, 1], 9000));
@@ -404,27 +2239,160 @@ async fn serve_wallet(
         tokio::try_join!(backend, frontend)?;
         Ok(())
     } else {
-        let server = axum::Server::bind(&addr)
-            .serve(app.into_make_service())
-            .with_graceful_shutdown(shutdown_signal());
+        let listener = std::net::TcpListener::bind(addr)?;
+
+        open_browser(
+            &format!("http://{}", addr),
+            !no_browser && has_display(),
+            |url| webbrowser::open(url),
+        );
+
+        serve_gracefully(listener, app, shutdown_signal(), shutdown_timeout).await?;
+        flush_state(&context, &paths.state).await?;
+        Ok(())
+    }
+}
+
+/// Whether a browser could show up, a headless Linux machine has neither an
+/// X11 nor a Wayland display. Other systems always have one.
+fn has_display() -> bool {
+    if cfg!(any(target_os = "macos", target_os = "windows")) {
+        return true;
+    }
+    ["DISPLAY", "WAYLAND_DISPLAY"]
+        .iter()
+        .any(|var| std::env::var_os(var).is_some_and(|value| !value.is_empty()))
+}
 
-        // Attempt to open the web browser
-        if webbrowser::open(&format!("http://{}", addr)).is_err() {
+/// Opens the web interface at `url` with `open` when `auto_open`, prints
+/// where to find it otherwise or when no browser opens. Returns whether one
+/// did.
+fn open_browser<F: FnOnce(&str) -> std::io::Result<()>>(
+    url: &str,
+    auto_open: bool,
+    open: F,
+) -> bool {
+    if !auto_open {
+        println!("The wallet is served on {}", url);
+        return false;
+    }
+    match open(url) {
+        Ok(()) => true,
+        Err(_) => {
             println!(
-                "Failed to open web browser. Please navigate to http://{} manually",
-                addr
+                "Failed to open web browser. Please navigate to {} manually",
+                url
             );
+            false
         }
+    }
+}
 
-        server.await.map_err(eyre::Report::new)?;
-        Ok(())
+/// Serves `app` until `signal` fires, then stops accepting connections and
+/// gives in-flight requests (proofs being generated) up to `grace` to finish.
+async fn serve_gracefully(
+    listener: std::net::TcpListener,
+    app: Router,
+    signal: impl Future<Output = ()>,
+    grace: Duration,
+) -> Result<()> {
+    let signalled = Arc::new(Notify::new());
+    let notify = signalled.clone();
+    let server = axum::Server::from_tcp(listener)?
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(async move {
+            signal.await;
+            notify.notify_one();
+        });
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => return result.map_err(eyre::Report::new),
+        _ = signalled.notified() => {}
+    }
+    tracing::info!(
+        "Shutting down, waiting up to {:?} for in-flight requests",
+        grace
+    );
+    match tokio::time::timeout(grace, server).await {
+        Ok(result) => result.map_err(eyre::Report::new),
+        Err(_) => {
+            tracing::warn!(
+                "In-flight requests did not finish in {:?}, dropping them",
+                grace
+            );
+            Ok(())
+        }
+    }
+}
+
+// Saves what changed since the last save when dropped, so that the state
+// isn't lost when `serve_wallet` bails out on an error rather than through
+// the graceful shutdown.
+struct StateGuard {
+    context: SharedContext,
+    state_file: PathBuf,
+}
+
+impl Drop for StateGuard {
+    fn drop(&mut self) {
+        // A writer can only be a task torn down along with the runtime.
+        match self.context.try_read() {
+            Ok(context) => {
+                if let Err(e) = context.save_if_dirty(&self.state_file) {
+                    tracing::warn!("Cannot save the state file: {}", e);
+                }
+            }
+            Err(_) => tracing::warn!("State is locked, cannot save it to {:?}", self.state_file),
+        }
     }
 }
 
+// Writes the synced state once the server is down, so that nothing learned
+// since the last periodic save is lost.
+async fn flush_state(context: &RwLock<Context>, state_file: &Path) -> Result<()> {
+    let context = context.read().await;
+    context.save(state_file)?;
+    tracing::info!(
+        "Saved {} coins, {} spent nullifiers, {} tree leaves and {} history entries up to block {:?} to {:?}",
+        context.coins.len(),
+        context.spent_nullifiers.len(),
+        context.tree.leaves().len(),
+        context.history.len(),
+        context.last_synced_block,
+        state_file
+    );
+    Ok(())
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM on Unix: process managers such as
+/// systemd and Docker stop services with the latter.
 async fn shutdown_signal() {
-    tokio::signal::ctrl_c()
-        .await
-        .expect("failed to listen for event");
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for event");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    shutdown_on(ctrl_c, terminate).await
+}
+
+// Resolves once `interrupt` or `terminate` does, the futures of the signals
+// `shutdown_signal` listens to.
+async fn shutdown_on(interrupt: impl Future<Output = ()>, terminate: impl Future<Output = ()>) {
+    tokio::select! {
+        _ = interrupt => tracing::info!("Interrupted, shutting down"),
+        _ = terminate => tracing::info!("Terminated, shutting down"),
+    }
 }
 
 impl Into<OwshenPoint> for Point {
@@ -436,133 +2404,352 @@ impl Into<OwshenPoint> for Point {
     }
 }
 
-async fn initialize_config(endpoint: String, name: String, is_test: bool) -> Config {
-    if is_test {
-        let provider = Provider::<Http>::try_from(endpoint.clone()).unwrap();
-        let provider = Arc::new(provider);
-        println!("Deploying hash function...");
-        let poseidon4_addr = deploy(
-            provider.clone(),
-            include_str!("assets/poseidon4.abi"),
-            include_str!("assets/poseidon4.evm"),
-        )
-        .await
-        .address();
-
-        let accounts = provider.get_accounts().await.unwrap();
-        let from = accounts[0];
+// Contracts already deployed on the chain, used instead of deploying new ones.
+#[derive(Clone, Debug, Default)]
+struct ExistingContracts {
+    owshen: Option<H160>,
+    dive: Option<H160>,
+    poseidon: Option<H160>,
+}
 
-        println!("Deploying DIVE token...");
-        let dive = SimpleErc20::deploy(
-            provider.clone(),
-            (
-                U256::from_str_radix("1000000000000000000000", 10).unwrap(),
-                String::from_str("dive_token").unwrap(),
-                String::from_str("DIVE").unwrap(),
-            ),
-        )
-        .unwrap()
-        .legacy()
-        .from(from)
-        .send()
-        .await
-        .unwrap();
-        println!("Deploying test tokens...");
-        let test_token = SimpleErc20::deploy(
-            provider.clone(),
-            (
-                U256::from_str_radix("1000000000000000000000", 10).unwrap(),
-                String::from_str("test_token").unwrap(),
-                String::from_str("TEST").unwrap(),
-            ),
+impl ExistingContracts {
+    // Outside of test mode nothing gets deployed, the DIVE and Poseidon
+    // contracts are only of use along with an Owshen contract.
+    fn check(&self, is_test: bool) -> Result<()> {
+        if !is_test && self.owshen.is_none() && (self.dive.is_some() || self.poseidon.is_some()) {
+            return Err(eyre::Report::msg(
+                "--dive-address and --poseidon-address need --owshen-address outside of test mode!",
+            ));
+        }
+        Ok(())
+    }
+}
+
+async fn ensure_contract(provider: &RpcProvider, address: H160) -> Result<()> {
+    if provider.get_code(address, None).await?.is_empty() {
+        return Err(eyre::Report::msg(format!(
+            "No contract is deployed at {:?}!",
+            address
+        )));
+    }
+    Ok(())
+}
+
+async fn code_hash(provider: &RpcProvider, address: H160) -> Result<H256> {
+    Ok(H256::from(ethers::utils::keccak256(
+        provider.get_code(address, None).await?,
+    )))
+}
+
+/// Functions and events of the Owshen contract the wallet and its frontend
+/// rely on.
+const OWSHEN_FUNCTIONS: [&str; 6] = ["deposit", "withdraw", "send", "isSpent", "root", "VERSION"];
+const OWSHEN_EVENTS: [&str; 2] = ["Sent", "Spend"];
+
+fn missing_from_abi(abi: &Abi) -> Vec<&'static str> {
+    OWSHEN_FUNCTIONS
+        .into_iter()
+        .filter(|name| !abi.functions.contains_key(*name))
+        .chain(
+            OWSHEN_EVENTS
+                .into_iter()
+                .filter(|name| !abi.events.contains_key(*name)),
         )
-        .unwrap()
-        .legacy()
-        .from(from)
-        .send()
-        .await
-        .unwrap();
+        .collect()
+}
 
-        let second_test_token = SimpleErc20::deploy(
-            provider.clone(),
-            (
-                U256::from_str_radix("1000000000000000000000", 10).unwrap(),
-                String::from_str("test_token").unwrap(),
-                String::from_str("TEST").unwrap(),
-            ),
+/// `VERSION` of the Owshen contract the wallet encodes its calls for and
+/// decodes the events of.
+const OWSHEN_VERSION: u64 = 2;
+
+/// Compares the version of the contract with the wallet's. Contracts which
+/// predate `VERSION` revert on it, some nodes answer with an error and no
+/// revert data.
+async fn version_mismatch(provider: &RpcProvider, address: H160) -> Result<Option<String>> {
+    let owshen = Owshen::new(address, Arc::new(provider.clone()));
+    let reverted = |e: &ContractError<RpcProvider>| {
+        e.is_revert()
+            || e.as_provider_error()
+                .and_then(|e| e.as_error_response())
+                .is_some()
+    };
+    match owshen.version().call().await {
+        Ok(version) if version == U256::from(OWSHEN_VERSION) => Ok(None),
+        Ok(version) => Ok(Some(format!(
+            "it is version {} of the contract instead of {}",
+            version, OWSHEN_VERSION
+        ))),
+        Err(e) if reverted(&e) => Ok(Some(format!(
+            "it predates version {} of the contract",
+            OWSHEN_VERSION
+        ))),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Compares the empty root of the wallet's tree with the contract's, which
+/// is only known while no coin was inserted yet.
+async fn empty_root_mismatch(provider: &RpcProvider, address: H160) -> Result<Option<String>> {
+    let owshen = Owshen::new(address, Arc::new(provider.clone()));
+    if !owshen.deposit_index().call().await?.is_zero() {
+        return Ok(None);
+    }
+    let expected: U256 = SparseMerkleTree::new(16).empty_root().into();
+    let actual = owshen.root().call().await?;
+    Ok((actual != expected).then(|| {
+        format!(
+            "its empty tree root is {:#x} instead of {:#x}",
+            actual, expected
         )
-        .unwrap()
-        .legacy()
-        .from(from)
-        .send()
-        .await
-        .unwrap();
+    }))
+}
 
-        println!("Deploying Owshen contract...");
-        let owshen = Owshen::deploy(provider.clone(), poseidon4_addr)
-            .unwrap()
-            .legacy()
+/// Refuses to start with a stored ABI lacking what the wallet calls, with
+/// a contract whose code changed since `config` was written, of another
+/// version or whose empty tree differs from the wallet's, unless `force` is
+/// given.
+async fn check_contract(provider: &RpcProvider, config: &Config, force: bool) -> Result<()> {
+    let mut problems = Vec::new();
+    let missing = missing_from_abi(&config.owshen_contract_abi);
+    if !missing.is_empty() {
+        problems.push(format!(
+            "the stored Owshen ABI lacks {}",
+            missing.join(", ")
+        ));
+    }
+    if let Some(expected) = config.owshen_code_hash {
+        let actual = code_hash(provider, config.owshen_contract_address).await?;
+        if actual != expected {
+            problems.push(format!(
+                "the code at {:?} hashes to {:?} instead of {:?}",
+                config.owshen_contract_address, actual, expected
+            ));
+        }
+    }
+    if let Some(problem) = version_mismatch(provider, config.owshen_contract_address).await? {
+        problems.push(problem);
+    }
+    if let Some(problem) = empty_root_mismatch(provider, config.owshen_contract_address).await? {
+        problems.push(problem);
+    }
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let problems = problems.join(" and ");
+    if force {
+        tracing::warn!("Owshen contract mismatch: {}!", problems);
+        Ok(())
+    } else {
+        Err(eyre::Report::msg(format!(
+            "Owshen contract mismatch: {}, run `owshen config` again or pass --force-contract \
+             to use it anyway",
+            problems
+        )))
+    }
+}
+
+async fn initialize_config(
+    endpoint: String,
+    name: String,
+    is_test: bool,
+    tx_type: Option<TxType>,
+    poseidon: &PoseidonArtifacts,
+    existing: ExistingContracts,
+    from: Option<H160>,
+    rpc_timeout: Duration,
+) -> Result<Config> {
+    existing.check(is_test)?;
+    let provider = rpc::connect_with_timeout(&endpoint, rpc_timeout).await?;
+    let provider = Arc::new(provider);
+    for address in [existing.owshen, existing.dive, existing.poseidon]
+        .into_iter()
+        .flatten()
+    {
+        ensure_contract(&provider, address).await?;
+    }
+    if let Some(address) = existing.poseidon {
+        check_poseidon(provider.clone(), address, poseidon.variant).await?;
+    }
+
+    if !is_test && existing.owshen.is_none() {
+        return Ok(Config::default());
+    }
+
+    let mut dive_contract_address = existing.dive.unwrap_or_default();
+    let mut owshen_contract_address = existing.owshen.unwrap_or_default();
+    let mut poseidon_contract_address = existing.poseidon;
+    if is_test {
+        let tx_type = resolve_tx_type(provider.as_ref(), tx_type).await?;
+        let from = resolve_from(provider.as_ref(), from).await?;
+
+        if existing.dive.is_none() {
+            println!("Deploying DIVE token...");
+            let dive = with_tx_type!(
+                SimpleErc20::deploy(
+                    provider.clone(),
+                    (
+                        U256::from_str_radix("1000000000000000000000", 10).unwrap(),
+                        String::from_str("dive_token").unwrap(),
+                        String::from_str("DIVE").unwrap(),
+                        18u8,
+                    ),
+                )
+                .unwrap(),
+                tx_type
+            )
             .from(from)
             .send()
             .await
             .unwrap();
-        let mut token_contracts: Vec<TokenInfo> = Vec::new();
+            dive_contract_address = dive.address();
+        }
 
-        token_contracts.push(TokenInfo {
-            token_address: test_token.address(),
-            symbol: "WETH".to_string(),
-        });
-        token_contracts.push(TokenInfo {
-            token_address: second_test_token.address(),
-            symbol: "USDC".to_string(),
-        });
+        println!("Deploying test tokens...");
+        for _ in 0..2 {
+            with_tx_type!(
+                SimpleErc20::deploy(
+                    provider.clone(),
+                    (
+                        U256::from_str_radix("1000000000000000000000", 10).unwrap(),
+                        String::from_str("test_token").unwrap(),
+                        String::from_str("TEST").unwrap(),
+                        18u8,
+                    ),
+                )
+                .unwrap(),
+                tx_type
+            )
+            .from(from)
+            .send()
+            .await
+            .unwrap();
+        }
 
-        return Config {
-            name,
-            endpoint,
-            owshen_contract_address: owshen.address(),
-            owshen_contract_abi: owshen.abi().clone(),
-            dive_contract_address: dive.address(),
-            erc20_abi: dive.abi().clone(),
-        };
-    } else {
-        return Config::default();
+        if existing.owshen.is_none() {
+            let poseidon4_addr = match existing.poseidon {
+                Some(address) => address,
+                None => {
+                    println!("Deploying hash function...");
+                    deploy(
+                        provider.clone(),
+                        &poseidon.abi,
+                        &poseidon.bytecode,
+                        tx_type,
+                        from,
+                    )
+                    .await
+                    .address()
+                }
+            };
+            // Before the Owshen contract gets bound to it for good.
+            if existing.poseidon.is_none() {
+                check_poseidon(provider.clone(), poseidon4_addr, poseidon.variant).await?;
+            }
+            poseidon_contract_address = Some(poseidon4_addr);
+
+            println!("Deploying Owshen contract...");
+            let owshen = with_tx_type!(
+                Owshen::deploy(provider.clone(), poseidon4_addr).unwrap(),
+                tx_type
+            )
+            .from(from)
+            .send()
+            .await
+            .unwrap();
+            owshen_contract_address = owshen.address();
+        }
+    }
+
+    // ABIs are bundled with the bindings, they match any Owshen deployment.
+    Ok(Config {
+        name,
+        endpoint,
+        owshen_contract_address,
+        owshen_contract_abi: Owshen::new(owshen_contract_address, provider.clone())
+            .abi()
+            .clone(),
+        dive_contract_address,
+        erc20_abi: SimpleErc20::new(dive_contract_address, provider.clone())
+            .abi()
+            .clone(),
+        owshen_code_hash: Some(code_hash(&provider, owshen_contract_address).await?),
+        poseidon_variant: poseidon.variant,
+        poseidon_contract_address,
+    })
+}
+
+/// Refuses to use a wallet against an endpoint on a different chain than the
+/// one it was initialized on, unless `force` is given.
+fn check_chain_id(expected: Option<U256>, actual: U256, force: bool) -> Result<()> {
+    match expected {
+        Some(expected) if expected != actual => {
+            if force {
+                tracing::warn!(
+                    "Wallet was initialized on chain {} but the endpoint is on chain {}!",
+                    expected,
+                    actual
+                );
+                Ok(())
+            } else {
+                Err(eyre::Report::msg(format!(
+                    "Wallet was initialized on chain {} but the endpoint is on chain {}, \
+                     pass --force-chain to use it anyway",
+                    expected, actual
+                )))
+            }
+        }
+        _ => Ok(()),
     }
 }
 
-async fn initialize_wallet(endpoint: String, mnemonic: Option<Mnemonic>, is_test: bool) -> Wallet {
+async fn initialize_wallet(
+    endpoint: String,
+    mnemonic: Option<Mnemonic>,
+    is_test: bool,
+    tx_type: Option<TxType>,
+    accounts: u32,
+    from: Option<H160>,
+    rpc_timeout: Duration,
+) -> Result<Wallet> {
     let mut token_contracts: Vec<TokenInfo> = Vec::new();
-    let provider = Provider::<Http>::try_from(endpoint.clone()).unwrap();
+    let provider = rpc::connect_with_timeout(&endpoint, rpc_timeout).await?;
     let provider = Arc::new(provider);
-    let accounts = provider.get_accounts().await.unwrap();
+    let chain_id = provider.get_chainid().await?;
 
     if is_test {
-        let from = accounts[0];
-        let test_token = SimpleErc20::deploy(
-            provider.clone(),
-            (
-                U256::from_str_radix("1000000000000000000000", 10).unwrap(),
-                String::from_str("test_token").unwrap(),
-                String::from_str("TEST").unwrap(),
-            ),
+        let from = resolve_from(provider.as_ref(), from).await?;
+        let tx_type = resolve_tx_type(provider.as_ref(), tx_type).await?;
+        let test_token = with_tx_type!(
+            SimpleErc20::deploy(
+                provider.clone(),
+                (
+                    U256::from_str_radix("1000000000000000000000", 10).unwrap(),
+                    String::from_str("test_token").unwrap(),
+                    String::from_str("TEST").unwrap(),
+                    18u8,
+                ),
+            )
+            .unwrap(),
+            tx_type
         )
-        .unwrap()
-        .legacy()
         .from(from)
         .send()
         .await
         .unwrap();
 
-        let second_test_token = SimpleErc20::deploy(
-            provider.clone(),
-            (
-                U256::from_str_radix("1000000000000000000000", 10).unwrap(),
-                String::from_str("test_token").unwrap(),
-                String::from_str("TEST").unwrap(),
-            ),
+        let second_test_token = with_tx_type!(
+            SimpleErc20::deploy(
+                provider.clone(),
+                (
+                    U256::from_str_radix("1000000000000000000000", 10).unwrap(),
+                    String::from_str("test_token").unwrap(),
+                    String::from_str("TEST").unwrap(),
+                    6u8,
+                ),
+            )
+            .unwrap(),
+            tx_type
         )
-        .unwrap()
-        .legacy()
         .from(from)
         .send()
         .await
@@ -571,28 +2758,39 @@ async fn initialize_wallet(endpoint: String, mnemonic: Option<Mnemonic>, is_test
         token_contracts.push(TokenInfo {
             token_address: test_token.address(),
             symbol: "WETH".to_string(),
+            decimals: default_decimals(),
         });
         token_contracts.push(TokenInfo {
             token_address: second_test_token.address(),
             symbol: "USDC".to_string(),
+            decimals: 6,
         });
     }
 
     let entropy = if let Some(m) = mnemonic {
         Entropy::from_mnemonic(m)
+    } else if is_test {
+        // Test wallets are reproducible, so are their addresses.
+        Entropy::generate(&mut StdRng::from_seed(TEST_SEED))
     } else {
         Entropy::generate(&mut rand::thread_rng())
     };
 
-    let wallet = Wallet {
-        entropy,
-        token_contracts,
+    let mut wallet = Wallet {
+        version: WALLET_VERSION,
+        entropy: Some(entropy),
+        token_contracts: Vec::new(),
+        chain_id: Some(chain_id),
+        accounts: derive_accounts(&entropy, accounts.max(1)),
     };
+    for token in token_contracts {
+        wallet.add_token(token)?;
+    }
 
     println!(
         "{} {}",
         "Your 12-word mnemonic phrase is:".bright_green(),
-        wallet.entropy.to_mnemonic().unwrap()
+        entropy.to_mnemonic().unwrap()
     );
     println!(
         "{}",
@@ -601,13 +2799,38 @@ async fn initialize_wallet(endpoint: String, mnemonic: Option<Mnemonic>, is_test
             .bright_red()
     );
 
-    wallet
+    Ok(wallet)
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let wallet_path = home::home_dir().unwrap().join(".owshen-wallet.json");
-    let config_path = home::home_dir().unwrap().join(".config-wallet.json");
+    let opt = Opt::from_args();
+
+    let rpc_timeout = Duration::from_secs(opt.rpc_timeout);
+    let artifacts_dir = opt.artifacts_dir.clone();
+    let mut paths = match opt.data_dir.clone() {
+        Some(data_dir) => Paths::new(data_dir),
+        None => Paths::new(Paths::default_data_dir()?),
+    };
+    paths.create()?;
+    // `--params` still wins over the proving key of the artifacts.
+    if let Some(dir) = &artifacts_dir {
+        if let Some(params) = artifacts::params(dir)? {
+            paths.params = params;
+        }
+    }
+    if let (None, Some(home)) = (&opt.data_dir, home::home_dir()) {
+        for legacy in paths.migrate_legacy(&home)? {
+            println!(
+                "Copied {} into {}",
+                legacy.to_string_lossy(),
+                paths.data_dir.to_string_lossy()
+            );
+        }
+    }
+    let wallet_path = paths.wallet.clone();
+    let config_path = paths.config.clone();
+    let address_book_path = paths.address_book.clone();
 
     println!(
         "{} {}",
@@ -615,25 +2838,34 @@ async fn main() -> Result<()> {
         wallet_path.to_string_lossy()
     );
 
-    let opt = OwshenCliOpt::from_args();
-
-    match opt {
+    match opt.command {
         OwshenCliOpt::Init(InitOpt {
             endpoint,
             db,
             mnemonic,
             test,
+            accounts,
+            tx_type,
+            from,
+            keystore,
         }) => {
             let wallet_path = db.unwrap_or(wallet_path.clone());
-            let wallet = std::fs::read_to_string(&wallet_path)
-                .map(|s| {
-                    let w: Wallet = serde_json::from_str(&s).expect("Invalid wallet file!");
-                    w
-                })
-                .ok();
+            let wallet = Wallet::load(&wallet_path)?;
             if wallet.is_none() {
-                let wallet = initialize_wallet(endpoint, mnemonic, test).await;
-                std::fs::write(wallet_path, serde_json::to_string(&wallet).unwrap()).unwrap();
+                let mut wallet = initialize_wallet(
+                    endpoint,
+                    mnemonic,
+                    test,
+                    tx_type,
+                    accounts,
+                    from,
+                    rpc_timeout,
+                )
+                .await?;
+                if keystore == KeyStoreKind::Keyring {
+                    keystore::move_keys(&mut wallet, keystore.open(&wallet_path).as_ref())?;
+                }
+                wallet.save(&wallet_path)?;
             } else {
                 println!("Wallet is already initialized!");
             }
@@ -643,6 +2875,12 @@ async fn main() -> Result<()> {
             name,
             config,
             test,
+            tx_type,
+            owshen_address,
+            dive_address,
+            poseidon_address,
+            poseidon_variant,
+            from,
         }) => {
             let config_path = config.unwrap_or(config_path.clone());
             let config = std::fs::read_to_string(&config_path)
@@ -652,8 +2890,23 @@ async fn main() -> Result<()> {
                 })
                 .ok();
             if config.is_none() {
-                let config = initialize_config(endpoint, name, test).await;
-                std::fs::write(config_path, serde_json::to_string(&config).unwrap()).unwrap();
+                let existing = ExistingContracts {
+                    owshen: owshen_address,
+                    dive: dive_address,
+                    poseidon: poseidon_address,
+                };
+                let config = initialize_config(
+                    endpoint,
+                    name,
+                    test,
+                    tx_type,
+                    &PoseidonArtifacts::load(artifacts_dir.as_deref(), poseidon_variant)?,
+                    existing,
+                    from,
+                    rpc_timeout,
+                )
+                .await?;
+                write_atomic(&config_path, serde_json::to_string(&config)?.as_bytes())?;
             } else {
                 println!("Config is already initialized!");
             }
@@ -664,14 +2917,59 @@ async fn main() -> Result<()> {
             endpoint,
             test,
             config,
+            debug,
+            viewing_key,
+            sync_interval,
+            state_file,
+            log_level,
+            scan_chunk_size,
+            scan_concurrency,
+            params,
+            force_chain,
+            force_contract,
+            rate_limit,
+            shutdown_timeout,
+            min_confirmations,
+            account,
+            metrics,
+            api_token,
+            auth_all,
+            blinding_seed,
+            no_browser,
+            keystore,
         }) => {
+            let log_file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(paths.log_file())?;
+            tracing_subscriber::registry()
+                .with(LevelFilter::from_level(log_level))
+                .with(tracing_subscriber::fmt::layer().with_span_events(FmtSpan::CLOSE))
+                .with(
+                    tracing_subscriber::fmt::layer()
+                        .with_span_events(FmtSpan::CLOSE)
+                        .with_ansi(false)
+                        .with_writer(std::sync::Mutex::new(log_file)),
+                )
+                .init();
+            let paths = Paths {
+                state: state_file.unwrap_or(paths.state),
+                params: params.unwrap_or(paths.params),
+                ..paths
+            };
+
+            let blinding = match blinding_seed {
+                Some(seed) if test => Blinding::seeded(seed),
+                Some(_) => {
+                    return Err(eyre::Report::msg(
+                        "--blinding-seed makes the outputs predictable, it needs --test!",
+                    ))
+                }
+                None => Blinding::Secure,
+            };
+
             let wallet_path = db.unwrap_or(wallet_path.clone());
-            let wallet = std::fs::read_to_string(&wallet_path)
-                .map(|s| {
-                    let w: Wallet = serde_json::from_str(&s).expect("Invalid wallet file!");
-                    w
-                })
-                .ok();
+            let wallet = Wallet::load(&wallet_path)?;
 
             let config_path = config.unwrap_or(config_path.clone());
             let config = std::fs::read_to_string(&config_path)
@@ -681,70 +2979,375 @@ async fn main() -> Result<()> {
                 })
                 .ok();
 
-            if let Some(wallet) = &wallet {
+            if let Some(viewing_key) = viewing_key {
+                let config = config.clone().unwrap_or_default();
+                let provider = rpc::connect_with_timeout(&config.endpoint, rpc_timeout).await?;
+                let provider = Arc::new(provider);
+                if let Some(wallet) = &wallet {
+                    check_chain_id(wallet.chain_id, provider.get_chainid().await?, force_chain)?;
+                }
+                check_contract(&provider, &config, force_contract).await?;
+                if let Some(poseidon) = config.poseidon_contract_address {
+                    check_poseidon(provider.clone(), poseidon, config.poseidon_variant).await?;
+                }
+                let token_contracts = wallet.map(|w| w.token_contracts).unwrap_or_default();
+
+                println!("{}", "Running in watch-only mode!".bright_yellow());
+                serve_wallet(
+                    provider,
+                    port,
+                    viewing_key.into(),
+                    viewing_key.public_key(),
+                    config.owshen_contract_address,
+                    config.dive_contract_address,
+                    config.owshen_contract_abi.clone(),
+                    config.erc20_abi.clone(),
+                    token_contracts,
+                    test,
+                    debug,
+                    true,
+                    Duration::from_secs(sync_interval),
+                    paths.clone(),
+                    scan_chunk_size,
+                    scan_concurrency,
+                    rate_limit.clone(),
+                    Duration::from_secs(shutdown_timeout),
+                    min_confirmations,
+                    metrics,
+                    api_token.clone(),
+                    auth_all,
+                    blinding.clone(),
+                    no_browser,
+                )
+                .await?;
+            } else if let Some(wallet) = &wallet {
                 let config = config.clone().unwrap_or_default();
-                let provider = Provider::<Http>::try_from(config.endpoint.clone()).unwrap();
+                let provider = rpc::connect_with_timeout(&config.endpoint, rpc_timeout).await?;
                 let provider = Arc::new(provider);
-                let priv_key = wallet.entropy.clone().into();
-                let pub_key = PublicKey::from(priv_key);
+                check_chain_id(wallet.chain_id, provider.get_chainid().await?, force_chain)?;
+                check_contract(&provider, &config, force_contract).await?;
+                if let Some(poseidon) = config.poseidon_contract_address {
+                    check_poseidon(provider.clone(), poseidon, config.poseidon_variant).await?;
+                }
+                let account = keystore::select_account(
+                    wallet,
+                    account.as_deref(),
+                    keystore.open(&wallet_path).as_ref(),
+                )?;
+                tracing::info!("Serving account {}", account.label);
+                let priv_key = account.priv_key;
 
                 serve_wallet(
                     provider,
                     port,
-                    priv_key,
-                    pub_key,
+                    priv_key.into(),
+                    priv_key.address(),
                     config.owshen_contract_address,
                     config.dive_contract_address,
                     config.owshen_contract_abi.clone(),
                     config.erc20_abi.clone(),
                     wallet.token_contracts.clone(),
                     test,
+                    debug,
+                    false,
+                    Duration::from_secs(sync_interval),
+                    paths.clone(),
+                    scan_chunk_size,
+                    scan_concurrency,
+                    rate_limit.clone(),
+                    Duration::from_secs(shutdown_timeout),
+                    min_confirmations,
+                    metrics,
+                    api_token.clone(),
+                    auth_all,
+                    blinding.clone(),
+                    no_browser,
                 )
                 .await?;
             } else {
                 if wallet.is_none() {
-                    let wallet = initialize_wallet(endpoint, None, test).await;
-                    std::fs::write(wallet_path, serde_json::to_string(&wallet).unwrap()).unwrap();
+                    let mut wallet =
+                        initialize_wallet(endpoint, None, test, None, 1, None, rpc_timeout).await?;
+                    if keystore == KeyStoreKind::Keyring {
+                        keystore::move_keys(&mut wallet, keystore.open(&wallet_path).as_ref())?;
+                    }
+                    wallet.save(&wallet_path)?;
                 } else {
                     println!("Wallet is already initialized!");
                 }
             }
         }
-        OwshenCliOpt::Info(InfoOpt {}) => {
-            let wallet = std::fs::read_to_string(&wallet_path)
-                .map(|s| {
-                    let w: Wallet = serde_json::from_str(&s).expect("Invalid wallet file!");
-                    w
-                })
-                .ok();
+        OwshenCliOpt::Info(InfoOpt {
+            qr,
+            account,
+            keystore,
+        }) => {
+            let wallet = Wallet::load(&wallet_path)?;
+            if let Some(wallet) = &wallet {
+                let account = keystore::select_account(
+                    wallet,
+                    account.as_deref(),
+                    keystore.open(&wallet_path).as_ref(),
+                )?;
+                let address = account.priv_key.address();
+                println!("Account: {}", account.label);
+                println!("Owshen Address: {}", address);
+                if qr {
+                    println!("{}", qr::to_terminal(&qr::address_qr(&address)?));
+                }
+            } else {
+                println!("Wallet is not initialized!");
+            }
+        }
+        OwshenCliOpt::ExportViewingKey(ExportViewingKeyOpt {
+            db,
+            account,
+            keystore,
+        }) => {
+            let wallet_path = db.unwrap_or(wallet_path.clone());
+            let wallet = Wallet::load(&wallet_path)?;
             if let Some(wallet) = &wallet {
+                let priv_key = keystore::select_account(
+                    wallet,
+                    account.as_deref(),
+                    keystore.open(&wallet_path).as_ref(),
+                )?
+                .priv_key;
+                println!("Viewing Key: {}", priv_key.viewing_key());
                 println!(
-                    "Owshen Address: {}",
-                    PublicKey::from(PrivateKey::from(wallet.entropy.clone()))
+                    "{}",
+                    "The viewing key reveals all of your incoming coins, only share it with parties you trust!"
+                        .bold()
+                        .bright_red()
                 );
             } else {
                 println!("Wallet is not initialized!");
             }
         }
+        OwshenCliOpt::Send(opt) => {
+            let wallet_path = opt.db.clone().unwrap_or(wallet_path);
+            let (wallet, config) = load_wallet_and_config(
+                wallet_path.clone(),
+                opt.config.clone().unwrap_or(config_path),
+            )?;
+            let provider =
+                Arc::new(rpc::connect_with_timeout(&config.endpoint, rpc_timeout).await?);
+            let priv_key = keystore::select_account(
+                &wallet,
+                opt.account.as_deref(),
+                opt.keystore.open(&wallet_path).as_ref(),
+            )?
+            .priv_key;
+            cli::send(
+                provider,
+                &config,
+                priv_key,
+                &wallet.token_contracts,
+                &paths,
+                &opt,
+            )
+            .await?;
+        }
+        OwshenCliOpt::Withdraw(opt) => {
+            let wallet_path = opt.db.clone().unwrap_or(wallet_path);
+            let (wallet, config) = load_wallet_and_config(
+                wallet_path.clone(),
+                opt.config.clone().unwrap_or(config_path),
+            )?;
+            let provider =
+                Arc::new(rpc::connect_with_timeout(&config.endpoint, rpc_timeout).await?);
+            let priv_key = keystore::select_account(
+                &wallet,
+                opt.account.as_deref(),
+                opt.keystore.open(&wallet_path).as_ref(),
+            )?
+            .priv_key;
+            cli::withdraw(
+                provider,
+                &config,
+                priv_key,
+                &wallet.token_contracts,
+                &paths,
+                &opt,
+            )
+            .await?;
+        }
+        OwshenCliOpt::Nullifier(opt) => {
+            let wallet_path = opt.db.clone().unwrap_or(wallet_path);
+            let (wallet, config) = load_wallet_and_config(
+                wallet_path.clone(),
+                opt.config.clone().unwrap_or(config_path),
+            )?;
+            let provider =
+                Arc::new(rpc::connect_with_timeout(&config.endpoint, rpc_timeout).await?);
+            let priv_key = keystore::select_account(
+                &wallet,
+                opt.account.as_deref(),
+                opt.keystore.open(&wallet_path).as_ref(),
+            )?
+            .priv_key;
+            cli::nullifier(provider, &config, priv_key, &opt).await?;
+        }
+        OwshenCliOpt::Export(opt) => {
+            let wallet_path = opt.db.clone().unwrap_or(wallet_path);
+            let (wallet, config) = load_wallet_and_config(
+                wallet_path.clone(),
+                opt.config.clone().unwrap_or(config_path),
+            )?;
+            let provider =
+                Arc::new(rpc::connect_with_timeout(&config.endpoint, rpc_timeout).await?);
+            let priv_key = keystore::select_account(
+                &wallet,
+                opt.account.as_deref(),
+                opt.keystore.open(&wallet_path).as_ref(),
+            )?
+            .priv_key;
+            cli::export(
+                provider,
+                &config,
+                priv_key,
+                &wallet.token_contracts,
+                &paths.state,
+                &opt,
+            )
+            .await?;
+        }
+        OwshenCliOpt::Watch(opt) => {
+            let config: Config = serde_json::from_str(
+                &std::fs::read_to_string(opt.config.clone().unwrap_or(config_path))
+                    .map_err(|_| eyre::Report::msg("Config is not initialized!"))?,
+            )?;
+            let provider =
+                Arc::new(rpc::connect_with_timeout(&config.endpoint, rpc_timeout).await?);
+            cli::watch(provider, &config, &opt).await?;
+        }
+        OwshenCliOpt::SetEndpoint(opt) => {
+            let config = set_endpoint(
+                &opt.db.unwrap_or(wallet_path),
+                &opt.config.unwrap_or(config_path),
+                opt.endpoint,
+                rpc_timeout,
+            )
+            .await?;
+            println!("{} now uses {}", config.name, config.endpoint);
+        }
+        OwshenCliOpt::AddressBook(opt) => {
+            let mut book = AddressBook::load(&address_book_path)?;
+            match opt {
+                AddressBookOpt::Add {
+                    label,
+                    address,
+                    replace,
+                } => {
+                    book.add(&label, &address, replace)?;
+                    book.save(&address_book_path)?;
+                }
+                AddressBookOpt::Remove { label } => {
+                    book.remove(&label)?;
+                    book.save(&address_book_path)?;
+                }
+                AddressBookOpt::List => {}
+            }
+            for (label, address) in book.entries() {
+                println!("{}: {}", label, address);
+            }
+        }
+        OwshenCliOpt::BenchProof(BenchProofOpt { iterations, params }) => {
+            let params = params.unwrap_or(paths.params);
+            proof::ensure_params(&params)?;
+            let (report, _) =
+                task::spawn_blocking(move || bench::bench_proof(params, iterations)).await??;
+            println!("{}", report);
+        }
+        OwshenCliOpt::Version(VersionOpt { params }) => {
+            let info = apis::build_info(&params.unwrap_or(paths.params));
+            println!("Version: {}", info.version);
+            println!("Commit: {}", info.git_commit);
+            println!("Target: {}", info.target);
+            match info.params_hash {
+                Some(hash) => println!("Proving key: {:?}", hash),
+                None => println!("Proving key: not found"),
+            }
+        }
+        OwshenCliOpt::Doctor(DoctorOpt { db, config, params }) => {
+            let checks = doctor::doctor(
+                &db.unwrap_or(wallet_path),
+                &config.unwrap_or(config_path),
+                params.unwrap_or(paths.params),
+                rpc_timeout,
+            )
+            .await;
+            for check in checks.iter() {
+                println!("{}", check);
+            }
+            let failed = checks.iter().filter(|check| !check.passed()).count();
+            if failed > 0 {
+                return Err(eyre::Report::msg(format!(
+                    "{} of {} checks failed!",
+                    failed,
+                    checks.len()
+                )));
+            }
+            println!("All checks passed!");
+        }
     }
 
     Ok(())
 }
+
+/// Points the config at `endpoint`, once it answers on the chain the wallet
+/// was initialized on and serves the configured Owshen contract. The coins
+/// and the state synced so far stay valid, nothing needs to be initialized
+/// again.
+async fn set_endpoint(
+    wallet_path: &Path,
+    config_path: &Path,
+    endpoint: String,
+    rpc_timeout: Duration,
+) -> Result<Config> {
+    let mut config: Config = serde_json::from_str(
+        &std::fs::read_to_string(config_path)
+            .map_err(|_| eyre::Report::msg("Config is not initialized!"))?,
+    )?;
+    let chain_id = Wallet::load(wallet_path)?.and_then(|wallet| wallet.chain_id);
+    let provider = rpc::connect_with_timeout(&endpoint, rpc_timeout).await?;
+    // Connecting over HTTP doesn't reach the node yet.
+    provider
+        .get_block_number()
+        .await
+        .map_err(|e| eyre::Report::msg(format!("{} doesn't answer: {}", endpoint, e)))?;
+    check_chain_id(chain_id, provider.get_chainid().await?, false)?;
+    ensure_contract(&provider, config.owshen_contract_address).await?;
+    config.endpoint = endpoint;
+    write_atomic(config_path, serde_json::to_string(&config)?.as_bytes())?;
+    Ok(config)
+}
+
+fn load_wallet_and_config(wallet_path: PathBuf, config_path: PathBuf) -> Result<(Wallet, Config)> {
+    let wallet =
+        Wallet::load(&wallet_path)?.ok_or(eyre::Report::msg("Wallet is not initialized!"))?;
+    let config: Config = serde_json::from_str(
+        &std::fs::read_to_string(&config_path)
+            .map_err(|_| eyre::Report::msg("Config is not initialized!"))?,
+    )?;
+    Ok((wallet, config))
+}
 use ethers::abi::Abi;
 use ethers::types::H160;
 
 use crate::keys::Entropy;
 
 async fn deploy(
-    client: Arc<Provider<Http>>,
+    client: Arc<RpcProvider>,
     abi: &str,
     bytecode: &str,
-) -> ContractInstance<Arc<Provider<Http>>, Provider<Http>> {
-    let from = client.get_accounts().await.unwrap()[0];
+    tx_type: TxType,
+    from: H160,
+) -> ContractInstance<Arc<RpcProvider>, RpcProvider> {
     let abi = serde_json::from_str::<Abi>(abi).unwrap();
     let bytecode = Bytes::from_str(bytecode).unwrap();
     let factory = ContractFactory::new(abi, bytecode, client);
-    let mut deployer = factory.deploy(()).unwrap().legacy();
+    let mut deployer = with_tx_type!(factory.deploy(()).unwrap(), tx_type);
     deployer.tx.set_from(from);
     let contract = deployer.send().await.unwrap();
     contract
@@ -810,4 +3413,1823 @@ mod tests {
             .unwrap()
         );
     }
+
+    // Sends a raw HTTP request and returns the whole response.
+    async fn raw_get(addr: SocketAddr, path: &str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                format!(
+                    "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                    path
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown() {
+        let app = Router::new().route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                "proof"
+            }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve_gracefully(
+            listener,
+            app,
+            async {
+                stopped.await.ok();
+            },
+            Duration::from_secs(10),
+        ));
+
+        let request = tokio::spawn(raw_get(addr, "/slow"));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        stop.send(()).unwrap();
+
+        // The in-flight request completes even though shutdown has begun.
+        let response = request.await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("proof"));
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown_timeout() {
+        let app = Router::new().route(
+            "/stuck",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                "proof"
+            }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve_gracefully(
+            listener,
+            app,
+            async {
+                stopped.await.ok();
+            },
+            Duration::from_millis(200),
+        ));
+
+        let _request = tokio::spawn(raw_get(addr, "/stuck"));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        stop.send(()).unwrap();
+
+        // Requests outliving the grace period don't hold the exit.
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sigterm_shuts_down() {
+        let app = Router::new().route("/", get(|| async { "proof" }));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (terminate, terminated) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve_gracefully(
+            listener,
+            app,
+            shutdown_on(std::future::pending(), async {
+                terminated.await.ok();
+            }),
+            Duration::from_secs(10),
+        ));
+        assert!(raw_get(addr, "/").await.ends_with("proof"));
+
+        terminate.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_flush_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_file = dir.path().join("state.json");
+        let context = RwLock::new(Context::new(H160::repeat_byte(1), false));
+        context.write().await.last_synced_block = Some(U64::from(42));
+        flush_state(&context, &state_file).await.unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&state_file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let mut restored = Context::new(H160::repeat_byte(1), false);
+        let key = PrivateKey::generate(&mut rand::thread_rng());
+        restored.load(&state_file, &key.into()).unwrap();
+        assert_eq!(restored.last_synced_block, Some(U64::from(42)));
+    }
+
+    #[tokio::test]
+    async fn test_state_guard() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_file = dir.path().join("state.json");
+        let context = Arc::new(RwLock::new(Context::new(H160::repeat_byte(1), false)));
+        let guard = || StateGuard {
+            context: context.clone(),
+            state_file: state_file.clone(),
+        };
+
+        // Nothing to save.
+        drop(guard());
+        assert!(!state_file.exists());
+
+        // Dropped halfway through, as on an early return.
+        {
+            let _guard = guard();
+            let mut ctx = context.write().await;
+            ctx.last_synced_block = Some(U64::from(42));
+            ctx.history.push(HistoryEntry::new(
+                HistoryKind::Send,
+                U256::from(1),
+                H160::default(),
+                Amount::from(10u64),
+            ));
+            ctx.mark_dirty();
+        }
+        let mut restored = Context::new(H160::repeat_byte(1), false);
+        let key = PrivateKey::generate(&mut rand::thread_rng());
+        restored.load(&state_file, &key.into()).unwrap();
+        assert_eq!(restored.last_synced_block, Some(U64::from(42)));
+        assert_eq!(restored.history.len(), 1);
+        // Only the state file is left in the directory.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // Saved already, it isn't written again.
+        std::fs::write(&state_file, "overwritten").unwrap();
+        drop(guard());
+        assert_eq!(std::fs::read_to_string(&state_file).unwrap(), "overwritten");
+    }
+
+    #[tokio::test]
+    async fn test_static_files_stay_in_root() {
+        let root = tempfile::tempdir().unwrap();
+        let static_files_path = root.path().join("static");
+        std::fs::create_dir(&static_files_path).unwrap();
+        std::fs::write(static_files_path.join("main.js"), "console.log(1);").unwrap();
+        std::fs::write(root.path().join("secret"), "secret").unwrap();
+
+        let app = static_files(static_files_path.clone());
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(get("/static/main.js")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .contains("javascript"));
+
+        for uri in ["/static/../secret", "/static/%2e%2e/secret"] {
+            let response = app.clone().oneshot(get(uri)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_static_content_types() {
+        let static_files_path = tempfile::tempdir().unwrap();
+        for file in ["main.js", "main.css", "logo.png"] {
+            std::fs::write(static_files_path.path().join(file), "").unwrap();
+        }
+        let app = static_files(static_files_path.path().to_path_buf());
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+        for (uri, content_type) in [
+            ("/static/main.js", "javascript"),
+            ("/static/main.css", "text/css"),
+            ("/static/logo.png", "image/png"),
+        ] {
+            let response = app.clone().oneshot(get(uri)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(response.headers()["content-type"]
+                .to_str()
+                .unwrap()
+                .contains(content_type));
+        }
+
+        let response = app.oneshot(get("/static/missing.js")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_resolve_static_path() {
+        let root = tempfile::tempdir().unwrap();
+        let static_files_path = root.path().join("static");
+        std::fs::create_dir_all(static_files_path.join("js")).unwrap();
+        std::fs::write(static_files_path.join("js/main.js"), "").unwrap();
+        std::fs::write(root.path().join("secret"), "secret").unwrap();
+
+        assert_eq!(
+            resolve_static_path(&static_files_path, "js/main.js"),
+            Some(static_files_path.join("js/main.js").canonicalize().unwrap())
+        );
+        assert_eq!(
+            resolve_static_path(&static_files_path, "js/missing.js"),
+            None
+        );
+        assert_eq!(resolve_static_path(&static_files_path, "../secret"), None);
+        assert_eq!(
+            resolve_static_path(&static_files_path, "js/../../secret"),
+            None
+        );
+        assert_eq!(
+            resolve_static_path(&static_files_path, "./js/main.js"),
+            None
+        );
+        let absolute = root.path().join("secret");
+        assert_eq!(
+            resolve_static_path(&static_files_path, absolute.to_str().unwrap()),
+            None
+        );
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.path().join("secret"), static_files_path.join("leak"))
+                .unwrap();
+            std::os::unix::fs::symlink(
+                static_files_path.join("js/main.js"),
+                static_files_path.join("main.js"),
+            )
+            .unwrap();
+            assert_eq!(resolve_static_path(&static_files_path, "leak"), None);
+            assert!(resolve_static_path(&static_files_path, "main.js").is_some());
+        }
+    }
+
+    #[test]
+    fn test_wallet_accounts() {
+        let entropy = Entropy::generate(&mut rand::thread_rng());
+        let wallet = Wallet {
+            version: WALLET_VERSION,
+            entropy: Some(entropy),
+            token_contracts: vec![],
+            chain_id: None,
+            accounts: derive_accounts(&entropy, 3),
+        };
+        let by_index = wallet.account(Some("1")).unwrap();
+        let by_label = wallet.account(Some("account1")).unwrap();
+        assert_eq!(by_index.priv_key.secret, by_label.priv_key.secret);
+        assert_eq!(
+            wallet.account(None).unwrap().priv_key.secret,
+            PrivateKey::from(entropy).secret
+        );
+        assert!(wallet.account(Some("3")).is_err());
+        assert!(wallet.account(Some("savings")).is_err());
+
+        // Wallets predating accounts keep their single key.
+        let mut json = serde_json::to_value(&wallet).unwrap();
+        json.as_object_mut().unwrap().remove("accounts");
+        let old: Wallet = serde_json::from_value(json).unwrap();
+        assert_eq!(old.accounts().len(), 1);
+        assert_eq!(
+            old.account(None).unwrap().priv_key.secret,
+            PrivateKey::from(entropy).secret
+        );
+    }
+
+    #[test]
+    fn test_wallet_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wallet.json");
+        let wallet = |entropy: Entropy| Wallet {
+            version: WALLET_VERSION,
+            entropy: Some(entropy),
+            token_contracts: vec![],
+            chain_id: Some(U256::from(1337)),
+            accounts: derive_accounts(&entropy, 2),
+        };
+        let entropy = Entropy::generate(&mut rand::thread_rng());
+        wallet(entropy).save(&path).unwrap();
+        let saved = Wallet::load_with_key(&path, None).unwrap().unwrap();
+        assert_eq!(saved.accounts().len(), 2);
+        assert_eq!(saved.chain_id, Some(U256::from(1337)));
+
+        // The process dies halfway through writing another wallet.
+        let other =
+            serde_json::to_string(&wallet(Entropy::generate(&mut rand::thread_rng()))).unwrap();
+        let interrupted = write_atomic_with(&path, |file| {
+            file.write_all(&other.as_bytes()[..other.len() / 2])?;
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "killed",
+            ))
+        });
+        assert!(interrupted.is_err());
+        let kept = Wallet::load_with_key(&path, None).unwrap().unwrap();
+        assert_eq!(
+            kept.account(None).unwrap().priv_key.secret,
+            PrivateKey::from(entropy).secret
+        );
+        // Nothing is left besides the wallet.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_wallet_tokens() {
+        let token = |address: u64, symbol: &str| TokenInfo {
+            token_address: H160::from_low_u64_be(address),
+            symbol: symbol.to_string(),
+            decimals: default_decimals(),
+        };
+        let mut wallet = Wallet {
+            version: WALLET_VERSION,
+            entropy: Some(Entropy::generate(&mut rand::thread_rng())),
+            token_contracts: vec![],
+            chain_id: None,
+            accounts: vec![],
+        };
+        wallet.add_token(token(3, "WETH")).unwrap();
+        wallet.add_token(token(2, "USDC")).unwrap();
+        wallet.add_token(token(1, "USDC")).unwrap();
+        assert!(wallet.add_token(token(3, "WETH")).is_err());
+        assert!(wallet.add_token(token(3, "DAI")).is_err());
+        let listed = |wallet: &Wallet| {
+            wallet
+                .token_contracts
+                .iter()
+                .map(|t| (t.token_address.to_low_u64_be(), t.symbol.clone()))
+                .collect::<Vec<_>>()
+        };
+        let expected = vec![
+            (1, "USDC".to_string()),
+            (2, "USDC".to_string()),
+            (3, "WETH".to_string()),
+        ];
+        assert_eq!(listed(&wallet), expected);
+
+        // Duplicates of a file are dropped on load, the first one is kept.
+        let mut json = serde_json::to_value(&wallet).unwrap();
+        let tokens = json["token_contracts"].as_array_mut().unwrap();
+        tokens.reverse();
+        tokens.push(serde_json::to_value(token(1, "DAI")).unwrap());
+        let loaded = Wallet::from_json(&json.to_string()).unwrap();
+        assert_eq!(listed(&loaded), expected);
+    }
+
+    #[test]
+    fn test_context_is_send_sync() {
+        // Fails to compile, rather than to run, if a field stops being
+        // shareable across tasks.
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Context>();
+        assert_send_sync::<SharedContext>();
+        assert_send_sync::<SparseMerkleTree>();
+        assert_send_sync::<Coin>();
+        assert_send_sync::<Proof>();
+    }
+
+    #[test]
+    fn test_open_browser() {
+        let url = "http://127.0.0.1:8000";
+        let opened = std::cell::Cell::new(None);
+        let open = |url: &str| {
+            opened.set(Some(url.to_string()));
+            Ok(())
+        };
+
+        // `--no-browser`, or no display.
+        assert!(!open_browser(url, false, open));
+        assert_eq!(opened.take(), None);
+
+        assert!(open_browser(url, true, open));
+        assert_eq!(opened.take(), Some(url.to_string()));
+        let failing = |_: &str| Err(std::io::Error::from(std::io::ErrorKind::NotFound));
+        assert!(!open_browser(url, true, failing));
+    }
+
+    #[test]
+    fn test_sync_interval() {
+        let parse = |interval: &str| {
+            WalletOpt::from_iter_safe(["wallet", "--sync-interval", interval])
+                .map(|opt| opt.sync_interval)
+        };
+        assert_eq!(parse("30").unwrap(), 30);
+        assert!(parse("0").is_err());
+        assert!(parse("-1").is_err());
+        assert_eq!(
+            WalletOpt::from_iter_safe(["wallet"]).unwrap().sync_interval,
+            10
+        );
+    }
+
+    #[test]
+    fn test_chain_id_mismatch() {
+        let goerli = U256::from(5);
+        let mainnet = U256::from(1);
+        assert!(check_chain_id(Some(goerli), goerli, false).is_ok());
+        assert!(check_chain_id(Some(goerli), mainnet, false).is_err());
+        assert!(check_chain_id(Some(goerli), mainnet, true).is_ok());
+        // Old wallet files don't record a chain id.
+        assert!(check_chain_id(None, mainnet, false).is_ok());
+
+        let wallet = Wallet {
+            version: WALLET_VERSION,
+            entropy: Some(Entropy::generate(&mut rand::thread_rng())),
+            token_contracts: vec![],
+            chain_id: Some(goerli),
+            accounts: vec![],
+        };
+        let mut json = serde_json::to_value(&wallet).unwrap();
+        json.as_object_mut().unwrap().remove("chain_id");
+        let old_wallet: Wallet = serde_json::from_value(json).unwrap();
+        assert_eq!(old_wallet.chain_id, None);
+    }
+
+    #[test]
+    fn test_wallet_versions() {
+        let entropy = Entropy::generate(&mut rand::thread_rng());
+        let wallet = Wallet {
+            version: WALLET_VERSION,
+            entropy: Some(entropy),
+            token_contracts: vec![],
+            chain_id: Some(U256::from(5)),
+            accounts: derive_accounts(&entropy, 2),
+        };
+        let json = serde_json::to_value(&wallet).unwrap();
+        let loaded = Wallet::from_json(&json.to_string()).unwrap();
+        assert_eq!(loaded.version, WALLET_VERSION);
+        assert_eq!(loaded.accounts().len(), 2);
+
+        // A version 0 file, as written before versions, accounts and chain
+        // ids existed.
+        let v0 = serde_json::json!({
+            "entropy": json["entropy"],
+            "token_contracts": [],
+        });
+        let migrated = Wallet::from_json(&v0.to_string()).unwrap();
+        assert_eq!(migrated.version, WALLET_VERSION);
+        assert_eq!(migrated.accounts.len(), 1);
+        assert_eq!(
+            migrated.account(None).unwrap().priv_key.secret,
+            PrivateKey::from(entropy).secret
+        );
+
+        let mut future = json.clone();
+        future["version"] = serde_json::json!(WALLET_VERSION + 1);
+        let err = Wallet::from_json(&future.to_string())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(&format!("schema version {}", WALLET_VERSION + 1)));
+        assert!(err.contains("upgrade owshen"));
+
+        let mut broken = v0.clone();
+        broken.as_object_mut().unwrap().remove("entropy");
+        let err = Wallet::from_json(&broken.to_string())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("schema version 0"), "{}", err);
+        assert!(err.contains("entropy"), "{}", err);
+        assert!(Wallet::from_json(r#"{"version": "1"}"#).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wallet.json");
+        assert!(Wallet::load(&path).unwrap().is_none());
+        std::fs::write(&path, future.to_string()).unwrap();
+        let err = Wallet::load(&path).unwrap_err().to_string();
+        assert!(err.contains("wallet.json"), "{}", err);
+    }
+
+    #[test]
+    fn test_private_key_from_env() {
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        assert!(private_key_from(None).unwrap().is_none());
+        assert!(private_key_from(Some(" ")).unwrap().is_none());
+        assert_eq!(
+            private_key_from(Some(&format!("{}\n", priv_key)))
+                .unwrap()
+                .unwrap()
+                .secret,
+            priv_key.secret
+        );
+        let err = private_key_from(Some("0x1234")).unwrap_err().to_string();
+        assert!(err.contains(PRIVATE_KEY_VAR), "{}", err);
+
+        // A wallet file holding no secret at all.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wallet.json");
+        let wallet = serde_json::json!({
+            "version": WALLET_VERSION,
+            "token_contracts": [],
+            "chain_id": "0x5",
+        });
+        std::fs::write(&path, wallet.to_string()).unwrap();
+        let keyless = Wallet::load_with_key(&path, None).unwrap().unwrap();
+        let err = keyless.account(None).unwrap_err().to_string();
+        assert!(err.contains(PRIVATE_KEY_VAR), "{}", err);
+        let supplied = Wallet::load_with_key(&path, Some(priv_key))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            supplied.account(None).unwrap().priv_key.secret,
+            priv_key.secret
+        );
+        assert_eq!(supplied.chain_id, Some(U256::from(5)));
+        // Nothing secret is written back either.
+        assert!(serde_json::to_value(&keyless)
+            .unwrap()
+            .get("entropy")
+            .is_none());
+
+        // The key of the environment wins over the ones of the file.
+        let entropy = Entropy::generate(&mut rand::thread_rng());
+        let full = Wallet {
+            version: WALLET_VERSION,
+            entropy: Some(entropy),
+            token_contracts: vec![],
+            chain_id: None,
+            accounts: derive_accounts(&entropy, 2),
+        };
+        std::fs::write(&path, serde_json::to_string(&full).unwrap()).unwrap();
+        let overridden = Wallet::load_with_key(&path, Some(priv_key))
+            .unwrap()
+            .unwrap();
+        assert_eq!(overridden.accounts().len(), 1);
+        let account = overridden.account(None).unwrap();
+        assert_eq!(account.label, "env");
+        assert_eq!(account.priv_key.secret, priv_key.secret);
+    }
+
+    #[test]
+    fn test_amounts_take_the_token_decimals() {
+        let usdc = H160::repeat_byte(6);
+        let unknown = H160::repeat_byte(7);
+        let context = Context {
+            token_decimals: HashMap::from([(usdc, 6)]),
+            ..Context::new(H160::default(), false)
+        };
+        assert_eq!(
+            context.parse_amount(usdc, "1.5", Some(6)).unwrap(),
+            Amount::from(1_500_000)
+        );
+        // Scaled by other decimals than the token's, the amount would be
+        // off by a trillion.
+        assert!(matches!(
+            context.parse_amount(usdc, "1.5", Some(18)),
+            Err(ApiError::BadRequest(_))
+        ));
+        assert!(context.ensure_precision(usdc, "1.5", Some(18)).is_err());
+        assert!(context.ensure_precision(usdc, "1.5", Some(6)).is_ok());
+        assert_eq!(
+            context.parse_amount(unknown, "1.5", Some(2)).unwrap(),
+            Amount::from(150)
+        );
+        // The smallest unit doesn't depend on the token.
+        assert_eq!(
+            context.parse_amount(usdc, "15", None).unwrap(),
+            Amount::from(15)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_context_snapshot() {
+        let owshen_contract = H160::from_low_u64_be(1);
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let (ephemeral, pub_key) = priv_key.address().derive(&mut rand::thread_rng());
+        let token = H160::from_low_u64_be(2);
+        let amount = Amount::from(100);
+        let coin_commitment =
+            commitment::commitment(amount.into(), &pub_key, h160_to_u256(token)).unwrap();
+        let coin = Coin {
+            index: U256::from(2),
+            uint_token: token,
+            amount,
+            priv_key: priv_key.stealth_key(ephemeral, &pub_key),
+            pub_key,
+            nullifier: Some(U256::from(3)),
+            commitment: coin_commitment,
+            block_number: None,
+            tx_hash: None,
+            ephemeral: Some(ephemeral),
+        };
+        let mut context = Context::new(owshen_contract, false);
+        Arc::make_mut(&mut context.coins).push(coin);
+        context.tree.set(2, Fp::try_from(coin_commitment).unwrap());
+        context.spent_nullifiers.insert(U256::from(5));
+        context.last_synced_block = Some(U64::from(10));
+
+        let snapshot: ContextSnapshot =
+            serde_json::from_str(&serde_json::to_string(&context.snapshot()).unwrap()).unwrap();
+        // The state file holds no key.
+        assert!(snapshot.coins.iter().all(|coin| coin.priv_key.is_none()));
+        let mut restored = Context::new(owshen_contract, false);
+        restored
+            .restore(snapshot.clone(), &priv_key.into())
+            .unwrap();
+        assert_eq!(
+            restored.coins[0].priv_key.map(|key| key.secret),
+            coin.priv_key.map(|key| key.secret)
+        );
+        assert_eq!(restored.tree.root(), context.tree.root());
+        assert_eq!(restored.spent_nullifiers, context.spent_nullifiers);
+        assert_eq!(restored.last_synced_block, context.last_synced_block);
+
+        let coins = apis::coins(
+            Query(GetCoinsRequest::default()),
+            Arc::new(RwLock::new(context)),
+        )
+        .await
+        .unwrap();
+        let restored_coins = apis::coins(
+            Query(GetCoinsRequest::default()),
+            Arc::new(RwLock::new(restored)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            serde_json::to_string(&coins.listed().collect::<Vec<_>>()).unwrap(),
+            serde_json::to_string(&restored_coins.listed().collect::<Vec<_>>()).unwrap()
+        );
+
+        // Another key doesn't open the coins, the chain is scanned again.
+        let other_key = PrivateKey::generate(&mut rand::thread_rng());
+        let mut other = Context::new(owshen_contract, false);
+        assert!(other.restore(snapshot.clone(), &other_key.into()).is_err());
+        assert!(other.coins.is_empty());
+        // A viewing key has nothing to derive.
+        let mut watching = Context::new(owshen_contract, true);
+        watching
+            .restore(snapshot.clone(), &priv_key.viewing_key().into())
+            .unwrap();
+        assert_eq!(watching.coins.len(), 1);
+        assert!(watching.coins[0].priv_key.is_none());
+
+        let mut other = Context::new(H160::from_low_u64_be(2), false);
+        assert!(other.restore(snapshot, &priv_key.into()).is_err());
+        assert!(other.coins.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_set_endpoint() {
+        let port = 8564u16;
+        let endpoint = format!("http://localhost:{}", port);
+        let _ganache = Ganache::new().port(port).spawn();
+        let dir = tempfile::tempdir().unwrap();
+        let wallet_path = dir.path().join("wallet.json");
+        let config_path = dir.path().join("config.json");
+        let wallet = initialize_wallet(
+            endpoint.clone(),
+            None,
+            true,
+            Some(TxType::Legacy),
+            1,
+            None,
+            rpc::DEFAULT_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        std::fs::write(&wallet_path, serde_json::to_string(&wallet).unwrap()).unwrap();
+        let config = initialize_config(
+            endpoint,
+            "switched".to_string(),
+            true,
+            Some(TxType::Legacy),
+            &PoseidonArtifacts::bundled(PoseidonVariant::default()),
+            ExistingContracts::default(),
+            None,
+            rpc::DEFAULT_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        std::fs::write(&config_path, serde_json::to_string(&config).unwrap()).unwrap();
+
+        // The same node under another URL, as another provider would be.
+        let switched = format!("http://127.0.0.1:{}", port);
+        set_endpoint(
+            &wallet_path,
+            &config_path,
+            switched.clone(),
+            rpc::DEFAULT_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        let (_, loaded) = load_wallet_and_config(wallet_path.clone(), config_path.clone()).unwrap();
+        assert_eq!(loaded.endpoint, switched);
+        assert_eq!(
+            loaded.owshen_contract_address,
+            config.owshen_contract_address
+        );
+        let provider = rpc::connect(&loaded.endpoint).await.unwrap();
+        assert!(check_contract(&provider, &loaded, false).await.is_ok());
+
+        // Nothing is written for a node which doesn't answer, nor for one on
+        // another chain than the wallet's.
+        assert!(set_endpoint(
+            &wallet_path,
+            &config_path,
+            "http://127.0.0.1:1".to_string(),
+            Duration::from_secs(1),
+        )
+        .await
+        .is_err());
+        let other_chain = Wallet {
+            chain_id: wallet.chain_id.map(|chain_id| chain_id + 1),
+            ..wallet
+        };
+        std::fs::write(&wallet_path, serde_json::to_string(&other_chain).unwrap()).unwrap();
+        let err = set_endpoint(
+            &wallet_path,
+            &config_path,
+            format!("http://localhost:{}", port),
+            rpc::DEFAULT_TIMEOUT,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("chain"));
+        let (_, loaded) = load_wallet_and_config(wallet_path, config_path).unwrap();
+        assert_eq!(loaded.endpoint, switched);
+    }
+
+    #[tokio::test]
+    async fn test_config_existing_contracts() {
+        let port = 8549u16;
+        let endpoint = format!("http://localhost:{}", port);
+        let _ganache = Ganache::new().port(port).spawn();
+        let provider = Arc::new(rpc::connect(&endpoint).await.unwrap());
+        let (owshen, token) = deploy_owshen(provider.clone()).await;
+
+        let config = initialize_config(
+            endpoint.clone(),
+            "existing".to_string(),
+            false,
+            None,
+            &PoseidonArtifacts::bundled(PoseidonVariant::default()),
+            ExistingContracts {
+                owshen: Some(owshen.address()),
+                dive: Some(token.address()),
+                poseidon: None,
+            },
+            None,
+            rpc::DEFAULT_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(config.owshen_contract_address, owshen.address());
+        assert_eq!(config.dive_contract_address, token.address());
+        assert_eq!(&config.owshen_contract_abi, owshen.abi());
+        assert_eq!(&config.erc20_abi, token.abi());
+        assert!(check_contract(&provider, &config, false).await.is_ok());
+        // Nothing was deposited yet, the contract's root is its empty root.
+        let empty_root: U256 = SparseMerkleTree::new(16).empty_root().into();
+        assert_eq!(owshen.root().call().await.unwrap(), empty_root);
+        assert!(empty_root_mismatch(&provider, owshen.address())
+            .await
+            .unwrap()
+            .is_none());
+        assert!(version_mismatch(&provider, owshen.address())
+            .await
+            .unwrap()
+            .is_none());
+        // Any other contract predates the versions, e.g. the token.
+        assert!(version_mismatch(&provider, token.address())
+            .await
+            .unwrap()
+            .unwrap()
+            .contains("predates"));
+
+        // A truncated ABI, e.g. stored before `withdraw` was added
+        let mut stale = config.clone();
+        stale.owshen_contract_abi.functions.remove("withdraw");
+        let err = check_contract(&provider, &stale, false)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("withdraw"));
+        assert!(check_contract(&provider, &stale, true).await.is_ok());
+
+        // Another contract got deployed at the address
+        let mut redeployed = config.clone();
+        redeployed.owshen_code_hash = Some(H256::zero());
+        assert!(check_contract(&provider, &redeployed, false).await.is_err());
+        // Older configs don't record the code hash.
+        redeployed.owshen_code_hash = None;
+        assert!(check_contract(&provider, &redeployed, false).await.is_ok());
+
+        // Accounts have no code
+        let account = provider.get_accounts().await.unwrap()[0];
+        assert!(initialize_config(
+            endpoint,
+            "existing".to_string(),
+            false,
+            None,
+            &PoseidonArtifacts::bundled(PoseidonVariant::default()),
+            ExistingContracts {
+                owshen: Some(account),
+                ..Default::default()
+            },
+            None,
+            rpc::DEFAULT_TIMEOUT,
+        )
+        .await
+        .is_err());
+    }
+
+    #[test]
+    fn test_existing_contracts_need_owshen() {
+        let dive_only = ExistingContracts {
+            dive: Some(H160::repeat_byte(1)),
+            ..Default::default()
+        };
+        assert!(dive_only.check(false).is_err());
+        // Test mode deploys the missing ones.
+        assert!(dive_only.check(true).is_ok());
+        let poseidon_only = ExistingContracts {
+            poseidon: Some(H160::repeat_byte(2)),
+            ..Default::default()
+        };
+        assert!(poseidon_only.check(false).is_err());
+        assert!(ExistingContracts::default().check(false).is_ok());
+        assert!(ExistingContracts {
+            owshen: Some(H160::repeat_byte(3)),
+            ..dive_only
+        }
+        .check(false)
+        .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_config_from_account() {
+        let port = 8552u16;
+        let endpoint = format!("http://localhost:{}", port);
+        let _ganache = Ganache::new().port(port).spawn();
+        let provider = Arc::new(rpc::connect(&endpoint).await.unwrap());
+        let accounts = provider.get_accounts().await.unwrap();
+
+        let config = initialize_config(
+            endpoint.clone(),
+            "from".to_string(),
+            true,
+            Some(TxType::Legacy),
+            &PoseidonArtifacts::bundled(PoseidonVariant::default()),
+            ExistingContracts::default(),
+            Some(accounts[2]),
+            rpc::DEFAULT_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        // The whole supply is minted to the deployer of the token.
+        let dive = SimpleErc20::new(config.dive_contract_address, provider.clone());
+        assert_eq!(
+            dive.balance_of(accounts[2]).call().await.unwrap(),
+            U256::from_dec_str("1000000000000000000000").unwrap()
+        );
+        assert!(dive.balance_of(accounts[0]).call().await.unwrap().is_zero());
+
+        // Nobody on the node can sign for a random address.
+        assert!(initialize_config(
+            endpoint,
+            "from".to_string(),
+            true,
+            Some(TxType::Legacy),
+            &PoseidonArtifacts::bundled(PoseidonVariant::default()),
+            ExistingContracts::default(),
+            Some(H160::random()),
+            rpc::DEFAULT_TIMEOUT,
+        )
+        .await
+        .is_err());
+    }
+
+    async fn deploy_owshen(
+        provider: Arc<RpcProvider>,
+    ) -> (Owshen<RpcProvider>, SimpleErc20<RpcProvider>) {
+        let from = provider.get_accounts().await.unwrap()[0];
+        let poseidon4_addr = deploy(
+            provider.clone(),
+            include_str!("assets/poseidon4.abi"),
+            include_str!("assets/poseidon4.evm"),
+            TxType::Legacy,
+            from,
+        )
+        .await
+        .address();
+        let token = SimpleErc20::deploy(
+            provider.clone(),
+            (
+                U256::from_str_radix("1000000000000000000000", 10).unwrap(),
+                String::from_str("test_token").unwrap(),
+                String::from_str("TEST").unwrap(),
+                18u8,
+            ),
+        )
+        .unwrap()
+        .legacy()
+        .from(from)
+        .send()
+        .await
+        .unwrap();
+        let owshen = Owshen::deploy(provider.clone(), poseidon4_addr)
+            .unwrap()
+            .legacy()
+            .from(from)
+            .send()
+            .await
+            .unwrap();
+        (owshen, token)
+    }
+
+    async fn deposit(
+        provider: Arc<RpcProvider>,
+        owshen: &Owshen<RpcProvider>,
+        token: &SimpleErc20<RpcProvider>,
+        pub_key: PublicKey,
+        amount: U256,
+    ) {
+        let from = provider.get_accounts().await.unwrap()[0];
+        token
+            .approve(owshen.address(), amount)
+            .legacy()
+            .from(from)
+            .send()
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        let (ephemeral, stealth_pub, view_tag) =
+            pub_key.derive_with_view_tag(&mut rand::thread_rng());
+        owshen
+            .deposit(
+                stealth_pub.point.into(),
+                ephemeral.point.into(),
+                token.address(),
+                amount,
+                from,
+                owshen.address(),
+                view_tag,
+            )
+            .legacy()
+            .from(from)
+            .send()
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+    }
+
+    async fn wait_for_coins(context: SharedContext, count: usize) -> Vec<Coin> {
+        for _ in 0..30 {
+            let coins = context.read().await.coins.clone();
+            if coins.len() >= count {
+                return coins.to_vec();
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        panic!("Coins were not synced in time!");
+    }
+
+    // A node with Owshen deployed and a coin of 100 tokens deposited to a
+    // fresh key, synced into a wallet of its own.
+    struct SyncedCoin {
+        _ganache: ethers::utils::GanacheInstance,
+        provider: Arc<RpcProvider>,
+        owshen: Owshen<RpcProvider>,
+        token: SimpleErc20<RpcProvider>,
+        pub_key: PublicKey,
+        context: SharedContext,
+        coin: Coin,
+    }
+
+    async fn synced_coin(port: u16) -> SyncedCoin {
+        let ganache = Ganache::new().port(port).spawn();
+        let provider = Arc::new(
+            rpc::connect(&format!("http://localhost:{}", port))
+                .await
+                .unwrap(),
+        );
+        let (owshen, token) = deploy_owshen(provider.clone()).await;
+
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let pub_key = PublicKey::from(priv_key);
+        deposit(provider.clone(), &owshen, &token, pub_key, U256::from(100)).await;
+
+        let context = Arc::new(RwLock::new(Context::new(H160::default(), false)));
+        let contract = Contract::new(owshen.address(), owshen.abi().clone(), provider.clone());
+        let (coin_sender, _) = broadcast::channel(16);
+        let sync = tokio::spawn(apis::sync_loop(
+            context.clone(),
+            contract,
+            priv_key.into(),
+            coin_sender,
+            Duration::from_secs(1),
+            None,
+            apis::DEFAULT_SCAN_CHUNK_SIZE,
+            apis::DEFAULT_SCAN_CONCURRENCY,
+        ));
+        let coins = wait_for_coins(context.clone(), 1).await;
+        sync.abort();
+
+        SyncedCoin {
+            _ganache: ganache,
+            provider,
+            owshen,
+            token,
+            pub_key,
+            context,
+            coin: coins[0],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_background_sync() {
+        let port = 8546u16;
+        let _ganache = Ganache::new().port(port).spawn();
+        let provider = Arc::new(
+            rpc::connect(&format!("http://localhost:{}", port))
+                .await
+                .unwrap(),
+        );
+        let (owshen, token) = deploy_owshen(provider.clone()).await;
+
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        deposit(
+            provider.clone(),
+            &owshen,
+            &token,
+            PublicKey::from(priv_key),
+            U256::from(100),
+        )
+        .await;
+
+        let context = Arc::new(RwLock::new(Context::new(H160::default(), false)));
+        let contract = Contract::new(owshen.address(), owshen.abi().clone(), provider.clone());
+        let (coin_sender, _) = broadcast::channel(16);
+        let sync = tokio::spawn(apis::sync_loop(
+            context.clone(),
+            contract,
+            priv_key.into(),
+            coin_sender,
+            Duration::from_millis(200),
+            None,
+            apis::DEFAULT_SCAN_CHUNK_SIZE,
+            apis::DEFAULT_SCAN_CONCURRENCY,
+        ));
+
+        let coins = wait_for_coins(context.clone(), 1).await;
+        sync.abort();
+        assert_eq!(coins[0].amount, Amount::from(100));
+        assert!(context.read().await.last_synced_block.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_ws_sync() {
+        let port = 8556u16;
+        let _ganache = Ganache::new().port(port).spawn();
+        let provider = Arc::new(
+            rpc::connect(&format!("ws://localhost:{}", port))
+                .await
+                .unwrap(),
+        );
+        let (owshen, token) = deploy_owshen(provider.clone()).await;
+
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let context = Arc::new(RwLock::new(Context::new(H160::default(), false)));
+        let contract = Contract::new(owshen.address(), owshen.abi().clone(), provider.clone());
+        let (coin_sender, _) = broadcast::channel(16);
+        // Nothing is polled during the test, only the subscription can
+        // trigger the second scan.
+        let sync = tokio::spawn(apis::sync_loop(
+            context.clone(),
+            contract,
+            priv_key.into(),
+            coin_sender,
+            Duration::from_secs(3600),
+            None,
+            apis::DEFAULT_SCAN_CHUNK_SIZE,
+            apis::DEFAULT_SCAN_CONCURRENCY,
+        ));
+        for _ in 0..30 {
+            if context.read().await.last_synced_block.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        assert!(context.read().await.coins.is_empty());
+
+        deposit(
+            provider.clone(),
+            &owshen,
+            &token,
+            PublicKey::from(priv_key),
+            U256::from(100),
+        )
+        .await;
+        let coins = wait_for_coins(context.clone(), 1).await;
+        sync.abort();
+        assert_eq!(coins[0].amount, Amount::from(100u64));
+    }
+
+    // Forwards the connections made to `port` to the node on `upstream`.
+    // Clearing the returned flag drops every connection and refuses new ones
+    // until it is set again.
+    async fn flaky_proxy(port: u16, upstream: u16) -> Arc<std::sync::atomic::AtomicBool> {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+            .await
+            .unwrap();
+        let up = Arc::new(AtomicBool::new(true));
+        let proxy_up = up.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut inbound, _) = listener.accept().await.unwrap();
+                if !proxy_up.load(Ordering::SeqCst) {
+                    continue;
+                }
+                let up = proxy_up.clone();
+                tokio::spawn(async move {
+                    let mut outbound = tokio::net::TcpStream::connect(("127.0.0.1", upstream))
+                        .await
+                        .unwrap();
+                    tokio::select! {
+                        _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound) => {}
+                        _ = async {
+                            while up.load(Ordering::SeqCst) {
+                                tokio::time::sleep(Duration::from_millis(50)).await;
+                            }
+                        } => {}
+                    }
+                });
+            }
+        });
+        up
+    }
+
+    #[tokio::test]
+    async fn test_ws_reconnect() {
+        use std::sync::atomic::Ordering;
+        let port = 8558u16;
+        let proxy_port = 8559u16;
+        let _ganache = Ganache::new().port(port).spawn();
+        let proxy = flaky_proxy(proxy_port, port).await;
+        let http = Arc::new(
+            rpc::connect(&format!("http://localhost:{}", port))
+                .await
+                .unwrap(),
+        );
+        let (owshen, token) = deploy_owshen(http.clone()).await;
+
+        let provider = Arc::new(
+            rpc::connect(&format!("ws://localhost:{}", proxy_port))
+                .await
+                .unwrap(),
+        );
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let context = Arc::new(RwLock::new(Context::new(H160::default(), false)));
+        let contract = Contract::new(owshen.address(), owshen.abi().clone(), provider.clone());
+        let (coin_sender, _) = broadcast::channel(16);
+        let sync = tokio::spawn(apis::sync_loop(
+            context.clone(),
+            contract,
+            priv_key.into(),
+            coin_sender,
+            Duration::from_secs(3600),
+            None,
+            apis::DEFAULT_SCAN_CHUNK_SIZE,
+            apis::DEFAULT_SCAN_CONCURRENCY,
+        ));
+        for _ in 0..30 {
+            if context.read().await.last_synced_block.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        assert!(context.read().await.last_synced_block.is_some());
+
+        // The coin is minted while the connection is down, its log never
+        // reaches the subscription and has to be backfilled.
+        proxy.store(false, Ordering::SeqCst);
+        let pub_key = PublicKey::from(priv_key);
+        deposit(http.clone(), &owshen, &token, pub_key, U256::from(100)).await;
+        proxy.store(true, Ordering::SeqCst);
+        let coins = wait_for_coins(context.clone(), 1).await;
+        assert_eq!(coins[0].amount, Amount::from(100u64));
+
+        // Then the new subscription picks up the next one.
+        deposit(http.clone(), &owshen, &token, pub_key, U256::from(50)).await;
+        let coins = wait_for_coins(context.clone(), 2).await;
+        sync.abort();
+        assert_eq!(coins[1].amount, Amount::from(50u64));
+        assert!(provider.get_block_number().await.unwrap() > U64::zero());
+    }
+
+    #[tokio::test]
+    async fn test_reorg() {
+        let port = 8560u16;
+        let _ganache = Ganache::new().port(port).spawn();
+        let provider = Arc::new(
+            rpc::connect(&format!("http://localhost:{}", port))
+                .await
+                .unwrap(),
+        );
+        let (owshen, token) = deploy_owshen(provider.clone()).await;
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        let pub_key = PublicKey::from(priv_key);
+        deposit(provider.clone(), &owshen, &token, pub_key, U256::from(100)).await;
+
+        let context = Arc::new(RwLock::new(Context::new(owshen.address(), false)));
+        let contract = Contract::new(owshen.address(), owshen.abi().clone(), provider.clone());
+        let (coin_sender, _) = broadcast::channel(16);
+        let sync = || {
+            apis::sync_coins(
+                context.clone(),
+                contract.clone(),
+                priv_key.into(),
+                coin_sender.clone(),
+                apis::DEFAULT_SCAN_CHUNK_SIZE,
+                apis::DEFAULT_SCAN_CONCURRENCY,
+            )
+        };
+        sync().await.unwrap();
+
+        // The second coin only lives on a fork that gets abandoned.
+        let snapshot: U256 = provider.request("evm_snapshot", ()).await.unwrap();
+        deposit(provider.clone(), &owshen, &token, pub_key, U256::from(50)).await;
+        sync().await.unwrap();
+        let coins = apis::coins(Query(GetCoinsRequest::default()), context.clone())
+            .await
+            .unwrap();
+        assert_eq!(coins.listed().count(), 2);
+
+        let reverted: bool = provider.request("evm_revert", [snapshot]).await.unwrap();
+        assert!(reverted);
+        for _ in 0..5 {
+            let _: String = provider.request("evm_mine", ()).await.unwrap();
+        }
+        sync().await.unwrap();
+
+        let coins = apis::coins(Query(GetCoinsRequest::default()), context.clone())
+            .await
+            .unwrap();
+        let coins = coins.listed().collect::<Vec<_>>();
+        assert_eq!(coins.len(), 1);
+        assert_eq!(coins[0].coin.amount, Amount::from(100u64));
+        let health = apis::health(context.clone()).await.unwrap();
+        assert_eq!(health.reorgs_handled, 1);
+
+        // Nothing happened since, nothing is rolled back.
+        sync().await.unwrap();
+        let health = apis::health(context.clone()).await.unwrap();
+        assert_eq!(health.reorgs_handled, 1);
+    }
+
+    #[tokio::test]
+    async fn test_cli_withdraw() {
+        let port = 8547u16;
+        let endpoint = format!("http://localhost:{}", port);
+        let _ganache = Ganache::new().port(port).spawn();
+        let provider = Arc::new(rpc::connect(&endpoint).await.unwrap());
+        let (owshen, token) = deploy_owshen(provider.clone()).await;
+
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        deposit(
+            provider.clone(),
+            &owshen,
+            &token,
+            PublicKey::from(priv_key),
+            U256::from(100),
+        )
+        .await;
+
+        let config = Config {
+            name: "test".to_string(),
+            endpoint,
+            dive_contract_address: token.address(),
+            owshen_contract_address: owshen.address(),
+            owshen_contract_abi: owshen.abi().clone(),
+            erc20_abi: token.abi().clone(),
+            owshen_code_hash: None,
+            ..Default::default()
+        };
+        let to = provider.get_accounts().await.unwrap()[1];
+        let mut opt = WithdrawOpt {
+            db: None,
+            config: None,
+            index: 0,
+            amount: "40".to_string(),
+            decimals: None,
+            to,
+            dry_run: true,
+            relayer: None,
+            relayer_fee: None,
+            account: None,
+            keystore: KeyStoreKind::File,
+            tx_type: Some(TxType::Legacy),
+            gas: GasOverrides::default(),
+            from: None,
+            params: None,
+        };
+
+        let confirmation = cli::withdraw(
+            provider.clone(),
+            &config,
+            priv_key,
+            &[],
+            &Paths::default(),
+            &opt,
+        )
+        .await
+        .unwrap();
+        assert!(confirmation.is_none());
+        assert_eq!(token.balance_of(to).call().await.unwrap(), U256::zero());
+
+        opt.dry_run = false;
+        let confirmation = cli::withdraw(
+            provider.clone(),
+            &config,
+            priv_key,
+            &[],
+            &Paths::default(),
+            &opt,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(confirmation.success);
+        assert_eq!(token.balance_of(to).call().await.unwrap(), U256::from(40));
+    }
+
+    #[tokio::test]
+    async fn test_cli_nullifier() {
+        let port = 8555u16;
+        let endpoint = format!("http://localhost:{}", port);
+        let _ganache = Ganache::new().port(port).spawn();
+        let provider = Arc::new(rpc::connect(&endpoint).await.unwrap());
+        let (owshen, token) = deploy_owshen(provider.clone()).await;
+
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        deposit(
+            provider.clone(),
+            &owshen,
+            &token,
+            PublicKey::from(priv_key),
+            U256::from(100),
+        )
+        .await;
+        let config = Config {
+            name: "test".to_string(),
+            endpoint,
+            dive_contract_address: token.address(),
+            owshen_contract_address: owshen.address(),
+            owshen_contract_abi: owshen.abi().clone(),
+            erc20_abi: token.abi().clone(),
+            owshen_code_hash: None,
+            ..Default::default()
+        };
+
+        let context = Arc::new(RwLock::new(Context::new(owshen.address(), false)));
+        let contract = Contract::new(owshen.address(), owshen.abi().clone(), provider.clone());
+        let (coin_sender, _) = broadcast::channel(16);
+        apis::sync_coins(
+            context.clone(),
+            contract,
+            priv_key.into(),
+            coin_sender,
+            apis::DEFAULT_SCAN_CHUNK_SIZE,
+            apis::DEFAULT_SCAN_CONCURRENCY,
+        )
+        .await
+        .unwrap();
+        let coin = context.read().await.coins[0];
+
+        let mut opt = NullifierOpt {
+            db: None,
+            config: None,
+            account: None,
+            keystore: KeyStoreKind::File,
+            index: coin.index.low_u64(),
+            check: true,
+        };
+        let (nullifier, spent) = cli::nullifier(provider.clone(), &config, priv_key, &opt)
+            .await
+            .unwrap();
+        assert_eq!(Some(nullifier), coin.nullifier);
+        assert_eq!(spent, Some(false));
+
+        // Spent coins are still found, and reported as such.
+        let withdraw = WithdrawOpt {
+            db: None,
+            config: None,
+            index: opt.index,
+            amount: "40".to_string(),
+            decimals: None,
+            to: provider.get_accounts().await.unwrap()[1],
+            dry_run: false,
+            relayer: None,
+            relayer_fee: None,
+            account: None,
+            keystore: KeyStoreKind::File,
+            tx_type: Some(TxType::Legacy),
+            gas: GasOverrides::default(),
+            from: None,
+            params: None,
+        };
+        cli::withdraw(
+            provider.clone(),
+            &config,
+            priv_key,
+            &[],
+            &Paths::default(),
+            &withdraw,
+        )
+        .await
+        .unwrap();
+        let (nullifier, spent) = cli::nullifier(provider.clone(), &config, priv_key, &opt)
+            .await
+            .unwrap();
+        assert_eq!(Some(nullifier), coin.nullifier);
+        assert_eq!(spent, Some(true));
+
+        opt.index = 42;
+        assert!(cli::nullifier(provider, &config, priv_key, &opt)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_nullifier_matches_spend() {
+        let port = 8566u16;
+        let endpoint = format!("http://localhost:{}", port);
+        let _ganache = Ganache::new().port(port).spawn();
+        let provider = Arc::new(rpc::connect(&endpoint).await.unwrap());
+        let (owshen, token) = deploy_owshen(provider.clone()).await;
+
+        let priv_key = PrivateKey::generate(&mut rand::thread_rng());
+        deposit(
+            provider.clone(),
+            &owshen,
+            &token,
+            priv_key.address(),
+            U256::from(100),
+        )
+        .await;
+        let config = Config {
+            name: "test".to_string(),
+            endpoint,
+            dive_contract_address: token.address(),
+            owshen_contract_address: owshen.address(),
+            owshen_contract_abi: owshen.abi().clone(),
+            erc20_abi: token.abi().clone(),
+            owshen_code_hash: None,
+            ..Default::default()
+        };
+
+        let context = Arc::new(RwLock::new(Context::new(owshen.address(), false)));
+        let contract = Contract::new(owshen.address(), owshen.abi().clone(), provider.clone());
+        let (coin_sender, _) = broadcast::channel(16);
+        apis::sync_coins(
+            context.clone(),
+            contract,
+            priv_key.into(),
+            coin_sender,
+            apis::DEFAULT_SCAN_CHUNK_SIZE,
+            apis::DEFAULT_SCAN_CONCURRENCY,
+        )
+        .await
+        .unwrap();
+        let index = context.read().await.coins[0].index;
+        let before_spend = apis::nullifier(extract::Path(index.as_u64()), context)
+            .await
+            .unwrap();
+
+        let withdraw = WithdrawOpt {
+            db: None,
+            config: None,
+            index: index.as_u64(),
+            amount: "40".to_string(),
+            decimals: None,
+            to: provider.get_accounts().await.unwrap()[1],
+            dry_run: false,
+            relayer: None,
+            relayer_fee: None,
+            account: None,
+            keystore: KeyStoreKind::File,
+            tx_type: Some(TxType::Legacy),
+            gas: GasOverrides::default(),
+            from: None,
+            params: None,
+        };
+        cli::withdraw(
+            provider.clone(),
+            &config,
+            priv_key,
+            &[],
+            &Paths::default(),
+            &withdraw,
+        )
+        .await
+        .unwrap();
+        let spends = owshen.spend_filter().from_block(0).query().await.unwrap();
+        assert_eq!(
+            spends
+                .iter()
+                .map(|spend| spend.nullifier)
+                .collect::<Vec<_>>(),
+            vec![before_spend.nullifier]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_estimate_withdraw() {
+        let SyncedCoin {
+            _ganache,
+            provider,
+            owshen,
+            pub_key,
+            context,
+            coin,
+            ..
+        } = synced_coin(8567).await;
+
+        let to = provider.get_accounts().await.unwrap()[1];
+        let req = GetEstimateWithdrawRequest {
+            index: coin.index,
+            address: pub_key,
+            desire_amount: "40".to_string(),
+            to,
+            relayer_fee: None,
+            decimals: None,
+            gas: GasOverrides::default(),
+        };
+        let estimate = |req: GetEstimateWithdrawRequest| {
+            apis::estimate_withdraw(
+                Query(req),
+                context.clone(),
+                owshen.clone(),
+                provider.clone(),
+                Paths::default().params,
+            )
+        };
+        let estimated = estimate(req.clone()).await.unwrap();
+        assert!(estimated.gas > U256::zero());
+        assert_eq!(estimated.cost, estimated.gas * estimated.gas_price);
+
+        let overridden = estimate(GetEstimateWithdrawRequest {
+            gas: GasOverrides {
+                gas_price: Some(U256::from(7)),
+                gas_limit: Some(estimated.gas * 2),
+                ..req.gas
+            },
+            ..req.clone()
+        })
+        .await
+        .unwrap();
+        assert_eq!(overridden.gas_price, U256::from(7));
+        assert_eq!(overridden.cost, estimated.gas * 7);
+
+        assert!(matches!(
+            estimate(GetEstimateWithdrawRequest {
+                gas: GasOverrides {
+                    gas_limit: Some(estimated.gas - 1),
+                    ..req.gas
+                },
+                ..req
+            })
+            .await,
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_estimate_send() {
+        let SyncedCoin {
+            _ganache,
+            provider,
+            owshen,
+            pub_key,
+            context,
+            coin,
+            ..
+        } = synced_coin(8568).await;
+
+        let receiver = PrivateKey::generate(&mut rand::thread_rng()).address();
+        let req = GetSendRequest {
+            receiver_address: receiver.to_string(),
+            ..GetSendRequest::new(coin.index, pub_key, "40".to_string())
+        };
+        let estimate = |req: GetSendRequest| {
+            apis::estimate_send(
+                Query(req),
+                context.clone(),
+                owshen.clone(),
+                provider.clone(),
+                Paths::default().params,
+            )
+        };
+        let estimated = estimate(req.clone()).await.unwrap();
+        assert!(estimated.gas > U256::zero());
+        assert_eq!(estimated.cost, estimated.gas * estimated.gas_price);
+
+        assert!(matches!(
+            estimate(GetSendRequest {
+                gas: GasOverrides {
+                    gas_limit: Some(estimated.gas - 1),
+                    ..req.gas
+                },
+                ..req
+            })
+            .await,
+            Err(ApiError::BadRequest(_))
+        ));
+        // Estimates aren't spends, the coin can still be sent.
+        assert!(context.read().await.history.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_root() {
+        let SyncedCoin {
+            _ganache,
+            owshen,
+            pub_key,
+            context,
+            coin,
+            ..
+        } = synced_coin(8550).await;
+
+        let withdrawal = apis::withdraw(
+            Query(GetWithdrawRequest::new(
+                coin.index,
+                pub_key,
+                "40".to_string(),
+                H160::default(),
+            )),
+            context.clone(),
+            context.clone(),
+            Paths::default().params,
+            false,
+        )
+        .await
+        .unwrap();
+
+        let ctx = context.read().await;
+        let root: U256 = ctx.tree.root().into();
+        assert_eq!(withdrawal.root, root);
+        assert_eq!(withdrawal.root_block, ctx.last_synced_block);
+        assert!(withdrawal.root_block.is_some());
+        assert_eq!(withdrawal.root, owshen.root().call().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_send_root() {
+        let SyncedCoin {
+            _ganache,
+            owshen,
+            pub_key,
+            context,
+            coin,
+            ..
+        } = synced_coin(8569).await;
+
+        let receiver = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        let send = apis::send(
+            Query(GetSendRequest {
+                receiver_address: receiver.to_string(),
+                ..GetSendRequest::new(coin.index, pub_key, "40".to_string())
+            }),
+            context.clone(),
+            context.clone(),
+            Paths::default().params,
+        )
+        .await
+        .unwrap();
+
+        let ctx = context.read().await;
+        let root: U256 = ctx.tree.root().into();
+        assert_eq!(send.root, root);
+        assert_eq!(send.root_block, ctx.last_synced_block);
+        assert!(send.root_block.is_some());
+        assert_eq!(send.root, owshen.root().call().await.unwrap());
+    }
+
+    async fn scrape_metrics(context: SharedContext) -> String {
+        let response = apis::metrics(context).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_proofs() {
+        let SyncedCoin {
+            _ganache,
+            pub_key,
+            context,
+            coin,
+            ..
+        } = synced_coin(8551).await;
+
+        let before = scrape_metrics(context.clone()).await;
+        assert_eq!(metrics::sample(&before, "owshen_scanned_coins"), Some(1.0));
+        assert!(metrics::sample(&before, "owshen_last_synced_block").unwrap() > 0.0);
+
+        apis::withdraw(
+            Query(GetWithdrawRequest::new(
+                coin.index,
+                pub_key,
+                "40".to_string(),
+                H160::default(),
+            )),
+            context.clone(),
+            context.clone(),
+            Paths::default().params,
+            false,
+        )
+        .await
+        .unwrap();
+
+        // The registry is shared by every test of the process, other proofs
+        // may be counted in between.
+        let after = scrape_metrics(context.clone()).await;
+        let proofs = |rendered: &str| metrics::sample(rendered, "owshen_proofs_total").unwrap();
+        assert!(proofs(&after) > proofs(&before));
+        let histogram_count = |rendered: &str| {
+            metrics::sample(rendered, "owshen_proof_duration_seconds_count").unwrap()
+        };
+        assert!(histogram_count(&after) > histogram_count(&before));
+    }
+
+    #[tokio::test]
+    async fn test_decoded_proof_verifies() {
+        let SyncedCoin {
+            _ganache,
+            provider,
+            owshen,
+            token,
+            pub_key,
+            context,
+            coin,
+        } = synced_coin(8553).await;
+        let accounts = provider.get_accounts().await.unwrap();
+
+        let mut withdrawal = apis::withdraw(
+            Query(GetWithdrawRequest {
+                relayer_fee: Some("5".to_string()),
+                ..GetWithdrawRequest::new(coin.index, pub_key, "40".to_string(), accounts[1])
+            }),
+            context.clone(),
+            context.clone(),
+            Paths::default().params,
+            false,
+        )
+        .await
+        .unwrap();
+
+        let decoded = Proof::from_base64(&withdrawal.proof.to_base64()).unwrap();
+        assert_eq!(decoded.to_bytes(), withdrawal.proof.to_bytes());
+        withdrawal.proof = decoded;
+
+        // The receiver and the fee are bound by the proof, a relayer can't
+        // raise its fee.
+        let raised = GetWithdrawResponse {
+            relayer_fee: Amount::from(10),
+            ..withdrawal.clone()
+        };
+        assert!(transaction::withdraw_call(&owshen, &raised)
+            .legacy()
+            .from(accounts[2])
+            .send()
+            .await
+            .is_err());
+
+        // The contract only pays out once the verifier accepted the proof.
+        transaction::withdraw_call(&owshen, &withdrawal)
+            .legacy()
+            .from(accounts[2])
+            .send()
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(
+            token.balance_of(accounts[1]).call().await.unwrap(),
+            U256::from(35)
+        );
+        assert_eq!(
+            token.balance_of(accounts[2]).call().await.unwrap(),
+            U256::from(5)
+        );
+    }
 }
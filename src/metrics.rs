@@ -0,0 +1,209 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds, in seconds, of the proof latency buckets. A proof on a
+/// laptop takes somewhere between a few seconds and a couple of minutes.
+const PROOF_SECONDS_BUCKETS: [f64; 8] = [1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0];
+
+lazy_static! {
+    /// Process wide registry, updated from the handlers and the sync loop.
+    pub static ref METRICS: Metrics = Metrics::default();
+}
+
+struct Histogram {
+    counts: [u64; PROOF_SECONDS_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: [0; PROOF_SECONDS_BUCKETS.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (count, bound) in self.counts.iter_mut().zip(PROOF_SECONDS_BUCKETS) {
+            if seconds <= bound {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    proofs: AtomicU64,
+    proof_failures: AtomicU64,
+    proof_seconds: Mutex<Histogram>,
+    rpc_errors: AtomicU64,
+    syncs: AtomicU64,
+    // Stored as milliseconds, gauges need no more precision than that.
+    last_sync_millis: AtomicU64,
+}
+
+/// Wallet state sampled when the metrics are scraped.
+pub struct Gauges {
+    pub scanned_coins: usize,
+    pub owned_coins: usize,
+    pub last_synced_block: Option<u64>,
+}
+
+impl Metrics {
+    /// Records a proof attempt taking `elapsed`, failed attempts are counted
+    /// separately and kept out of the latency histogram.
+    pub fn record_proof(&self, elapsed: Duration, success: bool) {
+        if success {
+            self.proofs.fetch_add(1, Ordering::Relaxed);
+            self.proof_seconds
+                .lock()
+                .unwrap()
+                .observe(elapsed.as_secs_f64());
+        } else {
+            self.proof_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_rpc_error(&self) {
+        self.rpc_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_sync(&self, elapsed: Duration) {
+        self.syncs.fetch_add(1, Ordering::Relaxed);
+        self.last_sync_millis
+            .store(elapsed.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+            writeln!(out, "{} {}", name, value).unwrap();
+        };
+        metric(
+            "owshen_scanned_coins",
+            "gauge",
+            "Coins indexed from the Owshen contract.",
+            gauges.scanned_coins.to_string(),
+        );
+        metric(
+            "owshen_owned_coins",
+            "gauge",
+            "Unspent coins belonging to the wallet.",
+            gauges.owned_coins.to_string(),
+        );
+        metric(
+            "owshen_last_synced_block",
+            "gauge",
+            "Last block scanned for coins, 0 before the first sync.",
+            gauges.last_synced_block.unwrap_or_default().to_string(),
+        );
+        metric(
+            "owshen_proofs_total",
+            "counter",
+            "Proofs generated.",
+            self.proofs.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "owshen_proof_failures_total",
+            "counter",
+            "Proofs that failed to generate.",
+            self.proof_failures.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "owshen_rpc_errors_total",
+            "counter",
+            "Requests to the Ethereum node that failed.",
+            self.rpc_errors.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "owshen_syncs_total",
+            "counter",
+            "Completed chain syncs.",
+            self.syncs.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "owshen_last_sync_duration_seconds",
+            "gauge",
+            "Duration of the last completed chain sync.",
+            (self.last_sync_millis.load(Ordering::Relaxed) as f64 / 1000.0).to_string(),
+        );
+
+        let histogram = self.proof_seconds.lock().unwrap();
+        let name = "owshen_proof_duration_seconds";
+        writeln!(out, "# HELP {} Time taken to generate a proof.", name).unwrap();
+        writeln!(out, "# TYPE {} histogram", name).unwrap();
+        for (bound, count) in PROOF_SECONDS_BUCKETS.iter().zip(histogram.counts) {
+            writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count).unwrap();
+        }
+        writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, histogram.count).unwrap();
+        writeln!(out, "{}_sum {}", name, histogram.sum).unwrap();
+        writeln!(out, "{}_count {}", name, histogram.count).unwrap();
+        out
+    }
+}
+
+/// Reads a sample from rendered metrics, e.g. `owshen_proofs_total`.
+#[cfg(test)]
+pub fn sample(rendered: &str, name: &str) -> Option<f64> {
+    rendered.lines().find_map(|line| {
+        let (metric, value) = line.split_once(' ')?;
+        (metric == name).then(|| value.parse().ok())?
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = Metrics::default();
+        metrics.record_proof(Duration::from_millis(1500), true);
+        metrics.record_proof(Duration::from_secs(45), true);
+        metrics.record_proof(Duration::from_secs(3), false);
+        metrics.record_rpc_error();
+        metrics.record_sync(Duration::from_millis(250));
+
+        let rendered = metrics.render(&Gauges {
+            scanned_coins: 12,
+            owned_coins: 3,
+            last_synced_block: Some(100),
+        });
+        assert_eq!(sample(&rendered, "owshen_scanned_coins"), Some(12.0));
+        assert_eq!(sample(&rendered, "owshen_owned_coins"), Some(3.0));
+        assert_eq!(sample(&rendered, "owshen_last_synced_block"), Some(100.0));
+        assert_eq!(sample(&rendered, "owshen_proofs_total"), Some(2.0));
+        assert_eq!(sample(&rendered, "owshen_proof_failures_total"), Some(1.0));
+        assert_eq!(sample(&rendered, "owshen_rpc_errors_total"), Some(1.0));
+        assert_eq!(
+            sample(&rendered, "owshen_last_sync_duration_seconds"),
+            Some(0.25)
+        );
+        let bucket = |le: &str| {
+            sample(
+                &rendered,
+                &format!("owshen_proof_duration_seconds_bucket{{le=\"{}\"}}", le),
+            )
+        };
+        assert_eq!(bucket("1"), Some(0.0));
+        assert_eq!(bucket("2"), Some(1.0));
+        assert_eq!(bucket("60"), Some(2.0));
+        assert_eq!(bucket("+Inf"), Some(2.0));
+        assert_eq!(
+            sample(&rendered, "owshen_proof_duration_seconds_sum"),
+            Some(46.5)
+        );
+    }
+}
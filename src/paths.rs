@@ -0,0 +1,215 @@
+use eyre::Result;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::proof;
+
+const DATA_DIR: &str = ".owshen";
+
+/// Overrides the data directory when `--data-dir` isn't given.
+pub const HOME_VAR: &str = "OWSHEN_HOME";
+
+// Files written straight into the home directory before the data directory
+// existed, with their new name inside of it.
+const LEGACY_FILES: [(&str, &str); 3] = [
+    (".owshen-wallet.json", "wallet.json"),
+    (".config-wallet.json", "config.json"),
+    (".owshen-address-book.json", "address-book.json"),
+];
+
+/// Every file the wallet reads or writes, resolved once at startup. Apart
+/// from the proving key and the web client, which ship with the binary, they
+/// all live in the data directory: backing a wallet up is copying it, and
+/// several wallets only need several data directories.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Paths {
+    pub data_dir: PathBuf,
+    pub wallet: PathBuf,
+    pub config: PathBuf,
+    pub address_book: PathBuf,
+    // Snapshot of the synced state, the leaves of the merkle tree included.
+    pub state: PathBuf,
+    pub logs: PathBuf,
+    pub params: PathBuf,
+    pub client: PathBuf,
+}
+
+impl Paths {
+    pub fn new(data_dir: PathBuf) -> Self {
+        let app_dir_path = std::env::var("APPDIR").unwrap_or_default();
+        Paths {
+            wallet: data_dir.join("wallet.json"),
+            config: data_dir.join("config.json"),
+            address_book: data_dir.join("address-book.json"),
+            state: data_dir.join("state.json"),
+            logs: data_dir.join("logs"),
+            params: proof::params_file(&data_dir),
+            client: PathBuf::from(format!("{}/usr/share/owshen/client", app_dir_path)),
+            data_dir,
+        }
+    }
+
+    /// `$OWSHEN_HOME`, or `~/.owshen`. Minimal environments, e.g. some
+    /// containers, may have no home directory: `.owshen` then goes into the
+    /// current directory.
+    pub fn default_data_dir() -> Result<PathBuf> {
+        resolve_data_dir(
+            std::env::var_os(HOME_VAR),
+            home::home_dir(),
+            std::env::current_dir().ok(),
+        )
+    }
+
+    pub fn log_file(&self) -> PathBuf {
+        self.logs.join("owshen.log")
+    }
+
+    /// Creates the data directory, and the logs directory inside of it.
+    pub fn create(&self) -> Result<()> {
+        std::fs::create_dir_all(&self.data_dir)?;
+        std::fs::create_dir_all(&self.logs)?;
+        Ok(())
+    }
+
+    /// Copies the files older versions kept in `home` into the data
+    /// directory, unless it already has its own. The originals are left in
+    /// place, nothing is lost if the copy turns out to be wrong.
+    pub fn migrate_legacy(&self, home: &Path) -> Result<Vec<PathBuf>> {
+        let mut migrated = Vec::new();
+        for (legacy, name) in LEGACY_FILES {
+            let (legacy, target) = (home.join(legacy), self.data_dir.join(name));
+            if legacy.is_file() && !target.exists() {
+                std::fs::copy(&legacy, &target)?;
+                migrated.push(legacy);
+            }
+        }
+        Ok(migrated)
+    }
+}
+
+impl Default for Paths {
+    fn default() -> Self {
+        Paths::new(Paths::default_data_dir().unwrap_or_else(|_| PathBuf::from(DATA_DIR)))
+    }
+}
+
+fn resolve_data_dir(
+    owshen_home: Option<OsString>,
+    home: Option<PathBuf>,
+    current: Option<PathBuf>,
+) -> Result<PathBuf> {
+    if let Some(owshen_home) = owshen_home.filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(owshen_home));
+    }
+    // `home_dir` gives an empty path rather than `None` on some platforms.
+    if let Some(home) = home.filter(|dir| !dir.as_os_str().is_empty()) {
+        return Ok(home.join(DATA_DIR));
+    }
+    match current {
+        Some(current) => {
+            tracing::warn!(
+                "No home directory, keeping the wallet in {:?}",
+                current.join(DATA_DIR)
+            );
+            Ok(current.join(DATA_DIR))
+        }
+        None => Err(eyre::Report::msg(format!(
+            "Cannot tell where to keep the wallet, set {} or pass --data-dir!",
+            HOME_VAR
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_data_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().join("wallet-2");
+        let paths = Paths::new(data_dir.clone());
+        paths.create().unwrap();
+
+        assert!(data_dir.is_dir());
+        assert!(paths.logs.is_dir());
+        for artifact in [
+            &paths.wallet,
+            &paths.config,
+            &paths.address_book,
+            &paths.state,
+            &paths.log_file(),
+        ] {
+            assert!(artifact.starts_with(&data_dir), "{:?}", artifact);
+        }
+
+        // A proving key dropped into the data directory is picked up.
+        let params = data_dir.join("coin_withdraw_0001.zkey");
+        std::fs::write(&params, "zkey").unwrap();
+        if std::env::var("OWSHEN_PARAMS").is_err() {
+            assert_eq!(Paths::new(data_dir).params, params);
+        }
+    }
+
+    #[test]
+    fn test_migrate_legacy_files() {
+        let home = tempfile::tempdir().unwrap();
+        std::fs::write(home.path().join(".owshen-wallet.json"), "wallet").unwrap();
+        std::fs::write(home.path().join(".config-wallet.json"), "config").unwrap();
+        let paths = Paths::new(home.path().join(DATA_DIR));
+        paths.create().unwrap();
+        std::fs::write(&paths.config, "newer config").unwrap();
+
+        let migrated = paths.migrate_legacy(home.path()).unwrap();
+        assert_eq!(migrated, vec![home.path().join(".owshen-wallet.json")]);
+        assert_eq!(std::fs::read_to_string(&paths.wallet).unwrap(), "wallet");
+        assert_eq!(
+            std::fs::read_to_string(&paths.config).unwrap(),
+            "newer config"
+        );
+        assert!(!paths.address_book.exists());
+        assert!(paths.migrate_legacy(home.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_owshen_home() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = resolve_data_dir(
+            Some(dir.path().as_os_str().to_owned()),
+            Some(PathBuf::from("/home/owshen")),
+            None,
+        )
+        .unwrap();
+        let paths = Paths::new(data_dir);
+        assert_eq!(paths.wallet, dir.path().join("wallet.json"));
+    }
+
+    #[test]
+    fn test_resolve_data_dir() {
+        let home = PathBuf::from("/home/owshen");
+        let current = PathBuf::from("/srv");
+        assert_eq!(
+            resolve_data_dir(
+                Some("/data".into()),
+                Some(home.clone()),
+                Some(current.clone())
+            )
+            .unwrap(),
+            PathBuf::from("/data")
+        );
+        assert_eq!(
+            resolve_data_dir(Some("".into()), Some(home.clone()), None).unwrap(),
+            home.join(".owshen")
+        );
+        assert_eq!(
+            resolve_data_dir(None, Some(PathBuf::new()), Some(current.clone())).unwrap(),
+            current.join(".owshen")
+        );
+        assert_eq!(
+            resolve_data_dir(None, None, Some(current.clone())).unwrap(),
+            current.join(".owshen")
+        );
+        let err = resolve_data_dir(None, None, None).unwrap_err().to_string();
+        assert!(err.contains("OWSHEN_HOME"));
+    }
+}
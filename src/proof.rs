@@ -1,6 +1,9 @@
 use crate::fp::Fp;
 use crate::keys::PublicKey;
+use crate::metrics::METRICS;
 
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use ff::PrimeField;
 
 use num_bigint::BigUint;
@@ -13,7 +16,8 @@ use eyre::Result;
 use serde::{Deserialize, Serialize};
 use std::process::Command;
 use std::str::FromStr;
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+use std::time::Instant;
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
 pub struct Proof {
     pub a: [U256; 2],
     pub b: [[U256; 2]; 2],
@@ -21,10 +25,152 @@ pub struct Proof {
     pub public: Vec<U256>,
 }
 
+const WORD_SIZE: usize = 32;
+
+// `a`, `b` and `c` take 8 words.
+const PROOF_WORDS: usize = 8;
+
+impl Proof {
+    /// Encodes `a`, `b`, `c` and the public inputs as 32 byte little-endian
+    /// words, in the order `verifyProof` takes them. The coordinates of `b`
+    /// are kept as `snarkjs generatecall` swapped them for the verifier.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let words = self
+            .a
+            .iter()
+            .chain(self.b.iter().flatten())
+            .chain(self.c.iter())
+            .chain(self.public.iter());
+        let mut bytes = Vec::with_capacity((PROOF_WORDS + self.public.len()) * WORD_SIZE);
+        for word in words {
+            let mut buf = [0u8; WORD_SIZE];
+            word.to_little_endian(&mut buf);
+            bytes.extend_from_slice(&buf);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() % WORD_SIZE != 0 || bytes.len() < PROOF_WORDS * WORD_SIZE {
+            return Err(eyre::Report::msg(format!(
+                "Invalid proof length {}, expected {} bytes and a multiple of {}!",
+                bytes.len(),
+                PROOF_WORDS * WORD_SIZE,
+                WORD_SIZE
+            )));
+        }
+        let w = bytes
+            .chunks(WORD_SIZE)
+            .map(U256::from_little_endian)
+            .collect::<Vec<_>>();
+        Ok(Proof {
+            a: [w[0], w[1]],
+            b: [[w[2], w[3]], [w[4], w[5]]],
+            c: [w[6], w[7]],
+            public: w[PROOF_WORDS..].to_vec(),
+        })
+    }
+
+    pub fn to_base64(&self) -> String {
+        BASE64.encode(self.to_bytes())
+    }
+
+    pub fn from_base64(s: &str) -> Result<Self> {
+        Self::from_bytes(&BASE64.decode(s.trim())?)
+    }
+}
+
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tempfile::NamedTempFile;
 
+const PARAMS_FILE: &str = "contracts/circuits/coin_withdraw_0001.zkey";
+
+pub const PARAMS_FILE_NAME: &str = "coin_withdraw_0001.zkey";
+
+/// The circuit the proving keys are set up for, see
+/// `contracts/circuits/coin_withdraw.circom`.
+pub const CIRCUIT_NAME: &str = "coin_withdraw";
+
+// Sections of a snarkjs proving key.
+const ZKEY_HEADER_SECTION: u32 = 1;
+const ZKEY_GROTH16_SECTION: u32 = 2;
+const ZKEY_GROTH16: u32 = 1;
+
+/// The number of public inputs of the Groth16 proving key `zkey`, read from
+/// its header: `zkey`, a version and the sections, each of them a type, a
+/// size and its content.
+pub fn num_public_inputs(zkey: &[u8]) -> Result<u32> {
+    let truncated = || eyre::Report::msg("Truncated proving key!");
+    let u32_at = |at: usize| -> Result<u32> {
+        let bytes = zkey.get(at..at + 4).ok_or_else(truncated)?;
+        Ok(u32::from_le_bytes(bytes.try_into()?))
+    };
+    if zkey.get(..4) != Some(&b"zkey"[..]) {
+        return Err(eyre::Report::msg("Not a snarkjs proving key!"));
+    }
+    let num_sections = u32_at(8)?;
+    let mut at = 12;
+    let mut protocol = None;
+    for _ in 0..num_sections {
+        let section = u32_at(at)?;
+        let size = zkey.get(at + 4..at + 12).ok_or_else(truncated)?;
+        let size = u64::from_le_bytes(size.try_into()?) as usize;
+        let start = at + 12;
+        match section {
+            ZKEY_HEADER_SECTION => protocol = Some(u32_at(start)?),
+            ZKEY_GROTH16_SECTION => {
+                if protocol.is_some_and(|protocol| protocol != ZKEY_GROTH16) {
+                    break;
+                }
+                // The field sizes and moduli come first, then the number of
+                // variables and of public inputs.
+                let n8q = u32_at(start)? as usize;
+                let n8r = u32_at(start + 4 + n8q)? as usize;
+                return u32_at(start + 8 + n8q + n8r + 4);
+            }
+            _ => {}
+        }
+        at = start
+            .checked_add(size)
+            .filter(|at| *at <= zkey.len())
+            .ok_or_else(truncated)?;
+    }
+    Err(eyre::Report::msg("Not a Groth16 proving key!"))
+}
+
+/// Resolves the default proving key, `--params` aside: the
+/// `OWSHEN_PARAMS` environment variable, then a copy in the data directory,
+/// then the copy bundled in the AppImage (`APPDIR`), and finally the path
+/// relative to the source tree.
+pub fn params_file(data_dir: &Path) -> PathBuf {
+    if let Ok(params) = std::env::var("OWSHEN_PARAMS") {
+        return PathBuf::from(params);
+    }
+    let params = data_dir.join(PARAMS_FILE_NAME);
+    if params.is_file() {
+        return params;
+    }
+    if let Ok(app_dir_path) = std::env::var("APPDIR") {
+        return PathBuf::from(format!(
+            "{}/usr/share/owshen/{}",
+            app_dir_path, PARAMS_FILE_NAME
+        ));
+    }
+    PathBuf::from(PARAMS_FILE)
+}
+
+pub fn ensure_params(params: &Path) -> Result<()> {
+    if !params.is_file() {
+        return Err(eyre::Report::msg(format!(
+            "Proving key not found at {:?}, pass --params or set OWSHEN_PARAMS!",
+            params
+        )));
+    }
+    Ok(())
+}
+
+/// Generates a withdraw/send proof, its latency is recorded in the metrics.
 pub fn prove<P: AsRef<Path>>(
     params: P,
     index: u32,
@@ -37,6 +183,92 @@ pub fn prove<P: AsRef<Path>>(
     secret: Fp,
     proof: [[Fp; 3]; 16],
 ) -> Result<Proof> {
+    let started = Instant::now();
+    let result = generate_proof(
+        params,
+        index,
+        token_address,
+        amount,
+        new_amount1,
+        new_amount2,
+        address_1,
+        address_2,
+        secret,
+        proof,
+    );
+    METRICS.record_proof(started.elapsed(), result.is_ok());
+    result
+}
+
+/// Extracts the verification key of `params` with snarkjs, which fails on a
+/// truncated or corrupted proving key.
+pub fn export_verification_key<P: AsRef<Path>>(params: P) -> Result<NamedTempFile> {
+    ensure_params(params.as_ref())?;
+    let vkey_file = NamedTempFile::new()?;
+    let export_output = Command::new("snarkjs")
+        .arg("zkey")
+        .arg("export")
+        .arg("verificationkey")
+        .arg(params.as_ref().as_os_str())
+        .arg(vkey_file.path())
+        .output()?;
+    if !export_output.status.success() {
+        return Err(eyre::Report::msg(format!(
+            "Cannot export the verification key: {}",
+            String::from_utf8_lossy(&export_output.stderr)
+        )));
+    }
+    Ok(vkey_file)
+}
+
+/// Checks `proof` against the verification key of `params` with snarkjs,
+/// the same check the verifier contract runs.
+pub fn verify<P: AsRef<Path>>(params: P, proof: &Proof) -> Result<bool> {
+    let vkey_file = export_verification_key(params)?;
+
+    // `generatecall` swapped the coordinates of `b`, snarkjs wants them back.
+    let dec = |v: &U256| v.to_string();
+    let proof_json = serde_json::json!({
+        "protocol": "groth16",
+        "curve": "bn128",
+        "pi_a": [dec(&proof.a[0]), dec(&proof.a[1]), "1"],
+        "pi_b": [
+            [dec(&proof.b[0][1]), dec(&proof.b[0][0])],
+            [dec(&proof.b[1][1]), dec(&proof.b[1][0])],
+            ["1", "0"]
+        ],
+        "pi_c": [dec(&proof.c[0]), dec(&proof.c[1]), "1"],
+    });
+    let public_json = proof.public.iter().map(dec).collect::<Vec<_>>();
+    let mut proof_file = NamedTempFile::new()?;
+    serde_json::to_writer(&mut proof_file, &proof_json)?;
+    let mut pub_inp_file = NamedTempFile::new()?;
+    serde_json::to_writer(&mut pub_inp_file, &public_json)?;
+
+    let verify_output = Command::new("snarkjs")
+        .arg("groth16")
+        .arg("verify")
+        .arg(vkey_file.path())
+        .arg(pub_inp_file.path())
+        .arg(proof_file.path())
+        .output()?;
+    Ok(verify_output.status.success()
+        && String::from_utf8_lossy(&verify_output.stdout).contains("OK!"))
+}
+
+fn generate_proof<P: AsRef<Path>>(
+    params: P,
+    index: u32,
+    token_address: U256,
+    amount: U256,
+    new_amount1: U256,
+    new_amount2: U256,
+    address_1: PublicKey,
+    address_2: PublicKey,
+    secret: Fp,
+    proof: [[Fp; 3]; 16],
+) -> Result<Proof> {
+    ensure_params(params.as_ref())?;
     let mut inputs_file = NamedTempFile::new()?;
 
     println!(
@@ -153,3 +385,92 @@ pub fn prove<P: AsRef<Path>>(
 
     Ok(proof)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_params() {
+        let params = PathBuf::from("/nonexistent/coin_withdraw.zkey");
+        let err = ensure_params(&params).unwrap_err().to_string();
+        assert!(err.contains("/nonexistent/coin_withdraw.zkey"));
+        assert!(err.contains("--params"));
+
+        let params_file = NamedTempFile::new().unwrap();
+        assert!(ensure_params(params_file.path()).is_ok());
+    }
+
+    // The start of a proving key, up to the number of public inputs.
+    fn zkey_header(num_public_inputs: u32) -> Vec<u8> {
+        let section = |kind: u32, content: Vec<u8>| {
+            let mut section = kind.to_le_bytes().to_vec();
+            section.extend((content.len() as u64).to_le_bytes());
+            section.extend(content);
+            section
+        };
+        let mut groth16 = Vec::new();
+        for n8 in [32u32, 32] {
+            groth16.extend(n8.to_le_bytes());
+            groth16.extend([0xffu8; 32]);
+        }
+        for word in [40u32, num_public_inputs, 1 << 12] {
+            groth16.extend(word.to_le_bytes());
+        }
+        let mut zkey = b"zkey".to_vec();
+        zkey.extend(1u32.to_le_bytes());
+        zkey.extend(3u32.to_le_bytes());
+        zkey.extend(section(
+            ZKEY_HEADER_SECTION,
+            ZKEY_GROTH16.to_le_bytes().to_vec(),
+        ));
+        zkey.extend(section(9, vec![0; 5]));
+        zkey.extend(section(ZKEY_GROTH16_SECTION, groth16));
+        zkey
+    }
+
+    #[test]
+    fn test_num_public_inputs() {
+        let zkey = zkey_header(4);
+        assert_eq!(num_public_inputs(&zkey).unwrap(), 4);
+        assert!(num_public_inputs(&zkey[..zkey.len() - 6]).is_err());
+        assert!(num_public_inputs(b"{}").is_err());
+
+        // A PLONK key has no Groth16 header.
+        let mut plonk = zkey.clone();
+        plonk[24..28].copy_from_slice(&2u32.to_le_bytes());
+        assert!(num_public_inputs(&plonk).is_err());
+    }
+
+    #[test]
+    fn test_proof_bytes() {
+        let proof = Proof {
+            a: [U256::from(1), U256::MAX],
+            b: [
+                [U256::from(2), U256::from(3)],
+                [U256::from(4), U256::from(1) << 200],
+            ],
+            c: [U256::from(5), U256::from(6)],
+            public: vec![U256::from(7), U256::from(8), U256::from(9), U256::zero()],
+        };
+        let bytes = proof.to_bytes();
+        assert_eq!(bytes.len(), 12 * 32);
+        assert_eq!(&bytes[..32], &{
+            let mut one = [0u8; 32];
+            one[0] = 1;
+            one
+        });
+        assert_eq!(Proof::from_bytes(&bytes).unwrap(), proof);
+        assert_eq!(Proof::from_base64(&proof.to_base64()).unwrap(), proof);
+
+        let no_public = Proof {
+            public: vec![],
+            ..proof.clone()
+        };
+        assert_eq!(Proof::from_bytes(&no_public.to_bytes()).unwrap(), no_public);
+
+        assert!(Proof::from_bytes(&bytes[..7 * 32]).is_err());
+        assert!(Proof::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Proof::from_base64("not base64!").is_err());
+    }
+}
@@ -0,0 +1,62 @@
+use eyre::Result;
+use qrcode::render::{svg, unicode};
+use qrcode::QrCode;
+
+use crate::keys::PublicKey;
+
+/// Encodes the canonical textual form of `address`, scanning the code gives
+/// back exactly what `Display` prints.
+pub fn address_qr(address: &PublicKey) -> Result<QrCode> {
+    Ok(QrCode::new(address.to_string().as_bytes())?)
+}
+
+pub fn to_svg(code: &QrCode) -> String {
+    code.render::<svg::Color>().min_dimensions(256, 256).build()
+}
+
+// Terminals usually draw light text on a dark background, so the colors are
+// swapped for the code to come out dark on light.
+pub fn to_terminal(code: &QrCode) -> String {
+    code.render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::PrivateKey;
+    use qrcode::Color;
+
+    #[test]
+    fn test_address_qr_round_trip() {
+        let address = PublicKey::from(PrivateKey::generate(&mut rand::thread_rng()));
+        let code = address_qr(&address).unwrap();
+
+        let (width, quiet_zone, scale) = (code.width(), 4, 4);
+        let colors = code.to_colors();
+        let size = (width + 2 * quiet_zone) * scale;
+        let mut image = rqrr::PreparedImage::prepare_from_greyscale(size, size, |x, y| {
+            let (x, y) = (x / scale, y / scale);
+            if x < quiet_zone
+                || y < quiet_zone
+                || x >= width + quiet_zone
+                || y >= width + quiet_zone
+            {
+                return 255;
+            }
+            match colors[(y - quiet_zone) * width + x - quiet_zone] {
+                Color::Dark => 0,
+                Color::Light => 255,
+            }
+        });
+        let grids = image.detect_grids();
+        assert_eq!(grids.len(), 1);
+        let (_, content) = grids[0].decode().unwrap();
+        assert_eq!(content, address.to_string());
+
+        assert!(to_svg(&code).starts_with("<?xml"));
+        assert!(!to_terminal(&code).is_empty());
+    }
+}
@@ -0,0 +1,186 @@
+use axum::body::Body;
+use axum::http::{header, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+
+use crate::apis::ApiError;
+
+/// Proofs per minute allowed on each spend endpoint unless configured.
+pub const DEFAULT_PROOFS_PER_MINUTE: u32 = 5;
+
+/// Endpoints generating a proof, each one gets its own bucket. The spending
+/// methods of `/rpc` share one, and `/estimate/withdraw` and `/estimate/send`
+/// use the one of the spend they estimate.
+pub const SPEND_ENDPOINTS: [&str; 4] = ["/withdraw", "/send", "/send_multi", "/rpc"];
+
+/// Per-endpoint override given on the command line as `/send=10`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimit {
+    pub endpoint: String,
+    pub per_minute: u32,
+}
+
+impl FromStr for RateLimit {
+    type Err = eyre::Report;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (endpoint, per_minute) = s.split_once('=').ok_or(eyre::Report::msg(
+            "Expected `<endpoint>=<requests per minute>`!",
+        ))?;
+        if !SPEND_ENDPOINTS.contains(&endpoint) {
+            return Err(eyre::Report::msg(format!(
+                "Unknown endpoint {}, expected one of {}!",
+                endpoint,
+                SPEND_ENDPOINTS.join(", ")
+            )));
+        }
+        Ok(RateLimit {
+            endpoint: endpoint.to_string(),
+            per_minute: per_minute.parse()?,
+        })
+    }
+}
+
+/// Requests per minute allowed on `endpoint`, the last override wins.
+pub fn per_minute(overrides: &[RateLimit], endpoint: &str) -> u32 {
+    overrides
+        .iter()
+        .rev()
+        .find(|limit| limit.endpoint == endpoint)
+        .map(|limit| limit.per_minute)
+        .unwrap_or(DEFAULT_PROOFS_PER_MINUTE)
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// A token bucket holding up to `capacity` requests, refilled continuously
+/// over `period`.
+pub struct RateLimiter {
+    capacity: f64,
+    period: Duration,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, period: Duration) -> Self {
+        RateLimiter {
+            capacity: capacity as f64,
+            period,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity as f64,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn per_minute(capacity: u32) -> Self {
+        Self::new(capacity, Duration::from_secs(60))
+    }
+
+    /// Takes a token, or returns how long to wait for the next one.
+    pub fn acquire(&self) -> Result<(), Duration> {
+        if self.capacity == 0.0 {
+            return Err(self.period);
+        }
+        let refill_rate = self.capacity / self.period.as_secs_f64();
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * refill_rate).min(self.capacity);
+        bucket.refilled_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / refill_rate))
+        }
+    }
+}
+
+/// Middleware answering 429 with a `Retry-After` header once `limiter` runs dry.
+pub async fn limit(
+    limiter: Arc<RateLimiter>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    match limiter.acquire() {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            // Retry-After only takes whole seconds, round up so that the
+            // retry is never early.
+            let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            tracing::warn!("Rate limiting {}", request.uri().path());
+            (
+                [(header::RETRY_AFTER, secs.to_string())],
+                ApiError::TooManyRequests(format!("Retry in {} seconds!", secs)),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_rate_limit_overrides() {
+        let overrides = vec![
+            "/send=10".parse::<RateLimit>().unwrap(),
+            "/send=20".parse::<RateLimit>().unwrap(),
+        ];
+        assert_eq!(per_minute(&overrides, "/send"), 20);
+        assert_eq!(
+            per_minute(&overrides, "/withdraw"),
+            DEFAULT_PROOFS_PER_MINUTE
+        );
+        assert!("/coins=10".parse::<RateLimit>().is_err());
+        assert!("/send".parse::<RateLimit>().is_err());
+        assert!("/send=-1".parse::<RateLimit>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter() {
+        let limiter = Arc::new(RateLimiter::per_minute(3));
+        let app = Router::new().route(
+            "/send",
+            get(|| async { "proof" }).layer(middleware::from_fn(move |request, next| {
+                limit(limiter.clone(), request, next)
+            })),
+        );
+        let request = || Request::get("/send").body(Body::empty()).unwrap();
+
+        for _ in 0..3 {
+            let response = app.clone().oneshot(request()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response.headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after > 0 && retry_after <= 20);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_refills() {
+        let limiter = RateLimiter::new(2, Duration::from_millis(200));
+        assert!(limiter.acquire().is_ok());
+        assert!(limiter.acquire().is_ok());
+        assert!(limiter.acquire().is_err());
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(limiter.acquire().is_ok());
+        assert!(RateLimiter::per_minute(0).acquire().is_err());
+    }
+}
@@ -0,0 +1,152 @@
+use bindings::owshen::Owshen;
+use ethers::prelude::*;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::amount::Amount;
+use crate::apis::ApiError;
+use crate::transaction::withdraw_call;
+use crate::GetWithdrawResponse;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayKind {
+    Withdraw,
+}
+
+/// Body POSTed to a relayer. The calldata is a complete `Owshen.withdraw`
+/// call, proof included, so the relayer only has to wrap it in a transaction
+/// to `contract` and pay for its gas. `nullifier`, `root` and `root_block` let
+/// the relayer reject spent coins and stale roots without decoding the
+/// calldata. `fee` is what the contract pays the relayer out of the
+/// withdrawal, it is bound by the proof. Sends aren't relayed: their token and
+/// amount are hidden, so no fee can be paid out of them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayRequest {
+    pub kind: RelayKind,
+    pub contract: H160,
+    pub calldata: Bytes,
+    pub nullifier: U256,
+    pub root: U256,
+    pub root_block: Option<U64>,
+    pub fee: Amount,
+}
+
+/// Answer of a relayer which accepted a `RelayRequest`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayResponse {
+    pub tx_hash: TxHash,
+}
+
+fn calldata<M: Middleware>(call: ContractCall<M, ()>) -> Result<Bytes> {
+    call.calldata()
+        .ok_or(eyre::Report::msg("Cannot encode the transaction!"))
+}
+
+impl RelayRequest {
+    pub fn withdraw<M: Middleware>(
+        owshen: &Owshen<M>,
+        withdrawal: &GetWithdrawResponse,
+    ) -> Result<Self> {
+        Ok(RelayRequest {
+            kind: RelayKind::Withdraw,
+            contract: owshen.address(),
+            calldata: calldata(withdraw_call(owshen, withdrawal))?,
+            nullifier: withdrawal.nullifier,
+            root: withdrawal.root,
+            root_block: withdrawal.root_block,
+            fee: withdrawal.relayer_fee,
+        })
+    }
+}
+
+impl GetWithdrawResponse {
+    /// Packages the withdrawal for a relayer, in `relay`.
+    pub fn with_relay<M: Middleware>(self, owshen: &Owshen<M>) -> Result<Self, ApiError> {
+        let relay = RelayRequest::withdraw(owshen, &self)?;
+        Ok(GetWithdrawResponse {
+            relay: Some(relay),
+            ..self
+        })
+    }
+}
+
+/// Hands `request` over to the relayer at `url`, returning the hash of the
+/// transaction it submitted.
+pub async fn submit(url: &str, request: &RelayRequest) -> Result<TxHash> {
+    let response = reqwest::Client::new()
+        .post(url)
+        .json(request)
+        .send()
+        .await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(eyre::Report::msg(format!(
+            "Relayer refused the transaction ({}): {}",
+            status,
+            response.text().await.unwrap_or_default()
+        )));
+    }
+    Ok(response.json::<RelayResponse>().await?.tx_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    // A relayer accepting anything and answering with a fixed hash.
+    fn mock_relayer(received: Arc<Mutex<Vec<RelayRequest>>>, tx_hash: TxHash) -> String {
+        let app = Router::new()
+            .route(
+                "/relay",
+                post(move |Json(request): Json<RelayRequest>| async move {
+                    received.lock().await.push(request);
+                    Json(RelayResponse { tx_hash })
+                }),
+            )
+            .route(
+                "/busy",
+                post(|| async { (StatusCode::SERVICE_UNAVAILABLE, "too busy") }),
+            );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_submit_to_relayer() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let tx_hash = TxHash::repeat_byte(0xab);
+        let url = mock_relayer(received.clone(), tx_hash);
+
+        let request = RelayRequest {
+            kind: RelayKind::Withdraw,
+            contract: H160::repeat_byte(1),
+            calldata: Bytes::from(vec![1, 2, 3]),
+            nullifier: U256::from(123),
+            root: U256::from(456),
+            root_block: Some(U64::from(7)),
+            fee: Amount::from(5),
+        };
+        assert_eq!(
+            submit(&format!("{}/relay", url), &request).await.unwrap(),
+            tx_hash
+        );
+        assert_eq!(*received.lock().await, vec![request.clone()]);
+
+        let err = submit(&format!("{}/busy", url), &request)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("too busy"));
+    }
+}
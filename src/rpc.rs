@@ -0,0 +1,311 @@
+use async_trait::async_trait;
+use ethers::providers::{
+    Http, HttpClientError, JsonRpcClient, JsonRpcError, Provider, ProviderError, PubsubClient,
+    RpcError, Ws, WsClientError,
+};
+use ethers::types::U256;
+use eyre::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use tokio::sync::Mutex;
+use tokio::time::{sleep, timeout, Duration};
+
+/// Delay before the second attempt to reconnect a WebSocket, doubled after
+/// every failure up to `MAX_RECONNECT_DELAY`.
+const RECONNECT_DELAY: Duration = Duration::from_millis(200);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const RECONNECT_ATTEMPTS: usize = 10;
+
+/// Time the node gets to answer a request unless `--rpc-timeout` is given.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Provider of the node, see `Transport`.
+pub type RpcProvider = Provider<Transport>;
+
+/// Connects to `endpoint` with the default timeout, see `connect_with_timeout`.
+pub async fn connect(endpoint: &str) -> Result<RpcProvider> {
+    connect_with_timeout(endpoint, DEFAULT_TIMEOUT).await
+}
+
+/// Connects to `endpoint`, over a WebSocket for `ws://` and `wss://` URLs
+/// and over HTTP otherwise. Requests the node doesn't answer within
+/// `timeout` fail with `TransportError::Timeout`.
+pub async fn connect_with_timeout(endpoint: &str, timeout: Duration) -> Result<RpcProvider> {
+    let connection = if endpoint.starts_with("ws://") || endpoint.starts_with("wss://") {
+        Connection::Ws(ReconnectingWs::connect(endpoint).await?)
+    } else {
+        Connection::Http(Http::from_str(endpoint)?)
+    };
+    Ok(Provider::new(Transport {
+        connection,
+        timeout,
+    }))
+}
+
+/// Whether `provider` can push notifications.
+pub fn is_pubsub(provider: &RpcProvider) -> bool {
+    matches!(provider.as_ref().connection, Connection::Ws(_))
+}
+
+/// Whether `e` reports a request the node didn't answer in time: a
+/// `TransportError::Timeout`, or a timeout of the HTTP client itself.
+pub fn is_timeout(e: &ProviderError) -> bool {
+    let e: &(dyn std::error::Error + 'static) = match e {
+        ProviderError::JsonRpcClientError(e) => e.as_ref(),
+        _ => return false,
+    };
+    match e.downcast_ref::<TransportError>() {
+        Some(TransportError::Timeout(_)) => true,
+        Some(TransportError::Http(HttpClientError::ReqwestError(e))) => e.is_timeout(),
+        _ => false,
+    }
+}
+
+/// Connection to a remote node, along with how long its requests may take.
+#[derive(Debug, Clone)]
+pub struct Transport {
+    connection: Connection,
+    timeout: Duration,
+}
+
+/// Either transport ethers offers for a remote node. Both answer requests,
+/// only a WebSocket can push notifications, e.g. new logs, so that they
+/// don't have to be polled for.
+#[derive(Debug, Clone)]
+enum Connection {
+    Http(Http),
+    Ws(ReconnectingWs),
+}
+
+/// WebSocket connection which is opened again when it drops. Subscriptions
+/// don't survive it: their streams end, and it is up to their owners to
+/// subscribe again and to catch up with what they missed in between.
+#[derive(Debug, Clone)]
+pub struct ReconnectingWs {
+    endpoint: Arc<String>,
+    // Current connection, along with how many times it was reopened.
+    conn: Arc<RwLock<(u64, Ws)>>,
+    reconnecting: Arc<Mutex<()>>,
+}
+
+impl ReconnectingWs {
+    async fn connect(endpoint: &str) -> Result<Self, WsClientError> {
+        Ok(ReconnectingWs {
+            endpoint: Arc::new(endpoint.to_string()),
+            conn: Arc::new(RwLock::new((0, Self::open(endpoint).await?))),
+            reconnecting: Arc::new(Mutex::new(())),
+        })
+    }
+
+    // Reconnections are handled here rather than by ethers, which would
+    // resubscribe silently and skip the logs emitted in between.
+    async fn open(endpoint: &str) -> Result<Ws, WsClientError> {
+        Ws::connect_with_reconnects(endpoint, 0).await
+    }
+
+    fn current(&self) -> (u64, Ws) {
+        self.conn.read().unwrap().clone()
+    }
+
+    /// Replaces the connection `dropped` with a new one, backing off
+    /// exponentially between attempts. Concurrent callers wait for the
+    /// first one to be done instead of connecting again.
+    async fn reconnect(&self, dropped: u64) -> Result<Ws, WsClientError> {
+        let _reconnecting = self.reconnecting.lock().await;
+        let (generation, ws) = self.current();
+        if generation != dropped {
+            return Ok(ws);
+        }
+        let mut delay = RECONNECT_DELAY;
+        for attempt in 1..=RECONNECT_ATTEMPTS {
+            tracing::info!(
+                "Reconnecting to {} (attempt {}/{})",
+                self.endpoint,
+                attempt,
+                RECONNECT_ATTEMPTS
+            );
+            match Self::open(&self.endpoint).await {
+                Ok(ws) => {
+                    *self.conn.write().unwrap() = (generation + 1, ws.clone());
+                    tracing::info!("Reconnected to {}", self.endpoint);
+                    return Ok(ws);
+                }
+                Err(e) => {
+                    tracing::warn!("Cannot reconnect to {}: {}", self.endpoint, e);
+                    if attempt < RECONNECT_ATTEMPTS {
+                        sleep(delay).await;
+                        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                    }
+                }
+            }
+        }
+        Err(WsClientError::TooManyReconnects)
+    }
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, WsClientError>
+    where
+        T: fmt::Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let (generation, ws) = self.current();
+        match ws.request(method, &params).await {
+            Err(WsClientError::UnexpectedClose | WsClientError::DeadChannel) => {
+                self.reconnect(generation)
+                    .await?
+                    .request(method, params)
+                    .await
+            }
+            result => result,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TransportError {
+    Http(HttpClientError),
+    Ws(WsClientError),
+    // Subscribing over HTTP.
+    NotPubsub,
+    // The node didn't answer within the timeout of the transport.
+    Timeout(Duration),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransportError::Http(e) => write!(f, "{}", e),
+            TransportError::Ws(e) => write!(f, "{}", e),
+            TransportError::NotPubsub => write!(f, "Subscriptions need a WebSocket endpoint"),
+            TransportError::Timeout(timeout) => {
+                write!(f, "Node didn't answer within {:?}", timeout)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+impl RpcError for TransportError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            TransportError::Http(e) => e.as_error_response(),
+            TransportError::Ws(e) => e.as_error_response(),
+            TransportError::NotPubsub | TransportError::Timeout(_) => None,
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            TransportError::Http(e) => e.as_serde_error(),
+            TransportError::Ws(e) => e.as_serde_error(),
+            TransportError::NotPubsub | TransportError::Timeout(_) => None,
+        }
+    }
+}
+
+impl From<TransportError> for ProviderError {
+    fn from(e: TransportError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(e))
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for Transport {
+    type Error = TransportError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: fmt::Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let request = async {
+            match &self.connection {
+                Connection::Http(http) => http
+                    .request(method, params)
+                    .await
+                    .map_err(TransportError::Http),
+                Connection::Ws(ws) => ws.request(method, params).await.map_err(TransportError::Ws),
+            }
+        };
+        timeout(self.timeout, request)
+            .await
+            .map_err(|_| TransportError::Timeout(self.timeout))?
+    }
+}
+
+impl PubsubClient for Transport {
+    type NotificationStream = <Ws as PubsubClient>::NotificationStream;
+
+    fn subscribe<T: Into<U256>>(&self, id: T) -> Result<Self::NotificationStream, Self::Error> {
+        match &self.connection {
+            Connection::Http(_) => Err(TransportError::NotPubsub),
+            Connection::Ws(ws) => ws.current().1.subscribe(id).map_err(TransportError::Ws),
+        }
+    }
+
+    fn unsubscribe<T: Into<U256>>(&self, id: T) -> Result<(), Self::Error> {
+        match &self.connection {
+            Connection::Http(_) => Err(TransportError::NotPubsub),
+            Connection::Ws(ws) => ws.current().1.unsubscribe(id).map_err(TransportError::Ws),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::Middleware;
+
+    #[tokio::test]
+    async fn test_connect() {
+        let port = 8557u16;
+        let _ganache = ethers::utils::Ganache::new().port(port).spawn();
+        let http = connect(&format!("http://localhost:{}", port))
+            .await
+            .unwrap();
+        assert!(!is_pubsub(&http));
+        let ws = connect(&format!("ws://localhost:{}", port)).await.unwrap();
+        assert!(is_pubsub(&ws));
+        assert_eq!(
+            http.get_accounts().await.unwrap(),
+            ws.get_accounts().await.unwrap()
+        );
+        assert!(connect("not a url").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        // Accepts connections and never answers on them.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let blackhole = tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+
+        let provider = connect_with_timeout(&endpoint, Duration::from_millis(200))
+            .await
+            .unwrap();
+        let started = tokio::time::Instant::now();
+        let err = provider.get_block_number().await.unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(is_timeout(&err), "{}", err);
+        // Told apart by the error itself rather than by its message.
+        let lookalike = ProviderError::CustomError(err.to_string());
+        assert!(!is_timeout(&lookalike));
+        assert!(matches!(
+            crate::apis::ApiError::from(err),
+            crate::apis::ApiError::NodeTimeout(_)
+        ));
+        assert!(matches!(
+            crate::apis::ApiError::from(lookalike),
+            crate::apis::ApiError::NodeUnreachable(_)
+        ));
+        blackhole.abort();
+    }
+}
@@ -0,0 +1,809 @@
+use bindings::owshen::{Owshen, Proof as OwshenProof};
+use ethers::abi::AbiDecode;
+use ethers::prelude::*;
+use ethers::providers::MiddlewareError;
+use eyre::Result;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::future::Future;
+use std::str::FromStr;
+use structopt::StructOpt;
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::apis::ApiError;
+use crate::h160_to_u256;
+use crate::proof::Proof;
+use crate::rpc::RpcProvider;
+use crate::{GetConfirmationResponse, GetSendResponse, GetWithdrawResponse};
+
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Applies a `TxType` to anything exposing `.legacy()` (deployers and contract calls).
+/// EIP-1559 transactions are left as they are, the provider fills in
+/// `max_fee_per_gas`/`max_priority_fee_per_gas` when sending them.
+macro_rules! with_tx_type {
+    ($tx:expr, $tx_type:expr) => {
+        match $tx_type {
+            $crate::transaction::TxType::Legacy => $tx.legacy(),
+            $crate::transaction::TxType::Eip1559 => $tx,
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxType {
+    Legacy,
+    Eip1559,
+}
+
+impl FromStr for TxType {
+    type Err = eyre::Report;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "legacy" => Ok(TxType::Legacy),
+            "eip1559" => Ok(TxType::Eip1559),
+            _ => Err(eyre::Report::msg("Expected `legacy` or `eip1559`!")),
+        }
+    }
+}
+
+impl TxType {
+    /// Chains supporting EIP-1559 carry a base fee in their blocks.
+    pub fn detect<TX>(block: &Block<TX>) -> Self {
+        if block.base_fee_per_gas.is_some() {
+            TxType::Eip1559
+        } else {
+            TxType::Legacy
+        }
+    }
+}
+
+/// Parses an amount of wei, in decimal or `0x` prefixed hex, the same way
+/// from the command line and from requests.
+fn parse_wei(s: &str) -> Result<U256, String> {
+    let s = s.trim();
+    match s.strip_prefix("0x") {
+        Some("") => Err("Expected hex digits after `0x`!".to_string()),
+        Some(hex) => U256::from_str_radix(hex, 16).map_err(|e| e.to_string()),
+        None => U256::from_dec_str(s).map_err(|e| e.to_string()),
+    }
+}
+
+// Wei in requests, a string `parse_wei` accepts or a JSON number.
+fn deserialize_wei<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<U256>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Wei {
+        Number(u64),
+        Text(String),
+    }
+    match Option::<Wei>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Wei::Number(n)) => Ok(Some(U256::from(n))),
+        Some(Wei::Text(s)) => parse_wei(&s).map(Some).map_err(de::Error::custom),
+    }
+}
+
+/// Gas settings replacing the ones the node would estimate, for chains with
+/// misbehaving gas oracles or during congestion. All amounts are in wei.
+#[derive(StructOpt, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GasOverrides {
+    #[structopt(
+        long,
+        parse(try_from_str = parse_wei),
+        help = "Gas price of a legacy transaction"
+    )]
+    #[serde(
+        default,
+        deserialize_with = "deserialize_wei",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub gas_price: Option<U256>,
+    #[structopt(
+        long,
+        parse(try_from_str = parse_wei),
+        help = "Max fee per gas of an EIP-1559 transaction"
+    )]
+    #[serde(
+        default,
+        deserialize_with = "deserialize_wei",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_fee: Option<U256>,
+    #[structopt(
+        long,
+        parse(try_from_str = parse_wei),
+        help = "Max priority fee per gas of an EIP-1559 transaction"
+    )]
+    #[serde(
+        default,
+        deserialize_with = "deserialize_wei",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub priority_fee: Option<U256>,
+    #[structopt(
+        long,
+        parse(try_from_str = parse_wei),
+        help = "Gas limit, at least the estimated gas"
+    )]
+    #[serde(
+        default,
+        deserialize_with = "deserialize_wei",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub gas_limit: Option<U256>,
+}
+
+impl GasOverrides {
+    pub fn is_empty(&self) -> bool {
+        *self == GasOverrides::default()
+    }
+
+    /// Rejects the overrides which can't make a valid transaction.
+    pub fn validate(&self) -> Result<(), ApiError> {
+        let zero = [self.gas_price, self.max_fee, self.gas_limit]
+            .into_iter()
+            .flatten()
+            .any(|value| value.is_zero());
+        if zero {
+            return Err(ApiError::BadRequest(
+                "Gas price, max fee and gas limit cannot be zero!".to_string(),
+            ));
+        }
+        if self.gas_price.is_some() && (self.max_fee.is_some() || self.priority_fee.is_some()) {
+            return Err(ApiError::BadRequest(
+                "A gas price is for legacy transactions, max fees for EIP-1559 ones!".to_string(),
+            ));
+        }
+        if let (Some(max_fee), Some(priority_fee)) = (self.max_fee, self.priority_fee) {
+            if priority_fee > max_fee {
+                return Err(ApiError::BadRequest(format!(
+                    "Priority fee {} exceeds the max fee {}!",
+                    priority_fee, max_fee
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the overrides to `tx`, once its type is settled.
+    pub fn apply(&self, tx: &mut TypedTransaction) -> Result<(), ApiError> {
+        self.validate()?;
+        if let Some(gas_limit) = self.gas_limit {
+            tx.set_gas(gas_limit);
+        }
+        if let Some(gas_price) = self.gas_price {
+            tx.set_gas_price(gas_price);
+        }
+        if self.max_fee.is_some() || self.priority_fee.is_some() {
+            let tx = tx.as_eip1559_mut().ok_or(ApiError::BadRequest(
+                "Max fees need an EIP-1559 transaction!".to_string(),
+            ))?;
+            if let Some(max_fee) = self.max_fee {
+                tx.max_fee_per_gas = Some(max_fee);
+            }
+            if let Some(priority_fee) = self.priority_fee {
+                tx.max_priority_fee_per_gas = Some(priority_fee);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fails when `gas_limit` is below what the transaction is estimated to use,
+/// as it would only run out of gas.
+pub fn check_gas_limit(gas_limit: U256, estimated_gas: U256) -> Result<(), ApiError> {
+    if gas_limit < estimated_gas {
+        return Err(ApiError::BadRequest(format!(
+            "Gas limit {} is below the estimated {}!",
+            gas_limit, estimated_gas
+        )));
+    }
+    Ok(())
+}
+
+/// The most `gas` can cost at `gas_price`. Both may be overridden by the
+/// caller, a product past `U256` is refused rather than overflowing.
+pub fn gas_cost(gas: U256, gas_price: U256) -> Result<U256, ApiError> {
+    gas.checked_mul(gas_price)
+        .ok_or(ApiError::BadRequest(format!(
+            "Gas {} at {} wei costs more wei than can be held!",
+            gas, gas_price
+        )))
+}
+
+/// Returns the requested transaction type, or detects it from the latest block.
+pub async fn resolve_tx_type<M: Middleware>(
+    client: &M,
+    requested: Option<TxType>,
+) -> Result<TxType> {
+    if let Some(tx_type) = requested {
+        return Ok(tx_type);
+    }
+    let block = client
+        .get_block(BlockNumber::Latest)
+        .await
+        .map_err(|e| eyre::Report::msg(e.to_string()))?
+        .ok_or(eyre::Report::msg("Latest block not found!"))?;
+    Ok(TxType::detect(&block))
+}
+
+/// Returns the requested sender once checked that the node can sign for it,
+/// or the first account unlocked on the node.
+pub async fn resolve_from<M: Middleware>(client: &M, requested: Option<H160>) -> Result<H160> {
+    let accounts = client
+        .get_accounts()
+        .await
+        .map_err(|e| eyre::Report::msg(e.to_string()))?;
+    match requested {
+        Some(from) if accounts.contains(&from) => Ok(from),
+        Some(from) => Err(eyre::Report::msg(format!(
+            "Account {:?} is not unlocked on the node!",
+            from
+        ))),
+        None => accounts
+            .first()
+            .copied()
+            .ok_or(eyre::Report::msg("No account is unlocked on the node!")),
+    }
+}
+
+/// Fails with `InsufficientGas` when `from` can't pay for the gas `call` is
+/// estimated to use, or its gas limit when overridden, so that nothing
+/// doomed to fail is broadcast.
+pub async fn ensure_gas<M: Middleware, D>(
+    client: &M,
+    call: &ContractCall<M, D>,
+    from: H160,
+) -> Result<(), ApiError> {
+    let to_api_error = |e: M::Error| ApiError::from_node(e);
+    // The node caps its estimate at the gas limit of the transaction.
+    let mut unlimited = call.tx.clone();
+    match &mut unlimited {
+        TypedTransaction::Legacy(tx) => tx.gas = None,
+        TypedTransaction::Eip2930(tx) => tx.tx.gas = None,
+        TypedTransaction::Eip1559(tx) => tx.gas = None,
+    }
+    let estimated_gas = client
+        .estimate_gas(&unlimited, call.block)
+        .await
+        .map_err(to_api_error)?;
+    let gas = match call.tx.gas() {
+        Some(&gas_limit) => {
+            check_gas_limit(gas_limit, estimated_gas)?;
+            gas_limit
+        }
+        None => estimated_gas,
+    };
+    let gas_price = match call.tx.gas_price() {
+        Some(gas_price) => gas_price,
+        None => client.get_gas_price().await.map_err(to_api_error)?,
+    };
+    let cost = gas_cost(gas, gas_price)?;
+    let balance = client.get_balance(from, None).await.map_err(to_api_error)?;
+    if balance < cost {
+        return Err(ApiError::InsufficientGas(format!(
+            "Account {:?} holds {} wei but the transaction costs up to {} wei, {} wei short!",
+            from,
+            balance,
+            cost,
+            cost - balance
+        )));
+    }
+    Ok(())
+}
+
+// Selector of `Error(string)`, what `require` and `revert` with a message
+// revert with.
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+// Decodes an `Error(string)` revert payload. Panics and custom errors carry no
+// message.
+fn decode_revert_reason(data: &[u8]) -> Option<String> {
+    String::decode(data.strip_prefix(&ERROR_SELECTOR)?).ok()
+}
+
+// Receipts don't carry the revert reason, so the transaction is replayed on
+// the state of the block it was mined in.
+async fn revert_reason(
+    provider: &RpcProvider,
+    tx_hash: TxHash,
+    block_number: U64,
+) -> Result<Option<String>, ProviderError> {
+    let tx = match provider.get_transaction(tx_hash).await? {
+        Some(tx) => tx,
+        None => return Ok(None),
+    };
+    let typed_tx: TypedTransaction = (&tx).into();
+    match provider.call(&typed_tx, Some(block_number.into())).await {
+        Ok(_) => Ok(None),
+        Err(e) => Ok(e
+            .as_error_response()
+            .and_then(|resp| resp.as_revert_data())
+            .and_then(|data| decode_revert_reason(&data))),
+    }
+}
+
+/// Polls for the receipt of `tx_hash` until it is mined. Returns `None` when
+/// the transaction is still pending after `timeout`.
+pub async fn wait_for_confirmation(
+    provider: &RpcProvider,
+    tx_hash: TxHash,
+    timeout: Duration,
+) -> Result<Option<GetConfirmationResponse>, ProviderError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(receipt) = provider.get_transaction_receipt(tx_hash).await? {
+            if let Some(block_number) = receipt.block_number {
+                let success = receipt.status == Some(U64::from(1));
+                let revert_reason = if success {
+                    None
+                } else {
+                    revert_reason(provider, tx_hash, block_number).await?
+                };
+                return Ok(Some(GetConfirmationResponse {
+                    tx_hash,
+                    block_number,
+                    success,
+                    revert_reason,
+                }));
+            }
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        sleep(RECEIPT_POLL_INTERVAL).await;
+    }
+}
+
+/// Attempts `broadcast_once` makes before giving up.
+pub const BROADCAST_ATTEMPTS: usize = 3;
+pub const BROADCAST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+// The transaction of `from` with `nonce` mined since block `since`, if any.
+async fn mined_with_nonce<M: Middleware>(
+    client: &M,
+    from: H160,
+    nonce: U256,
+    since: U64,
+) -> Result<Option<TxHash>, M::Error> {
+    let head = client.get_block_number().await?;
+    for number in since.as_u64()..=head.as_u64() {
+        if let Some(block) = client.get_block_with_txs(number).await? {
+            if let Some(tx) = block
+                .transactions
+                .iter()
+                .find(|tx| tx.from == from && tx.nonce == nonce)
+            {
+                return Ok(Some(tx.hash));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Broadcasts `tx` through `send`, retrying up to `attempts` times when it
+/// fails. The nonce is fixed before the first attempt, so that however many
+/// attempts reach the node only one can be included. An attempt may fail
+/// after reaching the node, e.g. when its answer is lost, so before each
+/// retry the hash of the previous attempt is looked up and the blocks mined
+/// since the first one are searched for the nonce. Nothing is resubmitted
+/// once the nonce is taken.
+pub async fn broadcast_once<M, F, Fut>(
+    client: &M,
+    mut tx: TypedTransaction,
+    attempts: usize,
+    delay: Duration,
+    mut send: F,
+) -> Result<TxHash>
+where
+    M: Middleware,
+    F: FnMut(TypedTransaction) -> Fut,
+    Fut: Future<Output = Result<TxHash, M::Error>>,
+{
+    let from = *tx
+        .from()
+        .ok_or(eyre::Report::msg("The transaction has no sender!"))?;
+    let since = client.get_block_number().await?;
+    let nonce = match tx.nonce() {
+        Some(nonce) => *nonce,
+        None => {
+            client
+                .get_transaction_count(from, Some(BlockNumber::Pending.into()))
+                .await?
+        }
+    };
+    tx.set_nonce(nonce);
+
+    let mut sent = None;
+    let mut last_error = String::new();
+    for attempt in 0..attempts {
+        if attempt > 0 {
+            sleep(delay).await;
+            if let Some(tx_hash) = sent {
+                if client.get_transaction(tx_hash).await?.is_some() {
+                    return Ok(tx_hash);
+                }
+            }
+            if let Some(tx_hash) = mined_with_nonce(client, from, nonce, since).await? {
+                return Ok(tx_hash);
+            }
+            let next_nonce = client
+                .get_transaction_count(from, Some(BlockNumber::Pending.into()))
+                .await?;
+            if next_nonce > nonce {
+                return Err(eyre::Report::msg(format!(
+                    "A transaction of {:?} with nonce {} is already pending, not resubmitting!",
+                    from, nonce
+                )));
+            }
+        }
+        match send(tx.clone()).await {
+            Ok(tx_hash) => {
+                // Known to the node, pending or mined.
+                if client.get_transaction(tx_hash).await?.is_some() {
+                    return Ok(tx_hash);
+                }
+                sent = Some(tx_hash);
+                last_error = format!("the node dropped {:?}", tx_hash);
+            }
+            Err(e) => last_error = e.to_string(),
+        }
+        tracing::warn!(
+            "Broadcast attempt {}/{} failed: {}",
+            attempt + 1,
+            attempts,
+            last_error
+        );
+    }
+    Err(eyre::Report::msg(format!(
+        "Cannot broadcast the transaction after {} attempts: {}",
+        attempts, last_error
+    )))
+}
+
+fn owshen_proof(proof: &Proof) -> OwshenProof {
+    OwshenProof {
+        a: proof.a,
+        b: proof.b,
+        c: proof.c,
+    }
+}
+
+/// Builds the `Owshen.withdraw` call paying out what the proof of `withdraw`
+/// was generated for, the relayer fee going to the sender of the transaction.
+pub fn withdraw_call<M: Middleware>(
+    owshen: &Owshen<M>,
+    withdraw: &GetWithdrawResponse,
+) -> ContractCall<M, ()> {
+    owshen.withdraw(
+        withdraw.nullifier,
+        withdraw.ephemeral.into(),
+        owshen_proof(&withdraw.proof),
+        withdraw.token,
+        withdraw.withdrawn_amount.into(),
+        withdraw.obfuscated_remaining_amount,
+        withdraw.to,
+        withdraw.commitment,
+        withdraw.view_tag,
+        withdraw.relayer_fee.into(),
+    )
+}
+
+// `Owshen.send` takes both view tags in a single argument, the receiver's in
+// the high byte, to stay within the stack limit of the EVM.
+fn view_tags(receiver: u8, sender: u8) -> u16 {
+    u16::from_be_bytes([receiver, sender])
+}
+
+/// Builds the `Owshen.send` call, always emitting the sender's change coin.
+pub fn send_call<M: Middleware>(owshen: &Owshen<M>, send: &GetSendResponse) -> ContractCall<M, ()> {
+    owshen.send(
+        send.nullifier,
+        owshen_proof(&send.proof),
+        send.receiver_ephemeral.into(),
+        send.sender_ephemeral.into(),
+        send.sender_commitment,
+        send.receiver_commitment,
+        h160_to_u256(send.token),
+        send.obfuscated_receiver_amount,
+        send.obfuscated_sender_amount,
+        true,
+        view_tags(send.receiver_view_tag, send.sender_view_tag),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_tx_type() {
+        let legacy_block = Block::<H256>::default();
+        assert_eq!(TxType::detect(&legacy_block), TxType::Legacy);
+
+        let london_block = Block::<H256> {
+            base_fee_per_gas: Some(U256::from(7)),
+            ..Default::default()
+        };
+        assert_eq!(TxType::detect(&london_block), TxType::Eip1559);
+
+        assert_eq!("legacy".parse::<TxType>().unwrap(), TxType::Legacy);
+        assert_eq!("eip1559".parse::<TxType>().unwrap(), TxType::Eip1559);
+        assert!("type2".parse::<TxType>().is_err());
+    }
+
+    #[test]
+    fn test_decode_revert_reason() {
+        let message = ethers::abi::encode(&[ethers::abi::Token::String(
+            "Owshen: invalid proof".to_string(),
+        )]);
+        let data = [ERROR_SELECTOR.to_vec(), message.clone()].concat();
+        assert_eq!(
+            decode_revert_reason(&data),
+            Some("Owshen: invalid proof".to_string())
+        );
+        assert_eq!(decode_revert_reason(&[0x08]), None);
+
+        // A custom error whose arguments happen to decode as a string.
+        let custom = [vec![0x12, 0x34, 0x56, 0x78], message].concat();
+        assert_eq!(decode_revert_reason(&custom), None);
+        // `Panic(uint256)`, e.g. an overflow.
+        let panic = [
+            vec![0x4e, 0x48, 0x7b, 0x71],
+            ethers::abi::encode(&[ethers::abi::Token::Uint(U256::from(0x11))]),
+        ]
+        .concat();
+        assert_eq!(decode_revert_reason(&panic), None);
+    }
+
+    #[tokio::test]
+    async fn test_ensure_gas() {
+        let port = 8554u16;
+        let _ganache = ethers::utils::Ganache::new().port(port).spawn();
+        let provider = Provider::<Http>::try_from(format!("http://localhost:{}", port)).unwrap();
+        let provider = std::sync::Arc::new(provider);
+        let accounts = provider.get_accounts().await.unwrap();
+        let token = bindings::simple_erc_20::SimpleErc20::deploy(
+            provider.clone(),
+            (
+                U256::from(1000),
+                "test_token".to_string(),
+                "TEST".to_string(),
+                18u8,
+            ),
+        )
+        .unwrap()
+        .legacy()
+        .from(accounts[0])
+        .send()
+        .await
+        .unwrap();
+
+        // Leave the account with nothing but dust.
+        let poor = accounts[3];
+        let gas_price = provider.get_gas_price().await.unwrap();
+        let balance = provider.get_balance(poor, None).await.unwrap();
+        let drain = TransactionRequest::pay(accounts[4], balance - gas_price * 21000 - 1)
+            .from(poor)
+            .gas(21000)
+            .gas_price(gas_price);
+        provider
+            .send_transaction(drain, None)
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        let nonce = provider.get_transaction_count(poor, None).await.unwrap();
+
+        let call = token
+            .transfer(accounts[0], U256::zero())
+            .legacy()
+            .from(poor);
+        match ensure_gas(provider.as_ref(), &call, poor).await {
+            Err(ApiError::InsufficientGas(msg)) => assert!(msg.contains("wei short")),
+            other => panic!("Expected insufficient gas, got {:?}", other),
+        }
+        // Nothing was broadcast.
+        assert_eq!(
+            provider.get_transaction_count(poor, None).await.unwrap(),
+            nonce
+        );
+
+        let call = token
+            .transfer(poor, U256::zero())
+            .legacy()
+            .from(accounts[0]);
+        assert!(ensure_gas(provider.as_ref(), &call, accounts[0])
+            .await
+            .is_ok());
+
+        // Would run out of gas.
+        let call = call.gas(21000);
+        assert!(matches!(
+            ensure_gas(provider.as_ref(), &call, accounts[0]).await,
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_gas_overrides() {
+        let provider = Provider::<Http>::try_from("http://localhost:1").unwrap();
+        let token = bindings::simple_erc_20::SimpleErc20::new(
+            H160::from_low_u64_be(1),
+            std::sync::Arc::new(provider),
+        );
+        let overrides = GasOverrides {
+            gas_limit: Some(U256::from(300_000)),
+            max_fee: Some(U256::from(50)),
+            priority_fee: Some(U256::from(2)),
+            ..Default::default()
+        };
+        let mut call = token.transfer(H160::zero(), U256::zero());
+        overrides.apply(&mut call.tx).unwrap();
+        assert_eq!(call.tx.gas(), Some(&U256::from(300_000)));
+        let tx = call.tx.as_eip1559_ref().unwrap();
+        assert_eq!(tx.max_fee_per_gas, Some(U256::from(50)));
+        assert_eq!(tx.max_priority_fee_per_gas, Some(U256::from(2)));
+
+        let mut legacy = token.transfer(H160::zero(), U256::zero()).legacy();
+        let gas_price = GasOverrides {
+            gas_price: Some(U256::from(7)),
+            ..Default::default()
+        };
+        gas_price.apply(&mut legacy.tx).unwrap();
+        assert_eq!(legacy.tx.gas_price(), Some(U256::from(7)));
+        assert_eq!(legacy.tx.gas(), None);
+        // Max fees don't exist before EIP-1559.
+        assert!(overrides.apply(&mut legacy.tx).is_err());
+
+        for invalid in [
+            GasOverrides {
+                gas_price: Some(U256::from(7)),
+                max_fee: Some(U256::from(50)),
+                ..Default::default()
+            },
+            GasOverrides {
+                max_fee: Some(U256::from(2)),
+                priority_fee: Some(U256::from(50)),
+                ..Default::default()
+            },
+            GasOverrides {
+                gas_limit: Some(U256::zero()),
+                ..Default::default()
+            },
+        ] {
+            assert!(matches!(invalid.validate(), Err(ApiError::BadRequest(_))));
+        }
+        assert!(GasOverrides::default().is_empty());
+        assert!(!overrides.is_empty());
+
+        assert!(check_gas_limit(U256::from(21000), U256::from(21000)).is_ok());
+        assert!(check_gas_limit(U256::from(20999), U256::from(21000)).is_err());
+
+        assert_eq!(
+            gas_cost(U256::from(21000), U256::from(7)).unwrap(),
+            U256::from(147_000)
+        );
+        assert!(matches!(
+            gas_cost(U256::from(21000), U256::MAX),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_wei_formats() {
+        assert_eq!(parse_wei("1000"), Ok(U256::from(1000)));
+        assert_eq!(parse_wei("0x3e8"), Ok(U256::from(1000)));
+        assert!(parse_wei("0x").is_err());
+        assert!(parse_wei("-1").is_err());
+        assert!(parse_wei("1.5").is_err());
+
+        // Requests take the same formats as the command line.
+        let overrides: GasOverrides =
+            serde_json::from_str(r#"{"gas_price": "1000", "max_fee": "0x3e8", "gas_limit": 1000}"#)
+                .unwrap();
+        assert_eq!(overrides.gas_price, Some(U256::from(1000)));
+        assert_eq!(overrides.max_fee, Some(U256::from(1000)));
+        assert_eq!(overrides.priority_fee, None);
+        assert_eq!(overrides.gas_limit, Some(U256::from(1000)));
+        assert!(serde_json::from_str::<GasOverrides>(r#"{"gas_price": "wei"}"#).is_err());
+        let serialized = serde_json::to_string(&overrides).unwrap();
+        assert_eq!(
+            serde_json::from_str::<GasOverrides>(&serialized).unwrap(),
+            overrides
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_confirmation() {
+        let port = 8548u16;
+        let _ganache = ethers::utils::Ganache::new().port(port).spawn();
+        let provider = crate::rpc::connect(&format!("http://localhost:{}", port))
+            .await
+            .unwrap();
+        let accounts = provider.get_accounts().await.unwrap();
+
+        let tx = TransactionRequest::pay(accounts[1], 1000).from(accounts[0]);
+        let tx_hash = provider.send_transaction(tx, None).await.unwrap().tx_hash();
+
+        let confirmation = wait_for_confirmation(&provider, tx_hash, Duration::from_secs(10))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(confirmation.tx_hash, tx_hash);
+        assert!(confirmation.success);
+        assert!(confirmation.revert_reason.is_none());
+        assert!(confirmation.block_number > U64::zero());
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_once() {
+        let port = 8565u16;
+        let _ganache = ethers::utils::Ganache::new().port(port).spawn();
+        let provider = crate::rpc::connect(&format!("http://localhost:{}", port))
+            .await
+            .unwrap();
+        let accounts = provider.get_accounts().await.unwrap();
+        let payment =
+            || TypedTransaction::from(TransactionRequest::pay(accounts[1], 1000).from(accounts[0]));
+        let nonce = || provider.get_transaction_count(accounts[0], None);
+        let sent = |tx: TypedTransaction| {
+            let provider = &provider;
+            async move {
+                let pending = provider.send_transaction(tx, None).await?;
+                Ok::<_, ProviderError>(pending.tx_hash())
+            }
+        };
+
+        // The first attempt doesn't reach the node.
+        let mut calls = 0;
+        let before = nonce().await.unwrap();
+        let tx_hash = broadcast_once(&provider, payment(), 3, Duration::ZERO, |tx| {
+            calls += 1;
+            let fails = calls == 1;
+            async move {
+                if fails {
+                    return Err(ProviderError::CustomError("connection reset".to_string()));
+                }
+                sent(tx).await
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(calls, 2);
+        assert_eq!(nonce().await.unwrap(), before + 1);
+        let mined = provider.get_transaction(tx_hash).await.unwrap().unwrap();
+        assert_eq!(mined.nonce, before);
+
+        // The first attempt is mined but its answer is lost, it isn't resent.
+        let mut calls = 0;
+        let lost = std::cell::Cell::new(None);
+        let before = nonce().await.unwrap();
+        let tx_hash = broadcast_once(&provider, payment(), 3, Duration::ZERO, |tx| {
+            calls += 1;
+            let first = calls == 1;
+            let lost = &lost;
+            async move {
+                let tx_hash = sent(tx).await?;
+                if first {
+                    lost.set(Some(tx_hash));
+                    return Err(ProviderError::CustomError("timed out".to_string()));
+                }
+                Ok(tx_hash)
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(calls, 1);
+        assert_eq!(Some(tx_hash), lost.get());
+        assert_eq!(nonce().await.unwrap(), before + 1);
+
+        // Gives up once the attempts are exhausted.
+        let err = broadcast_once(&provider, payment(), 2, Duration::ZERO, |_| async {
+            Err(ProviderError::CustomError("connection refused".to_string()))
+        })
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("connection refused"));
+    }
+}
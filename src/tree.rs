@@ -1,6 +1,6 @@
 use crate::fp::Fp;
 use crate::hash::hash4;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
 #[derive(Debug, Clone)]
 pub struct SparseMerkleTree {
@@ -19,8 +19,17 @@ impl SparseMerkleTree {
         self.layers.len() - 1
     }
 
+    /// An empty tree whose leaves default to 0, as `zeros(0)` of
+    /// `SparseMerkleTree.sol`.
     pub fn new(depth: usize) -> Self {
-        let mut defaults = vec![Fp::from(0)];
+        Self::with_default_leaf(depth, Fp::from(0))
+    }
+
+    /// An empty tree whose leaves default to `default_leaf`. The defaults of
+    /// the upper layers, the empty root among them, derive from it: it has to
+    /// match the contract's or none of the proofs verify on-chain.
+    pub fn with_default_leaf(depth: usize, default_leaf: Fp) -> Self {
+        let mut defaults = vec![default_leaf];
         for i in 0..depth {
             defaults.push(hash4([defaults[i], defaults[i], defaults[i], defaults[i]]));
         }
@@ -50,6 +59,30 @@ impl SparseMerkleTree {
         }
     }
 
+    /// Sets all of `leaves` at once, the later of two leaves at the same
+    /// index winning. Each node above them is hashed once, instead of once
+    /// per leaf below it with `set`, the tree ends up the same either way.
+    pub fn insert_many(&mut self, leaves: &[(u64, Fp)]) {
+        let mut indices = BTreeSet::new();
+        for (index, value) in leaves {
+            self.layers[0].insert(*index, *value);
+            indices.insert(*index);
+        }
+        for layer in 0..self.depth() {
+            let parents = indices
+                .iter()
+                .map(|index| index / 4)
+                .collect::<BTreeSet<_>>();
+            for parent in parents.iter() {
+                let vals = (0..4)
+                    .map(|i| self.get_at_layer(layer, parent * 4 + i))
+                    .collect::<Vec<_>>();
+                self.layers[layer + 1].insert(*parent, hash4(vals.try_into().unwrap()));
+            }
+            indices = parents;
+        }
+    }
+
     pub fn get(&self, mut index: u64) -> MerkleProof {
         let value = self.get_at_layer(0, index);
         let mut proof = vec![];
@@ -65,11 +98,38 @@ impl SparseMerkleTree {
         MerkleProof { value, proof }
     }
 
+    /// The leaf at `index`, unless it was never set.
+    pub fn leaf(&self, index: u64) -> Option<Fp> {
+        self.layers[0].get(&index).copied()
+    }
+
+    /// Number of leaves the tree has room for, 4 per node over `depth` layers.
+    pub fn capacity(&self) -> u64 {
+        4u64.pow(self.depth() as u32)
+    }
+
+    /// Number of non-default leaves.
+    pub fn num_leaves(&self) -> usize {
+        self.layers[0].len()
+    }
+
+    /// Returns the non-default leaves, ordered by index.
+    pub fn leaves(&self) -> Vec<(u64, Fp)> {
+        let mut leaves: Vec<(u64, Fp)> = self.layers[0].iter().map(|(i, v)| (*i, *v)).collect();
+        leaves.sort_by_key(|(i, _)| *i);
+        leaves
+    }
+
     #[allow(dead_code)]
     pub fn root(&self) -> Fp {
         self.get_at_layer(self.depth(), 0)
     }
 
+    /// Root of the tree before any leaf is set.
+    pub fn empty_root(&self) -> Fp {
+        self.defaults[self.depth()]
+    }
+
     #[allow(dead_code)]
     pub fn verify(root: Fp, mut index: u64, proof: &MerkleProof) -> bool {
         let mut value = proof.value;
@@ -86,6 +146,14 @@ impl SparseMerkleTree {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ethers::types::U256;
+
+    #[test]
+    fn test_capacity() {
+        assert_eq!(SparseMerkleTree::new(0).capacity(), 1);
+        assert_eq!(SparseMerkleTree::new(2).capacity(), 16);
+        assert_eq!(SparseMerkleTree::new(16).capacity(), 1 << 32);
+    }
 
     #[test]
     fn test_merkle_trees() {
@@ -99,5 +167,57 @@ mod tests {
         assert!(SparseMerkleTree::verify(tree.root(), 345, &res2));
         assert!(SparseMerkleTree::verify(tree.root(), 200, &res3));
         assert!(!SparseMerkleTree::verify(tree.root(), 123, &res2));
+        assert_eq!(tree.empty_root(), SparseMerkleTree::new(16).root());
+    }
+
+    #[test]
+    fn test_insert_many() {
+        let leaves = [
+            (0, Fp::from(1)),
+            (1, Fp::from(2)),
+            (5, Fp::from(3)),
+            (64, Fp::from(4)),
+            (4095, Fp::from(5)),
+            (1, Fp::from(6)),
+        ];
+        let mut sequential = SparseMerkleTree::new(16);
+        for (index, value) in leaves {
+            sequential.set(index, value);
+        }
+        let mut bulk = SparseMerkleTree::new(16);
+        bulk.insert_many(&leaves);
+        assert_eq!(bulk.root(), sequential.root());
+        assert_eq!(bulk.leaves(), sequential.leaves());
+        assert_eq!(bulk.leaf(1), Some(Fp::from(6)));
+        for (index, _) in leaves {
+            let proof = bulk.get(index);
+            assert_eq!(proof.proof, sequential.get(index).proof);
+            assert!(SparseMerkleTree::verify(bulk.root(), index, &proof));
+        }
+
+        // On top of leaves set before, and in batches.
+        let mut batched = SparseMerkleTree::new(16);
+        batched.set(3, Fp::from(7));
+        sequential.set(3, Fp::from(7));
+        batched.insert_many(&leaves[..2]);
+        batched.insert_many(&leaves[2..]);
+        batched.insert_many(&[]);
+        assert_eq!(batched.root(), sequential.root());
+    }
+
+    #[test]
+    fn test_empty_root() {
+        // `zeros(16)` of SparseMerkleTree.sol
+        let contract_empty_root = U256::from_str_radix(
+            "151399c724e17408a7a43cdadba2fc000da9339c56e4d49c6cdee6c4356fbc68",
+            16,
+        )
+        .unwrap();
+        let root: U256 = SparseMerkleTree::new(16).root().into();
+        assert_eq!(root, contract_empty_root);
+
+        let other = SparseMerkleTree::with_default_leaf(16, Fp::from(1));
+        assert_ne!(other.empty_root(), SparseMerkleTree::new(16).empty_root());
+        assert_eq!(other.root(), other.empty_root());
     }
 }